@@ -0,0 +1,49 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "prerender")]
+#[path = "src/markdown_options.rs"]
+#[allow(clippy::needless_return)]
+mod markdown_options;
+
+/// Renders every `raw/*.md` with the options posts render with at run time, into a table sorted by the
+/// markdown's fingerprint. Video embeds are still expanded at run time, as they are cheap next to parsing.
+#[cfg(feature = "prerender")]
+fn prerender() {
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("prerendered.rs");
+    let options = markdown_options::comrak_options();
+    let mut rendered: Vec<(u64, String)> = std::fs::read_dir("raw").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|extension| extension == "md").unwrap_or(false))
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .map(|markdown| (markdown_options::fingerprint(&markdown), comrak::markdown_to_html(&markdown, &options)))
+        .collect();
+    rendered.sort_by_key(|(fingerprint, _)| *fingerprint);
+    rendered.dedup_by_key(|(fingerprint, _)| *fingerprint);
+
+    let entries: Vec<String> = rendered.iter().map(|(fingerprint, html)| format!("    ({:#x}, {:?}),", fingerprint, html)).collect();
+    std::fs::write(out, format!("&[\n{}\n]\n", entries.join("\n"))).unwrap();
+
+    println!("cargo:rerun-if-changed=raw");
+    println!("cargo:rerun-if-changed=src/markdown_options.rs");
+}
+
+/// Embeds the git commit and build time for `/version`. `GIT_SHA` wins when set,
+/// for builds from a source tarball or a container without `.git`.
+fn main() {
+    let git_sha = std::env::var("GIT_SHA").ok()
+        .or_else(|| Command::new("git").args(["rev-parse", "--short", "HEAD"]).output().ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned()))
+        .unwrap_or_else(|| String::from("unknown"));
+    let built_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", built_at);
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    #[cfg(feature = "prerender")]
+    prerender();
+}
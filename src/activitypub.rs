@@ -0,0 +1,604 @@
+//! Following the blog from Mastodon and the rest of the Fediverse through [ActivityPub](https://www.w3.org/TR/activitypub/):
+//! `@<activitypub_user>@<host>` is found through WebFinger, and its actor has an outbox of the latest posts as
+//! `Article`s and an inbox taking `Follow` and `Undo`. A post's `Create` is delivered to every follower the first time
+//! the webhook or the watcher sees it change, and an `Update` every time after, signed with the actor's key.
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use comrak::markdown_to_html;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{StatusCode, Url};
+use rocket::data::{ByteUnit, Data};
+use rocket::http::{ContentType, Status};
+use rocket::response::status::Accepted;
+use rocket::tokio::time::sleep;
+use rocket::{get, post, routes, Route, State};
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use crate::blog::{comrak_options, find_post, CachedSource, Post};
+use crate::body;
+use crate::config::{config, BlogConfig, IdentityLink};
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::shortcodes::escape_html;
+use crate::tenant::Tenant;
+use crate::webmention::{is_public, public_client};
+use signature::SignedRequest;
+
+pub mod signature;
+
+const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+const MEDIA_TYPE: &str = "application/activity+json";
+/// The latest posts the outbox has; followers only look back so far.
+const OUTBOX_SIZE: usize = 20;
+const ATTEMPTS: u32 = 4;
+const FIRST_RETRY: Duration = Duration::from_secs(30);
+/// A `Create` of a long Note with its attachments described inline; Mastodon itself takes up to 1 MB.
+const MAX_ACTIVITY: ByteUnit = ByteUnit::Mebibyte(1);
+
+pub fn actor_id(public_url: &str) -> String {
+    return format!("{}/activitypub/actor", public_url);
+}
+
+fn key_id(public_url: &str) -> String {
+    return format!("{}#main-key", actor_id(public_url));
+}
+
+fn followers_id(public_url: &str) -> String {
+    return format!("{}/activitypub/followers", public_url);
+}
+
+fn object_id(public_url: &str, slug: &str) -> String {
+    return format!("{}/activitypub/posts/{}", public_url, slug);
+}
+
+fn activity_json() -> ContentType {
+    return ContentType::new("application", "activity+json");
+}
+
+/// `acct:<user>@<host>`, as WebFinger is asked for the actor.
+pub fn webfinger_subject(user: &str, public_url: &str) -> Option<String> {
+    let url = Url::parse(public_url).ok()?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str()?, port),
+        None => url.host_str()?.to_owned(),
+    };
+    return Some(format!("acct:{}@{}", user, host));
+}
+
+/// `me` shows on the profile as its fields, each linked with `rel="me"` for the profile it points at to verify.
+pub fn actor(user: &str, public_key_pem: &str, public_url: &str, title: &str, description: &str, me: &[IdentityLink]) -> Value {
+    let id = actor_id(public_url);
+    let fields: Vec<Value> = me.iter()
+        .map(|link| json!({
+            "type": "PropertyValue",
+            "name": link.label,
+            "value": format!(r#"<a href="{0}" rel="me nofollow noopener" target="_blank">{0}</a>"#, escape_html(&link.href)),
+        }))
+        .collect();
+    return json!({
+        "@context": [CONTEXT, SECURITY_CONTEXT],
+        "id": id,
+        "type": "Person",
+        "preferredUsername": user,
+        "name": title,
+        "summary": description,
+        "url": public_url,
+        "inbox": format!("{}/activitypub/inbox", public_url),
+        "outbox": format!("{}/activitypub/outbox", public_url),
+        "followers": followers_id(public_url),
+        "manuallyApprovesFollowers": false,
+        "attachment": fields,
+        "publicKey": { "id": key_id(public_url), "owner": id, "publicKeyPem": public_key_pem },
+    });
+}
+
+/// The post as an `Article`, with `html` its rendered markdown.
+pub fn article(public_url: &str, post: &Post, html: &str) -> Value {
+    return json!({
+        "id": object_id(public_url, &post.slug),
+        "type": "Article",
+        "attributedTo": actor_id(public_url),
+        "name": post.title,
+        "content": html,
+        "url": format!("{}/{}", public_url, post.slug),
+        "published": post.updated.to_rfc3339(),
+        "to": [PUBLIC],
+        "cc": [followers_id(public_url)],
+    });
+}
+
+/// `kind`, `Create` or `Update`, of `article` by the blog's actor.
+pub fn activity(kind: &str, public_url: &str, article: Value, at: DateTime<Utc>) -> Value {
+    let id = match kind {
+        "Create" => format!("{}#create", article["id"].as_str().unwrap_or_default()),
+        _ => format!("{}#{}-{}", article["id"].as_str().unwrap_or_default(), kind.to_ascii_lowercase(), at.timestamp()),
+    };
+    return json!({
+        "@context": CONTEXT,
+        "id": id,
+        "type": kind,
+        "actor": actor_id(public_url),
+        "published": at.to_rfc3339(),
+        "to": [PUBLIC],
+        "cc": [followers_id(public_url)],
+        "object": article,
+    });
+}
+
+/// An actor following one of the blog's.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Follower {
+    /// The blog's actor it follows, as a deployment serves one per tenant.
+    pub following: String,
+    pub id: String,
+    /// Where posts are delivered: its server's shared inbox, if it has one.
+    pub inbox: String,
+    pub followed: DateTime<Utc>,
+}
+
+/// Where followers are kept, and which posts they were sent.
+#[rocket::async_trait]
+pub trait FollowerStore: Send + Sync {
+    async fn add(&self, follower: &Follower) -> Result<(), String>;
+    async fn remove(&self, following: &str, id: &str) -> Result<(), String>;
+    async fn followers(&self, following: &str) -> Result<Vec<Follower>, String>;
+    /// Records `object` as delivered, `true` if it wasn't before.
+    async fn first_delivery(&self, object: &str) -> Result<bool, String>;
+}
+
+pub type SharedStore = Arc<dyn FollowerStore>;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    followers: Mutex<Vec<Follower>>,
+    delivered: Mutex<BTreeSet<String>>,
+}
+
+#[rocket::async_trait]
+impl FollowerStore for MemoryStore {
+    async fn add(&self, follower: &Follower) -> Result<(), String> {
+        let mut followers = self.followers.lock().unwrap();
+        followers.retain(|known| known.following != follower.following || known.id != follower.id);
+        followers.push(follower.to_owned());
+        return Ok(());
+    }
+
+    async fn remove(&self, following: &str, id: &str) -> Result<(), String> {
+        self.followers.lock().unwrap().retain(|known| known.following != following || known.id != id);
+        return Ok(());
+    }
+
+    async fn followers(&self, following: &str) -> Result<Vec<Follower>, String> {
+        return Ok(self.followers.lock().unwrap().iter().filter(|follower| follower.following == following).cloned().collect());
+    }
+
+    async fn first_delivery(&self, object: &str) -> Result<bool, String> {
+        return Ok(self.delivered.lock().unwrap().insert(object.to_owned()));
+    }
+}
+
+/// The parts of another actor's document the inbox and delivery need.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteActor {
+    id: String,
+    inbox: String,
+    #[serde(default)]
+    endpoints: Endpoints,
+    public_key: PublicKey,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoints {
+    shared_inbox: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicKey {
+    id: String,
+    public_key_pem: String,
+}
+
+/// How a delivery went; only a `Retry` is tried again.
+#[derive(Debug, PartialEq, Eq)]
+enum Delivery {
+    Delivered,
+    Refused(String),
+    Retry(String),
+}
+
+pub struct Federation {
+    user: String,
+    key: RsaPrivateKey,
+    public_key_pem: String,
+    store: SharedStore,
+    client: reqwest::Client,
+}
+
+/// The blog's actor when `activitypub_user` is set, or none when ActivityPub is off.
+#[derive(Clone, Default)]
+pub struct ActivityPub(pub Option<Arc<Federation>>);
+
+impl ActivityPub {
+    pub fn from_config(config: &BlogConfig) -> Result<ActivityPub, String> {
+        let user = match &config.activitypub_user {
+            Some(user) => user.to_owned(),
+            None => return Ok(ActivityPub(None))
+        };
+        let key = signature::load_private_key(config.activitypub_key.as_deref().ok_or("activitypub_user needs activitypub_key")?)?;
+        let store: SharedStore = match config.activitypub_store.as_str() {
+            "memory" => Arc::new(MemoryStore::default()),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => {
+                let path = config.activitypub_database.as_ref().ok_or("activitypub_store sqlite needs activitypub_database")?;
+                Arc::new(sqlite::SqliteStore::open(path)?)
+            },
+            other => return Err(format!("activitypub_store {} isn't compiled in", other))
+        };
+        let client = public_client()?;
+
+        let public_key_pem = signature::public_key_pem(&key);
+        return Ok(ActivityPub(Some(Arc::new(Federation { user, key, public_key_pem, store, client }))));
+    }
+
+    /// Delivers the posts behind `slugs` to the followers of the actor under `public_url`, in the background.
+    pub fn notify(&self, source: &CachedSource, public_url: &str, slugs: &[String]) {
+        let federation = match &self.0 {
+            Some(federation) if !slugs.is_empty() => federation.to_owned(),
+            _ => return
+        };
+        let (source, public_url, slugs) = (source.to_owned(), public_url.to_owned(), slugs.to_vec());
+        rocket::tokio::spawn(async move {
+            for slug in slugs {
+                if let Err(err) = federation.publish(&source, &public_url, &slug).await {
+                    warn!(%slug, error = %err, "cannot deliver to followers");
+                }
+            }
+        });
+    }
+}
+
+impl Federation {
+    async fn publish(self: &Arc<Self>, source: &CachedSource, public_url: &str, slug: &str) -> Result<(), String> {
+        let post = match find_post(&source.all_posts().await?, slug) {
+            Some(post) if post.is_listed() => post,
+            _ => return Ok(())
+        };
+        let html = markdown_to_html(&source.content(&post).await?, comrak_options());
+        let kind = if self.store.first_delivery(&object_id(public_url, slug)).await? { "Create" } else { "Update" };
+        let activity = activity(kind, public_url, article(public_url, &post, &html), Utc::now());
+
+        let inboxes: BTreeSet<String> = self.store.followers(&actor_id(public_url)).await?.into_iter().map(|follower| follower.inbox).collect();
+        info!(%slug, kind, inboxes = inboxes.len(), "delivering to followers");
+        for inbox in inboxes {
+            rocket::tokio::spawn(self.to_owned().deliver(public_url.to_owned(), inbox, activity.to_owned()));
+        }
+        return Ok(());
+    }
+
+    async fn deliver(self: Arc<Self>, public_url: String, inbox: String, activity: Value) {
+        let mut delay = FIRST_RETRY;
+        for attempt in 1..=ATTEMPTS {
+            match self.post(&public_url, &inbox, &activity).await {
+                Delivery::Delivered => return,
+                Delivery::Refused(reason) => return warn!(%inbox, %reason, "delivery refused"),
+                Delivery::Retry(reason) if attempt == ATTEMPTS => return warn!(%inbox, %reason, attempt, "not delivered, giving up"),
+                Delivery::Retry(reason) => warn!(%inbox, %reason, attempt, "not delivered, retrying"),
+            }
+            sleep(delay).await;
+            delay *= 4;
+        }
+    }
+
+    async fn post(&self, public_url: &str, inbox: &str, activity: &Value) -> Delivery {
+        let url = match Url::parse(inbox) {
+            Ok(url) if is_public(&url) => url,
+            _ => return Delivery::Refused(format!("{} is not a public URL", inbox))
+        };
+        let body = serde_json::to_vec(activity).unwrap_or_default();
+        let mut request = self.client.post(url.to_owned()).header(CONTENT_TYPE, MEDIA_TYPE);
+        for (name, value) in signature::sign(&self.key, &key_id(public_url), "POST", &url, Some(&body), Utc::now()) {
+            request = request.header(name, value);
+        }
+
+        return match request.body(body).send().await {
+            Ok(response) if response.status().is_success() => Delivery::Delivered,
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS || response.status().is_server_error() =>
+                Delivery::Retry(format!("inbox responded {}", response.status())),
+            Ok(response) => Delivery::Refused(format!("inbox responded {}", response.status())),
+            Err(err) => Delivery::Retry(err.to_string()),
+        };
+    }
+
+    /// The actor document at `id`, asked for with a signed request as servers in authorized fetch mode want.
+    async fn fetch_actor(&self, public_url: &str, id: &str) -> Result<RemoteActor, String> {
+        let url = Url::parse(id).ok().filter(is_public).ok_or(format!("{} is not a public URL", id))?;
+        let mut request = self.client.get(url.to_owned()).header(ACCEPT, MEDIA_TYPE);
+        for (name, value) in signature::sign(&self.key, &key_id(public_url), "GET", &url, None, Utc::now()) {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(|err| err.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("{} responded {}", id, response.status()));
+        }
+        return response.json().await.map_err(|err| format!("{} is not an actor, {}", id, err));
+    }
+}
+
+fn federation(activitypub: &ActivityPub) -> Result<&Arc<Federation>, (Status, String)> {
+    return activitypub.0.as_ref().ok_or((Status::NotFound, String::from("ActivityPub is off")));
+}
+
+#[get("/.well-known/webfinger?<resource>")]
+fn webfinger(resource: &str, activitypub: &State<ActivityPub>, public_url: PublicUrl) -> Result<(ContentType, String), (Status, String)> {
+    let federation = federation(activitypub)?;
+    let subject = webfinger_subject(&federation.user, &public_url.0).ok_or((Status::NotFound, format!("no {}", resource)))?;
+    let id = actor_id(&public_url.0);
+    if !resource.eq_ignore_ascii_case(&subject) && resource != id {
+        return Err((Status::NotFound, format!("no {}", resource)));
+    }
+
+    let mut links = vec![
+        json!({ "rel": "self", "type": MEDIA_TYPE, "href": id }),
+        json!({ "rel": "http://webfinger.net/rel/profile-page", "type": "text/html", "href": public_url.0 }),
+    ];
+    links.extend(config().me.iter().map(|link| json!({ "rel": "me", "href": link.href })));
+    let document = json!({ "subject": subject, "aliases": [id], "links": links });
+    return Ok((ContentType::new("application", "jrd+json"), document.to_string()));
+}
+
+#[get("/activitypub/actor")]
+fn actor_document(activitypub: &State<ActivityPub>, tenant: &Tenant, public_url: PublicUrl) -> Result<(ContentType, String), (Status, String)> {
+    let federation = federation(activitypub)?;
+    let document = actor(&federation.user, &federation.public_key_pem, &public_url.0, &tenant.title, &tenant.description, &config().me);
+    return Ok((activity_json(), document.to_string()));
+}
+
+/// The latest listed posts, each as its `Create`.
+#[get("/activitypub/outbox")]
+async fn outbox(activitypub: &State<ActivityPub>, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<(ContentType, String), (Status, String)> {
+    federation(activitypub)?;
+    let source = tenant.source.for_request(&request_id);
+    let posts: Vec<Post> = source.all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?
+        .into_iter()
+        .filter(Post::is_listed)
+        .take(OUTBOX_SIZE)
+        .collect();
+    let items: Vec<Value> = posts.iter().zip(source.prefetch(&posts).await)
+        .filter_map(|(post, markdown)| markdown.ok().map(|markdown| (post, markdown_to_html(&markdown, comrak_options()))))
+        .map(|(post, html)| activity("Create", &public_url.0, article(&public_url.0, post, &html), post.updated))
+        .collect();
+
+    let collection = json!({
+        "@context": CONTEXT,
+        "id": format!("{}/activitypub/outbox", public_url.0),
+        "type": "OrderedCollection",
+        "totalItems": items.len(),
+        "orderedItems": items,
+    });
+    return Ok((activity_json(), collection.to_string()));
+}
+
+/// How many follow the blog, but not who.
+#[get("/activitypub/followers")]
+async fn followers(activitypub: &State<ActivityPub>, public_url: PublicUrl) -> Result<(ContentType, String), (Status, String)> {
+    let federation = federation(activitypub)?;
+    let followers = federation.store.followers(&actor_id(&public_url.0)).await.map_err(|err| (Status::BadGateway, err))?;
+    let collection = json!({
+        "@context": CONTEXT,
+        "id": followers_id(&public_url.0),
+        "type": "OrderedCollection",
+        "totalItems": followers.len(),
+    });
+    return Ok((activity_json(), collection.to_string()));
+}
+
+#[get("/activitypub/posts/<slug>")]
+async fn post_object(slug: &str, activitypub: &State<ActivityPub>, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<(ContentType, String), (Status, String)> {
+    federation(activitypub)?;
+    let source = tenant.source.for_request(&request_id);
+    let post = find_post(&source.all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?, slug)
+        .filter(Post::is_listed)
+        .ok_or((Status::NotFound, format!("no post {}", slug)))?;
+    let markdown = source.content(&post).await.map_err(|err| (Status::ServiceUnavailable, err))?;
+
+    let mut object = article(&public_url.0, &post, &markdown_to_html(&markdown, comrak_options()));
+    object["@context"] = json!(CONTEXT);
+    return Ok((activity_json(), object.to_string()));
+}
+
+/// The id of an activity's object, given inline or by reference.
+fn object_ref(object: &Value) -> Option<&str> {
+    return object.as_str().or_else(|| object["id"].as_str());
+}
+
+/// Takes `Follow`, and `Undo` of one, from actors whose signature checks out; anything else is accepted and ignored.
+/// A follow is accepted straight away, as the actor doesn't approve followers by hand.
+#[post("/activitypub/inbox", data = "<data>")]
+async fn inbox(signed: SignedRequest, data: Data<'_>, activitypub: &State<ActivityPub>, public_url: PublicUrl) -> Result<Accepted<String>, (Status, String)> {
+    let federation = federation(activitypub)?;
+    let body = body::read(data, MAX_ACTIVITY).await?;
+    let activity: Value = serde_json::from_slice(&body).map_err(|err| (Status::BadRequest, err.to_string()))?;
+    let actor = activity["actor"].as_str().ok_or((Status::BadRequest, String::from("activity has no actor")))?;
+
+    let key_id = signed.key_id().ok_or((Status::Unauthorized, String::from("not signed")))?;
+    let signer = federation.fetch_actor(&public_url.0, key_id.split('#').next().unwrap_or_default()).await
+        .map_err(|err| (Status::Unauthorized, format!("cannot fetch the signer, {}", err)))?;
+    if signer.id != actor || signer.public_key.id != key_id {
+        return Err((Status::Unauthorized, String::from("signed by someone other than the actor")));
+    }
+    signed.verify(&body, &signer.public_key.public_key_pem, Utc::now()).map_err(|err| (Status::Unauthorized, err))?;
+
+    let following = actor_id(&public_url.0);
+    match (activity["type"].as_str().unwrap_or_default(), activity["object"]["type"].as_str()) {
+        ("Follow", _) if object_ref(&activity["object"]) == Some(following.as_str()) => {
+            let inbox = signer.endpoints.shared_inbox.to_owned().unwrap_or_else(|| signer.inbox.to_owned());
+            let follower = Follower { following: following.to_owned(), id: signer.id.to_owned(), inbox, followed: Utc::now() };
+            federation.store.add(&follower).await.map_err(|err| (Status::BadGateway, err))?;
+            info!(follower = %follower.id, "followed");
+
+            let accept = json!({
+                "@context": CONTEXT,
+                "id": format!("{}#accepts/{}", following, uuid::Uuid::new_v4()),
+                "type": "Accept",
+                "actor": following,
+                "object": activity,
+            });
+            rocket::tokio::spawn(federation.to_owned().deliver(public_url.0.to_owned(), signer.inbox, accept));
+        },
+        ("Undo", Some("Follow")) => {
+            federation.store.remove(&following, &signer.id).await.map_err(|err| (Status::BadGateway, err))?;
+            info!(follower = %signer.id, "unfollowed");
+        },
+        _ => {},
+    }
+
+    return Ok(Accepted(Some(String::new())));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![webfinger, actor_document, outbox, followers, post_object, inbox];
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::sync::Mutex;
+    use rusqlite::{params, Connection, Row};
+    use crate::store;
+    use super::{Follower, FollowerStore};
+
+    /// Followers in `followers`, and the ids of posts delivered in `deliveries`.
+    pub struct SqliteStore(Mutex<Connection>);
+
+    fn follower(row: &Row) -> rusqlite::Result<Follower> {
+        return Ok(Follower { following: row.get(0)?, id: row.get(1)?, inbox: row.get(2)?, followed: row.get(3)? });
+    }
+
+    impl SqliteStore {
+        pub fn open(path: &str) -> Result<SqliteStore, String> {
+            return Ok(SqliteStore(store::sqlite::open(path,
+                "CREATE TABLE IF NOT EXISTS followers (
+                    following TEXT NOT NULL, id TEXT NOT NULL, inbox TEXT NOT NULL, followed TEXT NOT NULL, PRIMARY KEY (following, id)
+                );
+                CREATE TABLE IF NOT EXISTS deliveries (object TEXT PRIMARY KEY);",
+            )?));
+        }
+    }
+
+    #[rocket::async_trait]
+    impl FollowerStore for SqliteStore {
+        async fn add(&self, follower: &Follower) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute(
+                    "INSERT OR REPLACE INTO followers (following, id, inbox, followed) VALUES (?1, ?2, ?3, ?4)",
+                    params![follower.following, follower.id, follower.inbox, follower.followed],
+                )
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, following: &str, id: &str) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute("DELETE FROM followers WHERE following = ?1 AND id = ?2", [following, id])
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn followers(&self, following: &str) -> Result<Vec<Follower>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT following, id, inbox, followed FROM followers WHERE following = ?1")
+                .map_err(|err| err.to_string())?;
+            let followers = statement.query_map([following], follower).map_err(|err| err.to_string())?;
+            return followers.collect::<rusqlite::Result<Vec<_>>>().map_err(|err| err.to_string());
+        }
+
+        async fn first_delivery(&self, object: &str) -> Result<bool, String> {
+            return self.0.lock().unwrap()
+                .execute("INSERT OR IGNORE INTO deliveries (object) VALUES (?1)", [object])
+                .map(|inserted| inserted == 1)
+                .map_err(|err| err.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::blog::{to_posts, Registry};
+
+    fn post() -> Post {
+        let manifest = r#"[{ "title": "Zip is scan", "markdown": "zip.md", "updated": "2024-01-25T23:08:00Z" }]"#;
+        return to_posts(&serde_json::from_str::<Vec<Registry>>(manifest).unwrap()).remove(0);
+    }
+
+    #[test]
+    fn test_webfinger_subject() {
+        assert_eq!(webfinger_subject("hackle", "https://hacklewayne.com"), Some(String::from("acct:hackle@hacklewayne.com")));
+        assert_eq!(webfinger_subject("hackle", "http://localhost:8000"), Some(String::from("acct:hackle@localhost:8000")));
+        assert_eq!(webfinger_subject("hackle", "hacklewayne.com"), None);
+    }
+
+    #[test]
+    fn test_activity() {
+        let at = Utc.ymd(2024, 2, 1).and_hms(0, 0, 0);
+        let article = article("https://hacklewayne.com", &post(), "<p>Zip is scan</p>");
+        assert_eq!(article["id"], "https://hacklewayne.com/activitypub/posts/zip-is-scan");
+        assert_eq!(article["url"], "https://hacklewayne.com/zip-is-scan");
+        assert_eq!(article["published"], "2024-01-25T23:08:00+00:00");
+
+        let create = activity("Create", "https://hacklewayne.com", article.to_owned(), at);
+        assert_eq!(create["id"], "https://hacklewayne.com/activitypub/posts/zip-is-scan#create");
+        assert_eq!(create["actor"], "https://hacklewayne.com/activitypub/actor");
+        assert_eq!(create["object"], article);
+        let update = activity("Update", "https://hacklewayne.com", article, at);
+        assert_eq!(update["id"], format!("https://hacklewayne.com/activitypub/posts/zip-is-scan#update-{}", at.timestamp()));
+
+        let me = [IdentityLink { label: String::from("Mastodon"), href: String::from("https://mastodon.social/@hackle?a=1&b=2") }];
+        let actor = actor("hackle", "PEM", "https://hacklewayne.com", "Hackle's blog", "On types", &me);
+        assert_eq!(actor["inbox"], "https://hacklewayne.com/activitypub/inbox");
+        assert_eq!(actor["publicKey"]["id"], "https://hacklewayne.com/activitypub/actor#main-key");
+        assert_eq!(actor["attachment"][0]["name"], "Mastodon");
+        assert_eq!(
+            actor["attachment"][0]["value"],
+            r#"<a href="https://mastodon.social/@hackle?a=1&amp;b=2" rel="me nofollow noopener" target="_blank">https://mastodon.social/@hackle?a=1&amp;b=2</a>"#
+        );
+    }
+
+    async fn exercise(store: &dyn FollowerStore) {
+        let follower = |id: &str, inbox: &str| Follower {
+            following: actor_id("https://hacklewayne.com"),
+            id: id.to_owned(),
+            inbox: inbox.to_owned(),
+            followed: Utc.ymd(2024, 1, 25).and_hms(23, 8, 0),
+        };
+        store.add(&follower("https://example.com/users/a", "https://example.com/inbox")).await.unwrap();
+        store.add(&follower("https://example.com/users/a", "https://example.com/users/a/inbox")).await.unwrap();
+        store.add(&follower("https://example.org/users/b", "https://example.org/inbox")).await.unwrap();
+        store.remove(&actor_id("https://hacklewayne.com"), "https://example.org/users/b").await.unwrap();
+
+        assert_eq!(store.followers(&actor_id("https://hacklewayne.com")).await.unwrap(), vec![follower("https://example.com/users/a", "https://example.com/users/a/inbox")]);
+        assert!(store.followers(&actor_id("https://other.blog")).await.unwrap().is_empty());
+        assert!(store.first_delivery("https://hacklewayne.com/activitypub/posts/zip-is-scan").await.unwrap());
+        assert!(!store.first_delivery("https://hacklewayne.com/activitypub/posts/zip-is-scan").await.unwrap());
+    }
+
+    #[rocket::async_test]
+    async fn test_memory_store() {
+        exercise(&MemoryStore::default()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn test_sqlite_store() {
+        exercise(&sqlite::SqliteStore::open(":memory:").unwrap()).await;
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use rocket::form::{Form, FromForm};
+use rocket::fs::TempFile;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::Json;
+use rocket::{delete, get, post, routes, Route, State};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::info;
+use crate::auth::{bearer_token, constant_time_eq, BasicAuth};
+use crate::blog::{Post, RenderCache};
+use crate::cache::CacheStats;
+use crate::cdn::{purge_keys, Cdn};
+use crate::config::config;
+use crate::oauth::AdminSession;
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::shortcodes::LinkCards;
+use crate::tenant::Tenant;
+use crate::uploads::{file_name, image_type, markdown, Uploaded, Uploads};
+
+/// Passes requests carrying `Authorization: Bearer <admin_token>`, or the Basic auth credentials from
+/// `basic_auth_user` and `basic_auth_password`, or a GitHub login session. With none configured the admin routes don't exist at all, rather than being open.
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = config().admin_token.to_owned();
+        let bearer = request.headers().get_one("Authorization").and_then(bearer_token);
+
+        if let (Some(expected), Some(given)) = (&token, bearer) {
+            if constant_time_eq(given.as_bytes(), expected.as_bytes()) {
+                return Outcome::Success(AdminToken);
+            }
+        }
+
+        let session = request.guard::<AdminSession>().await;
+        if session.is_success() {
+            return Outcome::Success(AdminToken);
+        }
+
+        return match request.guard::<BasicAuth>().await {
+            Outcome::Success(_) => Outcome::Success(AdminToken),
+            Outcome::Forward(_) if token.is_none() && session.is_forward() => Outcome::Forward(()),
+            _ => Outcome::Failure((Status::Unauthorized, ()))
+        };
+    }
+}
+
+#[derive(Serialize)]
+struct Purged {
+    purged: usize,
+    cdn_purged: Vec<String>,
+}
+
+#[get("/cache")]
+fn cache_stats(_token: AdminToken, tenant: &Tenant, renderer: &State<RenderCache>, link_cards: &State<LinkCards>) -> Json<String> {
+    let mut caches: BTreeMap<&str, CacheStats> = tenant.source.cache_stats().into_iter().collect();
+    caches.insert("rendered", renderer.cache_stats());
+    caches.insert("search_index", tenant.search_index.cache_stats());
+    caches.insert("feeds", tenant.feeds.cache_stats());
+    caches.insert("link_cards", link_cards.cache_stats());
+
+    return Json(serde_json::to_string(&caches).unwrap());
+}
+
+/// Everything but link cards, which only change when the linked site does; `?slug=` narrows it to one post's content.
+/// The CDN's copies go too.
+#[delete("/cache?<slug>")]
+async fn purge_cache(_token: AdminToken, slug: Option<&str>, tenant: &Tenant, renderer: &State<RenderCache>, cdn: &State<Cdn>) -> Result<Json<String>, (Status, String)> {
+    let purged = tenant.source.purge(slug).await.map_err(|err| (Status::BadGateway, err))?;
+    let (purged, keys) = match slug {
+        Some(slug) => (purged + tenant.search_index.purge(), purge_keys(&[slug.to_owned()], false)),
+        None => (purged + tenant.search_index.purge() + renderer.purge() + tenant.feeds.purge(), purge_keys(&[], true)),
+    };
+    let cdn_purged = cdn.purge(&keys).await;
+
+    return Ok(Json(serde_json::to_string(&Purged { purged, cdn_purged }).unwrap()));
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostState {
+    Published,
+    /// Dated after `now`; listed on the site all the same, the manifest is trusted to hold it back.
+    Scheduled,
+    Hidden,
+}
+
+pub fn post_state(post: &Post, now: DateTime<Utc>) -> PostState {
+    return if post.hidden { PostState::Hidden }
+        else if post.updated > now { PostState::Scheduled }
+        else { PostState::Published };
+}
+
+#[derive(Serialize)]
+struct AdminPost {
+    slug: String,
+    title: String,
+    path: String,
+    updated: DateTime<Utc>,
+    tags: Vec<String>,
+    pinned: bool,
+    state: PostState,
+}
+
+/// Every post in the manifest, hidden and future-dated ones included, with its state.
+#[get("/posts")]
+async fn posts(_token: AdminToken, request_id: RequestId, tenant: &Tenant) -> Result<Json<String>, (Status, String)> {
+    let all_posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|err| (Status::BadGateway, err))?;
+    let now = Utc::now();
+    let posts: Vec<AdminPost> = all_posts.into_iter()
+        .map(|post| AdminPost {
+            state: post_state(&post, now),
+            slug: post.slug,
+            title: post.title,
+            path: post.path,
+            updated: post.updated,
+            tags: post.tags,
+            pinned: post.pinned,
+        })
+        .collect();
+
+    return Ok(Json(serde_json::to_string(&posts).unwrap()));
+}
+
+#[derive(FromForm)]
+struct AssetUpload<'r> {
+    file: TempFile<'r>,
+    alt: Option<String>,
+}
+
+/// An image as multipart form data, its `file` and optionally the `alt` text for the markdown, stored where
+/// `upload_store` says. Answers with its public URL and the markdown to put it in a post.
+#[post("/assets", data = "<upload>")]
+async fn upload_asset(_token: AdminToken, upload: Form<AssetUpload<'_>>, uploads: &State<Uploads>, public_url: PublicUrl) -> Result<(Status, Json<String>), (Status, String)> {
+    let store = uploads.store().ok_or((Status::ServiceUnavailable, String::from("uploads are off, as upload_store is none")))?;
+    let path = upload.file.path().ok_or((Status::BadRequest, String::from("send the image as a file")))?;
+    let bytes = rocket::tokio::fs::read(path).await.map_err(|err| (Status::InternalServerError, err.to_string()))?;
+    let (content_type, extension) = image_type(&bytes).ok_or((Status::UnsupportedMediaType, String::from("upload a PNG, JPEG, GIF, WebP or AVIF image")))?;
+
+    let name = file_name(upload.file.name(), &bytes, extension);
+    let url = store.put(&name, bytes, content_type).await.map_err(|err| (Status::BadGateway, err))?;
+    let url = if url.starts_with('/') { format!("{}{}", public_url.0, url) } else { url };
+    let alt = upload.alt.to_owned().unwrap_or_else(|| upload.file.name().unwrap_or_default().replace(['-', '_'], " "));
+    info!(store = store.name(), %url, "asset uploaded");
+
+    let uploaded = Uploaded { markdown: markdown(&alt, &url), url };
+    return Ok((Status::Created, Json(serde_json::to_string(&uploaded).unwrap())));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![cache_stats, purge_cache, posts, upload_asset];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blog::Kind;
+
+    #[test]
+    fn test_post_state() {
+        use chrono::TimeZone;
+
+        let now = Utc.ymd(2021, 6, 1).and_hms(0, 0, 0);
+        let post = Post {
+            slug: String::from("fin"),
+            title: String::from("Fin"),
+            path: String::from("fin.md"),
+            hidden: false,
+            updated: Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+
+        assert_eq!(post_state(&post, now), PostState::Published);
+        assert_eq!(post_state(&Post { updated: Utc.ymd(2021, 7, 1).and_hms(0, 0, 0), ..post.clone() }, now), PostState::Scheduled);
+        assert_eq!(post_state(&Post { hidden: true, updated: Utc.ymd(2021, 7, 1).and_hms(0, 0, 0), ..post }, now), PostState::Hidden);
+    }
+}
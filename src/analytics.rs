@@ -0,0 +1,228 @@
+//! A first-party path for [Plausible](https://plausible.io/docs/proxy/introduction) or
+//! [Umami](https://umami.is/docs/guides/bypass-ad-blockers): the analytics script and the events it sends are
+//! served from under `analytics_proxy_path` on the blog and forwarded to `analytics_upstream`, so ad blockers that
+//! block the provider's own domain don't lose the stats, and no CNAME to the provider is needed.
+use std::sync::Arc;
+use std::time::Duration;
+use rocket::data::{ByteUnit, Data};
+use rocket::http::{ContentType, Header, Status};
+use rocket::{get, post, routes, Responder, Route, State};
+use serde::Serialize;
+use tracing::warn;
+use crate::body;
+use crate::cache::TtlCache;
+use crate::config::{config, BlogConfig};
+use crate::spam::Visitor;
+
+/// How long the provider's script is served before it is fetched again.
+const SCRIPT_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Events are a few hundred bytes of JSON; anything much bigger isn't one.
+const MAX_EVENT: ByteUnit = ByteUnit::Kibibyte(64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    Plausible,
+    Umami,
+}
+
+impl Provider {
+    pub fn parse(provider: &str) -> Option<Provider> {
+        return match provider {
+            "plausible" => Some(Provider::Plausible),
+            "umami" => Some(Provider::Umami),
+            _ => None
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Provider::Plausible => "plausible",
+            Provider::Umami => "umami",
+        };
+    }
+
+    /// Where the provider is hosted when `analytics_upstream` doesn't say.
+    pub fn default_upstream(&self) -> &'static str {
+        return match self {
+            Provider::Plausible => "https://plausible.io",
+            Provider::Umami => "https://cloud.umami.is",
+        };
+    }
+
+    /// The script's path on the upstream.
+    pub fn script(&self) -> &'static str {
+        return match self {
+            Provider::Plausible => "/js/script.js",
+            Provider::Umami => "/script.js",
+        };
+    }
+
+    /// The last segment of the path under `/api/` that the script sends events to.
+    pub fn endpoint(&self) -> &'static str {
+        return match self {
+            Provider::Plausible => "event",
+            Provider::Umami => "send",
+        };
+    }
+}
+
+/// What a template needs for the script tag, given to every template as `analytics`: Plausible's
+/// `<script defer data-domain="{{site_id}}" data-api="{{api}}" src="{{script}}">`, or Umami's
+/// `<script defer data-website-id="{{site_id}}" data-host-url="{{host}}" src="{{script}}">`.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct AnalyticsTag {
+    pub provider: &'static str,
+    pub site_id: String,
+    pub script: String,
+    pub host: String,
+    pub api: String,
+}
+
+impl AnalyticsTag {
+    pub fn of(config: &BlogConfig) -> Option<AnalyticsTag> {
+        let provider = Provider::parse(&config.analytics_provider)?;
+        let host = config.analytics_proxy_path.to_owned();
+        return Some(AnalyticsTag {
+            provider: provider.as_str(),
+            site_id: config.analytics_site_id.to_owned()?,
+            script: format!("{}/script.js", host),
+            api: format!("{}/api/{}", host, provider.endpoint()),
+            host,
+        });
+    }
+}
+
+/// The tag for the configured provider, if there is one.
+pub fn tag() -> Option<AnalyticsTag> {
+    return AnalyticsTag::of(config());
+}
+
+pub struct Upstream {
+    provider: Provider,
+    base: String,
+    client: reqwest::Client,
+    script: TtlCache<String>,
+}
+
+/// The provider `analytics_provider` names, at `analytics_upstream`; without one the proxy paths don't exist.
+#[derive(Clone, Default)]
+pub struct AnalyticsProxy(Option<Arc<Upstream>>);
+
+impl AnalyticsProxy {
+    pub fn from_config(config: &BlogConfig) -> AnalyticsProxy {
+        return AnalyticsProxy(Provider::parse(&config.analytics_provider).map(|provider| Arc::new(Upstream {
+            provider,
+            base: config.analytics_upstream.as_deref().unwrap_or(provider.default_upstream()).trim_end_matches('/').to_owned(),
+            client: reqwest::Client::new(),
+            script: TtlCache::new(SCRIPT_TTL),
+        })));
+    }
+}
+
+fn upstream(proxy: &AnalyticsProxy) -> Result<&Upstream, Status> {
+    return proxy.0.as_deref().ok_or(Status::NotFound);
+}
+
+#[derive(Responder)]
+pub struct Script {
+    inner: (ContentType, String),
+    cache_control: Header<'static>,
+}
+
+/// The provider's script, fetched at most every few hours.
+#[get("/script.js")]
+async fn script(proxy: &State<AnalyticsProxy>) -> Result<Script, Status> {
+    let upstream = upstream(proxy)?;
+    let body = match upstream.script.get("script") {
+        Some(body) => body,
+        None => {
+            let url = format!("{}{}", upstream.base, upstream.provider.script());
+            let response = upstream.client.get(&url).send().await
+                .and_then(|response| response.error_for_status())
+                .map_err(|err| {
+                    warn!(%url, error = %err, "cannot fetch analytics script");
+                    Status::BadGateway
+                })?;
+            let body = response.text().await.map_err(|_| Status::BadGateway)?;
+            upstream.script.insert("script", body.to_owned());
+            body
+        }
+    };
+
+    return Ok(Script { inner: (ContentType::JavaScript, body), cache_control: Header::new("Cache-Control", "public, max-age=3600") });
+}
+
+/// Forwards an event with who sent it, as the provider counts visitors by address and user agent; the address is
+/// `client_address`, so a reader cannot pick it with `X-Real-IP`.
+#[post("/api/<endpoint>", data = "<data>")]
+async fn event(endpoint: &str, data: Data<'_>, visitor: Visitor, proxy: &State<AnalyticsProxy>) -> Result<(Status, String), Status> {
+    let upstream = upstream(proxy)?;
+    if endpoint != upstream.provider.endpoint() {
+        return Err(Status::NotFound);
+    }
+    let body = body::read(data, MAX_EVENT).await.map_err(|(status, _)| status)?;
+
+    let mut request = upstream.client.post(format!("{}/api/{}", upstream.base, endpoint))
+        .header("Content-Type", "application/json")
+        .body(body);
+    for (name, value) in [("User-Agent", &visitor.user_agent), ("X-Forwarded-For", &visitor.ip), ("Referer", &visitor.referrer)] {
+        if let Some(value) = value {
+            request = request.header(name, value);
+        }
+    }
+    let response = request.send().await.map_err(|err| {
+        warn!(error = %err, "cannot forward analytics event");
+        Status::BadGateway
+    })?;
+
+    let status = Status::from_code(response.status().as_u16()).unwrap_or(Status::BadGateway);
+    return Ok((status, response.text().await.unwrap_or_default()));
+}
+
+/// Mounted at `analytics_proxy_path`.
+pub fn routes() -> Vec<Route> {
+    return routes![script, event];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_tag() {
+        assert_eq!(AnalyticsTag::of(&BlogConfig::default()), None);
+
+        let config = BlogConfig { analytics_provider: String::from("plausible"), analytics_site_id: Some(String::from("hacklewayne.com")), ..BlogConfig::default() };
+        assert_eq!(AnalyticsTag::of(&config), Some(AnalyticsTag {
+            provider: "plausible",
+            site_id: String::from("hacklewayne.com"),
+            script: String::from("/a/script.js"),
+            host: String::from("/a"),
+            api: String::from("/a/api/event"),
+        }));
+        let config = BlogConfig { analytics_provider: String::from("umami"), analytics_proxy_path: String::from("/u"), ..config };
+        assert_eq!(AnalyticsTag::of(&config).unwrap().api, "/u/api/send");
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(AnalyticsProxy::from_config(&BlogConfig::default()).0.is_none());
+
+        let config = BlogConfig { analytics_provider: String::from("umami"), analytics_upstream: Some(String::from("https://umami.example.com/")), ..BlogConfig::default() };
+        let upstream = AnalyticsProxy::from_config(&config).0.unwrap();
+        assert_eq!((upstream.provider, upstream.base.as_str()), (Provider::Umami, "https://umami.example.com"));
+        let upstream = AnalyticsProxy::from_config(&BlogConfig { analytics_upstream: None, analytics_provider: String::from("plausible"), ..config }).0.unwrap();
+        assert_eq!(upstream.base, "https://plausible.io");
+    }
+
+    #[rocket::async_test]
+    async fn test_event_too_large() {
+        let config = BlogConfig { analytics_provider: String::from("plausible"), analytics_upstream: Some(String::from("http://127.0.0.1:9")), ..BlogConfig::default() };
+        let client = Client::untracked(rocket::build().mount("/", routes![event]).manage(AnalyticsProxy::from_config(&config))).await.unwrap();
+
+        let huge = "x".repeat(MAX_EVENT.as_u64() as usize + 1);
+        let response = client.post("/api/event").body(huge).dispatch().await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::{catch, catchers, get, routes, Catcher, Route, State};
+use serde::Serialize;
+use std::io::Cursor;
+use tracing::instrument;
+use crate::blog::{self, find_post, Post, RenderCache};
+use crate::config::config;
+use crate::request_id::RequestId;
+use crate::shortcodes::LinkCards;
+use crate::tenant::Tenant;
+use crate::views::{most_viewed, PostViews, Views};
+
+pub const API_VERSION: &str = "v1";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PostListItem {
+    pub slug: String,
+    pub title: String,
+    pub updated: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub summary: String,
+    pub pinned: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PostDetail {
+    #[serde(flatten)]
+    pub post: PostListItem,
+    pub html: String,
+    pub markdown: String,
+    pub word_count: usize,
+    pub reading_time: usize,
+}
+
+impl PostListItem {
+    pub fn new(post: &Post, summary: &str) -> PostListItem {
+        return PostListItem {
+            slug: post.slug.to_owned(),
+            title: post.title.to_owned(),
+            updated: post.updated.to_owned(),
+            tags: post.tags.to_owned(),
+            summary: summary.to_owned(),
+            pinned: post.pinned,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound,
+    Upstream(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let (status, error) = match self {
+            ApiError::NotFound => (Status::NotFound, String::from("Not found")),
+            ApiError::Upstream(err) => (Status::BadGateway, err),
+        };
+        let body = serde_json::to_string(&ErrorBody { error }).unwrap();
+
+        return Response::build()
+            .status(status)
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .ok();
+    }
+}
+
+/// JSON with the given value, or a JSON error with a matching status.
+pub struct ApiJson<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for ApiJson<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        return match serde_json::to_string(&self.0) {
+            Ok(body) => Response::build()
+                .header(ContentType::JSON)
+                .sized_body(body.len(), Cursor::new(body))
+                .ok(),
+            Err(err) => ApiError::Upstream(err.to_string()).respond_to(request)
+        };
+    }
+}
+
+#[get("/posts")]
+#[instrument(skip(tenant, request_id), fields(%request_id))]
+async fn list_posts(tenant: &Tenant, request_id: RequestId) -> Result<ApiJson<Vec<PostListItem>>, ApiError> {
+    let source = &tenant.source.for_request(&request_id);
+    let all_posts = source.all_posts().await.map_err(ApiError::Upstream)?;
+    let listed = all_posts.into_iter().filter(Post::is_listed).collect();
+
+    let items = blog::summarise(source, listed).await
+        .iter()
+        .map(|(post, summary)| PostListItem::new(post, summary))
+        .collect();
+    return Ok(ApiJson(items));
+}
+
+#[get("/posts/<slug>")]
+#[instrument(skip(tenant, renderer, link_cards, request_id), fields(%request_id))]
+async fn get_post(slug: &str, tenant: &Tenant, renderer: &State<RenderCache>, link_cards: &State<LinkCards>, request_id: RequestId) -> Result<ApiJson<PostDetail>, ApiError> {
+    let source = &tenant.source.for_request(&request_id);
+    let all_posts = source.all_posts().await.map_err(ApiError::Upstream)?;
+    let current_post = find_post(&all_posts, slug).ok_or(ApiError::NotFound)?;
+    let markdown = source.content(&current_post).await.map_err(ApiError::Upstream)?;
+
+    renderer.warm(&markdown).await;
+    let blog = blog::make_blog(&current_post, &all_posts, &markdown, renderer);
+    let html = link_cards.expand(&blog.content).await;
+
+    return Ok(ApiJson(PostDetail {
+        post: PostListItem::new(&current_post, &blog.description),
+        html,
+        markdown,
+        word_count: blog.word_count,
+        reading_time: blog.reading_time,
+    }));
+}
+
+/// How many times each post was viewed, when `view_store` counts them.
+#[get("/stats")]
+#[instrument(skip(tenant, views, request_id), fields(%request_id))]
+async fn stats(tenant: &Tenant, views: &State<Views>, request_id: RequestId) -> Result<ApiJson<Vec<PostViews>>, ApiError> {
+    let counts = views.all(tenant).await.ok_or(ApiError::NotFound)?.map_err(ApiError::Upstream)?;
+    let all_posts = tenant.source.for_request(&request_id).all_posts().await.map_err(ApiError::Upstream)?;
+    return Ok(ApiJson(most_viewed(&all_posts, &counts)));
+}
+
+/// The posts most viewed over the last `popular_days`, `limit` of them or `popular_limit`.
+#[get("/popular?<limit>")]
+#[instrument(skip(tenant, views, request_id), fields(%request_id))]
+async fn popular(limit: Option<usize>, tenant: &Tenant, views: &State<Views>, request_id: RequestId) -> Result<ApiJson<Vec<PostViews>>, ApiError> {
+    let all_posts = tenant.source.for_request(&request_id).all_posts().await.map_err(ApiError::Upstream)?;
+    let listed: Vec<Post> = all_posts.into_iter().filter(Post::is_listed).collect();
+    let mut popular = views.popular(tenant, &listed).await.ok_or(ApiError::NotFound)?.map_err(ApiError::Upstream)?;
+
+    popular.truncate(limit.unwrap_or(config().popular_limit));
+    return Ok(ApiJson(popular));
+}
+
+#[catch(404)]
+fn not_found() -> ApiError {
+    return ApiError::NotFound;
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![list_posts, get_post, stats, popular];
+}
+
+/// So that a mistyped API URL gets a JSON 404 rather than the HTML error page.
+pub fn catchers() -> Vec<Catcher> {
+    return catchers![not_found];
+}
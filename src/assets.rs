@@ -0,0 +1,106 @@
+//! Fingerprinted static files. `asset_url` links to `/static/<fingerprint>/<file>`, and as that URL changes whenever
+//! the file does, it is served with a year's `immutable` caching rather than `cache_control_static`. A page cached
+//! from before a deploy asks for a fingerprint that is gone, and is redirected to the file as it is now.
+use std::path::{Path, PathBuf};
+use rocket::fs::NamedFile;
+use rocket::http::Header;
+use rocket::request::FromParam;
+use rocket::response::Redirect;
+use rocket::{get, routes, Responder, Route, State};
+use crate::helpers::{asset_url, Fingerprints};
+use crate::theme::{static_fingerprints, Theme};
+
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// The static directories, the theme's first, and the fingerprints of their files as the blog started.
+pub struct Assets {
+    directories: Vec<PathBuf>,
+    fingerprints: Fingerprints,
+}
+
+impl Assets {
+    pub fn new(static_dir: &Path, theme: Option<&Theme>) -> Assets {
+        let directories = theme.map(Theme::static_dir).filter(|directory| directory.is_dir()).into_iter()
+            .chain([static_dir.to_owned()])
+            .collect();
+        return Assets { directories, fingerprints: static_fingerprints(static_dir, theme) };
+    }
+
+    /// Every fingerprinted URL, for the export.
+    pub fn urls(&self) -> Vec<String> {
+        return self.fingerprints.keys().map(|file| asset_url(&self.fingerprints, file)).collect();
+    }
+
+    pub fn url(&self, file: &str) -> String {
+        return asset_url(&self.fingerprints, file);
+    }
+
+    /// A file as it is served, the theme's if it has one.
+    pub fn read(&self, file: &str) -> Option<Vec<u8>> {
+        return std::fs::read(self.find(Path::new(file))?).ok();
+    }
+
+    fn find(&self, file: &Path) -> Option<PathBuf> {
+        return self.directories.iter().map(|directory| directory.join(file)).find(|path| path.is_file());
+    }
+}
+
+/// The 16 hex digits of a fingerprint; any other segment forwards to the files under `/static` as they are.
+pub struct Fingerprint(u64);
+
+impl<'r> FromParam<'r> for Fingerprint {
+    type Error = &'r str;
+
+    fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        if param.len() != 16 {
+            return Err(param);
+        }
+        return u64::from_str_radix(param, 16).map(Fingerprint).map_err(|_| param);
+    }
+}
+
+#[derive(Responder)]
+pub enum Asset {
+    Current(NamedFile, Header<'static>),
+    Moved(Redirect),
+}
+
+#[get("/static/<fingerprint>/<file..>", rank = 8)]
+async fn asset(fingerprint: Fingerprint, file: PathBuf, assets: &State<Assets>) -> Option<Asset> {
+    let name = file.to_string_lossy().replace('\\', "/");
+    let current = assets.fingerprints.get(&name)?.fingerprint;
+    if current != fingerprint.0 {
+        return Some(Asset::Moved(Redirect::to(asset_url(&assets.fingerprints, &name))));
+    }
+    let file = NamedFile::open(assets.find(&file)?).await.ok()?;
+    return Some(Asset::Current(file, Header::new("Cache-Control", IMMUTABLE)));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![asset];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+
+    #[rocket::async_test]
+    async fn test_asset() {
+        let assets = Assets::new(Path::new("static"), None);
+        let url = asset_url(&assets.fingerprints, "styles.css");
+        let client = Client::untracked(rocket::build().manage(assets).mount("/", routes())).await.unwrap();
+
+        let response = client.get(url.as_str()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some(IMMUTABLE));
+
+        let response = client.get("/static/0000000000000000/styles.css").dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("Location"), Some(url.as_str()));
+
+        assert_eq!(client.get("/static/0000000000000000/missing.css").dispatch().await.status(), Status::NotFound);
+        assert_eq!(client.get("/static/styles/styles.css").dispatch().await.status(), Status::NotFound);
+    }
+}
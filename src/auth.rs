@@ -0,0 +1,113 @@
+use base64::Engine;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use crate::config::{config, BlogConfig};
+
+const REALM: &str = "blog";
+
+/// Compares in time independent of where the first difference is, so the token can't be guessed byte by byte.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    return a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0;
+}
+
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    return authorization.strip_prefix("Bearer ").map(|token| token.trim()).filter(|token| !token.is_empty());
+}
+
+/// The user and password of an `Authorization: Basic <base64 of user:password>` header.
+pub fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix("Basic ")?.trim();
+    let decoded = String::from_utf8(base64::engine::general_purpose::STANDARD.decode(encoded).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+
+    return Some((user.to_owned(), password.to_owned()));
+}
+
+/// The one user and password from `basic_auth_user` and `basic_auth_password`.
+#[derive(Clone, Debug)]
+pub struct BasicCredentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl BasicCredentials {
+    pub fn from_config(config: &BlogConfig) -> Option<BasicCredentials> {
+        return Some(BasicCredentials { user: config.basic_auth_user.to_owned()?, password: config.basic_auth_password.to_owned()? });
+    }
+
+    pub fn accepts(&self, authorization: &str) -> bool {
+        return match basic_credentials(authorization) {
+            // both compared regardless, so a right user name takes no longer to reject than a wrong one
+            Some((user, password)) => constant_time_eq(user.as_bytes(), self.user.as_bytes()) & constant_time_eq(password.as_bytes(), self.password.as_bytes()),
+            None => false
+        };
+    }
+}
+
+/// HTTP Basic auth for preview, admin and metrics routes: add `_auth: BasicAuth` to a handler.
+/// Without credentials configured the guarded routes don't exist at all, rather than being open.
+pub struct BasicAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasicAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let credentials = match BasicCredentials::from_config(config()) {
+            Some(credentials) => credentials,
+            None => return Outcome::Forward(())
+        };
+
+        let authorised = request.headers().get_one("Authorization")
+            .map(|authorization| credentials.accepts(authorization))
+            .unwrap_or(false);
+
+        return if authorised { Outcome::Success(BasicAuth) } else { Outcome::Failure((Status::Unauthorized, ())) };
+    }
+}
+
+/// Wraps the 401 page with a `WWW-Authenticate` challenge when Basic auth is configured, so browsers prompt for it.
+pub struct Challenge<R> {
+    pub inner: R,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Challenge<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let response = self.inner.respond_to(request)?;
+        if BasicCredentials::from_config(config()).is_none() {
+            return Ok(response);
+        }
+
+        return Response::build_from(response)
+            .raw_header("WWW-Authenticate", format!("Basic realm=\"{}\", charset=\"UTF-8\"", REALM))
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer s3cret"), Some("s3cret"));
+        assert_eq!(bearer_token("Bearer "), None);
+        assert_eq!(bearer_token("Basic czNjcmV0"), None);
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cre7"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cret!"));
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        // "hackle:pass:word" in base64
+        assert_eq!(basic_credentials("Basic aGFja2xlOnBhc3M6d29yZA=="), Some((String::from("hackle"), String::from("pass:word"))));
+        assert_eq!(basic_credentials("Basic not base64!"), None);
+        assert_eq!(basic_credentials("Bearer aGFja2xlOnBhc3M6d29yZA=="), None);
+
+        let credentials = BasicCredentials { user: String::from("hackle"), password: String::from("pass:word") };
+        assert!(credentials.accepts("Basic aGFja2xlOnBhc3M6d29yZA=="));
+        assert!(!credentials.accepts("Basic aGFja2xlOnBhc3N3b3Jk"));
+    }
+}
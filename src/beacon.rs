@@ -0,0 +1,311 @@
+//! First-party analytics: `static/beacon.js` tells `POST /beacon` which page was read, where the reader came
+//! from and how big their screen is, and each is added to a count for the day in the store `beacon_store` names.
+//! Nothing about the reader is kept, not even a hash; the counts are all there is, reported under
+//! `/admin/analytics`. As anyone can send a beacon, only the blog's own pages and a bounded number of referrers are
+//! counted by name; the rest are counted as `other`.
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use chrono::{Duration as Days, NaiveDate, Utc};
+use reqwest::Url;
+use rocket::form::{Form, FromForm};
+use rocket::http::Status;
+use rocket::response::content::Json;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{get, post, routes, Route, State};
+use crate::admin::AdminToken;
+use crate::blog::{find_post, Post};
+use crate::config::{config, BlogConfig};
+use crate::counters::{self, CounterStore, DailyCount, SharedCounters};
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+use crate::views::is_bot;
+
+/// What each beacon is counted by.
+pub const PAGE: &str = "page";
+pub const REFERRER: &str = "referrer";
+pub const CLIENT: &str = "client";
+/// The coarse kinds of client counted; a hint that isn't one of these is `other`.
+const CLIENTS: [&str; 3] = ["mobile", "tablet", "desktop"];
+const MAX_PAGE: usize = 200;
+const MAX_REFERRER: usize = 100;
+/// The referrer hosts counted by name a day by each instance; past them, a new one is `other`.
+const MAX_REFERRERS: usize = 200;
+/// What a page or referrer that isn't counted by name is counted as.
+pub const OTHER: &str = "other";
+
+/// The counters' key for beacons, under each blog's `Tenant::key`, with a name a dimension and value, e.g.
+/// `page:/fin`.
+const BEACONS: &str = "beacons";
+
+/// Days, each with the counts of its dimensions' values.
+pub type Report = BTreeMap<String, BTreeMap<String, BTreeMap<String, u64>>>;
+
+/// The counters' key for `tenant`'s beacons.
+pub fn key(tenant: &Tenant) -> String {
+    return tenant.key(BEACONS);
+}
+
+/// Adds one to the count under `key` on `day` of each (dimension, value) in `hits`.
+pub async fn record(store: &dyn CounterStore, key: &str, day: NaiveDate, hits: &[(&str, String)]) -> Result<(), String> {
+    let names: Vec<String> = hits.iter().map(|(dimension, value)| format!("{}:{}", dimension, value)).collect();
+    store.increment(key, day, &names.iter().map(String::as_str).collect::<Vec<_>>()).await?;
+    return Ok(());
+}
+
+/// The dimension and value a counter's name is of.
+pub fn hit(name: &str) -> Option<(&str, &str)> {
+    return name.split_once(':');
+}
+
+pub fn report(counts: &[DailyCount]) -> Report {
+    let mut report = Report::new();
+    for count in counts {
+        let (dimension, value) = match hit(&count.name) {
+            Some(hit) => hit,
+            None => continue
+        };
+        *report.entry(count.day.format("%F").to_string()).or_default()
+            .entry(dimension.to_owned()).or_default()
+            .entry(value.to_owned()).or_default() += count.count;
+    }
+    return report;
+}
+
+/// The path of `page`, given as a path or a URL on the blog, without its query or fragment.
+pub fn page_path(page: &str, public_url: &str) -> Option<String> {
+    let page = page.strip_prefix(public_url).unwrap_or(page);
+    let path = page.split(['?', '#']).next().unwrap_or("");
+    return Some(path.to_owned()).filter(|path| path.starts_with('/') && path.len() <= MAX_PAGE);
+}
+
+/// The page counted for `path`: the home page, or a post in `posts` under its own slug; `other` for anything else,
+/// so a client can't make up pages to count.
+pub fn counted_page(path: &str, posts: &Vec<Post>) -> String {
+    if path == "/" {
+        return path.to_owned();
+    }
+    return match find_post(posts, path.trim_start_matches('/')) {
+        Some(post) => format!("/{}", post.slug),
+        None => String::from(OTHER),
+    };
+}
+
+/// The host a reader came from, without `www.`; `direct` without one or from the blog itself, which is a reader
+/// moving between pages rather than arriving, and `other` for a host longer than any real one.
+pub fn referrer_host(referrer: Option<&str>, public_url: &str) -> String {
+    let host = |url: &str| Url::parse(url).ok()?.host_str().map(|host| host.trim_start_matches("www.").to_owned());
+    return match referrer.and_then(host) {
+        Some(referrer) if referrer.len() > MAX_REFERRER => String::from(OTHER),
+        Some(referrer) if Some(&referrer) != host(public_url).as_ref() => referrer,
+        _ => String::from("direct"),
+    };
+}
+
+/// The referrer hosts counted by name today, at most `limit` of them.
+#[derive(Default)]
+pub struct Referrers {
+    limit: usize,
+    counted: Mutex<(Option<NaiveDate>, HashSet<String>)>,
+}
+
+impl Referrers {
+    pub fn new(limit: usize) -> Referrers {
+        return Referrers { limit, counted: Mutex::default() };
+    }
+
+    /// `host` if it is already counted by name on `day` or there is room for it, remembering it if so; else `other`.
+    pub fn admit(&self, host: String, day: NaiveDate) -> String {
+        let mut counted = self.counted.lock().unwrap();
+        if counted.0 != Some(day) {
+            *counted = (Some(day), HashSet::new());
+        }
+        if host == "direct" || counted.1.contains(&host) {
+            return host;
+        }
+        if counted.1.len() >= self.limit {
+            return String::from(OTHER);
+        }
+        counted.1.insert(host.to_owned());
+        return host;
+    }
+}
+
+/// `mobile`, `tablet` or `desktop` as the beacon hints, or else as `Sec-CH-UA-Mobile` does; `other` when neither says.
+pub fn client(hint: Option<&str>, mobile_header: Option<&str>) -> String {
+    return match (hint, mobile_header) {
+        (Some(hint), _) if CLIENTS.contains(&hint) => hint.to_owned(),
+        (_, Some("?1")) => String::from("mobile"),
+        (_, Some("?0")) => String::from("desktop"),
+        _ => String::from("other"),
+    };
+}
+
+/// The store `beacon_store` names, or none when it is `none` and beacons aren't counted.
+#[derive(Clone, Default)]
+pub struct Beacons {
+    pub store: Option<SharedCounters>,
+    referrers: Arc<Referrers>,
+}
+
+impl Beacons {
+    pub async fn from_config(config: &BlogConfig) -> Result<Beacons, String> {
+        let store = counters::from_config("beacon", &config.beacon_store, config.beacon_database.as_deref(), config.beacon_table.as_deref()).await?;
+        return Ok(Beacons { store, referrers: Arc::new(Referrers::new(MAX_REFERRERS)) });
+    }
+}
+
+/// Whether pages send beacons, given to every template as `beacon`.
+pub fn enabled() -> bool {
+    return config().beacon_store != "none";
+}
+
+fn store(beacons: &Beacons) -> Result<&SharedCounters, (Status, String)> {
+    return beacons.store.as_ref().ok_or((Status::NotFound, String::from("beacons are off")));
+}
+
+/// `Sec-CH-UA-Mobile`, and whether the user agent is a crawler's, whose beacons aren't counted.
+pub struct Client {
+    mobile: Option<String>,
+    bot: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Client {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(Client {
+            mobile: request.headers().get_one("Sec-CH-UA-Mobile").map(String::from),
+            bot: is_bot(request.headers().get_one("User-Agent").unwrap_or("")),
+        });
+    }
+}
+
+#[derive(FromForm)]
+struct BeaconForm<'r> {
+    page: &'r str,
+    referrer: Option<&'r str>,
+    hint: Option<&'r str>,
+}
+
+/// Counts a page read; `navigator.sendBeacon` doesn't look at the response, so it is always empty.
+#[post("/beacon", data = "<form>")]
+async fn beacon(form: Form<BeaconForm<'_>>, client: Client, beacons: &State<Beacons>, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Status, (Status, String)> {
+    let store = store(beacons)?;
+    let page = page_path(form.page, &public_url.0).ok_or((Status::BadRequest, format!("page {} is not a path on the blog", form.page)))?;
+    if client.bot {
+        return Ok(Status::NoContent);
+    }
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?;
+
+    let today = Utc::today().naive_utc();
+    let referrer = referrer_host(form.referrer.filter(|referrer| !referrer.is_empty()), &public_url.0);
+    let hits = [
+        (PAGE, counted_page(&page, &posts)),
+        (REFERRER, beacons.referrers.admit(referrer, today)),
+        (CLIENT, self::client(form.hint, client.mobile.as_deref())),
+    ];
+    record(store.as_ref(), &key(tenant), today, &hits).await.map_err(|err| (Status::BadGateway, err))?;
+    return Ok(Status::NoContent);
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![beacon];
+}
+
+/// The blog's counts of the last `?days=` days, 30 unless given, by day.
+#[get("/analytics?<days>")]
+async fn analytics(_token: AdminToken, days: Option<u32>, beacons: &State<Beacons>, tenant: &Tenant) -> Result<Json<String>, (Status, String)> {
+    let since = Utc::today().naive_utc() - Days::days(days.unwrap_or(30).saturating_sub(1) as i64);
+    let counts = store(beacons)?.since(&key(tenant), since).await.map_err(|err| (Status::BadGateway, err))?;
+    return Ok(Json(serde_json::to_string(&report(&counts)).unwrap()));
+}
+
+pub fn admin_routes() -> Vec<Route> {
+    return routes![analytics];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const PUBLIC_URL: &str = "https://www.hacklewayne.com";
+
+    #[test]
+    fn test_page_path() {
+        assert_eq!(page_path("/zip-is-scan?utm_source=feed#comments", PUBLIC_URL), Some(String::from("/zip-is-scan")));
+        assert_eq!(page_path("https://www.hacklewayne.com/fin", PUBLIC_URL), Some(String::from("/fin")));
+        assert_eq!(page_path("https://example.com/fin", PUBLIC_URL), None);
+        assert_eq!(page_path(&format!("/{}", "a".repeat(MAX_PAGE)), PUBLIC_URL), None);
+    }
+
+    #[test]
+    fn test_referrer_host() {
+        assert_eq!(referrer_host(Some("https://news.ycombinator.com/item?id=1"), PUBLIC_URL), "news.ycombinator.com");
+        assert_eq!(referrer_host(Some("https://www.google.com/"), PUBLIC_URL), "google.com");
+        assert_eq!(referrer_host(Some("https://www.hacklewayne.com/fin"), PUBLIC_URL), "direct");
+        assert_eq!(referrer_host(Some("android-app"), PUBLIC_URL), "direct");
+        assert_eq!(referrer_host(None, PUBLIC_URL), "direct");
+        assert_eq!(referrer_host(Some(&format!("https://{}.com/", "a".repeat(MAX_REFERRER))), PUBLIC_URL), OTHER);
+    }
+
+    #[test]
+    fn test_counted_page() {
+        let post = |slug: &str| Post {
+            slug: slug.to_owned(),
+            title: slug.to_owned(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: crate::blog::Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+        let posts = vec![post("zip-is-scan"), post("fin")];
+
+        assert_eq!(counted_page("/", &posts), "/");
+        assert_eq!(counted_page("/fin", &posts), "/fin");
+        assert_eq!(counted_page("/no-such-post", &posts), OTHER);
+        assert_eq!(counted_page("/tags/rust", &posts), OTHER);
+    }
+
+    #[test]
+    fn test_referrers() {
+        let referrers = Referrers::new(1);
+        let (monday, tuesday) = (NaiveDate::from_ymd(2021, 5, 3), NaiveDate::from_ymd(2021, 5, 4));
+
+        assert_eq!(referrers.admit(String::from("google.com"), monday), "google.com");
+        assert_eq!(referrers.admit(String::from("google.com"), monday), "google.com");
+        assert_eq!(referrers.admit(String::from("example.com"), monday), OTHER);
+        assert_eq!(referrers.admit(String::from("direct"), monday), "direct");
+        assert_eq!(referrers.admit(String::from("example.com"), tuesday), "example.com");
+    }
+
+    #[test]
+    fn test_client() {
+        assert_eq!(client(Some("tablet"), Some("?1")), "tablet");
+        assert_eq!(client(Some("fridge"), Some("?1")), "mobile");
+        assert_eq!(client(None, Some("?0")), "desktop");
+        assert_eq!(client(None, None), "other");
+    }
+
+    #[test]
+    fn test_report() {
+        let (monday, tuesday) = (NaiveDate::from_ymd(2021, 5, 3), NaiveDate::from_ymd(2021, 5, 4));
+        let count = |day: NaiveDate, name: &str, count: u64| DailyCount { day, name: name.to_owned(), count };
+        let report = report(&[count(monday, "page:/fin", 1), count(monday, "client:mobile", 1), count(tuesday, "page:/fin", 2), count(tuesday, "stray", 1)]);
+
+        assert_eq!(report.len(), 2);
+        assert_eq!(report["2021-05-03"][CLIENT]["mobile"], 1);
+        assert_eq!(report["2021-05-04"][PAGE]["/fin"], 2);
+        assert_eq!(report["2021-05-04"].len(), 1);
+    }
+}
@@ -1,10 +1,25 @@
-use std::{path::PathBuf};
-use chrono::{DateTime, Utc };
-use comrak::{ComrakExtensionOptions, ComrakOptions, markdown_to_html};
+use std::{collections::BTreeMap, collections::HashSet, collections::hash_map::DefaultHasher, hash::Hasher, path::PathBuf, time::Duration, time::Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::{DateTime, Datelike, Utc };
+use comrak::{ComrakOptions, markdown_to_html};
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
 use regex::Regex;
 use rocket::{response::content::Xml};
+use rocket::tokio::time::timeout;
 use rss::{ItemBuilder, ChannelBuilder, Item};
-use serde::{Deserialize};
+use rss::extension::{itunes, Extension, ExtensionMap};
+use serde::{Deserialize, Serialize};
+use tracing::{field, instrument, Span};
+use crate::cache::{CacheStats, LruCache, TtlCache};
+use crate::cache_backend::SharedBackend;
+use crate::config::{config, BlogConfig};
+use crate::lint;
+use crate::markdown_options;
+use crate::reporting::capture_error;
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+use crate::shortcodes;
 
 #[derive(Clone, Debug)]
 pub struct Blog {
@@ -13,15 +28,94 @@ pub struct Blog {
     pub description: String,
     pub date_updated: String,
     pub see_also: Vec<(String, String)>,
+    /// Languages and slugs for `hreflang`.
+    pub alternates: Vec<(String, String)>,
+    pub word_count: usize,
+    pub reading_time: usize,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Post {
     pub slug: String,
     pub title: String,
     pub path: String,
     pub hidden: bool,
-    pub updated: DateTime<Utc>
+    pub updated: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub pinned: bool,
+    pub template: Option<String>,
+    pub kind: Kind,
+    pub lang: Option<String>,
+    /// Slugs of this post in other languages by language, both ways: a post is among the translations of each
+    /// of its own.
+    pub translations: BTreeMap<String, String>,
+    /// The code of its short link under `/s/`, rather than one hashed from the slug.
+    pub short: Option<String>,
+    pub enclosure: Option<Enclosure>,
+    pub cover: Option<String>,
+}
+
+/// An audio version of a post, attached to its item in the feed so the feed can be subscribed to as a podcast, e.g.
+/// `{ "url": "/static/audio/fin.mp3", "length": 4718592, "mime_type": "audio/mpeg", "duration": "5:12" }`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Enclosure {
+    /// Absolute, or from the root of the site.
+    pub url: String,
+    /// In bytes.
+    pub length: u64,
+    pub mime_type: String,
+    /// For `itunes:duration`, as `HH:MM:SS`, `MM:SS` or seconds.
+    #[serde(default)]
+    pub duration: Option<String>,
+}
+
+/// A page, such as /about or /now, renders at its slug as a post does but is left out of the index, the archive,
+/// the feed and every post's see also.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Post,
+    Page,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Kind::Post => "post",
+            Kind::Page => "page",
+        };
+    }
+}
+
+impl Post {
+    /// Whether it shows up among the posts, rather than only at its slug.
+    pub fn is_listed(&self) -> bool {
+        return !self.hidden && self.kind == Kind::Post;
+    }
+
+    /// The language it is written in, `site_lang` unless the manifest says otherwise.
+    pub fn lang(&self) -> &str {
+        return self.lang.as_deref().unwrap_or(&config().site_lang);
+    }
+
+    /// Whether it is written in `lang`, any language if none is given.
+    pub fn is_in(&self, lang: Option<&str>) -> bool {
+        return lang.map(|lang| self.lang() == lang).unwrap_or(true);
+    }
+
+    /// `hreflang` alternates, this post among them, or none if it has no translations.
+    pub fn alternates(&self) -> Vec<(String, String)> {
+        if self.translations.is_empty() {
+            return vec![];
+        }
+        let mut alternates: Vec<(String, String)> = self.translations.iter()
+            .map(|(lang, slug)| (lang.to_owned(), slug.to_owned()))
+            .chain([(self.lang().to_owned(), self.slug.to_owned())])
+            .collect();
+        alternates.sort();
+        return alternates;
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -31,51 +125,168 @@ pub struct Registry {
     #[serde(default)]
     pub hidden: bool,
     pub updated: DateTime<Utc>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    /// The template to render this post with instead of `main`, such as `talks` for `talks.html.hbs`.
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub kind: Kind,
+    /// `site_lang` unless given.
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// Slugs of this post in other languages by language, such as `{ "zh": "zip-is-scan-zh" }`.
+    #[serde(default)]
+    pub translations: BTreeMap<String, String>,
+    /// A short link code of its own, such as `zip` for `/s/zip`, for links read out in talks or printed.
+    #[serde(default)]
+    pub short: Option<String>,
+    /// An audio version, such as a reading of the post, for the feed.
+    #[serde(default)]
+    pub enclosure: Option<Enclosure>,
+    /// An image for the top of the post, for it when shared and in feed readers; absolute, or from the root of the site.
+    #[serde(default)]
+    pub cover: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct GithubSource {
-    pub base_url: String
+    pub base_url: String,
+    /// Forwarded as `X-Request-Id` on every fetch, so upstream logs line up with ours.
+    pub request_id: Option<String>,
 }
 
+#[cfg(feature = "embed")]
+static EMBEDDED: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/raw");
+
+#[derive(Clone)]
 pub struct LocalSource {
-    pub directory: PathBuf 
+    pub directory: PathBuf,
+    /// Whether to fall back to the `raw/` compiled in with the `embed` feature, for files `directory` doesn't have.
+    pub embedded: bool,
 }
 
 impl LocalSource {
+    pub fn new(directory: PathBuf) -> LocalSource {
+        return LocalSource { directory, embedded: false };
+    }
+
+    #[cfg(feature = "embed")]
+    fn read_embedded(&self, name: &str) -> Option<String> {
+        return if self.embedded { EMBEDDED.get_file(name).and_then(|file| file.contents_utf8()).map(String::from) } else { None };
+    }
+
+    #[cfg(not(feature = "embed"))]
+    fn read_embedded(&self, _name: &str) -> Option<String> {
+        return None;
+    }
+
+    #[cfg(feature = "embed")]
+    fn read_embedded_bytes(&self, name: &str) -> Option<Vec<u8>> {
+        return if self.embedded { EMBEDDED.get_file(name).map(|file| file.contents().to_vec()) } else { None };
+    }
+
+    #[cfg(not(feature = "embed"))]
+    fn read_embedded_bytes(&self, _name: &str) -> Option<Vec<u8>> {
+        return None;
+    }
+
+    /// A file other than markdown, such as an image alongside the posts; `None` if there is no such file.
+    pub fn read_bytes(&self, name: &str) -> Option<Vec<u8>> {
+        return std::fs::read(self.directory.join(name)).ok().or_else(|| self.read_embedded_bytes(name));
+    }
+
+    fn read(&self, name: &str) -> Result<String, String> {
+        let path = self.directory.join(name);
+        return std::fs::read_to_string(&path)
+            .or_else(|err| self.read_embedded(name).ok_or(err))
+            .map_err(|err| format!("Cannot read {}, {}", path.display(), err));
+    }
+
+    /// Whether `name` can be read, from the directory or what is embedded.
+    pub fn contains(&self, name: &str) -> bool {
+        return self.directory.join(name).is_file() || self.read_embedded(name).is_some();
+    }
+
     pub fn get_manifest(&self) -> Result<Vec<Registry>, String> {
-        let manifest = std::fs::read_to_string(&self.directory.join("manifest.json")).unwrap();
-        let manifest: Vec<Registry> = serde_json::from_str(&manifest).unwrap();
-        Ok(manifest)
+        let manifest = self.read("manifest.json")?;
+        return serde_json::from_str(&manifest).map_err(|err| format!("Cannot parse {}, {}", self.directory.join("manifest.json").display(), err));
     }
 
     pub fn read_content(&self, p: &String) -> Result<String, String> {
-        std::fs::read_to_string(&self.directory.join(p))
+        self.read(p)
             .map_err(|_| String::from("Cannot read markdown"))
     }
 
+    pub fn check(&self) -> Result<(), String> {
+        return if self.contains("manifest.json") { Ok(()) } else { Err(format!("{} not found", self.directory.join("manifest.json").display())) };
+    }
+
     pub fn default() -> LocalSource {
-        return LocalSource { directory: std::env::current_dir().unwrap().join("raw") }
+        return LocalSource { directory: std::env::current_dir().unwrap().join(&config().local_directory), embedded: true }
     }
 }
 
 impl GithubSource {
+    #[instrument(name = "manifest fetch", skip(self), fields(base_url = %self.base_url, status = field::Empty))]
     pub async fn get_manifest(&self) -> Result<Vec<Registry>, String> {
         let url = format!("{}/{}", self.base_url, "manifest.json");
-        return match reqwest::get(&url).await {
+        return match self.get(&url).await {
             Err(_) => Err(String::from("Cannot read remote manifest")),
-            Ok(response) => response.json::<Vec<Registry>>().await.map_err(|err| String::from(format!("Cannot deserialize response, {:?}, {:?}", &url, err)))
+            Ok(response) => {
+                Span::current().record("status", response.status().as_u16());
+                response.json::<Vec<Registry>>().await.map_err(|err| format!("Cannot deserialize response, {:?}, {:?}", &url, err))
+            }
         };
     }
 
+    #[instrument(name = "content fetch", skip(self), fields(base_url = %self.base_url, status = field::Empty))]
     pub async fn read_content(&self, markdown: &String) -> Result<String, String> {
-        return match reqwest::get(format!("{}/{}", &self.base_url, &markdown)).await {
+        return match self.get(&format!("{}/{}", &self.base_url, &markdown)).await {
             Err(_) => Err(String::from("Cannot read remote markdown file")),
-            Ok(response) => response.text().await.map_err(|_| String::from("Cannot read remote markdown content"))
+            Ok(response) => {
+                Span::current().record("status", response.status().as_u16());
+                response.text().await.map_err(|_| String::from("Cannot read remote markdown content"))
+            }
+        }
+    }
+
+    /// A file other than markdown, such as an image alongside the posts; `None` if the repository has no such file.
+    pub async fn read_bytes(&self, path: &str) -> Result<Option<Vec<u8>>, String> {
+        let response = self.get(&format!("{}/{}", &self.base_url, path)).await.map_err(|err| err.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("{} responded {}", path, response.status()));
         }
+        return response.bytes().await.map(|bytes| Some(bytes.to_vec())).map_err(|err| err.to_string());
+    }
+
+    /// Whether the manifest can be fetched right now, giving up after `timeout`.
+    pub async fn check(&self, timeout: Duration) -> Result<(), String> {
+        let response = reqwest::Client::builder().timeout(timeout).build()
+            .map_err(|err| err.to_string())?
+            .head(format!("{}/{}", self.base_url, "manifest.json"))
+            .send().await
+            .map_err(|err| err.to_string())?;
+
+        return if response.status().is_success() { Ok(()) } else { Err(format!("manifest responded {}", response.status())) };
+    }
+
+    async fn get(&self, url: &str) -> reqwest::Result<reqwest::Response> {
+        let request = reqwest::Client::new().get(url);
+        let request = match &self.request_id {
+            Some(request_id) => request.header(REQUEST_ID_HEADER, request_id),
+            None => request
+        };
+        return request.send().await;
     }
 
     pub fn new(remote_url: &String) -> GithubSource {
-        return GithubSource { base_url: remote_url.to_owned() };
+        return GithubSource { base_url: remote_url.to_owned(), request_id: None };
     }
 }
 
@@ -86,66 +297,462 @@ pub async fn load_all_posts_remote(source: &GithubSource) -> Result<Vec<Post>, S
     };
 }
 
-pub async fn load_remote(remote_url: &String, slug: &str) -> Result<(Post, Vec<Post>, String), String> {
-    let source = GithubSource::new(remote_url);
+pub fn load_all_posts_local(source: &LocalSource) -> Result<Vec<Post>, String> {
+    return source.get_manifest()
+        .map(|manifest| to_posts(&manifest));
+}
 
-    return match load_all_posts_remote(&source).await {
-        Ok(all_posts) => {
-            let current_post = find_post_for_slug(&all_posts, slug);
+pub fn cache_ttl() -> Duration {
+    return Duration::from_secs(config().cache_ttl_secs);
+}
 
-            return match source.read_content(&current_post.path).await {
-                Err(_) => Err(String::from("Reading current post failed")),
-                Ok(content) => Ok((current_post, all_posts, content))
-            };
-        },
-        Err(err) => Err(err)
-    };
+pub fn fetch_concurrency() -> usize {
+    return config().fetch_concurrency;
 }
 
-pub fn load_all_posts_local(source: &LocalSource) -> Result<Vec<Post>, String> {
-    return source.get_manifest()
-        .map(|manifest| to_posts(&manifest));
+const MANIFEST: &str = "manifest.json";
+
+/// The remote source when `REMOTE_MARKDOWN_PATH` is set, falling back to the local `raw` directory
+/// whenever the remote fails. Both the manifest and markdown files are cached for `cache_ttl_secs`.
+/// Clones share the same caches.
+#[derive(Clone)]
+pub struct CachedSource {
+    remote: Option<GithubSource>,
+    local: LocalSource,
+    manifest: TtlCache<Vec<Post>>,
+    contents: TtlCache<String>,
+    manifest_loaded: Arc<AtomicBool>,
+    /// Behind the in-memory caches for what comes from the remote, keyed by its URL.
+    backend: Option<SharedBackend>,
 }
 
-pub fn load_local(slug: &str) -> Result<(Post, Vec<Post>, String), String> {
-    let source = LocalSource::default();
+impl CachedSource {
+    pub fn new(remote: Option<GithubSource>, local: LocalSource, ttl: Duration) -> CachedSource {
+        return CachedSource {
+            remote,
+            local,
+            manifest: TtlCache::new(ttl),
+            contents: TtlCache::new(ttl),
+            manifest_loaded: Arc::new(AtomicBool::new(false)),
+            backend: None,
+        };
+    }
+
+    pub fn with_backend(self, backend: Option<SharedBackend>) -> CachedSource {
+        return CachedSource { backend, ..self };
+    }
+
+    /// The shared backend and the key `path` has in it, only for a remote source.
+    fn shared(&self, path: &str) -> Option<(&SharedBackend, String)> {
+        return Some((self.backend.as_ref()?, format!("{}/{}", self.remote.as_ref()?.base_url, path)));
+    }
+
+    async fn shared_get(&self, path: &str) -> Option<String> {
+        let (backend, key) = self.shared(path)?;
+        return backend.get(&key).await;
+    }
+
+    async fn shared_put(&self, path: &str, value: &str) {
+        if let Some((backend, key)) = self.shared(path) {
+            backend.put(&key, value, cache_ttl()).await;
+        }
+    }
+
+    async fn shared_remove(&self, path: &str) {
+        if let Some((backend, key)) = self.shared(path) {
+            backend.remove(&key).await;
+        }
+    }
+
+    /// Shares the caches, but tags upstream fetches with the id of the request they are made for.
+    pub fn for_request(&self, request_id: &RequestId) -> CachedSource {
+        let mut source = self.clone();
+        if let Some(remote) = source.remote.as_mut() {
+            remote.request_id = Some(request_id.0.to_owned());
+        }
+        return source;
+    }
+
+    pub fn from_config(config: &BlogConfig) -> CachedSource {
+        let remote = config.remote_markdown_path.as_ref().map(GithubSource::new);
+        return CachedSource::new(remote, LocalSource::default(), cache_ttl());
+    }
+
+    #[instrument(name = "manifest", skip(self), fields(cache_hit = true, source = field::Empty))]
+    pub async fn all_posts(&self) -> Result<Vec<Post>, String> {
+        if let Some(posts) = self.manifest.get("manifest") {
+            return Ok(posts);
+        }
 
-    return load_all_posts_local(&source).and_then(|all_posts| {
+        Span::current().record("cache_hit", false);
+        if let Some(posts) = self.shared_get(MANIFEST).await.and_then(|json| serde_json::from_str::<Vec<Post>>(&json).ok()) {
+            Span::current().record("source", "shared");
+            self.manifest.insert("manifest", posts.to_owned());
+            self.manifest_loaded.store(true, Ordering::Release);
+            return Ok(posts);
+        }
+
+        let remote = match &self.remote {
+            Some(source) => load_all_posts_remote(source).await
+                .inspect(|_| { Span::current().record("source", "remote"); })
+                .inspect_err(|err| capture_error(err, &[("source", "remote")])),
+            None => Err(String::from("REMOTE_MARKDOWN_PATH not set"))
+        };
+        if let Ok(posts) = &remote {
+            self.shared_put(MANIFEST, &serde_json::to_string(posts).unwrap()).await;
+        }
+        let all_posts = remote.or_else(|_| {
+            Span::current().record("source", "local");
+            load_all_posts_local(&self.local)
+        })?;
+
+        self.manifest.insert("manifest", all_posts.to_owned());
+        self.manifest_loaded.store(true, Ordering::Release);
+        return Ok(all_posts);
+    }
+
+    /// Whether the manifest has been loaded at least once since start up.
+    pub fn manifest_loaded(&self) -> bool {
+        return self.manifest_loaded.load(Ordering::Acquire);
+    }
+
+    /// How old the cached manifest is, `None` when nothing fresh is cached.
+    pub fn manifest_age(&self) -> Option<Duration> {
+        return self.manifest.age("manifest");
+    }
+
+    pub fn cache_stats(&self) -> Vec<(&'static str, CacheStats)> {
+        return vec![("manifest", self.manifest.stats()), ("contents", self.contents.stats())];
+    }
+
+    /// Drops the manifest and every post's content, or with a slug just that post's content,
+    /// returning how many entries went.
+    pub async fn purge(&self, slug: Option<&str>) -> Result<usize, String> {
+        return match slug {
+            None => {
+                if self.backend.is_some() {
+                    for post in &self.all_posts().await? {
+                        self.shared_remove(&post.path).await;
+                    }
+                    self.shared_remove(MANIFEST).await;
+                }
+                Ok(self.manifest.clear() + self.contents.clear())
+            },
+            Some(slug) => match find_post(&self.all_posts().await?, slug) {
+                Some(post) => {
+                    self.shared_remove(&post.path).await;
+                    Ok(self.contents.remove(&post.path) as usize)
+                },
+                None => Ok(0)
+            }
+        };
+    }
+
+    /// After a push touching `paths` (repository paths like `raw/fin.md`), drops the manifest if it changed
+    /// and the content of every post whose markdown changed, then fetches them again so no reader waits on it.
+    /// Returns the slugs that were refetched.
+    pub async fn invalidate(&self, paths: &[String]) -> Result<Vec<String>, String> {
+        if paths.iter().any(|path| is_repository_path(path, MANIFEST)) {
+            self.manifest.remove("manifest");
+            self.shared_remove(MANIFEST).await;
+        }
+
+        let changed = changed_posts(&self.all_posts().await?, paths);
+        for post in &changed {
+            self.contents.remove(&post.path);
+            self.shared_remove(&post.path).await;
+        }
+        for (post, content) in changed.iter().zip(self.prefetch(&changed).await) {
+            content.map_err(|err| format!("Cannot re-warm {}, {}", post.slug, err))?;
+        }
+
+        return Ok(changed.into_iter().map(|post| post.slug).collect());
+    }
+
+    pub fn remote(&self) -> Option<&GithubSource> {
+        return self.remote.as_ref();
+    }
+
+    pub fn local(&self) -> &LocalSource {
+        return &self.local;
+    }
+
+    #[instrument(name = "content", skip_all, fields(slug = %post.slug, cache_hit = true, source = field::Empty))]
+    pub async fn content(&self, post: &Post) -> Result<String, String> {
+        if let Some(content) = self.contents.get(&post.path) {
+            return Ok(content);
+        }
+
+        Span::current().record("cache_hit", false);
+        if let Some(content) = self.shared_get(&post.path).await {
+            Span::current().record("source", "shared");
+            self.contents.insert(&post.path, content.to_owned());
+            return Ok(content);
+        }
+
+        let remote = match &self.remote {
+            Some(source) => source.read_content(&post.path).await
+                .inspect(|_| { Span::current().record("source", "remote"); })
+                .inspect_err(|err| capture_error(err, &[("source", "remote"), ("slug", &post.slug)])),
+            None => Err(String::from("REMOTE_MARKDOWN_PATH not set"))
+        };
+        if let Ok(content) = &remote {
+            self.shared_put(&post.path, content).await;
+        }
+        let content = remote.or_else(|_| {
+            Span::current().record("source", "local");
+            self.local.read_content(&post.path)
+        })?;
+
+        if config().markdown_lint {
+            lint::log(&post.slug, &content);
+        }
+        self.contents.insert(&post.path, content.to_owned());
+        return Ok(content);
+    }
+
+    /// A file from the remote repository when there is one, and from the local directory when there isn't or it
+    /// can't be reached, as posts are.
+    pub async fn file(&self, path: &str) -> Result<Option<Vec<u8>>, String> {
+        let remote = match &self.remote {
+            Some(source) => source.read_bytes(path).await,
+            None => return Ok(self.local.read_bytes(path))
+        };
+        return match remote {
+            Ok(bytes) => Ok(bytes),
+            Err(err) => {
+                capture_error(&err, &[("source", "remote"), ("path", path)]);
+                Ok(self.local.read_bytes(path))
+            }
+        };
+    }
+
+    /// The content of every post, in the order given, fetching up to `FETCH_CONCURRENCY` at a time
+    /// so that a full feed or search index doesn't wait on one round trip after another.
+    pub async fn prefetch(&self, posts: &[Post]) -> Vec<Result<String, String>> {
+        // owned posts and a shared clone of the caches keep the futures 'static, which rocket's handlers need to stay Send
+        let mut fetched: Vec<(usize, Result<String, String>)> = stream::iter(posts.iter().cloned().enumerate())
+            .map(|(index, post)| {
+                let source = self.clone();
+                async move { (index, source.content(&post).await) }
+            })
+            .buffer_unordered(fetch_concurrency())
+            .collect()
+            .await;
+
+        fetched.sort_by_key(|(index, _)| *index);
+        return fetched.into_iter().map(|(_, content)| content).collect();
+    }
+
+    /// The markdown of exactly this post, `None` if there is no such slug; unlike `load`, no fallback to the latest post.
+    pub async fn markdown(&self, slug: &str) -> Result<Option<String>, String> {
+        let all_posts = self.all_posts().await?;
+
+        return match find_post(&all_posts, slug) {
+            None => Ok(None),
+            Some(post) => self.content(&post).await.map(Some)
+        };
+    }
+
+    pub async fn load(&self, slug: &str) -> Result<(Post, Vec<Post>, String), String> {
+        let all_posts = self.all_posts().await?;
         let current_post = find_post_for_slug(&all_posts, slug);
-        return source.read_content(&current_post.path).map(|content| (current_post, all_posts, content));
-    });
+        let content = self.content(&current_post).await?;
+
+        return Ok((current_post, all_posts, content));
+    }
+}
+
+/// Whether a path in the repository is `path` under the markdown directory, wherever that is.
+fn is_repository_path(repository_path: &str, path: &str) -> bool {
+    return repository_path == path || repository_path.ends_with(&format!("/{}", path));
+}
+
+pub fn changed_posts(posts: &Vec<Post>, paths: &[String]) -> Vec<Post> {
+    return posts.iter()
+        .filter(|post| paths.iter().any(|path| is_repository_path(path, &post.path)))
+        .cloned()
+        .collect();
 }
 
 pub fn to_posts(registries: &Vec<Registry>) -> Vec<Post> {
-    return registries.iter()
-        .map(|Registry{ title, markdown, hidden, updated } | Post {
+    let mut posts: Vec<Post> = registries.iter()
+        .map(|Registry{ title, markdown, hidden, updated, tags, pinned, template, kind, lang, translations, short, enclosure, cover } | Post {
             title: title.to_owned(),
             slug: to_slug(title),
             path: markdown.to_owned(),
             hidden: *hidden,
             updated: updated.to_owned(),
+            tags: tags.to_owned(),
+            pinned: *pinned,
+            template: template.to_owned(),
+            kind: *kind,
+            lang: lang.to_owned(),
+            translations: translations.to_owned(),
+            short: short.to_owned(),
+            enclosure: enclosure.to_owned(),
+            cover: cover.to_owned(),
         })
         .rev()
         .collect();
+
+    let reverse: Vec<(String, String, String)> = posts.iter()
+        .flat_map(|post| post.translations.values().map(|slug| (slug.to_owned(), post.lang().to_owned(), post.slug.to_owned())))
+        .collect();
+    for (translated, lang, slug) in reverse {
+        if let Some(post) = posts.iter_mut().find(|post| post.slug == translated && post.slug != slug) {
+            post.translations.entry(lang).or_insert(slug);
+        }
+    }
+    return posts;
 }
 
-pub fn make_blog(current_post: &Post, all_posts: &Vec<Post>, markdown: &String) -> Blog {
-    let options = ComrakOptions {
-        extension: ComrakExtensionOptions {
-            table: true,
-            ..ComrakExtensionOptions::default()
-        },
-        ..ComrakOptions::default()
-    };
-    let content =  markdown_to_html(&markdown.to_string(), &options);
-    let description = markdown_to_text::convert(&markdown.to_string())
-        .split("\n")
-        .collect::<Vec<_>>()
-        .first().unwrap().to_string();;
+static COMRAK_OPTIONS: Lazy<ComrakOptions> = Lazy::new(markdown_options::comrak_options);
+
+/// HTML rendered from `raw/*.md` by build.rs with the `prerender` feature, sorted by the markdown's fingerprint.
+#[cfg(feature = "prerender")]
+static PRERENDERED: &[(u64, &str)] = include!(concat!(env!("OUT_DIR"), "/prerendered.rs"));
+#[cfg(not(feature = "prerender"))]
+static PRERENDERED: &[(u64, &str)] = &[];
+
+fn prerendered(markdown: &str) -> Option<&'static str> {
+    let fingerprint = markdown_options::fingerprint(markdown);
+    return PRERENDERED.binary_search_by_key(&fingerprint, |(key, _)| *key).ok().map(|index| PRERENDERED[index].1);
+}
+
+pub fn comrak_options() -> &'static ComrakOptions {
+    return &COMRAK_OPTIONS;
+}
 
-    let see_also = all_posts
+const RENDERED_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Rendered HTML keyed by a hash of the markdown and the comrak options, so a hot post
+/// renders once rather than on every request. Holds `RENDER_CACHE_SIZE` posts; clones share entries.
+#[derive(Clone)]
+pub struct RenderCache {
+    options: ComrakOptions,
+    options_fingerprint: String,
+    /// Only with the options build.rs rendered with.
+    use_prerendered: bool,
+    cache: LruCache<String>,
+    backend: Option<SharedBackend>,
+}
+
+impl RenderCache {
+    pub fn new(options: ComrakOptions, capacity: usize) -> RenderCache {
+        let options_fingerprint = format!("{:?}", options);
+        let use_prerendered = options_fingerprint == format!("{:?}", comrak_options());
+        return RenderCache { options, options_fingerprint, use_prerendered, cache: LruCache::new(capacity), backend: None };
+    }
+
+    pub fn with_backend(self, backend: Option<SharedBackend>) -> RenderCache {
+        return RenderCache { backend, ..self };
+    }
+
+    /// With a shared backend, puts `markdown`'s HTML in memory ahead of `render`: from the backend when another
+    /// instance rendered it already, otherwise rendered here and shared. Rendered HTML is keyed by its markdown,
+    /// so it never goes stale and is kept for `RENDERED_TTL`.
+    pub async fn warm(&self, markdown: &str) {
+        let backend = match &self.backend {
+            Some(backend) => backend,
+            None => return
+        };
+        let key = self.key(markdown);
+        if self.cache.get(&key).is_some() {
+            return;
+        }
+
+        let shared_key = format!("rendered/{}", key);
+        match backend.get(&shared_key).await {
+            Some(html) => self.cache.insert(&key, html),
+            None => backend.put(&shared_key, &self.render(markdown), RENDERED_TTL).await
+        };
+    }
+
+    pub fn default() -> RenderCache {
+        return RenderCache::new(comrak_options().to_owned(), config().render_cache_size);
+    }
+
+    fn key(&self, markdown: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(self.options_fingerprint.as_bytes());
+        hasher.write(markdown.as_bytes());
+        return format!("{:016x}", hasher.finish());
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.cache.stats();
+    }
+
+    pub fn purge(&self) -> usize {
+        return self.cache.clear();
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.cache.capacity();
+    }
+
+    #[instrument(name = "render", skip_all)]
+    pub fn render(&self, markdown: &str) -> String {
+        let key = self.key(markdown);
+        if let Some(html) = self.cache.get(&key) {
+            return html;
+        }
+
+        let html = match prerendered(markdown).filter(|_| self.use_prerendered) {
+            Some(html) => shortcodes::expand_video_embeds(html),
+            None => shortcodes::expand_video_embeds(&markdown_to_html(markdown, &self.options))
+        };
+        self.cache.insert(&key, html.to_owned());
+        return html;
+    }
+}
+
+/// The posts worth rendering ahead of readers: the newest listed ones, as many as the render cache holds,
+/// oldest first so the newest end up the most recently used.
+pub fn prerender_order(posts: &Vec<Post>, limit: usize) -> Vec<Post> {
+    let mut listed: Vec<Post> = posts.iter().filter(|post| !post.hidden).cloned().collect();
+    listed.sort_by_key(|post| std::cmp::Reverse(post.updated));
+    listed.truncate(limit);
+    listed.reverse();
+    return listed;
+}
+
+/// Loads the manifest and renders the newest posts into `renderer`, fetching them `fetch_concurrency` at a time
+/// until `budget` runs out. Returns how many were rendered.
+pub async fn prerender(source: &CachedSource, renderer: &RenderCache, budget: Duration) -> Result<usize, String> {
+    let started = Instant::now();
+    let posts = timeout(budget, source.all_posts()).await
+        .map_err(|_| String::from("The manifest didn't load within the budget"))??;
+
+    let mut rendered = 0;
+    for batch in prerender_order(&posts, renderer.capacity()).chunks(fetch_concurrency()) {
+        let remaining = match budget.checked_sub(started.elapsed()) {
+            Some(remaining) => remaining,
+            None => break
+        };
+        let contents = match timeout(remaining, source.prefetch(batch)).await {
+            Ok(contents) => contents,
+            Err(_) => break
+        };
+        for content in contents.into_iter().flatten() {
+            renderer.warm(&content).await;
+            renderer.render(&content);
+            rendered += 1;
+        }
+    }
+
+    return Ok(rendered);
+}
+
+pub fn make_blog(current_post: &Post, all_posts: &Vec<Post>, markdown: &String, renderer: &RenderCache) -> Blog {
+    let content = renderer.render(markdown);
+    let text = markdown_to_text::convert(&markdown.to_string());
+    let description = describe(&text);
+    let word_count = count_words(&text);
+
+    let see_also = related_posts(current_post, &text, all_posts, see_also_limit())
         .iter()
-        .filter(|Post{ title, hidden, .. }| !*hidden && title != &current_post.title)
         .map(|Post{ title,.. }| (title.to_string(), to_slug(title).to_string()))
         .collect();
 
@@ -154,57 +761,399 @@ pub fn make_blog(current_post: &Post, all_posts: &Vec<Post>, markdown: &String)
         content,
         description,
         see_also,
-        date_updated: format!("{}", current_post.updated.format("%v"))
+        alternates: current_post.alternates(),
+        date_updated: format!("{}", current_post.updated.format("%v")),
+        word_count,
+        reading_time: reading_time(word_count, words_per_minute()),
     }
 }
 
-fn to_slug(raw: &str) -> String {
-    let no_whitespace_regex = Regex::new(r"[^a-zA-Z]+").unwrap();
-    let no_ws = no_whitespace_regex.replace_all(raw.trim(), r"-").into_owned();
+pub fn describe(text: &str) -> String {
+    return text
+        .split("\n")
+        .collect::<Vec<_>>()
+        .first().unwrap().to_string();
+}
 
-    return no_ws.trim_matches(|c| c == '-').to_ascii_lowercase();
+fn see_also_limit() -> usize {
+    return config().see_also_limit;
 }
 
-pub fn find_post_for_slug(posts: &Vec<Post>, slug_to_find: &str) -> Post {
-    assert!(posts.len() > 0);
+const STOP_WORDS: [&str; 24] = [
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "has", "have",
+    "its", "just", "more", "one", "our", "out", "that", "this", "was", "what", "with", "your",
+];
+
+fn tokenize(text: &str) -> HashSet<String> {
+    return text
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|token| token.to_lowercase())
+        .filter(|token| token.len() > 2 && !STOP_WORDS.contains(&token.as_str()))
+        .collect();
+}
+
+/// Ranks the other listed posts with pinned ones first, then by shared tags, then by how many words
+/// of their title show up in the current post's title (weighted double) or content.
+/// Ties keep manifest order, i.e. the most recent post wins.
+pub fn related_posts(current_post: &Post, current_text: &str, all_posts: &Vec<Post>, limit: usize) -> Vec<Post> {
+    let title_tokens = tokenize(&current_post.title);
+    let content_tokens = tokenize(current_text);
+
+    let mut scored: Vec<(bool, usize, usize, &Post)> = all_posts
+        .iter()
+        .filter(|post| post.is_listed() && post.title != current_post.title)
+        .map(|post| {
+            let shared_tags = post.tags.iter().filter(|tag| current_post.tags.contains(tag)).count();
+            let overlap = tokenize(&post.title)
+                .iter()
+                .map(|token| 2 * title_tokens.contains(token) as usize + content_tokens.contains(token) as usize)
+                .sum();
+            (post.pinned, shared_tags, overlap, post)
+        })
+        .collect();
 
+    scored.sort_by(|(pinned_a, tags_a, overlap_a, _), (pinned_b, tags_b, overlap_b, _)|
+        (pinned_b, tags_b, overlap_b).cmp(&(pinned_a, tags_a, overlap_a)));
+
+    return scored.into_iter()
+        .take(limit)
+        .map(|(_, _, _, post)| post.to_owned())
+        .collect();
+}
+
+pub fn words_per_minute() -> usize {
+    return config().reading_words_per_minute;
+}
+
+pub fn count_words(text: &str) -> usize {
+    return text.split_whitespace().count();
+}
+
+/// In whole minutes, rounded up, and never less than one so a short post doesn't read "0 min".
+pub fn reading_time(word_count: usize, words_per_minute: usize) -> usize {
+    return std::cmp::max(1, word_count.div_ceil(words_per_minute));
+}
+
+static NON_LETTERS: Lazy<Regex> = Lazy::new(|| Regex::new(r"[^a-zA-Z]+").unwrap());
+
+pub fn to_slug(raw: &str) -> String {
+    let no_ws = NON_LETTERS.replace_all(raw.trim(), r"-").into_owned();
+
+    return no_ws.trim_matches(|c| c == '-').to_ascii_lowercase();
+}
+
+pub fn find_post(posts: &Vec<Post>, slug_to_find: &str) -> Option<Post> {
     return posts
         .iter()
         .find(|Post { slug, path,.. } | slug == slug_to_find || *path == format!("{}.md", slug_to_find))
-        .unwrap_or_else(|| posts.iter().filter(|Post{hidden, ..}| !*hidden).nth(0).unwrap())
-        .to_owned();
-}
-
-pub async fn build_rss(remote_url: &Result<String, String>) -> Result<Xml<String>, String> {
-    let all_posts = match remote_url {
-        Ok(remote_url) => load_all_posts_remote(&GithubSource::new(&remote_url)).await,
-        Err(var_err) => Err(var_err.to_string())
-    }.or_else(|_| load_all_posts_local(&LocalSource::default()));
-
-    let host_name = "https://hacklewayne.com";
-
-    return all_posts.and_then(|posts| {
-        let pub_date = posts.first().unwrap().updated.to_owned();
-        
-        let items: Vec<Item> = posts.iter()
-            .map(|post| ItemBuilder::default()
-                .title(Some(post.title.to_owned()))
-                .link(Some(format!("{}/{}", host_name, post.slug)))
-                .pub_date(Some(format!("{}", post.updated.to_rfc2822())))
-                .build()
-            )
+        .map(|post| post.to_owned());
+}
+
+pub fn find_post_for_slug(posts: &Vec<Post>, slug_to_find: &str) -> Post {
+    assert!(!posts.is_empty());
+
+    return find_post(posts, slug_to_find)
+        .or_else(|| posts.iter().find(|post| post.is_listed() && post.pinned).cloned())
+        .or_else(|| posts.iter().find(|post| post.is_listed()).cloned())
+        .unwrap_or_else(|| posts.iter().find(|post| !post.hidden).unwrap().to_owned());
+}
+
+/// The languages posts are written in, each with a feed of its own.
+pub fn languages(posts: &[Post]) -> Vec<String> {
+    let mut languages: Vec<String> = posts.iter().filter(|post| post.kind == Kind::Post).map(|post| post.lang().to_owned()).collect();
+    languages.sort();
+    languages.dedup();
+    return languages;
+}
+
+/// Whether `lang` looks like a language tag, such as `en` or `zh-Hans`, and so is safe to put in a URL.
+pub fn is_lang_tag(lang: &str) -> bool {
+    return !lang.is_empty() && lang.len() <= 35 && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+}
+
+/// Every tag in use by a listed post, sorted.
+pub fn tags(posts: &[Post]) -> Vec<String> {
+    let mut tags: Vec<String> = posts.iter().filter(|post| post.is_listed()).flat_map(|post| post.tags.to_owned()).collect();
+    tags.sort();
+    tags.dedup();
+    return tags;
+}
+
+/// Posts in a feed, and `(rel, href)` of the `atom:link`s to the feed's other pages.
+#[derive(Debug)]
+pub struct FeedPage {
+    pub posts: Vec<Post>,
+    pub links: Vec<(&'static str, String)>,
+}
+
+/// The posts in the main feed, or in its archive `page`, with the RFC 5005 links between them. Past `rss_item_limit`
+/// posts the feed keeps the newest and links to archives of the rest, that many each, numbered from the oldest; only
+/// the newest archive can be partial, so the others never change. Each page links both ways as an archived feed
+/// (`prev-archive`, `next-archive`, `current`) and as a paged one (`next` to older posts, `previous` to newer), for
+/// readers that only know one. `None` for a page that doesn't exist.
+pub fn feed_page(mut posts: Vec<Post>, limit: usize, page: Option<usize>) -> Option<FeedPage> {
+    if limit == 0 || posts.len() <= limit {
+        return if page.is_none() { Some(FeedPage { posts, links: vec![] }) } else { None };
+    }
+    posts.sort_by_key(|post| std::cmp::Reverse(post.updated));
+    let archived = posts.len() - limit;
+    let archives = archived.div_ceil(limit);
+    let archive = |page: usize| format!("/rss/page/{}.xml", page);
+
+    return match page {
+        None => {
+            posts.truncate(limit);
+            Some(FeedPage { posts, links: vec![("next", archive(archives)), ("prev-archive", archive(archives))] })
+        },
+        Some(page) if (1..=archives).contains(&page) => {
+            let newest = limit + archived.saturating_sub(page * limit);
+            let oldest = limit + archived - (page - 1) * limit;
+            let posts = posts.into_iter().take(oldest).skip(newest).collect();
+            let mut links = vec![("current", String::from("/rss/index.xml"))];
+            if page > 1 {
+                links.extend([("next", archive(page - 1)), ("prev-archive", archive(page - 1))]);
+            }
+            if page < archives {
+                links.extend([("previous", archive(page + 1)), ("next-archive", archive(page + 1))]);
+            }
+            Some(FeedPage { posts, links })
+        },
+        Some(_) => None,
+    };
+}
+
+/// `url` as is if it is absolute, otherwise under `host_name`.
+pub fn absolute_url(host_name: &str, url: &str) -> String {
+    return if url.starts_with('/') && !url.starts_with("//") { format!("{}{}", host_name, url) } else { url.to_owned() };
+}
+
+fn extension(name: &str, attrs: &[(&str, &str)]) -> Extension {
+    return Extension {
+        name: name.to_owned(),
+        attrs: attrs.iter().map(|(name, value)| ((*name).to_owned(), (*value).to_owned())).collect(),
+        ..Extension::default()
+    };
+}
+
+/// The feed of posts, in `lang` and with `tag` if given; none when a language or tag has no posts.
+/// `page` is one of the main feed's archives, see `feed_page`; feeds by language or tag aren't paged.
+pub async fn build_rss(source: &CachedSource, title: &str, description: &str, host_name: &str, lang: Option<&str>, tag: Option<&str>, page: Option<usize>) -> Result<Option<Xml<String>>, String> {
+    let all_posts = source.all_posts().await;
+
+    let words_per_minute = words_per_minute();
+
+    let posts: Vec<Post> = all_posts?.into_iter()
+        .filter(|post| post.kind == Kind::Post && post.is_in(lang) && tag.map(|tag| post.tags.iter().any(|tagged| tagged == tag)).unwrap_or(true))
+        .collect();
+    if (lang.is_some() || tag.is_some()) && posts.is_empty() {
+        return Ok(None);
+    }
+    let limit = if lang.is_none() && tag.is_none() { config().rss_item_limit } else { 0 };
+    let FeedPage { posts, links } = match feed_page(posts, limit, page) {
+        Some(paged) => paged,
+        None => return Ok(None),
+    };
+    let title = match tag {
+        Some(tag) => format!("{}: {}", title, tag),
+        None => title.to_owned(),
+    };
+    let pub_date = posts.first().map(|post| post.updated.to_rfc2822());
+
+    let contents = source.prefetch(&posts).await;
+
+    let mut items: Vec<Item> = vec![];
+    for (post, content) in posts.iter().zip(contents) {
+        let description = content
+            .map(|markdown| {
+                let minutes = reading_time(count_words(&markdown_to_text::convert(&markdown)), words_per_minute);
+                format!("{} min read", minutes)
+            })
+            .ok();
+
+        let enclosure = post.enclosure.as_ref().map(|enclosure| rss::Enclosure {
+            url: absolute_url(host_name, &enclosure.url),
+            length: enclosure.length.to_string(),
+            mime_type: enclosure.mime_type.to_owned(),
+        });
+        let extensions: ExtensionMap = post.cover.iter()
+            .map(|cover| (String::from("media"), BTreeMap::from([(String::from("content"), vec![extension("media:content", &[("url", &absolute_url(host_name, cover)), ("medium", "image")])])])))
             .collect();
+        let itunes_ext = post.enclosure.as_ref()
+            .filter(|_| config().itunes_author.is_some())
+            .map(|enclosure| itunes::ITunesItemExtension { duration: enclosure.duration.to_owned(), ..Default::default() });
 
-        let channel = ChannelBuilder::default()
-        .title(String::from("Hackle's blog"))
-        .link(String::from(host_name))
-        .description(String::from("Between the abstractions we need and the abstractions we get"))
-        .items(items)
-        .pub_date(Some(pub_date.to_rfc2822()))
-        .build();
+        items.push(ItemBuilder::default()
+            .title(Some(post.title.to_owned()))
+            .link(Some(format!("{}/{}", host_name, post.slug)))
+            .description(description)
+            .pub_date(Some(post.updated.to_rfc2822()))
+            .enclosure(enclosure)
+            .extensions(extensions)
+            .itunes_ext(itunes_ext)
+            .build());
+    }
 
-        return Ok(Xml(channel.to_string()));
+    let mut namespaces = BTreeMap::new();
+    let mut extensions = ExtensionMap::default();
+    if !links.is_empty() {
+        let links = links.iter().map(|(rel, href)| extension("atom:link", &[("rel", rel), ("href", &format!("{}{}", host_name, href))])).collect();
+        namespaces.insert(String::from("atom"), String::from("http://www.w3.org/2005/Atom"));
+        extensions.insert(String::from("atom"), BTreeMap::from([(String::from("link"), links)]));
+    }
+    let itunes_ext = config().itunes_author.as_ref().map(|author| itunes::ITunesChannelExtension {
+        author: Some(author.to_owned()),
+        image: config().itunes_image.to_owned(),
+        categories: config().itunes_category.iter().map(|text| itunes::ITunesCategory { text: text.to_owned(), subcategory: None }).collect(),
+        explicit: Some(config().itunes_explicit.to_string()),
+        ..Default::default()
     });
+    if posts.iter().any(|post| post.cover.is_some()) {
+        namespaces.insert(String::from("media"), String::from("http://search.yahoo.com/mrss/"));
+    }
+    if itunes_ext.is_some() {
+        namespaces.insert(String::from("itunes"), String::from(itunes::NAMESPACE));
+    }
+    if page.is_some() {
+        namespaces.insert(String::from("fh"), String::from("http://purl.org/syndication/history/1.0"));
+        extensions.insert(String::from("fh"), BTreeMap::from([(String::from("archive"), vec![extension("fh:archive", &[])])]));
+    }
+
+    let channel = ChannelBuilder::default()
+    .title(title)
+    .link(String::from(host_name))
+    .description(String::from(description))
+    .items(items)
+    .pub_date(pub_date)
+    .language(lang.map(str::to_owned))
+    .namespaces(namespaces)
+    .extensions(extensions)
+    .itunes_ext(itunes_ext)
+    .build();
+
+    return Ok(Some(Xml(channel.to_string())));
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PostSummary {
+    pub title: String,
+    pub slug: String,
+    pub date: String,
+    /// RFC 3339, for the `format_date` helper.
+    pub updated: String,
+    pub summary: String,
+    pub featured: bool,
+    pub lang: String,
+    /// Slugs by language, for the index to link to the post in each.
+    pub translations: BTreeMap<String, String>,
+}
+
+impl PostSummary {
+    pub fn new(post: &Post, summary: &str) -> PostSummary {
+        return PostSummary {
+            title: post.title.to_owned(),
+            slug: post.slug.to_owned(),
+            date: format!("{}", post.updated.format("%v")),
+            updated: post.updated.to_rfc3339(),
+            summary: summary.to_owned(),
+            featured: post.pinned,
+            lang: post.lang().to_owned(),
+            translations: post.translations.to_owned(),
+        };
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ArchiveYear {
+    pub year: i32,
+    pub posts: Vec<PostSummary>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct IndexPage {
+    pub posts: Vec<PostSummary>,
+    pub page: usize,
+    pub total_pages: usize,
+}
+
+/// Groups listed posts by the year they were last updated, newest first.
+pub fn group_by_year(posts: &Vec<(Post, String)>) -> Vec<ArchiveYear> {
+    let mut visible: Vec<&(Post, String)> = posts.iter().filter(|(post, _)| post.is_listed()).collect();
+    visible.sort_by_key(|(post, _)| std::cmp::Reverse(post.updated));
+
+    let mut years: Vec<ArchiveYear> = vec![];
+    for (post, summary) in visible {
+        let entry = PostSummary::new(post, summary);
+
+        match years.last_mut() {
+            Some(archive_year) if archive_year.year == post.updated.year() => archive_year.posts.push(entry),
+            _ => years.push(ArchiveYear { year: post.updated.year(), posts: vec![entry] }),
+        }
+    }
+
+    return years;
+}
+
+pub async fn summarise(source: &CachedSource, posts: Vec<Post>) -> Vec<(Post, String)> {
+    let contents = source.prefetch(&posts).await;
+
+    return posts.into_iter()
+        .zip(contents)
+        .map(|(post, content)| {
+            let summary = content
+                .map(|markdown| describe(&markdown_to_text::convert(&markdown)))
+                .unwrap_or_default();
+            (post, summary)
+        })
+        .collect();
+}
+
+pub async fn build_archive(source: &CachedSource, lang: Option<&str>) -> Result<Vec<ArchiveYear>, String> {
+    let all_posts = source.all_posts().await?;
+    let visible = all_posts.into_iter().filter(|post| post.is_listed() && post.is_in(lang)).collect();
+
+    return Ok(group_by_year(&summarise(source, visible).await));
+}
+
+/// Slices the listed posts in `lang`, pinned first and otherwise newest first, into pages counted from 1.
+/// Without a language, translations of a post in `site_lang` are left out as the index links to them from it.
+/// Returns `None` for a page past the end; page 1 always exists, even if it is empty.
+pub fn page_of(posts: &Vec<Post>, page: usize, page_size: usize, lang: Option<&str>) -> Option<(Vec<Post>, usize)> {
+    let mut visible: Vec<Post> = posts.iter().filter(|post| post.is_listed() && post.is_in(lang)).cloned().collect();
+    visible.sort_by_key(|post| !post.pinned);
+    if lang.is_none() {
+        let site_lang = &config().site_lang;
+        let in_site_lang: HashSet<String> = visible.iter().filter(|post| post.lang() == site_lang).map(|post| post.slug.to_owned()).collect();
+        visible.retain(|post| post.lang() == site_lang || !post.translations.values().any(|slug| in_site_lang.contains(slug)));
+    }
+
+    let page_size = std::cmp::max(1, page_size);
+    let total_pages = std::cmp::max(1, visible.len().div_ceil(page_size));
+    if page == 0 || page > total_pages {
+        return None;
+    }
+
+    let on_page = visible.into_iter().skip((page - 1) * page_size).take(page_size).collect();
+    return Some((on_page, total_pages));
+}
+
+fn page_size() -> usize {
+    return config().page_size;
+}
+
+pub async fn build_index(source: &CachedSource, page: usize, lang: Option<&str>) -> Result<Option<IndexPage>, String> {
+    let all_posts = source.all_posts().await?;
+
+    return match page_of(&all_posts, page, page_size(), lang) {
+        None => Ok(None),
+        Some((on_page, total_pages)) => {
+            let posts = summarise(source, on_page).await
+                .iter()
+                .map(|(post, summary)| PostSummary::new(post, summary))
+                .collect();
+
+            Ok(Some(IndexPage { posts, page, total_pages }))
+        }
+    };
 }
 
 #[cfg(test)]
@@ -215,11 +1164,326 @@ mod tests {
 
     #[test]
     fn test_to_slugs() {
-        assert_eq!(to_slug(&"slug-slug"), String::from("slug-slug"));
-        assert_eq!(to_slug(&" A B C D"), String::from("a-b-c-d"));
-        assert_eq!(to_slug(&" A  D"), String::from("a-d"));
-        assert_eq!(to_slug(&"Great_is not bad"), String::from("great-is-not-bad"));
-        assert_eq!(to_slug(&"but, we shall see!"), String::from("but-we-shall-see"));
+        assert_eq!(to_slug("slug-slug"), String::from("slug-slug"));
+        assert_eq!(to_slug(" A B C D"), String::from("a-b-c-d"));
+        assert_eq!(to_slug(" A  D"), String::from("a-d"));
+        assert_eq!(to_slug("Great_is not bad"), String::from("great-is-not-bad"));
+        assert_eq!(to_slug("but, we shall see!"), String::from("but-we-shall-see"));
+    }
+
+    #[test]
+    fn test_reading_time() {
+        assert_eq!(count_words("one two\n\nthree   four"), 4);
+        assert_eq!(reading_time(0, 200), 1);
+        assert_eq!(reading_time(200, 200), 1);
+        assert_eq!(reading_time(201, 200), 2);
+        assert_eq!(reading_time(1000, 250), 4);
+    }
+
+    /// A rough benchmark rather than a check: `cargo test --release -- --ignored --nocapture bench_`
+    #[test]
+    #[ignore]
+    #[allow(clippy::regex_creation_in_loops)]
+    fn bench_to_slug_with_static_regex() {
+        let title = "Lens in TypeScript, without the ceremony";
+        let iterations = 10_000;
+
+        let started = std::time::Instant::now();
+        for _ in 0..iterations {
+            let regex = Regex::new(r"[^a-zA-Z]+").unwrap();
+            std::hint::black_box(regex.replace_all(title, "-").into_owned());
+        }
+        let compiled_per_call = started.elapsed();
+
+        let started = std::time::Instant::now();
+        for _ in 0..iterations {
+            std::hint::black_box(to_slug(title));
+        }
+        let compiled_once = started.elapsed();
+
+        println!("to_slug x{}: regex per call {:?}, static regex {:?}", iterations, compiled_per_call, compiled_once);
+        assert!(compiled_once < compiled_per_call);
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let renderer = RenderCache::new(comrak_options().to_owned(), 1);
+        let html = renderer.render("# Fin");
+
+        assert_eq!(html, "<h1>Fin</h1>\n");
+        assert_eq!(renderer.cache.get(&renderer.key("# Fin")), Some(html));
+        assert_ne!(renderer.key("# Fin"), RenderCache::new(ComrakOptions::default(), 1).key("# Fin"));
+    }
+
+    fn post(title: &str, tags: &[&str]) -> Post {
+        Post {
+            slug: to_slug(title),
+            title: title.to_owned(),
+            path: format!("{}.md", to_slug(title)),
+            hidden: false,
+            updated: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        }
+    }
+
+    /// A manifest entry for `fin.md` with `fields` besides the required ones.
+    fn entry(fields: &str) -> Registry {
+        return serde_json::from_str(&format!(r#"{{ "title": "Fin", "markdown": "fin.md", "updated": "2021-01-01T00:00:00Z", {} }}"#, fields)).unwrap();
+    }
+
+    #[cfg(feature = "prerender")]
+    #[test]
+    fn test_prerendered() {
+        let markdown = std::fs::read_to_string("raw/fin.md").unwrap();
+
+        assert_eq!(prerendered(&markdown), Some(markdown_to_html(&markdown, comrak_options()).as_str()));
+        assert_eq!(prerendered("# Not a post"), None);
+    }
+
+    #[test]
+    fn test_related_posts() {
+        let current = post("Covariance and contravariance", &["types"]);
+        let all_posts = vec![
+            post("Fin", &[]),
+            current.to_owned(),
+            post("Nesting and positions in covariance and contravariance", &[]),
+            post("Dependent types in TypeScript?", &["types"]),
+            post("Inject functions, not interfaces", &[]),
+        ];
+
+        let titles: Vec<String> = related_posts(&current, "a function is contravariant in its input", &all_posts, 3)
+            .into_iter()
+            .map(|Post{ title, .. }| title)
+            .collect();
+
+        assert_eq!(titles, vec![
+            String::from("Dependent types in TypeScript?"),
+            String::from("Nesting and positions in covariance and contravariance"),
+            String::from("Fin"),
+        ]);
+    }
+
+    #[test]
+    fn test_pinned_posts_come_first() {
+        let current = post("Covariance and contravariance", &["types"]);
+        let pinned = Post { pinned: true, ..post("Fin", &[]) };
+        let all_posts = vec![
+            post("Dependent types in TypeScript?", &["types"]),
+            pinned.to_owned(),
+            current.to_owned(),
+        ];
+
+        assert_eq!(related_posts(&current, "", &all_posts, 1)[0].title, pinned.title);
+        assert_eq!(find_post_for_slug(&all_posts, "").title, pinned.title);
+        assert_eq!(find_post_for_slug(&all_posts, "dependent-types-in-typescript").title, all_posts[0].title);
+        assert!(find_post(&all_posts, "no-such-post").is_none());
+
+        let fin = entry(r#""tags": ["types"], "pinned": true"#);
+        assert_eq!((fin.tags, fin.pinned), (vec![String::from("types")], true));
+    }
+
+    #[test]
+    fn test_pages_are_not_listed() {
+        let current = post("Covariance and contravariance", &["types"]);
+        let about = Post { kind: Kind::Page, pinned: true, ..post("About", &["types"]) };
+        let all_posts = vec![about.to_owned(), post("Fin", &[]), current.to_owned()];
+
+        assert!(!about.is_listed());
+        assert_eq!(related_posts(&current, "", &all_posts, 3).len(), 1);
+        assert_eq!(find_post_for_slug(&all_posts, "").title, "Fin");
+        assert_eq!(find_post_for_slug(&all_posts, "about").title, "About");
+        assert_eq!(page_of(&all_posts, 1, 10, None).unwrap().0.len(), 2);
+        assert!(group_by_year(&vec![(about, String::from("about"))]).is_empty());
+        assert_eq!(entry(r#""kind": "page""#).kind, Kind::Page);
+    }
+
+    #[test]
+    fn test_tags() {
+        let all_posts = vec![
+            post("Fin", &["types", "haskell"]),
+            Post { hidden: true, ..post("Draft", &["rust"]) },
+            Post { kind: Kind::Page, ..post("About", &["me"]) },
+            post("Scan", &["haskell"]),
+        ];
+        assert_eq!(tags(&all_posts), vec!["haskell", "types"]);
+    }
+
+    #[test]
+    fn test_translations() {
+        let registry = |title: &str, lang: Option<&str>, translations: &[(&str, &str)]| Registry {
+            title: title.to_owned(),
+            markdown: format!("{}.md", to_slug(title)),
+            hidden: false,
+            updated: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: lang.map(str::to_owned),
+            translations: translations.iter().map(|(lang, slug)| (lang.to_string(), slug.to_string())).collect(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+        let all_posts = to_posts(&vec![
+            registry("Zip is scan", None, &[("zh", "zip-is-scan-zh")]),
+            registry("Zip is scan zh", Some("zh"), &[]),
+            registry("Fin", None, &[]),
+        ]);
+        let zh = find_post(&all_posts, "zip-is-scan-zh").unwrap();
+
+        assert_eq!(zh.translations, BTreeMap::from([(String::from("en"), String::from("zip-is-scan"))]));
+        assert_eq!(zh.alternates(), vec![
+            (String::from("en"), String::from("zip-is-scan")),
+            (String::from("zh"), String::from("zip-is-scan-zh")),
+        ]);
+        assert!(find_post(&all_posts, "fin").unwrap().alternates().is_empty());
+
+        let titles = |lang: Option<&str>| page_of(&all_posts, 1, 10, lang).unwrap().0.into_iter().map(|post| post.title).collect::<Vec<_>>();
+        assert_eq!(titles(None), vec![String::from("Fin"), String::from("Zip is scan")]);
+        assert_eq!(titles(Some("zh")), vec![String::from("Zip is scan zh")]);
+        assert!(titles(Some("fr")).is_empty());
+        assert_eq!(languages(&all_posts), vec![String::from("en"), String::from("zh")]);
+
+        assert!(is_lang_tag("zh-Hans"));
+        assert!(!is_lang_tag("zh&x=1"));
+        assert!(!is_lang_tag(""));
+
+        let fin = entry(r#""lang": "en", "translations": { "zh": "fin-zh" }"#);
+        assert_eq!(fin.lang, Some(String::from("en")));
+        assert_eq!(fin.translations, BTreeMap::from([(String::from("zh"), String::from("fin-zh"))]));
+    }
+
+    #[test]
+    fn test_template() {
+        assert_eq!(entry(r#""template": "talks""#).template, Some(String::from("talks")));
+        assert_eq!(to_posts(&vec![entry(r#""template": "talks""#)])[0].template, Some(String::from("talks")));
+    }
+
+    #[test]
+    fn test_enclosure() {
+        let fin = entry(r#""enclosure": { "url": "/static/audio/fin.mp3", "length": 4718592, "mime_type": "audio/mpeg", "duration": "12:34" }"#);
+        assert_eq!(fin.enclosure, Some(Enclosure {
+            url: String::from("/static/audio/fin.mp3"), length: 4718592, mime_type: String::from("audio/mpeg"), duration: Some(String::from("12:34")),
+        }));
+        assert_eq!(entry(r#""enclosure": { "url": "/static/audio/fin.mp3", "length": 1, "mime_type": "audio/mpeg" }"#).enclosure.unwrap().duration, None);
+    }
+
+    #[test]
+    fn test_cover() {
+        assert_eq!(entry(r#""cover": "/static/covers/fin.png""#).cover, Some(String::from("/static/covers/fin.png")));
+    }
+
+    #[test]
+    fn test_group_by_year() {
+        let at = |title: &str, year: i32, month: u32| Post { updated: Utc.ymd(year, month, 1).and_hms(0, 0, 0), ..post(title, &[]) };
+        let posts = vec![
+            (at("Fin", 2018, 11), String::from("fin")),
+            (at("Lens", 2019, 5), String::from("lens")),
+            (Post { hidden: true, ..at("About", 2021, 8) }, String::from("about")),
+            (at("Reducer", 2019, 8), String::from("reducer")),
+        ];
+
+        let years: Vec<(i32, Vec<String>)> = group_by_year(&posts)
+            .into_iter()
+            .map(|ArchiveYear{ year, posts }| (year, posts.into_iter().map(|entry| entry.summary).collect()))
+            .collect();
+
+        assert_eq!(years, vec![
+            (2019, vec![String::from("reducer"), String::from("lens")]),
+            (2018, vec![String::from("fin")]),
+        ]);
+    }
+
+    #[test]
+    fn test_page_of() {
+        let all_posts = vec![
+            post("One", &[]),
+            Post { hidden: true, ..post("Two", &[]) },
+            post("Three", &[]),
+            Post { pinned: true, ..post("Four", &[]) },
+            post("Five", &[]),
+        ];
+        let titles = |page: Option<(Vec<Post>, usize)>| page.map(|(posts, total_pages)|
+            (posts.into_iter().map(|Post{ title, .. }| title).collect::<Vec<_>>(), total_pages));
+
+        assert_eq!(titles(page_of(&all_posts, 1, 3, None)), Some((vec![String::from("Four"), String::from("One"), String::from("Three")], 2)));
+        assert_eq!(titles(page_of(&all_posts, 2, 3, None)), Some((vec![String::from("Five")], 2)));
+        assert_eq!(titles(page_of(&all_posts, 3, 3, None)), None);
+        assert_eq!(titles(page_of(&all_posts, 0, 3, None)), None);
+        assert_eq!(titles(page_of(&vec![], 1, 3, None)), Some((vec![], 1)));
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        assert_eq!(absolute_url("https://hacklewayne.com", "/static/covers/fin.png"), "https://hacklewayne.com/static/covers/fin.png");
+        assert_eq!(absolute_url("https://hacklewayne.com", "https://cdn.example.com/fin.png"), "https://cdn.example.com/fin.png");
+        assert_eq!(absolute_url("https://hacklewayne.com", "//cdn.example.com/fin.png"), "//cdn.example.com/fin.png");
+    }
+
+    #[test]
+    fn test_feed_page() {
+        let at = |title: &str, year: i32| Post { updated: Utc.ymd(year, 1, 1).and_hms(0, 0, 0), ..post(title, &[]) };
+        let all_posts = vec![at("Five", 2021), at("Four", 2020), at("Three", 2019), at("Two", 2018), at("One", 2017)];
+        let page_from = |posts: &[Post], limit: usize, page: Option<usize>| feed_page(posts.to_vec(), limit, page)
+            .map(|FeedPage { posts, links }| (posts.into_iter().map(|post| post.title).collect::<Vec<_>>(), links));
+        let page = |limit: usize, page: Option<usize>| page_from(&all_posts, limit, page);
+        let archive = |page: usize| format!("/rss/page/{}.xml", page);
+
+        assert_eq!(page(0, None).unwrap().0.len(), 5);
+        assert_eq!(page(5, None).unwrap().1, vec![]);
+        assert!(page(5, Some(1)).is_none());
+
+        assert_eq!(page(2, None), Some((vec![String::from("Five"), String::from("Four")], vec![("next", archive(2)), ("prev-archive", archive(2))])));
+        assert_eq!(page(2, Some(1)), Some((vec![String::from("Two"), String::from("One")], vec![
+            ("current", String::from("/rss/index.xml")), ("previous", archive(2)), ("next-archive", archive(2)),
+        ])));
+        assert_eq!(page(2, Some(2)), Some((vec![String::from("Three")], vec![
+            ("current", String::from("/rss/index.xml")), ("next", archive(1)), ("prev-archive", archive(1)),
+        ])));
+        assert!(page(2, Some(3)).is_none());
+
+        let four = &all_posts[1..];
+        assert_eq!(page_from(four, 2, None), Some((vec![String::from("Four"), String::from("Three")], vec![("next", archive(1)), ("prev-archive", archive(1))])));
+        assert_eq!(page_from(four, 2, Some(1)), Some((vec![String::from("Two"), String::from("One")], vec![("current", String::from("/rss/index.xml"))])));
+        assert!(page_from(four, 2, Some(2)).is_none());
+    }
+
+    #[test]
+    fn test_prerender_order() {
+        let at = |title: &str, year: i32| Post { updated: Utc.ymd(year, 1, 1).and_hms(0, 0, 0), ..post(title, &[]) };
+        let all_posts = vec![at("Fin", 2018), Post { hidden: true, ..at("About", 2021) }, at("Lens", 2019), at("Reducer", 2020)];
+        let titles: Vec<String> = prerender_order(&all_posts, 2).into_iter().map(|post| post.title).collect();
+
+        assert_eq!(titles, vec![String::from("Lens"), String::from("Reducer")]);
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn test_embedded() {
+        let embedded = LocalSource { directory: PathBuf::from("missing"), embedded: true };
+        assert!(embedded.get_manifest().is_ok());
+        assert!(embedded.read_content(&String::from("fin.md")).is_ok());
+        assert!(LocalSource::new(PathBuf::from("missing")).get_manifest().is_err());
+    }
+
+    #[test]
+    fn test_changed_posts() {
+        let all_posts = vec![post("One", &[]), post("Two", &[]), post("Three", &[])];
+        let paths = vec![String::from("raw/two.md"), String::from("three.md"), String::from("raw/manifest.json"), String::from("raw/one.md.bak")];
+        let slugs: Vec<String> = changed_posts(&all_posts, &paths).into_iter().map(|post| post.slug).collect();
+
+        assert_eq!(slugs, vec![String::from("two"), String::from("three")]);
+        assert!(is_repository_path("raw/manifest.json", "manifest.json"));
+        assert!(!is_repository_path("raw/old-manifest.json", "manifest.json"));
     }
 
     #[test]
@@ -233,16 +1497,34 @@ mod tests {
                 title: String::from("A few things about unit testing"), 
                 markdown: String::from("presso-pragmatic-unit-testing.md"), 
                 hidden: false, 
-                updated: Utc.ymd(2021, 3, 21).and_hms(1, 23, 45) 
+                updated: Utc.ymd(2021, 3, 21).and_hms(1, 23, 45),
+                tags: vec![],
+                pinned: false,
+                template: None,
+                kind: Kind::Post,
+                lang: None,
+                translations: BTreeMap::new(),
+                short: None,
+                enclosure: None,
+                cover: None,
             },
             Registry { 
                 title: String::from("LINQ, infinity, laziness and oh my!"), 
                 markdown: String::from("linq-tips.md"), 
                 hidden: true, 
-                updated: Utc.ymd(2021, 4, 1).and_hms(1, 23, 45) 
+                updated: Utc.ymd(2021, 4, 1).and_hms(1, 23, 45),
+                tags: vec![],
+                pinned: false,
+                template: None,
+                kind: Kind::Post,
+                lang: None,
+                translations: BTreeMap::new(),
+                short: None,
+                enclosure: None,
+                cover: None,
             },
         ];
-        let posts: Vec<Registry> = serde_json::from_str(&raw).unwrap();
+        let posts: Vec<Registry> = serde_json::from_str(raw).unwrap();
 
         assert_eq!(posts, expected)
     }
@@ -255,4 +1537,56 @@ mod tests {
         let source = load_all_posts_local(&LocalSource::default());
         assert!(source.is_ok())
     }
-}
\ No newline at end of file
+
+    #[rocket::async_test]
+    async fn test_prefetch_keeps_order() {
+        let source = CachedSource::new(None, LocalSource::default(), Duration::from_secs(60));
+        let posts: Vec<Post> = source.all_posts().await.unwrap().into_iter().take(5).collect();
+
+        let contents = source.prefetch(&posts).await;
+
+        assert_eq!(contents.len(), posts.len());
+        for (post, content) in posts.iter().zip(contents) {
+            assert_eq!(content, source.content(post).await);
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryBackend(std::sync::Mutex<std::collections::HashMap<String, String>>);
+
+    #[rocket::async_trait]
+    impl crate::cache_backend::CacheBackend for MemoryBackend {
+        async fn get(&self, key: &str) -> Option<String> {
+            return self.0.lock().unwrap().get(key).cloned();
+        }
+
+        async fn put(&self, key: &str, value: &str, _ttl: Duration) {
+            self.0.lock().unwrap().insert(key.to_owned(), value.to_owned());
+        }
+
+        async fn remove(&self, key: &str) {
+            self.0.lock().unwrap().remove(key);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_shared_backend() {
+        // nothing listens on the remote and there is no local copy, so all of it has to come from the backend
+        let backend = Arc::new(MemoryBackend::default());
+        let source = CachedSource::new(Some(GithubSource::new(&String::from("http://127.0.0.1:9"))), LocalSource::new(PathBuf::from("missing")), Duration::from_secs(60))
+            .with_backend(Some(backend.clone()));
+        backend.0.lock().unwrap().insert(String::from("http://127.0.0.1:9/manifest.json"), serde_json::to_string(&vec![post("Fin", &[])]).unwrap());
+        backend.0.lock().unwrap().insert(String::from("http://127.0.0.1:9/fin.md"), String::from("# Fin"));
+
+        let posts = source.all_posts().await.unwrap();
+        assert_eq!(posts[0].title, "Fin");
+        assert_eq!(source.content(&posts[0]).await, Ok(String::from("# Fin")));
+
+        assert_eq!(source.purge(Some("fin")).await, Ok(1));
+        assert!(!backend.0.lock().unwrap().contains_key("http://127.0.0.1:9/fin.md"));
+
+        let renderer = RenderCache::new(comrak_options().to_owned(), 1).with_backend(Some(backend.clone()));
+        renderer.warm("# Fin").await;
+        assert_eq!(backend.0.lock().unwrap().get(&format!("rendered/{}", renderer.key("# Fin"))), Some(&String::from("<h1>Fin</h1>\n")));
+    }
+}
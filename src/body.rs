@@ -0,0 +1,13 @@
+//! Request bodies read whole up to a limit of the route's own. Taken as a `Vec<u8>` or `String`, a body is capped at
+//! Rocket's `bytes` or `string` limit, 8 KiB unless Rocket.toml raises it for every route at once.
+use rocket::data::{ByteUnit, Data};
+use rocket::http::Status;
+
+/// The body, or `413 Payload Too Large` if it is over `limit`.
+pub async fn read(data: Data<'_>, limit: ByteUnit) -> Result<Vec<u8>, (Status, String)> {
+    let body = data.open(limit).into_bytes().await.map_err(|err| (Status::BadRequest, err.to_string()))?;
+    if !body.is_complete() {
+        return Err((Status::PayloadTooLarge, format!("the body is over {}", limit)));
+    }
+    return Ok(body.into_inner());
+}
@@ -0,0 +1,235 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub age_secs: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Hits over lookups, `None` before the first lookup.
+    pub hit_rate: Option<f64>,
+    /// Only for caches that expire by age.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<CacheEntry>,
+}
+
+/// Lookup counters shared between clones of a cache.
+#[derive(Clone, Default)]
+struct Counters {
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl Counters {
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self, entries: usize, keys: Vec<CacheEntry>) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+
+        return CacheStats {
+            entries,
+            hits,
+            misses,
+            hit_rate: Some(lookups).filter(|lookups| *lookups > 0).map(|lookups| hits as f64 / lookups as f64),
+            keys,
+        };
+    }
+}
+
+/// A small in-memory cache where every entry expires `ttl` after it was inserted.
+/// Expired entries are dropped lazily, when they are next looked up. Clones share their entries.
+#[derive(Clone)]
+pub struct TtlCache<V> {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, V)>>>,
+    counters: Counters,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration) -> TtlCache<V> {
+        return TtlCache { ttl, entries: Arc::new(Mutex::new(HashMap::new())), counters: Counters::default() };
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let value = match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.to_owned()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            },
+            None => None
+        };
+        self.counters.record(value.is_some());
+        return value;
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        self.entries.lock().unwrap().insert(key.to_owned(), (Instant::now(), value));
+    }
+
+    /// How long ago the entry was inserted, if it is still fresh.
+    pub fn age(&self, key: &str) -> Option<Duration> {
+        return self.entries.lock().unwrap()
+            .get(key)
+            .map(|(inserted, _)| inserted.elapsed())
+            .filter(|age| *age < self.ttl);
+    }
+
+    pub fn remove(&self, key: &str) -> bool {
+        return self.entries.lock().unwrap().remove(key).is_some();
+    }
+
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        return count;
+    }
+
+    /// Counts and ages of the entries that haven't expired yet, oldest first.
+    pub fn stats(&self) -> CacheStats {
+        let mut keys: Vec<CacheEntry> = self.entries.lock().unwrap()
+            .iter()
+            .map(|(key, (inserted, _))| (key, inserted.elapsed()))
+            .filter(|(_, age)| *age < self.ttl)
+            .map(|(key, age)| CacheEntry { key: key.to_owned(), age_secs: age.as_secs() })
+            .collect();
+        keys.sort_by_key(|entry| std::cmp::Reverse(entry.age_secs));
+
+        return self.counters.stats(keys.len(), keys);
+    }
+}
+
+/// An in-memory cache holding at most `capacity` entries, evicting the least recently used.
+/// Clones share their entries.
+#[derive(Clone)]
+pub struct LruCache<V> {
+    capacity: usize,
+    entries: Arc<Mutex<LruEntries<V>>>,
+    counters: Counters,
+}
+
+struct LruEntries<V> {
+    values: HashMap<String, V>,
+    /// Keys from least to most recently used.
+    recency: VecDeque<String>,
+}
+
+impl<V: Clone> LruCache<V> {
+    pub fn new(capacity: usize) -> LruCache<V> {
+        return LruCache {
+            capacity,
+            entries: Arc::new(Mutex::new(LruEntries { values: HashMap::new(), recency: VecDeque::new() })),
+            counters: Counters::default(),
+        };
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let LruEntries { values, recency } = &mut *entries;
+
+        let value = values.get(key).map(|value| value.to_owned());
+        self.counters.record(value.is_some());
+        let value = value?;
+        recency.retain(|recent| recent != key);
+        recency.push_back(key.to_owned());
+        return Some(value);
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+        let LruEntries { values, recency } = &mut *entries;
+
+        recency.retain(|recent| recent != key);
+        recency.push_back(key.to_owned());
+        values.insert(key.to_owned(), value);
+
+        while values.len() > self.capacity {
+            match recency.pop_front() {
+                Some(oldest) => values.remove(&oldest),
+                None => break
+            };
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.values.len();
+        entries.values.clear();
+        entries.recency.clear();
+        return count;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        return self.counters.stats(self.entries.lock().unwrap().values.len(), vec![]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_before_and_after_expiry() {
+        let cache = TtlCache::new(Duration::from_millis(20));
+        cache.insert("a", 1);
+
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("b"), None);
+        assert!(cache.age("a").is_some());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.age("a"), None);
+    }
+
+    #[test]
+    fn test_stats_and_purge() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        cache.get("a");
+        cache.get("c");
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses, stats.hit_rate), (2, 1, 1, Some(0.5)));
+
+        assert!(cache.remove("a"));
+        assert!(!cache.remove("a"));
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+
+        cache.insert("c", 3);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("c"), Some(3));
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use std::time::Duration;
+use crate::config::BlogConfig;
+#[cfg(feature = "dynamodb")]
+use crate::store::dynamodb::Table;
+
+/// A cache shared between Lambda instances and outliving them, behind the in-memory caches: the manifest and
+/// markdown keyed by their URL, and rendered posts keyed by their render key. It is best effort; a backend that
+/// fails logs it and reads as a miss, so the blog keeps serving from the source.
+#[rocket::async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Option<String>;
+    async fn put(&self, key: &str, value: &str, ttl: Duration);
+    async fn remove(&self, key: &str);
+    /// Called once the server has stopped and in-flight requests have drained, for a backend that buffers writes
+    /// to persist them. DynamoDB writes through, so has nothing left to do.
+    async fn flush(&self) {}
+}
+
+pub type SharedBackend = Arc<dyn CacheBackend>;
+
+/// The backend `cache_backend` names, `None` for `memory`, which is only the in-memory caches.
+pub async fn from_config(config: &BlogConfig) -> Result<Option<SharedBackend>, String> {
+    return match config.cache_backend.as_str() {
+        "memory" => Ok(None),
+        #[cfg(feature = "dynamodb")]
+        "dynamodb" => {
+            let table = config.dynamodb_table.to_owned().ok_or("cache_backend dynamodb needs dynamodb_table")?;
+            Ok(Some(Arc::new(dynamodb::DynamoBackend(Table::from_env(&table).await))))
+        },
+        other => Err(format!("cache_backend {} isn't compiled in", other))
+    };
+}
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use tracing::warn;
+    use crate::store::dynamodb::Table;
+    use super::CacheBackend;
+
+    /// Items are `{ key: S, value: S, expires_at: N }`, with `key` the partition key. Turn on TTL for `expires_at`
+    /// so DynamoDB deletes expired items; until it gets round to it, they are ignored here.
+    pub struct DynamoBackend(pub Table);
+
+    fn now() -> u64 {
+        return SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    }
+
+    /// The value of an item that hasn't expired.
+    pub fn live_value(item: &HashMap<String, AttributeValue>, now: u64) -> Option<String> {
+        let expires_at: u64 = item.get("expires_at")?.as_n().ok()?.parse().ok()?;
+        return if expires_at > now { item.get("value")?.as_s().ok().cloned() } else { None };
+    }
+
+    #[rocket::async_trait]
+    impl CacheBackend for DynamoBackend {
+        async fn get(&self, key: &str) -> Option<String> {
+            let item = self.0.client.get_item()
+                .table_name(&self.0.name)
+                .key("key", AttributeValue::S(key.to_owned()))
+                .send().await
+                .inspect_err(|err| warn!(%key, error = %err, "dynamodb get failed"))
+                .ok()?
+                .item?;
+            return live_value(&item, now());
+        }
+
+        async fn put(&self, key: &str, value: &str, ttl: Duration) {
+            let _ = self.0.client.put_item()
+                .table_name(&self.0.name)
+                .item("key", AttributeValue::S(key.to_owned()))
+                .item("value", AttributeValue::S(value.to_owned()))
+                .item("expires_at", AttributeValue::N((now() + ttl.as_secs()).to_string()))
+                .send().await
+                .inspect_err(|err| warn!(%key, error = %err, "dynamodb put failed"));
+        }
+
+        async fn remove(&self, key: &str) {
+            let _ = self.0.client.delete_item()
+                .table_name(&self.0.name)
+                .key("key", AttributeValue::S(key.to_owned()))
+                .send().await
+                .inspect_err(|err| warn!(%key, error = %err, "dynamodb delete failed"));
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_live_value() {
+            let item = HashMap::from([
+                (String::from("key"), AttributeValue::S(String::from("https://example.com/manifest.json"))),
+                (String::from("value"), AttributeValue::S(String::from("[]"))),
+                (String::from("expires_at"), AttributeValue::N(String::from("1000"))),
+            ]);
+
+            assert_eq!(live_value(&item, 999), Some(String::from("[]")));
+            assert_eq!(live_value(&item, 1000), None);
+        }
+    }
+}
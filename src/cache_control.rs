@@ -0,0 +1,107 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::Response;
+use crate::config::BlogConfig;
+use crate::prefs::ThemePreference;
+
+/// Pages rendered with a reader's own preferences are theirs alone, not for a CDN to hand to others.
+const PERSONAL_HTML: &str = "private, no-cache";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteClass {
+    Html,
+    Feed,
+    Static,
+    Health,
+}
+
+/// Which `Cache-Control` class a response falls into; anything else (the JSON APIs, errors) is left alone.
+pub fn classify(path: &str, content_type: Option<&ContentType>) -> Option<RouteClass> {
+    if path == "/health" || path == "/ready" {
+        return Some(RouteClass::Health);
+    }
+    if path.starts_with("/static/") || path == "/favicon.ico" {
+        return Some(RouteClass::Static);
+    }
+    if path.starts_with("/rss/") || content_type == Some(&ContentType::XML) {
+        return Some(RouteClass::Feed);
+    }
+    if content_type == Some(&ContentType::HTML) {
+        return Some(RouteClass::Html);
+    }
+    return None;
+}
+
+/// `Cache-Control` values per route class, from `cache_control_html`, `_feed`, `_static` and `_health`.
+/// `s-maxage` is what a CDN in front of Lambda honours, `max-age` is for browsers.
+#[derive(Clone, Debug)]
+pub struct CacheControl {
+    pub html: String,
+    pub feed: String,
+    pub assets: String,
+    pub health: String,
+}
+
+impl CacheControl {
+    pub fn from_config(config: &BlogConfig) -> CacheControl {
+        return CacheControl {
+            html: config.cache_control_html.to_owned(),
+            feed: config.cache_control_feed.to_owned(),
+            assets: config.cache_control_static.to_owned(),
+            health: config.cache_control_health.to_owned(),
+        };
+    }
+
+    pub fn for_class(&self, class: RouteClass) -> &str {
+        return match class {
+            RouteClass::Html => &self.html,
+            RouteClass::Feed => &self.feed,
+            RouteClass::Static => &self.assets,
+            RouteClass::Health => &self.health,
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for CacheControl {
+    fn info(&self) -> Info {
+        return Info { name: "Cache-Control per route class", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let cacheable = response.status() == Status::Ok || response.status() == Status::NotModified;
+        if !cacheable || response.headers().contains("Cache-Control") {
+            return;
+        }
+
+        let class = match classify(request.uri().path().as_str(), response.content_type().as_ref()) {
+            Some(class) => class,
+            None => return
+        };
+        if class == RouteClass::Html {
+            // a page changes with the theme cookie, which a browser's cache must not overlook after a switch
+            response.adjoin_raw_header("Vary", "Cookie");
+            if ThemePreference::of(request).0.is_some() {
+                response.set_raw_header("Cache-Control", PERSONAL_HTML);
+                return;
+            }
+        }
+        response.set_raw_header("Cache-Control", self.for_class(class).to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("/health", Some(&ContentType::Plain)), Some(RouteClass::Health));
+        assert_eq!(classify("/static/styles.css", Some(&ContentType::CSS)), Some(RouteClass::Static));
+        assert_eq!(classify("/favicon.ico", None), Some(RouteClass::Static));
+        assert_eq!(classify("/rss/index.xml", Some(&ContentType::XML)), Some(RouteClass::Feed));
+        assert_eq!(classify("/lens-in-typescript", Some(&ContentType::HTML)), Some(RouteClass::Html));
+        assert_eq!(classify("/api/v1/posts", Some(&ContentType::JSON)), None);
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::Response;
+use tracing::{info, warn};
+use crate::cache_control::{classify, RouteClass};
+use crate::config::BlogConfig;
+
+/// Every page lists posts from the manifest, through the index, the archive, see also or the links to the
+/// previous and next post, so purging `manifest` purges them all.
+pub const MANIFEST_KEY: &str = "manifest";
+pub const FEED_KEY: &str = "feed";
+pub const SEARCH_KEY: &str = "search";
+
+/// The surrogate keys of a page or feed: `feed` for the feed, `search` for the search page,
+/// the slug for a post, and `manifest` for all of them.
+pub fn surrogate_keys(path: &str, class: RouteClass) -> Vec<String> {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let key = match (class, segments.as_slice()) {
+        (RouteClass::Feed, _) => Some(FEED_KEY.to_owned()),
+        (RouteClass::Html, ["search"]) => Some(SEARCH_KEY.to_owned()),
+        (RouteClass::Html, ["archive"]) => None,
+        (RouteClass::Html, [slug]) => Some((*slug).to_owned()),
+        _ => None,
+    };
+
+    return key.into_iter().chain([MANIFEST_KEY.to_owned()]).collect();
+}
+
+/// The keys to purge after the posts behind `slugs` changed, and the manifest with them if `manifest_changed`.
+/// The feed and search read every post, so they go whenever anything does.
+pub fn purge_keys(slugs: &[String], manifest_changed: bool) -> Vec<String> {
+    if !manifest_changed && slugs.is_empty() {
+        return vec![];
+    }
+    let manifest = if manifest_changed { Some(MANIFEST_KEY.to_owned()) } else { None };
+
+    return manifest.into_iter()
+        .chain([FEED_KEY.to_owned(), SEARCH_KEY.to_owned()])
+        .chain(slugs.iter().cloned())
+        .collect();
+}
+
+/// Tags pages and feeds with `Surrogate-Key` for Fastly, and the same as `Cache-Tag` for Cloudflare and Akamai,
+/// so a purge by key drops exactly the responses that changed. Static files and health checks don't change with content.
+pub struct SurrogateKeys;
+
+#[rocket::async_trait]
+impl Fairing for SurrogateKeys {
+    fn info(&self) -> Info {
+        return Info { name: "Surrogate-Key and Cache-Tag", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status() != Status::Ok && response.status() != Status::NotModified {
+            return;
+        }
+
+        let path = request.uri().path();
+        let keys = match classify(path.as_str(), response.content_type().as_ref()) {
+            Some(class @ (RouteClass::Html | RouteClass::Feed)) => surrogate_keys(path.as_str(), class),
+            _ => return
+        };
+        response.set_raw_header("Surrogate-Key", keys.join(" "));
+        response.set_raw_header("Cache-Tag", keys.join(","));
+    }
+}
+
+/// Drops responses from the CDN in front of the blog.
+#[rocket::async_trait]
+pub trait Purger: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn purge(&self, keys: &[String]) -> Result<(), String>;
+}
+
+/// Purges by surrogate key through Fastly's API.
+pub struct FastlyPurger {
+    client: reqwest::Client,
+    service_id: String,
+    api_token: String,
+}
+
+#[rocket::async_trait]
+impl Purger for FastlyPurger {
+    fn name(&self) -> &'static str {
+        return "fastly";
+    }
+
+    async fn purge(&self, keys: &[String]) -> Result<(), String> {
+        let response = self.client.post(format!("https://api.fastly.com/service/{}/purge", self.service_id))
+            .header("Fastly-Key", &self.api_token)
+            .header("Surrogate-Key", keys.join(" "))
+            .send().await
+            .map_err(|err| err.to_string())?;
+
+        return if response.status().is_success() { Ok(()) } else { Err(format!("Fastly responded {}", response.status())) };
+    }
+}
+
+/// The purger `cdn_purge` names, managed so the webhook and the admin purge can reach it. Without one,
+/// the CDN keeps what it has until its `s-maxage` is up.
+#[derive(Clone, Default)]
+pub struct Cdn(Option<Arc<dyn Purger>>);
+
+impl Cdn {
+    pub async fn from_config(config: &BlogConfig) -> Result<Cdn, String> {
+        let purger: Option<Arc<dyn Purger>> = match config.cdn_purge.as_str() {
+            "none" => None,
+            "fastly" => Some(Arc::new(FastlyPurger {
+                client: reqwest::Client::new(),
+                service_id: config.fastly_service_id.to_owned().ok_or("cdn_purge fastly needs fastly_service_id")?,
+                api_token: config.fastly_api_token.to_owned().ok_or("cdn_purge fastly needs fastly_api_token")?,
+            })),
+            #[cfg(feature = "cloudfront")]
+            "cloudfront" => {
+                let distribution_id = config.cloudfront_distribution_id.to_owned().ok_or("cdn_purge cloudfront needs cloudfront_distribution_id")?;
+                Some(Arc::new(cloudfront::CloudFrontPurger::from_env(distribution_id).await))
+            },
+            other => return Err(format!("cdn_purge {} isn't compiled in", other))
+        };
+
+        return Ok(Cdn(purger));
+    }
+
+    /// Purges `keys`, returning them if the CDN took them. A failed purge is logged rather than failing the caller,
+    /// whose own caches are already invalidated.
+    pub async fn purge(&self, keys: &[String]) -> Vec<String> {
+        let purger = match &self.0 {
+            Some(purger) if !keys.is_empty() => purger,
+            _ => return vec![]
+        };
+
+        return match purger.purge(keys).await {
+            Ok(()) => {
+                info!(cdn = purger.name(), ?keys, "cdn purged");
+                keys.to_vec()
+            },
+            Err(err) => {
+                warn!(cdn = purger.name(), ?keys, error = %err, "cdn purge failed");
+                vec![]
+            }
+        };
+    }
+}
+
+#[cfg(feature = "cloudfront")]
+pub mod cloudfront {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use aws_sdk_cloudfront::types::{InvalidationBatch, Paths};
+    use aws_sdk_cloudfront::Client;
+    use super::{Purger, FEED_KEY, MANIFEST_KEY, SEARCH_KEY};
+
+    /// CloudFront has no surrogate keys, so each key stands for the paths it tags; `manifest` tags every page.
+    pub fn invalidation_paths(keys: &[String]) -> Vec<String> {
+        if keys.iter().any(|key| key == MANIFEST_KEY) {
+            return vec![String::from("/*")];
+        }
+
+        let mut paths: Vec<String> = keys.iter()
+            .flat_map(|key| match key.as_str() {
+                FEED_KEY => vec![String::from("/rss/*")],
+                SEARCH_KEY => vec![String::from("/search*")],
+                slug => vec![format!("/{}", slug), format!("/{}.md", slug), format!("/{}.txt", slug)],
+            })
+            .collect();
+        paths.sort();
+        paths.dedup();
+        return paths;
+    }
+
+    /// Invalidates paths of a distribution. Credentials come from the usual AWS environment, the Lambda's role included.
+    pub struct CloudFrontPurger {
+        client: Client,
+        distribution_id: String,
+    }
+
+    impl CloudFrontPurger {
+        pub async fn from_env(distribution_id: String) -> CloudFrontPurger {
+            let config = aws_config::load_from_env().await;
+            return CloudFrontPurger { client: Client::new(&config), distribution_id };
+        }
+    }
+
+    #[rocket::async_trait]
+    impl Purger for CloudFrontPurger {
+        fn name(&self) -> &'static str {
+            return "cloudfront";
+        }
+
+        async fn purge(&self, keys: &[String]) -> Result<(), String> {
+            let paths = invalidation_paths(keys);
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_nanos()).unwrap_or(0);
+            let batch = InvalidationBatch::builder()
+                .paths(Paths::builder().quantity(paths.len() as i32).set_items(Some(paths)).build().map_err(|err| err.to_string())?)
+                .caller_reference(format!("blog-{}", nanos))
+                .build()
+                .map_err(|err| err.to_string())?;
+
+            return self.client.create_invalidation()
+                .distribution_id(&self.distribution_id)
+                .invalidation_batch(batch)
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_invalidation_paths() {
+            assert_eq!(invalidation_paths(&[String::from("feed"), String::from("manifest")]), vec!["/*"]);
+            assert_eq!(
+                invalidation_paths(&[String::from("feed"), String::from("fin")]),
+                vec!["/fin", "/fin.md", "/fin.txt", "/rss/*"]
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surrogate_keys() {
+        assert_eq!(surrogate_keys("/", RouteClass::Html), vec!["manifest"]);
+        assert_eq!(surrogate_keys("/page/2", RouteClass::Html), vec!["manifest"]);
+        assert_eq!(surrogate_keys("/archive", RouteClass::Html), vec!["manifest"]);
+        assert_eq!(surrogate_keys("/search", RouteClass::Html), vec!["search", "manifest"]);
+        assert_eq!(surrogate_keys("/zip-is-scan", RouteClass::Html), vec!["zip-is-scan", "manifest"]);
+        assert_eq!(surrogate_keys("/rss/index.xml", RouteClass::Feed), vec!["feed", "manifest"]);
+    }
+
+    #[test]
+    fn test_purge_keys() {
+        assert!(purge_keys(&[], false).is_empty());
+        assert_eq!(purge_keys(&[String::from("fin")], false), vec!["feed", "search", "fin"]);
+        assert_eq!(purge_keys(&[], true), vec!["manifest", "feed", "search"]);
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+
+/// The blog as a server and as a toolchain over the same content. Without a command it serves,
+/// which is how Lambda runs it.
+#[derive(Debug, Parser)]
+#[command(name = "blog", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand, PartialEq, Eq)]
+pub enum Command {
+    /// Run the server, locally or on Lambda.
+    Serve,
+    /// Check that each blog's local manifest parses, every post it lists exists and every link between posts resolves.
+    Validate,
+    /// Run every startup check and print the checklist.
+    Doctor,
+    /// Ask every site the posts link to for the page linked, and report those gone or moved.
+    CheckLinks,
+    /// Write the whole blog as a static site, with relative links, a sitemap and the static assets.
+    Export {
+        #[arg(long, default_value = "dist")]
+        out: PathBuf,
+    },
+    /// Email each blog's newest posts to its confirmed newsletter subscribers, e.g. weekly from cron.
+    Digest {
+        /// Only the blog serving this host, every blog unless given.
+        #[arg(long)]
+        host: Option<String>,
+        /// How many posts, `digest_posts` unless given.
+        #[arg(long)]
+        count: Option<usize>,
+        /// Only posts updated in the last this many days; without any nothing is sent.
+        #[arg(long)]
+        days: Option<u32>,
+    },
+    /// Add a markdown file and its manifest entry for a new, hidden post.
+    New {
+        title: String,
+        /// Open the new file in $VISUAL or $EDITOR.
+        #[arg(long)]
+        edit: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Cli::parse_from(["bootstrap"]).command, None);
+        assert_eq!(Cli::parse_from(["bootstrap", "export"]).command, Some(Command::Export { out: PathBuf::from("dist") }));
+        assert_eq!(Cli::parse_from(["bootstrap", "new", "Scan is Zip?"]).command, Some(Command::New { title: String::from("Scan is Zip?"), edit: false }));
+        assert_eq!(Cli::parse_from(["bootstrap", "new", "Fin", "--edit"]).command, Some(Command::New { title: String::from("Fin"), edit: true }));
+        assert_eq!(Cli::parse_from(["bootstrap", "digest", "--days", "7"]).command, Some(Command::Digest { host: None, count: None, days: Some(7) }));
+        assert_eq!(Cli::parse_from(["bootstrap", "digest", "--host", "example.com"]).command, Some(Command::Digest { host: Some(String::from("example.com")), count: None, days: None }));
+        assert_eq!(Cli::parse_from(["bootstrap", "check-links"]).command, Some(Command::CheckLinks));
+        assert!(Cli::try_parse_from(["bootstrap", "publish"]).is_err());
+    }
+}
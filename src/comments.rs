@@ -0,0 +1,565 @@
+//! Comments left on posts through the form under them, kept in the store `comment_store` names. A comment waits
+//! as `pending` until it is approved, and only approved ones are shown. The form has a field hidden from people,
+//! `subject`, that bots fill in; a comment that has it is thanked like any other and dropped. New comments are
+//! announced to `comment_notify_email` and `comment_notify_webhook`, and moderated through `/admin/comments`.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use rocket::form::{Form, FromForm};
+use rocket::http::Status;
+use rocket::response::content::Json;
+use rocket::response::Redirect;
+use rocket::{delete, get, post, routes, Route, State};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+use crate::admin::AdminToken;
+use crate::blog::{find_post, Kind};
+use crate::cdn::{purge_keys, Cdn};
+use crate::config::BlogConfig;
+use crate::mail::{Mailer, Message};
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::spam::{SpamFilter, Submission, Visitor};
+use crate::store::{self, Backend, Moderation};
+use crate::tenant::Tenant;
+
+const MAX_NAME_CHARS: usize = 80;
+const MAX_URL_CHARS: usize = 200;
+const MAX_BODY_CHARS: usize = 5000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: String,
+    /// The blog commented on, its `Tenant::id`.
+    #[serde(default)]
+    pub blog: String,
+    /// The post commented on.
+    pub slug: String,
+    pub name: String,
+    /// The commenter's own site, linked from their name.
+    pub url: Option<String>,
+    /// Plain text, escaped where it is shown.
+    pub body: String,
+    pub received: DateTime<Utc>,
+    pub state: Moderation,
+}
+
+impl Comment {
+    /// A new, pending comment, if its name, URL and body are ones this blog keeps.
+    pub fn new(slug: &str, name: &str, url: Option<&str>, body: &str, received: DateTime<Utc>) -> Result<Comment, String> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+            return Err(format!("a name needs 1 to {} characters", MAX_NAME_CHARS));
+        }
+        let url = url.map(str::trim).filter(|url| !url.is_empty());
+        if let Some(url) = url {
+            let valid = url.chars().count() <= MAX_URL_CHARS && Url::parse(url).map(|url| matches!(url.scheme(), "http" | "https")).unwrap_or(false);
+            if !valid {
+                return Err(format!("a website must be an http(s) URL of at most {} characters", MAX_URL_CHARS));
+            }
+        }
+        let body = body.trim().replace("\r\n", "\n");
+        if body.is_empty() || body.chars().count() > MAX_BODY_CHARS {
+            return Err(format!("a comment needs 1 to {} characters", MAX_BODY_CHARS));
+        }
+
+        return Ok(Comment {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            blog: String::new(),
+            slug: slug.to_owned(),
+            name,
+            url: url.map(String::from),
+            body,
+            received,
+            state: Moderation::Pending,
+        });
+    }
+}
+
+/// Where comments are kept, by id.
+#[rocket::async_trait]
+pub trait CommentStore: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<Comment>, String>;
+    async fn put(&self, comment: &Comment) -> Result<(), String>;
+    async fn remove(&self, id: &str) -> Result<(), String>;
+    /// Every comment, for the moderation queue.
+    async fn all(&self) -> Result<Vec<Comment>, String>;
+    /// The approved comments on `slug` on `blog`, in any order, without reading the rest.
+    async fn approved_for(&self, blog: &str, slug: &str) -> Result<Vec<Comment>, String>;
+}
+
+pub type SharedStore = Arc<dyn CommentStore>;
+
+/// The store `comment_store` names, or none when it is `none` and comments are off.
+#[derive(Clone, Default)]
+pub struct Comments(pub Option<SharedStore>);
+
+impl Comments {
+    pub async fn from_config(config: &BlogConfig) -> Result<Comments, String> {
+        let store: Option<SharedStore> = match store::backend("comment", &config.comment_store, config.comment_database.as_deref(), config.comment_table.as_deref()).await? {
+            Backend::None => None,
+            Backend::Memory => Some(Arc::new(MemoryStore::default())),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(path) => Some(Arc::new(sqlite::SqliteStore::open(&path)?)),
+            #[cfg(feature = "dynamodb")]
+            Backend::DynamoDb(table) => Some(Arc::new(dynamodb::DynamoStore(table))),
+        };
+        return Ok(Comments(store));
+    }
+
+    pub fn open(&self) -> bool {
+        return self.0.is_some();
+    }
+
+    /// Approved comments on `slug` on `tenant`, oldest first. A store that fails is logged and shows none.
+    pub async fn approved(&self, tenant: &Tenant, slug: &str) -> Vec<Comment> {
+        let store = match &self.0 {
+            Some(store) => store,
+            None => return vec![]
+        };
+        let mut comments: Vec<Comment> = match store.approved_for(tenant.id(), slug).await {
+            Ok(comments) => comments,
+            Err(err) => {
+                warn!(%slug, error = %err, "cannot read comments");
+                return vec![];
+            }
+        };
+        comments.sort_by_key(|comment| comment.received);
+        return comments;
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore(Mutex<BTreeMap<String, Comment>>);
+
+#[rocket::async_trait]
+impl CommentStore for MemoryStore {
+    async fn get(&self, id: &str) -> Result<Option<Comment>, String> {
+        return Ok(self.0.lock().unwrap().get(id).cloned());
+    }
+
+    async fn put(&self, comment: &Comment) -> Result<(), String> {
+        self.0.lock().unwrap().insert(comment.id.to_owned(), comment.to_owned());
+        return Ok(());
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), String> {
+        self.0.lock().unwrap().remove(id);
+        return Ok(());
+    }
+
+    async fn all(&self) -> Result<Vec<Comment>, String> {
+        return Ok(self.0.lock().unwrap().values().cloned().collect());
+    }
+
+    async fn approved_for(&self, blog: &str, slug: &str) -> Result<Vec<Comment>, String> {
+        return Ok(self.0.lock().unwrap().values()
+            .filter(|comment| comment.blog == blog && comment.slug == slug && comment.state == Moderation::Approved)
+            .cloned()
+            .collect());
+    }
+}
+
+/// The message announcing a new comment, with how to approve it.
+pub fn announcement(comment: &Comment, public_url: &str) -> String {
+    let by = match &comment.url {
+        Some(url) => format!("{} ({})", comment.name, url),
+        None => comment.name.to_owned(),
+    };
+    return format!(
+        "New comment on {}/{} from {}:\n\n{}\n\nApprove it with POST {}/admin/comments/{}/approved, or see every pending one at {}/admin/comments?state=pending",
+        public_url, comment.slug, by, comment.body, public_url, comment.id, public_url,
+    );
+}
+
+/// Tells `comment_notify_email` and `comment_notify_webhook` about new comments, so they needn't be looked for.
+#[derive(Clone, Default)]
+pub struct CommentNotifier {
+    mailer: Mailer,
+    email: Option<String>,
+    webhook: Option<String>,
+    client: reqwest::Client,
+}
+
+impl CommentNotifier {
+    pub fn from_config(config: &BlogConfig, mailer: Mailer) -> CommentNotifier {
+        return CommentNotifier {
+            email: config.comment_notify_email.to_owned().filter(|_| mailer.enabled()),
+            webhook: config.comment_notify_webhook.to_owned(),
+            client: reqwest::Client::new(),
+            mailer,
+        };
+    }
+
+    /// Sends the announcement in the background; one that fails is logged, the comment being kept regardless.
+    pub fn notify(&self, comment: &Comment, public_url: &str) {
+        if self.email.is_none() && self.webhook.is_none() {
+            return;
+        }
+        let (notifier, comment, text) = (self.to_owned(), comment.to_owned(), announcement(comment, public_url));
+        rocket::tokio::spawn(async move {
+            if let Some(to) = &notifier.email {
+                let message = Message { to: to.to_owned(), subject: format!("New comment on {} from {}", comment.slug, comment.name), text: text.to_owned(), html: None };
+                if let Err(err) = notifier.mailer.send(&message).await {
+                    warn!(id = %comment.id, error = %err, "cannot email the new comment");
+                }
+            }
+            if let Some(webhook) = &notifier.webhook {
+                // `text` is what Slack shows, `content` what Discord does
+                let sent = notifier.client.post(webhook)
+                    .json(&json!({ "text": text, "content": text, "comment": comment }))
+                    .send().await
+                    .and_then(|response| response.error_for_status());
+                if let Err(err) = sent {
+                    warn!(id = %comment.id, error = %err, "cannot post the new comment to the webhook");
+                }
+            }
+        });
+    }
+}
+
+#[derive(FromForm)]
+struct CommentForm<'r> {
+    name: &'r str,
+    url: Option<&'r str>,
+    body: &'r str,
+    /// The honeypot, hidden from people.
+    subject: Option<&'r str>,
+}
+
+/// Keeps a comment on a post for moderation and sends the commenter back to the post.
+#[allow(clippy::too_many_arguments)]
+#[post("/comments/<slug>", data = "<form>")]
+async fn receive(slug: &str, form: Form<CommentForm<'_>>, comments: &State<Comments>, notifier: &State<CommentNotifier>, spam: &State<SpamFilter>, visitor: Visitor, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Redirect, (Status, String)> {
+    let store = comments.0.as_ref().ok_or((Status::NotFound, String::from("comments are off")))?;
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?;
+    let post = find_post(&posts, slug).filter(|post| post.kind == Kind::Post).ok_or((Status::NotFound, format!("no post {}", slug)))?;
+    let back = Redirect::to(format!("/{}#comments", post.slug));
+
+    if form.subject.is_some_and(|subject| !subject.is_empty()) {
+        info!(%request_id, slug = %post.slug, "comment caught by the honeypot");
+        return Ok(back);
+    }
+    let mut comment = Comment::new(&post.slug, form.name, form.url, form.body, Utc::now()).map_err(|message| (Status::BadRequest, message))?;
+    comment.blog = tenant.id().to_owned();
+    let submission = Submission {
+        kind: "comment",
+        author: comment.name.to_owned(),
+        author_url: comment.url.to_owned(),
+        content: comment.body.to_owned(),
+        permalink: format!("{}/{}", public_url.0, post.slug),
+        visitor,
+    };
+    if spam.rejects(&submission).await {
+        comment.state = Moderation::Rejected;
+    }
+    store.put(&comment).await.map_err(|err| (Status::BadGateway, err))?;
+    if comment.state == Moderation::Pending {
+        notifier.notify(&comment, &public_url.0);
+    }
+
+    info!(%request_id, id = %comment.id, slug = %post.slug, state = comment.state.as_str(), "comment received");
+    return Ok(back);
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![receive];
+}
+
+fn store(comments: &Comments) -> Result<&SharedStore, (Status, String)> {
+    return comments.0.as_ref().ok_or((Status::NotFound, String::from("comment_store is none")));
+}
+
+/// Every comment, or those in `?state=`, newest first; `?state=pending` is the moderation queue.
+#[get("/comments?<state>")]
+async fn list(_token: AdminToken, state: Option<&str>, comments: &State<Comments>) -> Result<Json<String>, (Status, String)> {
+    let state = state.map(|state| Moderation::parse(state).ok_or((Status::BadRequest, format!("no state {}", state)))).transpose()?;
+    let mut all = store(comments)?.all().await.map_err(|err| (Status::BadGateway, err))?;
+    all.retain(|comment| state.map(|state| comment.state == state).unwrap_or(true));
+    all.sort_by_key(|comment| std::cmp::Reverse(comment.received));
+
+    return Ok(Json(serde_json::to_string(&all).unwrap()));
+}
+
+/// Moves a comment to `pending`, `approved` or `rejected`, purging its post from the CDN.
+#[post("/comments/<id>/<state>")]
+async fn moderate(_token: AdminToken, id: &str, state: &str, comments: &State<Comments>, cdn: &State<Cdn>) -> Result<Json<String>, (Status, String)> {
+    let state = Moderation::parse(state).ok_or((Status::NotFound, format!("no state {}", state)))?;
+    let store = store(comments)?;
+    let mut comment = store.get(id).await.map_err(|err| (Status::BadGateway, err))?.ok_or((Status::NotFound, format!("no comment {}", id)))?;
+    comment.state = state;
+    store.put(&comment).await.map_err(|err| (Status::BadGateway, err))?;
+    info!(%id, state = state.as_str(), slug = %comment.slug, "comment moderated");
+    cdn.purge(&purge_keys(&[comment.slug.to_owned()], false)).await;
+
+    return Ok(Json(serde_json::to_string(&comment).unwrap()));
+}
+
+#[delete("/comments/<id>")]
+async fn remove(_token: AdminToken, id: &str, comments: &State<Comments>, cdn: &State<Cdn>) -> Result<Status, (Status, String)> {
+    let store = store(comments)?;
+    let comment = store.get(id).await.map_err(|err| (Status::BadGateway, err))?.ok_or((Status::NotFound, format!("no comment {}", id)))?;
+    store.remove(id).await.map_err(|err| (Status::BadGateway, err))?;
+    cdn.purge(&purge_keys(&[comment.slug], false)).await;
+
+    return Ok(Status::NoContent);
+}
+
+/// Mounted under `/admin`.
+pub fn admin_routes() -> Vec<Route> {
+    return routes![list, moderate, remove];
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::sync::Mutex;
+    use rusqlite::{params, Connection, OptionalExtension, Row};
+    use crate::store::{self, Moderation};
+    use super::{Comment, CommentStore};
+
+    /// One row a comment in `comments`, indexed by blog, slug and state for a post's approved comments.
+    pub struct SqliteStore(Mutex<Connection>);
+
+    fn comment(row: &Row) -> rusqlite::Result<Comment> {
+        let state: String = row.get(7)?;
+        return Ok(Comment {
+            id: row.get(0)?,
+            blog: row.get(1)?,
+            slug: row.get(2)?,
+            name: row.get(3)?,
+            url: row.get(4)?,
+            body: row.get(5)?,
+            received: row.get(6)?,
+            state: Moderation::parse(&state).unwrap_or(Moderation::Pending),
+        });
+    }
+
+    impl SqliteStore {
+        pub fn open(path: &str) -> Result<SqliteStore, String> {
+            return Ok(SqliteStore(store::sqlite::open(path,
+                "CREATE TABLE IF NOT EXISTS comments (
+                    id TEXT PRIMARY KEY, blog TEXT NOT NULL, slug TEXT NOT NULL, name TEXT NOT NULL, url TEXT, body TEXT NOT NULL, received TEXT NOT NULL, state TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS comments_post ON comments (blog, slug, state);",
+            )?));
+        }
+    }
+
+    #[rocket::async_trait]
+    impl CommentStore for SqliteStore {
+        async fn get(&self, id: &str) -> Result<Option<Comment>, String> {
+            return self.0.lock().unwrap()
+                .query_row("SELECT id, blog, slug, name, url, body, received, state FROM comments WHERE id = ?1", [id], comment)
+                .optional()
+                .map_err(|err| err.to_string());
+        }
+
+        async fn put(&self, comment: &Comment) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute(
+                    "INSERT OR REPLACE INTO comments (id, blog, slug, name, url, body, received, state) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![comment.id, comment.blog, comment.slug, comment.name, comment.url, comment.body, comment.received, comment.state.as_str()],
+                )
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, id: &str) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute("DELETE FROM comments WHERE id = ?1", [id])
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn all(&self) -> Result<Vec<Comment>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT id, blog, slug, name, url, body, received, state FROM comments")
+                .map_err(|err| err.to_string())?;
+            let comments = statement.query_map([], comment).map_err(|err| err.to_string())?;
+            return comments.collect::<rusqlite::Result<Vec<_>>>().map_err(|err| err.to_string());
+        }
+
+        async fn approved_for(&self, blog: &str, slug: &str) -> Result<Vec<Comment>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection
+                .prepare("SELECT id, blog, slug, name, url, body, received, state FROM comments WHERE blog = ?1 AND slug = ?2 AND state = ?3")
+                .map_err(|err| err.to_string())?;
+            let comments = statement.query_map(params![blog, slug, Moderation::Approved.as_str()], comment).map_err(|err| err.to_string())?;
+            return comments.collect::<rusqlite::Result<Vec<_>>>().map_err(|err| err.to_string());
+        }
+    }
+}
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb {
+    use std::collections::HashMap;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use crate::store::dynamodb::Table;
+    use crate::store::Moderation;
+    use crate::tenant::scoped;
+    use super::{Comment, CommentStore};
+
+    /// Items are `{ id: S, comment: S, approved: S }`, with `id` the partition key and the comment as JSON. An
+    /// approved comment's slug, under its blog's id, is in `approved`, the partition key of the global secondary
+    /// index `approved`; the attribute is left off every other comment, so the index only holds what a post shows.
+    pub struct DynamoStore(pub Table);
+
+    const APPROVED_INDEX: &str = "approved";
+
+    fn comment(item: &HashMap<String, AttributeValue>) -> Option<Comment> {
+        return serde_json::from_str(item.get("comment")?.as_s().ok()?).ok();
+    }
+
+    #[rocket::async_trait]
+    impl CommentStore for DynamoStore {
+        async fn get(&self, id: &str) -> Result<Option<Comment>, String> {
+            let item = self.0.client.get_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(id.to_owned()))
+                .send().await
+                .map_err(|err| err.to_string())?
+                .item;
+            return Ok(item.as_ref().and_then(comment));
+        }
+
+        async fn put(&self, comment: &Comment) -> Result<(), String> {
+            let mut item = HashMap::from([
+                (String::from("id"), AttributeValue::S(comment.id.to_owned())),
+                (String::from("comment"), AttributeValue::S(serde_json::to_string(comment).map_err(|err| err.to_string())?)),
+            ]);
+            if comment.state == Moderation::Approved {
+                item.insert(String::from("approved"), AttributeValue::S(scoped(&comment.blog, &comment.slug)));
+            }
+            return self.0.client.put_item()
+                .table_name(&self.0.name)
+                .set_item(Some(item))
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, id: &str) -> Result<(), String> {
+            return self.0.client.delete_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(id.to_owned()))
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn all(&self) -> Result<Vec<Comment>, String> {
+            let mut comments = vec![];
+            let mut start = None;
+            loop {
+                let page = self.0.client.scan()
+                    .table_name(&self.0.name)
+                    .set_exclusive_start_key(start)
+                    .send().await
+                    .map_err(|err| err.to_string())?;
+                comments.extend(page.items().iter().filter_map(comment));
+                start = page.last_evaluated_key().cloned();
+                if start.is_none() {
+                    return Ok(comments);
+                }
+            }
+        }
+
+        async fn approved_for(&self, blog: &str, slug: &str) -> Result<Vec<Comment>, String> {
+            let mut comments = vec![];
+            let mut start = None;
+            loop {
+                let page = self.0.client.query()
+                    .table_name(&self.0.name)
+                    .index_name(APPROVED_INDEX)
+                    .key_condition_expression("approved = :post")
+                    .expression_attribute_values(":post", AttributeValue::S(scoped(blog, slug)))
+                    .set_exclusive_start_key(start)
+                    .send().await
+                    .map_err(|err| err.to_string())?;
+                comments.extend(page.items().iter().filter_map(comment));
+                start = page.last_evaluated_key().cloned();
+                if start.is_none() {
+                    return Ok(comments);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::tenant::TenantConfig;
+
+    #[test]
+    fn test_new_comment() {
+        let received = Utc.ymd(2024, 2, 1).and_hms(0, 0, 0);
+        let comment = Comment::new("zip-is-scan", "  Hackle \n Wayne ", Some(" https://hacklewayne.com "), " Or is it?\r\nIt is. ", received).unwrap();
+        assert_eq!((comment.name.as_str(), comment.url.as_deref(), comment.body.as_str()), ("Hackle Wayne", Some("https://hacklewayne.com"), "Or is it?\nIt is."));
+        assert_eq!(comment.state, Moderation::Pending);
+        assert_eq!(Comment::new("zip-is-scan", "Hackle", Some(""), "Or is it?", received).unwrap().url, None);
+
+        assert!(Comment::new("zip-is-scan", " ", None, "Or is it?", received).is_err());
+        assert!(Comment::new("zip-is-scan", &"a".repeat(81), None, "Or is it?", received).is_err());
+        assert!(Comment::new("zip-is-scan", "Hackle", Some("javascript:alert(1)"), "Or is it?", received).is_err());
+        assert!(Comment::new("zip-is-scan", "Hackle", None, "\n", received).is_err());
+        assert!(Comment::new("zip-is-scan", "Hackle", None, &"a".repeat(5001), received).is_err());
+    }
+
+    #[test]
+    fn test_announcement() {
+        let comment = Comment::new("zip-is-scan", "Hackle", Some("https://example.com"), "Or is it?", Utc.ymd(2024, 2, 1).and_hms(0, 0, 0)).unwrap();
+        let text = announcement(&comment, "https://hacklewayne.com");
+
+        assert!(text.starts_with("New comment on https://hacklewayne.com/zip-is-scan from Hackle (https://example.com):\n\nOr is it?"));
+        assert!(text.contains(&format!("POST https://hacklewayne.com/admin/comments/{}/approved", comment.id)));
+    }
+
+    #[rocket::async_test]
+    async fn test_approved() {
+        let store = Arc::new(MemoryStore::default());
+        let comment = |slug: &str, state: Moderation, day: u32| Comment {
+            state,
+            ..Comment::new(slug, "Hackle", None, &format!("on the {}", day), Utc.ymd(2024, 2, day).and_hms(0, 0, 0)).unwrap()
+        };
+        store.put(&comment("zip-is-scan", Moderation::Approved, 2)).await.unwrap();
+        store.put(&comment("zip-is-scan", Moderation::Approved, 1)).await.unwrap();
+        store.put(&comment("zip-is-scan", Moderation::Pending, 1)).await.unwrap();
+        store.put(&comment("fin", Moderation::Approved, 1)).await.unwrap();
+        store.put(&Comment { blog: String::from("example.com"), ..comment("zip-is-scan", Moderation::Approved, 3) }).await.unwrap();
+        let comments = Comments(Some(store));
+        let only = Tenant::new(TenantConfig::default());
+        let example = Tenant::new(TenantConfig { hosts: vec![String::from("example.com")], ..TenantConfig::default() });
+
+        let bodies: Vec<String> = comments.approved(&only, "zip-is-scan").await.into_iter().map(|comment| comment.body).collect();
+        assert_eq!(bodies, vec![String::from("on the 1"), String::from("on the 2")]);
+        assert_eq!(comments.approved(&example, "zip-is-scan").await.len(), 1);
+        assert!(comments.approved(&only, "no-such-post").await.is_empty());
+        assert!(Comments::default().approved(&only, "zip-is-scan").await.is_empty());
+        assert!(!Comments::default().open());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn test_sqlite_store() {
+        let path = std::env::temp_dir().join(format!("blog-comments-{}.db", std::process::id()));
+        let store = sqlite::SqliteStore::open(path.to_str().unwrap()).unwrap();
+        let comment = Comment::new("zip-is-scan", "Hackle", Some("https://hacklewayne.com"), "Or is it?", Utc.ymd(2024, 2, 1).and_hms(0, 0, 0)).unwrap();
+
+        store.put(&comment).await.unwrap();
+        store.put(&Comment { state: Moderation::Approved, ..comment.to_owned() }).await.unwrap();
+        assert_eq!(store.get(&comment.id).await.unwrap(), Some(Comment { state: Moderation::Approved, ..comment.to_owned() }));
+        assert_eq!(store.all().await.unwrap().len(), 1);
+        assert_eq!(store.approved_for("", "zip-is-scan").await.unwrap(), vec![Comment { state: Moderation::Approved, ..comment.to_owned() }]);
+        assert!(store.approved_for("", "fin").await.unwrap().is_empty());
+        assert!(store.approved_for("example.com", "zip-is-scan").await.unwrap().is_empty());
+        store.put(&Comment { state: Moderation::Rejected, ..comment.to_owned() }).await.unwrap();
+        assert!(store.approved_for("", "zip-is-scan").await.unwrap().is_empty());
+        store.remove(&comment.id).await.unwrap();
+        assert_eq!(store.get(&comment.id).await.unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{Cursor, Write};
+use brotli::enc::BrotliEncoderParams;
+use flate2::write::GzEncoder;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::Response;
+
+/// Bodies smaller than this are not worth the CPU, nor the extra headers.
+const MIN_COMPRESS_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        };
+    }
+}
+
+/// Picks brotli over gzip when the client accepts both; a `q=0` rules an encoding out.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let accepted: Vec<String> = accept_encoding
+        .split(',')
+        .filter_map(|candidate| {
+            let mut parts = candidate.split(';').map(|part| part.trim());
+            let name = parts.next()?.to_lowercase();
+            let refused = parts.any(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+            if refused { None } else { Some(name) }
+        })
+        .collect();
+
+    return [Encoding::Brotli, Encoding::Gzip].into_iter()
+        .find(|encoding| accepted.iter().any(|name| name == encoding.name() || name == "*"));
+}
+
+pub fn compressible(content_type: &ContentType) -> bool {
+    return content_type.top() == "text"
+        || content_type.sub() == "json"
+        || content_type.sub() == "xml"
+        || content_type.sub().as_str().ends_with("+xml")
+        || content_type.sub().as_str().ends_with("+json");
+}
+
+pub fn compress(body: &[u8], encoding: Encoding) -> Result<Vec<u8>, String> {
+    return match encoding {
+        Encoding::Brotli => {
+            let mut compressed: Vec<u8> = vec![];
+            brotli::BrotliCompress(&mut Cursor::new(body), &mut compressed, &BrotliEncoderParams::default())
+                .map_err(|err| err.to_string())?;
+            Ok(compressed)
+        },
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(body).map_err(|err| err.to_string())?;
+            encoder.finish().map_err(|err| err.to_string())
+        }
+    };
+}
+
+/// Compresses HTML, XML, JSON and other text responses according to `Accept-Encoding`.
+/// On Lambda, lambda_web leaves a response alone once it carries a `Content-Encoding`.
+pub struct Compression;
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        return Info { name: "gzip / brotli compression", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let encoding = match request.headers().get_one("Accept-Encoding").and_then(negotiate) {
+            Some(encoding) => encoding,
+            None => return
+        };
+        let compressible = response.content_type().map(|content_type| compressible(&content_type)).unwrap_or(false);
+        if !compressible || response.headers().contains("Content-Encoding") {
+            return;
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(_) => return
+        };
+        let compressed = match compress(&body, encoding) {
+            Ok(compressed) if body.len() >= MIN_COMPRESS_SIZE => compressed,
+            _ => {
+                response.set_sized_body(body.len(), Cursor::new(body));
+                return;
+            }
+        };
+
+        // the same ETag can't name two different byte streams, so it is only weakly valid from here on
+        if let Some(etag) = response.headers().get_one("ETag").filter(|etag| !etag.starts_with("W/")).map(|etag| format!("W/{}", etag)) {
+            response.set_raw_header("ETag", etag);
+        }
+        response.set_raw_header("Content-Encoding", encoding.name());
+        response.adjoin_raw_header("Vary", "Accept-Encoding");
+        response.set_sized_body(compressed.len(), Cursor::new(compressed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("identity"), None);
+    }
+
+    #[test]
+    fn test_compressible() {
+        assert!(compressible(&ContentType::HTML));
+        assert!(compressible(&ContentType::XML));
+        assert!(compressible(&ContentType::JSON));
+        assert!(!compressible(&ContentType::PNG));
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let body = "<p>lens</p>".repeat(100);
+        let compressed = compress(body.as_bytes(), Encoding::Gzip).unwrap();
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+
+        assert!(compressed.len() < body.len());
+        assert_eq!(decompressed, body);
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io::Cursor;
+use chrono::{DateTime, Utc};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Method, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+
+/// A strong ETag derived from the response body.
+pub fn etag_for<B: AsRef<[u8]>>(body: B) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body.as_ref());
+    return format!("\"{:016x}\"", hasher.finish());
+}
+
+pub fn http_date(at: &DateTime<Utc>) -> String {
+    return at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+}
+
+/// Whether a resource last modified at `last_modified` is unchanged since `if_modified_since`;
+/// an unparseable date never counts as a match.
+pub fn unmodified_since(if_modified_since: &str, last_modified: &DateTime<Utc>) -> bool {
+    return DateTime::parse_from_rfc2822(if_modified_since)
+        .map(|since| last_modified.timestamp() <= since.timestamp())
+        .unwrap_or(false);
+}
+
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    return if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == etag);
+}
+
+/// Wraps a responder with an `ETag` header, answering `304 Not Modified` instead
+/// when the request's `If-None-Match` already names it.
+pub struct WithETag<R> {
+    pub etag: String,
+    pub inner: R,
+}
+
+impl<R> WithETag<R> {
+    pub fn new(etag: String, inner: R) -> WithETag<R> {
+        return WithETag { etag, inner };
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for WithETag<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let not_modified = request.headers()
+            .get_one("If-None-Match")
+            .map(|if_none_match| etag_matches(if_none_match, &self.etag))
+            .unwrap_or(false);
+
+        if not_modified {
+            return Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", self.etag)
+                .ok();
+        }
+
+        return Response::build_from(self.inner.respond_to(request)?)
+            .raw_header("ETag", self.etag)
+            .ok();
+    }
+}
+
+/// Whether the request already has what `etag` and `last_modified` describe: `If-None-Match` decides when given,
+/// else `If-Modified-Since`.
+pub fn not_modified(request: &Request<'_>, etag: &str, last_modified: Option<&DateTime<Utc>>) -> bool {
+    return match (request.headers().get_one("If-None-Match"), request.headers().get_one("If-Modified-Since")) {
+        (Some(if_none_match), _) => etag_matches(if_none_match, etag),
+        (None, Some(if_modified_since)) => last_modified
+            .map(|last_modified| unmodified_since(if_modified_since, last_modified))
+            .unwrap_or(false),
+        (None, None) => false
+    };
+}
+
+/// Wraps a responder with both `ETag` and `Last-Modified`, answering `304 Not Modified` instead when the request
+/// already has it, for responses the `ConditionalGet` fairing leaves alone.
+pub struct Validated<R> {
+    pub etag: String,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub inner: R,
+}
+
+impl<R> Validated<R> {
+    pub fn new(etag: String, last_modified: Option<DateTime<Utc>>, inner: R) -> Validated<R> {
+        return Validated { etag, last_modified, inner };
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Validated<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = if not_modified(request, &self.etag, self.last_modified.as_ref()) {
+            Response::build().status(Status::NotModified).finalize()
+        } else {
+            self.inner.respond_to(request)?
+        };
+        response.set_raw_header("ETag", self.etag);
+        if let Some(last_modified) = self.last_modified {
+            response.set_raw_header("Last-Modified", http_date(&last_modified));
+        }
+        return Ok(response);
+    }
+}
+
+/// Adds a `Last-Modified` header, when known, for `ConditionalGet` to check `If-Modified-Since` against.
+pub struct WithLastModified<R> {
+    pub last_modified: Option<DateTime<Utc>>,
+    pub inner: R,
+}
+
+impl<R> WithLastModified<R> {
+    pub fn new(last_modified: Option<DateTime<Utc>>, inner: R) -> WithLastModified<R> {
+        return WithLastModified { last_modified, inner };
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for WithLastModified<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(last_modified) = self.last_modified {
+            response.set_raw_header("Last-Modified", http_date(&last_modified));
+        }
+        return Ok(response);
+    }
+}
+
+/// Gives every successful HTML page an ETag hashed from its body, then answers `304 Not Modified`
+/// when `If-None-Match` names it or, failing that, when `If-Modified-Since` is no older than `Last-Modified`.
+pub struct ConditionalGet;
+
+#[rocket::async_trait]
+impl Fairing for ConditionalGet {
+    fn info(&self) -> Info {
+        return Info { name: "Conditional GET for HTML pages", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let is_get = request.method() == Method::Get || request.method() == Method::Head;
+        if !is_get || response.status() != Status::Ok || response.content_type() != Some(ContentType::HTML) {
+            return;
+        }
+
+        if response.headers().get_one("ETag").is_none() {
+            let body = match response.body_mut().to_bytes().await {
+                Ok(body) => body,
+                Err(_) => return
+            };
+            response.set_raw_header("ETag", etag_for(&body));
+            response.set_sized_body(body.len(), Cursor::new(body));
+        }
+
+        let etag = response.headers().get_one("ETag").map(|etag| etag.to_owned()).unwrap_or_default();
+        let last_modified = response.headers().get_one("Last-Modified")
+            .and_then(|last_modified| DateTime::parse_from_rfc2822(last_modified).ok())
+            .map(|last_modified| last_modified.with_timezone(&Utc));
+
+        if not_modified(request, &etag, last_modified.as_ref()) {
+            response.set_status(Status::NotModified);
+            response.body_mut().take();
+            response.remove_header("Content-Type");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = etag_for("body");
+
+        assert_eq!(etag, etag_for("body"));
+        assert_ne!(etag, etag_for("other body"));
+        assert!(etag_matches(&etag, &etag));
+        assert!(etag_matches(&format!("\"stale\", W/{}", etag), &etag));
+        assert!(etag_matches("*", &etag));
+        assert!(!etag_matches("\"stale\"", &etag));
+    }
+
+    #[test]
+    fn test_unmodified_since() {
+        use chrono::TimeZone;
+        let last_modified = Utc.ymd(2021, 9, 5).and_hms(3, 53, 47);
+
+        assert_eq!(http_date(&last_modified), "Sun, 05 Sep 2021 03:53:47 GMT");
+        assert!(unmodified_since("Sun, 05 Sep 2021 03:53:47 GMT", &last_modified));
+        assert!(unmodified_since("Mon, 06 Sep 2021 00:00:00 GMT", &last_modified));
+        assert!(!unmodified_since("Sat, 04 Sep 2021 00:00:00 GMT", &last_modified));
+        assert!(!unmodified_since("yesterday", &last_modified));
+    }
+}
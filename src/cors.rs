@@ -0,0 +1,97 @@
+use std::io::Cursor;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::request::Request;
+use rocket::response::Response;
+use crate::config::BlogConfig;
+
+/// Whether a path is one other sites may read from the browser: the JSON API, the search index and the feeds.
+pub fn cors_path(path: &str) -> bool {
+    return path.starts_with("/api/") || path == "/search-index.json" || path.starts_with("/rss/");
+}
+
+/// CORS for the public, read-only endpoints. `cors_allowed_origins` is a comma separated list of origins,
+/// or `*` (the default) for any; `cors_allowed_methods` defaults to `GET, HEAD, OPTIONS`.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    pub origins: Vec<String>,
+    pub methods: String,
+}
+
+impl Cors {
+    pub fn from_config(config: &BlogConfig) -> Cors {
+        return Cors {
+            origins: config.cors_allowed_origins.split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_owned())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+            methods: config.cors_allowed_methods.to_owned(),
+        };
+    }
+
+    /// The `Access-Control-Allow-Origin` for a request from `origin`, `None` if it isn't allowed.
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        return if self.origins.iter().any(|allowed| allowed == "*") {
+            Some(String::from("*"))
+        } else {
+            self.origins.iter().find(|allowed| *allowed == origin).cloned()
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        return Info { name: "CORS", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !cors_path(request.uri().path().as_str()) {
+            return;
+        }
+
+        let allowed = request.headers().get_one("Origin").and_then(|origin| self.allow_origin(origin));
+        if allowed.as_deref() != Some("*") {
+            response.adjoin_raw_header("Vary", "Origin");
+        }
+        let allowed = match allowed {
+            Some(allowed) => allowed,
+            None => return
+        };
+
+        response.set_raw_header("Access-Control-Allow-Origin", allowed);
+        response.set_raw_header("Access-Control-Expose-Headers", "ETag, X-Request-Id");
+
+        // there are no OPTIONS routes, so a preflight arrives here as a 404
+        let preflight = request.method() == Method::Options && request.headers().contains("Access-Control-Request-Method");
+        if preflight {
+            response.set_status(Status::NoContent);
+            response.set_sized_body(0, Cursor::new(""));
+            response.remove_header("Content-Type");
+            response.set_raw_header("Access-Control-Allow-Methods", self.methods.to_owned());
+            if let Some(headers) = request.headers().get_one("Access-Control-Request-Headers") {
+                response.set_raw_header("Access-Control-Allow-Headers", headers.to_owned());
+            }
+            response.set_raw_header("Access-Control-Max-Age", "86400");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_origin() {
+        let any = Cors { origins: vec![String::from("*")], methods: String::new() };
+        assert_eq!(any.allow_origin("https://example.com"), Some(String::from("*")));
+
+        let listed = Cors { origins: vec![String::from("https://example.com"), String::from("http://localhost:3000")], methods: String::new() };
+        assert_eq!(listed.allow_origin("http://localhost:3000"), Some(String::from("http://localhost:3000")));
+        assert_eq!(listed.allow_origin("https://evil.example"), None);
+
+        assert!(cors_path("/api/v1/posts"));
+        assert!(cors_path("/rss/index.xml"));
+        assert!(!cors_path("/admin/cache"));
+    }
+}
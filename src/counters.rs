@@ -0,0 +1,300 @@
+//! The counters behind views, reactions and beacons: under a key, e.g. `views`, a count a name, e.g. a post's slug,
+//! kept in all and by day, in the store `<name>_store` names.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use chrono::NaiveDate;
+use crate::store::{self, Backend};
+
+/// A count of a name under a key on a day, e.g. 3 views of `zip-is-scan` on 2021-05-03.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DailyCount {
+    pub day: NaiveDate,
+    pub name: String,
+    pub count: u64,
+}
+
+/// Where counters are kept, by key and name, in all and by day.
+#[rocket::async_trait]
+pub trait CounterStore: Send + Sync {
+    /// Adds one to each of `names` under `key`, in all and on `day`, returning their new counts in all.
+    async fn increment(&self, key: &str, day: NaiveDate, names: &[&str]) -> Result<Vec<u64>, String>;
+    /// The count in all of `name` under `key`, 0 if it was never counted.
+    async fn count(&self, key: &str, name: &str) -> Result<u64, String>;
+    /// Every name counted under `key`, with its count in all.
+    async fn totals(&self, key: &str) -> Result<BTreeMap<String, u64>, String>;
+    /// Every count under `key` on `day` or after.
+    async fn since(&self, key: &str, day: NaiveDate) -> Result<Vec<DailyCount>, String>;
+}
+
+pub type SharedCounters = Arc<dyn CounterStore>;
+
+/// The counters `<name>_store` names, or none when it is `none`.
+pub async fn from_config(name: &str, store: &str, database: Option<&str>, table: Option<&str>) -> Result<Option<SharedCounters>, String> {
+    return Ok(match store::backend(name, store, database, table).await? {
+        Backend::None => None,
+        Backend::Memory => Some(Arc::new(MemoryStore::default())),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite(path) => Some(Arc::new(sqlite::SqliteStore::open(&path)?)),
+        #[cfg(feature = "dynamodb")]
+        Backend::DynamoDb(table) => Some(Arc::new(dynamodb::DynamoStore(table))),
+    });
+}
+
+#[derive(Default)]
+pub struct MemoryStore(Mutex<BTreeMap<(String, String, NaiveDate), u64>>);
+
+impl MemoryStore {
+    fn sum(&self, key: &str, name: &str) -> u64 {
+        return self.0.lock().unwrap().iter()
+            .filter(|((counted, named, _), _)| counted == key && named == name)
+            .map(|(_, count)| count)
+            .sum();
+    }
+}
+
+#[rocket::async_trait]
+impl CounterStore for MemoryStore {
+    async fn increment(&self, key: &str, day: NaiveDate, names: &[&str]) -> Result<Vec<u64>, String> {
+        for name in names {
+            *self.0.lock().unwrap().entry((key.to_owned(), name.to_string(), day)).or_default() += 1;
+        }
+        return Ok(names.iter().map(|name| self.sum(key, name)).collect());
+    }
+
+    async fn count(&self, key: &str, name: &str) -> Result<u64, String> {
+        return Ok(self.sum(key, name));
+    }
+
+    async fn totals(&self, key: &str) -> Result<BTreeMap<String, u64>, String> {
+        let mut totals = BTreeMap::new();
+        for ((_, name, _), count) in self.0.lock().unwrap().iter().filter(|((counted, _, _), _)| counted == key) {
+            *totals.entry(name.to_owned()).or_default() += count;
+        }
+        return Ok(totals);
+    }
+
+    async fn since(&self, key: &str, day: NaiveDate) -> Result<Vec<DailyCount>, String> {
+        return Ok(self.0.lock().unwrap().iter()
+            .filter(|((counted, _, on), _)| counted == key && *on >= day)
+            .map(|((_, name, day), count)| DailyCount { day: *day, name: name.to_owned(), count: *count })
+            .collect());
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use chrono::NaiveDate;
+    use rusqlite::{params, Connection};
+    use crate::store;
+    use super::{CounterStore, DailyCount};
+
+    /// One row a key and name in `counts`, and one a key, day and name in `daily_counts`.
+    pub struct SqliteStore(Mutex<Connection>);
+
+    impl SqliteStore {
+        pub fn open(path: &str) -> Result<SqliteStore, String> {
+            return Ok(SqliteStore(store::sqlite::open(path, concat!(
+                "CREATE TABLE IF NOT EXISTS counts (key TEXT NOT NULL, name TEXT NOT NULL, count INTEGER NOT NULL, PRIMARY KEY (key, name));",
+                "CREATE TABLE IF NOT EXISTS daily_counts (key TEXT NOT NULL, day TEXT NOT NULL, name TEXT NOT NULL, count INTEGER NOT NULL, PRIMARY KEY (key, day, name));",
+            ))?));
+        }
+    }
+
+    #[rocket::async_trait]
+    impl CounterStore for SqliteStore {
+        async fn increment(&self, key: &str, day: NaiveDate, names: &[&str]) -> Result<Vec<u64>, String> {
+            let connection = self.0.lock().unwrap();
+            let day = day.format("%F").to_string();
+            let mut counts = vec![];
+            for name in names {
+                connection.execute(
+                    "INSERT INTO daily_counts (key, day, name, count) VALUES (?1, ?2, ?3, 1)
+                    ON CONFLICT (key, day, name) DO UPDATE SET count = count + 1",
+                    params![key, day, name],
+                ).map_err(|err| err.to_string())?;
+                counts.push(connection
+                    .query_row(
+                        "INSERT INTO counts (key, name, count) VALUES (?1, ?2, 1)
+                        ON CONFLICT (key, name) DO UPDATE SET count = count + 1 RETURNING count",
+                        params![key, name],
+                        |row| row.get(0),
+                    )
+                    .map_err(|err| err.to_string())?);
+            }
+            return Ok(counts);
+        }
+
+        async fn count(&self, key: &str, name: &str) -> Result<u64, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT count FROM counts WHERE key = ?1 AND name = ?2").map_err(|err| err.to_string())?;
+            let mut counts = statement.query_map([key, name], |row| row.get(0)).map_err(|err| err.to_string())?;
+            return counts.next().unwrap_or(Ok(0)).map_err(|err| err.to_string());
+        }
+
+        async fn totals(&self, key: &str) -> Result<BTreeMap<String, u64>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT name, count FROM counts WHERE key = ?1").map_err(|err| err.to_string())?;
+            let counts = statement.query_map([key], |row| Ok((row.get(0)?, row.get(1)?))).map_err(|err| err.to_string())?;
+            return counts.collect::<rusqlite::Result<BTreeMap<_, _>>>().map_err(|err| err.to_string());
+        }
+
+        async fn since(&self, key: &str, day: NaiveDate) -> Result<Vec<DailyCount>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT day, name, count FROM daily_counts WHERE key = ?1 AND day >= ?2").map_err(|err| err.to_string())?;
+            let counts = statement.query_map(params![key, day.format("%F").to_string()], |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?)))
+                .map_err(|err| err.to_string())?;
+            return counts
+                .map(|count| {
+                    let (day, name, count) = count.map_err(|err| err.to_string())?;
+                    let day = NaiveDate::parse_from_str(&day, "%F").map_err(|err| err.to_string())?;
+                    Ok(DailyCount { day, name, count })
+                })
+                .collect();
+        }
+    }
+}
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb {
+    use std::collections::{BTreeMap, HashMap};
+    use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+    use chrono::NaiveDate;
+    use crate::store::dynamodb::Table;
+    use super::{CounterStore, DailyCount};
+
+    /// Items are `{ id: S, counter: S, name: S, day: S, count: N }`, one a key and name and one a key, name and day,
+    /// so none grows with the names counted. The key is `id`, the partition key, and `counter` the sort key:
+    /// `total#<name>` for the count in all, `day#<yyyy-mm-dd>#<name>` for a day's, so either is a query by prefix.
+    pub struct DynamoStore(pub Table);
+
+    const TOTAL: &str = "total#";
+    const DAY: &str = "day#";
+    /// Sort after every `total#...` and `day#...` counter, `$` following `#`.
+    const TOTALS_END: &str = "total$";
+    const DAYS_END: &str = "day$";
+
+    fn count(item: &HashMap<String, AttributeValue>) -> Option<u64> {
+        return item.get("count")?.as_n().ok()?.parse().ok();
+    }
+
+    fn name(item: &HashMap<String, AttributeValue>) -> Option<String> {
+        return item.get("name")?.as_s().ok().cloned();
+    }
+
+    impl DynamoStore {
+        /// Adds one to the counter `counter` of `name` under `key`, returning its new count.
+        async fn add(&self, key: &str, counter: String, name: &str, day: Option<&str>) -> Result<u64, String> {
+            let mut update = self.0.client.update_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(key.to_owned()))
+                .key("counter", AttributeValue::S(counter))
+                .expression_attribute_names("#count", "count")
+                .expression_attribute_names("#name", "name")
+                .expression_attribute_values(":one", AttributeValue::N(String::from("1")))
+                .expression_attribute_values(":name", AttributeValue::S(name.to_owned()))
+                .return_values(ReturnValue::UpdatedNew);
+            update = match day {
+                Some(day) => update
+                    .update_expression("ADD #count :one SET #name = :name, #day = :day")
+                    .expression_attribute_names("#day", "day")
+                    .expression_attribute_values(":day", AttributeValue::S(day.to_owned())),
+                None => update.update_expression("ADD #count :one SET #name = :name"),
+            };
+            let attributes = update.send().await
+                .map_err(|err| err.to_string())?
+                .attributes
+                .unwrap_or_default();
+            return count(&attributes).ok_or_else(|| format!("no count of {} returned", name));
+        }
+
+        /// Every item under `key` whose counter is from `start` to `end`, paged through.
+        async fn query(&self, key: &str, start: &str, end: &str) -> Result<Vec<HashMap<String, AttributeValue>>, String> {
+            return self.0.client.query()
+                .table_name(&self.0.name)
+                .key_condition_expression("id = :key AND #counter BETWEEN :start AND :end")
+                .expression_attribute_names("#counter", "counter")
+                .expression_attribute_values(":key", AttributeValue::S(key.to_owned()))
+                .expression_attribute_values(":start", AttributeValue::S(start.to_owned()))
+                .expression_attribute_values(":end", AttributeValue::S(end.to_owned()))
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<_, _>>().await
+                .map_err(|err| err.to_string());
+        }
+    }
+
+    #[rocket::async_trait]
+    impl CounterStore for DynamoStore {
+        async fn increment(&self, key: &str, day: NaiveDate, names: &[&str]) -> Result<Vec<u64>, String> {
+            let day = day.format("%F").to_string();
+            let mut counts = vec![];
+            for name in names {
+                self.add(key, format!("{}{}#{}", DAY, day, name), name, Some(&day)).await?;
+                counts.push(self.add(key, format!("{}{}", TOTAL, name), name, None).await?);
+            }
+            return Ok(counts);
+        }
+
+        async fn count(&self, key: &str, name: &str) -> Result<u64, String> {
+            let item = self.0.client.get_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(key.to_owned()))
+                .key("counter", AttributeValue::S(format!("{}{}", TOTAL, name)))
+                .send().await
+                .map_err(|err| err.to_string())?
+                .item
+                .unwrap_or_default();
+            return Ok(count(&item).unwrap_or(0));
+        }
+
+        async fn totals(&self, key: &str) -> Result<BTreeMap<String, u64>, String> {
+            let items = self.query(key, TOTAL, TOTALS_END).await?;
+            return Ok(items.iter().filter_map(|item| Some((name(item)?, count(item)?))).collect());
+        }
+
+        async fn since(&self, key: &str, day: NaiveDate) -> Result<Vec<DailyCount>, String> {
+            let items = self.query(key, &format!("{}{}", DAY, day.format("%F")), DAYS_END).await?;
+            return Ok(items.iter()
+                .filter_map(|item| {
+                    let day = NaiveDate::parse_from_str(item.get("day")?.as_s().ok()?, "%F").ok()?;
+                    Some(DailyCount { day, name: name(item)?, count: count(item)? })
+                })
+                .collect());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_store(store: &dyn CounterStore) {
+        let (monday, tuesday) = (NaiveDate::from_ymd(2021, 5, 3), NaiveDate::from_ymd(2021, 5, 4));
+        assert_eq!(store.increment("views", monday, &["zip-is-scan", "fin"]).await.unwrap(), vec![1, 1]);
+        assert_eq!(store.increment("views", tuesday, &["zip-is-scan"]).await.unwrap(), vec![2]);
+        store.increment("reactions", tuesday, &["zip-is-scan"]).await.unwrap();
+
+        assert_eq!(store.count("views", "zip-is-scan").await.unwrap(), 2);
+        assert_eq!(store.count("views", "unseen").await.unwrap(), 0);
+        assert_eq!(store.totals("views").await.unwrap(), BTreeMap::from([(String::from("fin"), 1), (String::from("zip-is-scan"), 2)]));
+        assert_eq!(store.since("views", tuesday).await.unwrap(), vec![DailyCount { day: tuesday, name: String::from("zip-is-scan"), count: 1 }]);
+        assert_eq!(store.since("views", monday).await.unwrap().len(), 3);
+        assert!(store.totals("beacons").await.unwrap().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn test_memory_store() {
+        test_store(&MemoryStore::default()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn test_sqlite_store() {
+        let path = std::env::temp_dir().join(format!("blog-counters-{}.db", std::process::id()));
+        test_store(&sqlite::SqliteStore::open(path.to_str().unwrap()).unwrap()).await;
+        std::fs::remove_file(&path).unwrap();
+    }
+}
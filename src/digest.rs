@@ -0,0 +1,241 @@
+//! The newsletter itself: a blog's newest `digest_posts` posts, rendered through the same markdown pipeline as the
+//! blog into an HTML and a plain-text email, sent to each of its confirmed subscribers with their own unsubscribe
+//! link. Sent for every blog by `blog digest` from cron, or by `POST /admin/digest` from a scheduler that can only make requests.
+use chrono::{DateTime, Duration as Days, Utc};
+use rocket::http::Status;
+use rocket::response::content::{Html, Json};
+use rocket::{get, post, routes, Route, State};
+use serde::Serialize;
+use tracing::{info, warn};
+use crate::admin::AdminToken;
+use crate::blog::{CachedSource, Kind, Post, RenderCache};
+use crate::config::config;
+use crate::mail::{Mailer, Message};
+use crate::newsletter::{unsubscribe_url, Newsletter, Subscriber, SubscriberState};
+use crate::public_url::PublicUrl;
+use crate::shortcodes::escape_html;
+use crate::tenant::Tenant;
+
+/// The newest `count` posts, those updated since `since` if given, newest first.
+pub fn recent(posts: &[Post], count: usize, since: Option<DateTime<Utc>>) -> Vec<Post> {
+    let mut recent: Vec<Post> = posts.iter()
+        .filter(|post| post.is_listed() && post.kind == Kind::Post && since.map(|since| post.updated >= since).unwrap_or(true))
+        .cloned()
+        .collect();
+    recent.sort_by_key(|post| std::cmp::Reverse(post.updated));
+    recent.truncate(count);
+    return recent;
+}
+
+/// Root-relative links and images in rendered posts made absolute, as an email has no page to be relative to.
+pub fn absolutize(html: &str, public_url: &str) -> String {
+    return ["href", "src"].iter().fold(html.to_owned(), |html, attribute| {
+        let root = format!("{}=\"/", attribute);
+        let mut absolute = String::with_capacity(html.len());
+        let mut rest = html.as_str();
+        while let Some(index) = rest.find(&root) {
+            let after = &rest[index + root.len()..];
+            absolute.push_str(&rest[..index]);
+            absolute.push_str(&root[..root.len() - 1]);
+            if !after.starts_with('/') {
+                absolute.push_str(public_url);
+            }
+            absolute.push('/');
+            rest = after;
+        }
+        absolute.push_str(rest);
+        absolute
+    });
+}
+
+/// A digest, before it is addressed to anyone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Digest {
+    /// The slugs of the posts in it.
+    pub posts: Vec<String>,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// The marker each subscriber's unsubscribe link replaces.
+const UNSUBSCRIBE: &str = "{{unsubscribe}}";
+
+impl Digest {
+    /// `posts` with their markdown, each in full under its title.
+    pub fn new(site_title: &str, public_url: &str, posts: &[(Post, String)], renderer: &RenderCache) -> Digest {
+        let mut text = String::new();
+        let mut html = format!("<h1>{}</h1>\n", escape_html(site_title));
+        for (post, markdown) in posts {
+            let url = format!("{}/{}", public_url, post.slug);
+            text.push_str(&format!("{}\n{}\n\n{}\n\n", post.title, url, markdown_to_text::convert(markdown).trim()));
+            html.push_str(&format!(
+                "<article>\n<h2><a href=\"{}\">{}</a></h2>\n{}\n</article>\n<hr>\n",
+                url, escape_html(&post.title), absolutize(&renderer.render(markdown), public_url),
+            ));
+        }
+        text.push_str(&format!("--\nUnsubscribe: {}\n", UNSUBSCRIBE));
+        html.push_str(&format!("<p><a href=\"{}\">Unsubscribe</a></p>\n", UNSUBSCRIBE));
+
+        let titles: Vec<&str> = posts.iter().map(|(post, _)| post.title.as_str()).collect();
+        return Digest {
+            posts: posts.iter().map(|(post, _)| post.slug.to_owned()).collect(),
+            subject: format!("{}: {}", site_title, titles.join(", ")),
+            text,
+            html,
+        };
+    }
+
+    /// The digest for `subscriber`, with their unsubscribe link.
+    pub fn to(&self, subscriber: &Subscriber, public_url: &str) -> Message {
+        let unsubscribe = unsubscribe_url(public_url, subscriber);
+        return Message {
+            to: subscriber.email.to_owned(),
+            subject: self.subject.to_owned(),
+            text: self.text.replace(UNSUBSCRIBE, &unsubscribe),
+            html: Some(self.html.replace(UNSUBSCRIBE, &escape_html(&unsubscribe))),
+        };
+    }
+}
+
+/// What sending a digest did; nothing is sent when no post is recent enough.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct Sent {
+    pub posts: Vec<String>,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+/// The digest of the newest `count` posts, updated in the last `days` if given, or none without such posts.
+pub async fn digest(site_title: &str, public_url: &str, source: &CachedSource, renderer: &RenderCache, count: usize, days: Option<u32>) -> Result<Option<Digest>, String> {
+    let since = days.map(|days| Utc::now() - Days::days(days as i64));
+    let posts = recent(&source.all_posts().await?, count, since);
+    if posts.is_empty() {
+        return Ok(None);
+    }
+    let mut contents = vec![];
+    for (post, content) in posts.iter().zip(source.prefetch(&posts).await) {
+        contents.push((post.to_owned(), content?));
+    }
+    return Ok(Some(Digest::new(site_title, public_url, &contents, renderer)));
+}
+
+/// Sends the blog's digest to each of its confirmed subscribers in turn; one that fails is logged and counted,
+/// and the rest still get theirs.
+#[allow(clippy::too_many_arguments)]
+pub async fn send(tenant: &Tenant, public_url: &str, renderer: &RenderCache, newsletter: &Newsletter, mailer: &Mailer, count: usize, days: Option<u32>) -> Result<Sent, String> {
+    let store = newsletter.0.as_ref().ok_or("newsletter_store is none")?;
+    if !mailer.enabled() {
+        return Err(String::from("mail_provider is none"));
+    }
+    let digest = match self::digest(&tenant.title, public_url, &tenant.source, renderer, count, days).await? {
+        Some(digest) => digest,
+        None => return Ok(Sent::default()),
+    };
+
+    let mut sent = Sent { posts: digest.posts.to_owned(), ..Sent::default() };
+    for subscriber in store.all(tenant.id()).await?.iter().filter(|subscriber| subscriber.state == SubscriberState::Confirmed) {
+        match mailer.send(&digest.to(subscriber, public_url)).await {
+            Ok(()) => sent.sent += 1,
+            Err(err) => {
+                warn!(error = %err, "cannot email the digest");
+                sent.failed += 1;
+            }
+        }
+    }
+    info!(posts = sent.posts.len(), sent = sent.sent, failed = sent.failed, "digest sent");
+    return Ok(sent);
+}
+
+/// The digest as subscribers would see it, unaddressed; `?count=` posts, `digest_posts` unless given, updated in
+/// the last `?days=` if given.
+#[get("/digest?<count>&<days>")]
+async fn preview(_token: AdminToken, count: Option<usize>, days: Option<u32>, tenant: &Tenant, renderer: &State<RenderCache>, public_url: PublicUrl) -> Result<Html<String>, (Status, String)> {
+    let count = count.unwrap_or(config().digest_posts);
+    let digest = digest(&tenant.title, &public_url.0, &tenant.source, renderer, count, days).await.map_err(|err| (Status::ServiceUnavailable, err))?
+        .ok_or((Status::NotFound, String::from("no posts to send")))?;
+    return Ok(Html(digest.html));
+}
+
+/// Sends the digest of the blog asked on, as `blog digest --host` does.
+#[allow(clippy::too_many_arguments)]
+#[post("/digest?<count>&<days>")]
+async fn send_digest(_token: AdminToken, count: Option<usize>, days: Option<u32>, tenant: &Tenant, renderer: &State<RenderCache>, newsletter: &State<Newsletter>, mailer: &State<Mailer>, public_url: PublicUrl) -> Result<Json<String>, (Status, String)> {
+    let count = count.unwrap_or(config().digest_posts);
+    let sent = send(tenant, &public_url.0, renderer, newsletter, mailer, count, days).await.map_err(|err| (Status::ServiceUnavailable, err))?;
+    return Ok(Json(serde_json::to_string(&sent).unwrap()));
+}
+
+/// Mounted under `/admin`.
+pub fn admin_routes() -> Vec<Route> {
+    return routes![preview, send_digest];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use chrono::TimeZone;
+
+    fn post(slug: &str, day: u32) -> Post {
+        return Post {
+            slug: slug.to_owned(),
+            title: slug.to_owned(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2024, 2, day).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+    }
+
+    #[test]
+    fn test_recent() {
+        let posts = vec![post("fin", 1), post("zip-is-scan", 9), Post { hidden: true, ..post("draft", 10) }, Post { kind: Kind::Page, ..post("about", 10) }, post("scan", 5)];
+        let slugs = |posts: Vec<Post>| posts.into_iter().map(|post| post.slug).collect::<Vec<_>>();
+
+        assert_eq!(slugs(recent(&posts, 2, None)), vec!["zip-is-scan", "scan"]);
+        assert_eq!(slugs(recent(&posts, 5, Some(Utc.ymd(2024, 2, 5).and_hms(0, 0, 0)))), vec!["zip-is-scan", "scan"]);
+        assert!(recent(&posts, 5, Some(Utc.ymd(2024, 3, 1).and_hms(0, 0, 0))).is_empty());
+    }
+
+    #[test]
+    fn test_absolutize() {
+        let html = r#"<a href="/fin">Fin</a> <img src="/static/scan.png"> <a href="//cdn.example.com/x">x</a> <a href="https://example.com/">y</a>"#;
+        assert_eq!(
+            absolutize(html, "https://www.hacklewayne.com"),
+            r#"<a href="https://www.hacklewayne.com/fin">Fin</a> <img src="https://www.hacklewayne.com/static/scan.png"> <a href="//cdn.example.com/x">x</a> <a href="https://example.com/">y</a>"#,
+        );
+    }
+
+    #[test]
+    fn test_digest() {
+        let posts = vec![(post("zip-is-scan", 9), String::from("Zip is *scan*, [see](/fin).")), (post("fin", 1), String::from("The end."))];
+        let digest = Digest::new("Hackle's blog", "https://www.hacklewayne.com", &posts, &RenderCache::default());
+        assert_eq!(digest.posts, vec!["zip-is-scan", "fin"]);
+        assert_eq!(digest.subject, "Hackle's blog: zip-is-scan, fin");
+        assert!(digest.text.starts_with("zip-is-scan\nhttps://www.hacklewayne.com/zip-is-scan\n\nZip is scan, see."));
+        assert!(digest.html.contains("<h2><a href=\"https://www.hacklewayne.com/zip-is-scan\">zip-is-scan</a></h2>"));
+        assert!(digest.html.contains("<em>scan</em>, <a href=\"https://www.hacklewayne.com/fin\">see</a>"));
+
+        let subscriber = Subscriber {
+            blog: String::new(),
+            email: String::from("fan@example.com"),
+            token: String::from("abc"),
+            state: SubscriberState::Confirmed,
+            requested: Utc.ymd(2024, 2, 1).and_hms(9, 0, 0),
+            confirmed: None,
+        };
+        let message = digest.to(&subscriber, "https://www.hacklewayne.com");
+        assert_eq!(message.to, "fan@example.com");
+        assert!(message.text.ends_with("Unsubscribe: https://www.hacklewayne.com/unsubscribe?email=fan%40example.com&token=abc\n"));
+        assert!(message.html.unwrap().contains("<a href=\"https://www.hacklewayne.com/unsubscribe?email=fan%40example.com&amp;token=abc\">Unsubscribe</a>"));
+    }
+}
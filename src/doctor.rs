@@ -0,0 +1,303 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use rocket::figment::Figment;
+use tracing::{error, info, warn};
+use crate::blog::{to_slug, LocalSource};
+use crate::config::BlogConfig;
+use crate::engine;
+use crate::health::ComponentState;
+use crate::links::check_links;
+use crate::lint::check_lint;
+use crate::tenant::{Tenant, Tenants};
+use crate::theme::Theme;
+
+const REMOTE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+const TEMPLATES: [&str; 4] = ["main", "index", "archive", "search"];
+const STATIC_FILES: [&str; 2] = ["favicon.ico", "styles.css"];
+
+/// One line of the checklist. A `required` check that is down stops the launch; any other is a warning,
+/// such as the remote source being unreachable while the local copy can still serve.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: ComponentState,
+    pub detail: String,
+    pub required: bool,
+}
+
+impl Check {
+    fn new(name: &str, required: bool, result: Result<String, String>) -> Check {
+        let (status, detail) = match result {
+            Ok(detail) => (ComponentState::Ok, detail),
+            Err(detail) => (ComponentState::Down, detail),
+        };
+        return Check { name: name.to_owned(), status, detail, required };
+    }
+
+    fn disabled(name: &str, detail: &str) -> Check {
+        return Check { name: name.to_owned(), status: ComponentState::Disabled, detail: detail.to_owned(), required: false };
+    }
+
+    pub fn failed(&self) -> bool {
+        return self.required && self.status == ComponentState::Down;
+    }
+
+    fn mark(&self) -> &'static str {
+        return match self.status {
+            ComponentState::Ok => "ok",
+            ComponentState::Disabled => "off",
+            ComponentState::Down if self.required => "FAIL",
+            ComponentState::Down => "warn",
+        };
+    }
+}
+
+/// Where Rocket loads templates from, `templates` unless `template_dir` says otherwise.
+pub fn template_dir(figment: &Figment) -> PathBuf {
+    return figment.extract_inner::<PathBuf>("template_dir").unwrap_or_else(|_| PathBuf::from("templates"));
+}
+
+/// Templates are `<name>.html.<extension>`, as the template engine has it.
+pub fn check_templates(template_dir: &Path, directory: Option<&str>, extension: &str) -> Result<String, String> {
+    let directory = directory.map(|directory| template_dir.join(directory)).unwrap_or_else(|| template_dir.to_owned());
+    let missing: Vec<&str> = TEMPLATES.iter()
+        .filter(|template| !directory.join(format!("{}.html.{}", template, extension)).is_file())
+        .cloned()
+        .collect();
+
+    return if missing.is_empty() {
+        Ok(format!("{} in {}", TEMPLATES.join(", "), directory.display()))
+    } else {
+        Err(format!("{} missing from {}", missing.join(", "), directory.display()))
+    };
+}
+
+/// Every template a post names in the manifest is there, alongside `main`.
+pub fn check_post_templates(local: &LocalSource, template_dir: &Path, directory: Option<&str>, extension: &str) -> Result<String, String> {
+    let directory = directory.map(|directory| template_dir.join(directory)).unwrap_or_else(|| template_dir.to_owned());
+    let mut templates: Vec<String> = local.get_manifest()?.into_iter().filter_map(|registry| registry.template).collect();
+    templates.sort();
+    templates.dedup();
+    if templates.is_empty() {
+        return Ok(String::from("every post renders with main"));
+    }
+
+    let missing: Vec<&str> = templates.iter()
+        .filter(|template| !directory.join(format!("{}.html.{}", template, extension)).is_file())
+        .map(|template| template.as_str())
+        .collect();
+
+    return if missing.is_empty() {
+        Ok(format!("{} in {}", templates.join(", "), directory.display()))
+    } else {
+        Err(format!("{} named in the manifest but missing from {}", missing.join(", "), directory.display()))
+    };
+}
+
+pub fn check_static(directory: &Path) -> Result<String, String> {
+    let missing: Vec<&str> = STATIC_FILES.iter().filter(|file| !directory.join(file).is_file()).cloned().collect();
+
+    return if missing.is_empty() {
+        Ok(format!("{} in {}", STATIC_FILES.join(", "), directory.display()))
+    } else {
+        Err(format!("{} missing from {}", missing.join(", "), directory.display()))
+    };
+}
+
+/// The local manifest parses, every post it lists is there to fall back to, no two titles make the same slug
+/// and every translation is of a post in it.
+pub fn check_manifest(local: &LocalSource) -> Result<String, String> {
+    let manifest = local.get_manifest()?;
+    let missing: Vec<&str> = manifest.iter()
+        .filter(|registry| !local.contains(&registry.markdown))
+        .map(|registry| registry.markdown.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("{} listed but not found in {}", missing.join(", "), local.directory.display()));
+    }
+
+    let mut slugs: Vec<String> = manifest.iter().map(|registry| to_slug(&registry.title)).collect();
+    slugs.sort();
+    let mut duplicates: Vec<&str> = slugs.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0].as_str()).collect();
+    duplicates.dedup();
+    if !duplicates.is_empty() {
+        return Err(format!("more than one post has the slug {}", duplicates.join(", ")));
+    }
+
+    let unknown: Vec<String> = manifest.iter()
+        .flat_map(|registry| registry.translations.values().map(move |slug| (registry, slug)))
+        .filter(|(_, slug)| !slugs.contains(slug))
+        .map(|(registry, slug)| format!("{} of {}", slug, registry.title))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!("no post has the slug of translation {}", unknown.join(", ")));
+    }
+
+    return Ok(format!("{} posts in {}", manifest.len(), local.directory.join("manifest.json").display()));
+}
+
+/// The theme loads and its templates need nothing the blog doesn't give them.
+pub fn check_theme(config: &BlogConfig) -> Result<String, String> {
+    let theme = match Theme::from_config(config)? {
+        Some(theme) => theme,
+        None => return Ok(String::from("none"))
+    };
+    let missing = theme.missing_context();
+
+    return if missing.is_empty() {
+        Ok(format!("{} from {}", theme.manifest.name, theme.directory.display()))
+    } else {
+        Err(format!("{} requires {}, which the blog doesn't give", theme.manifest.name, missing.join(", ")))
+    };
+}
+
+/// Values that pass validation but are likely mistakes.
+pub fn check_settings(config: &BlogConfig) -> Result<String, String> {
+    let mut warnings = vec![];
+    if config.session_secret.as_ref().map(|secret| secret.len() < 32).unwrap_or(false) {
+        warnings.push("session_secret is shorter than 32 characters");
+    }
+    if config.admin_token.as_ref().map(|token| token.len() < 16).unwrap_or(false) {
+        warnings.push("admin_token is shorter than 16 characters");
+    }
+    if config.public_url.starts_with("http://") && !config.trust_proxy_headers {
+        warnings.push("public_url is not https");
+    }
+    if config.remote_markdown_path.is_none() && config.github_webhook_secret.is_some() {
+        warnings.push("github_webhook_secret is set but remote_markdown_path is not");
+    }
+
+    return if warnings.is_empty() { Ok(String::from("no warnings")) } else { Err(warnings.join("; ")) };
+}
+
+/// Lint is only looked for with `markdown_lint`, and only ever warned of.
+fn lint(config: &BlogConfig, tenant: &Tenant, name: &str) -> Check {
+    return if config.markdown_lint { Check::new(name, false, check_lint(tenant.source.local())) } else { Check::disabled(name, "markdown_lint is off") };
+}
+
+async fn check_tenant(config: &BlogConfig, tenant: &Tenant, template_dir: &Path, extension: &str, label: &str) -> Vec<Check> {
+    let name = |check: &str| if label.is_empty() { check.to_owned() } else { format!("{} {}", label, check) };
+    let public_url = tenant.public_url.as_deref().unwrap_or(&config.public_url);
+    let remote = match tenant.source.remote() {
+        Some(remote) => Check::new(&name("remote source"), false, remote.check(REMOTE_CHECK_TIMEOUT).await.map(|_| remote.base_url.to_owned())),
+        None => Check::disabled(&name("remote source"), "remote_markdown_path not set"),
+    };
+
+    return vec![
+        Check::new(&name("templates"), true, check_templates(template_dir, tenant.templates.as_deref(), extension)),
+        Check::new(&name("manifest"), true, check_manifest(tenant.source.local())),
+        Check::new(&name("post templates"), true, check_post_templates(tenant.source.local(), template_dir, tenant.templates.as_deref(), extension)),
+        Check::new(&name("internal links"), false, check_links(tenant.source.local(), public_url)),
+        lint(config, tenant, &name("markdown lint")),
+        remote,
+    ];
+}
+
+/// Everything the blog needs before it can serve: its settings, templates, static files,
+/// a local manifest to fall back to and, if there is one, the remote source.
+pub async fn checklist(config: &BlogConfig, tenants: &Tenants, template_dir: &Path) -> Vec<Check> {
+    let engine = engine::from_config(config);
+    let extension = engine.as_ref().map(|engine| engine.extension()).unwrap_or("hbs");
+    let mut checks = vec![
+        Check::new("configuration", true, Ok(String::from("valid"))),
+        Check::new("settings", false, check_settings(config)),
+        Check::new("template engine", true, engine.as_ref().map(|engine| engine.name().to_owned()).map_err(|message| message.to_owned())),
+        Check::new("static files", true, check_static(Path::new("static"))),
+    ];
+    checks.push(match &config.theme {
+        Some(_) => Check::new("theme", true, check_theme(config)),
+        None => Check::disabled("theme", "theme not set"),
+    });
+    for tenant in &tenants.0 {
+        let label = if tenants.0.len() > 1 { tenant.title.as_str() } else { "" };
+        checks.extend(check_tenant(config, tenant, template_dir, extension, label).await);
+    }
+
+    return checks;
+}
+
+/// The manifest, internal link and lint checks alone, for `validate`, where a dead link fails as it only warns at launch.
+pub fn validate(config: &BlogConfig, tenants: &Tenants) -> Vec<Check> {
+    return tenants.0.iter()
+        .flat_map(|tenant| {
+            let label = if tenants.0.len() > 1 { format!("{} ", tenant.title) } else { String::new() };
+            let public_url = tenant.public_url.as_deref().unwrap_or(&config.public_url);
+            vec![
+                Check::new(&format!("{}manifest", label), true, check_manifest(tenant.source.local())),
+                Check::new(&format!("{}internal links", label), true, check_links(tenant.source.local(), public_url)),
+                lint(config, tenant, &format!("{}markdown lint", label)),
+            ]
+        })
+        .collect();
+}
+
+/// The checklist for `doctor`, which reports invalid configuration as a failed check rather than stopping at it.
+pub async fn diagnose(figment: &Figment) -> Vec<Check> {
+    let config = match BlogConfig::from_figment(figment) {
+        Ok(config) => config,
+        Err(message) => return vec![Check::new("configuration", true, Err(message))]
+    };
+    crate::config::init(config.to_owned());
+
+    return match Tenants::from_config(&config) {
+        Ok(tenants) => checklist(&config, &tenants, &template_dir(figment)).await,
+        Err(message) => vec![Check::new("configuration", true, Err(message))]
+    };
+}
+
+pub fn passed(checks: &[Check]) -> bool {
+    return !checks.iter().any(Check::failed);
+}
+
+pub fn render(checks: &[Check]) -> String {
+    let width = checks.iter().map(|check| check.name.len()).max().unwrap_or_default();
+    return checks.iter()
+        .map(|check| format!("[{:>4}] {:width$}  {}", check.mark(), check.name, check.detail, width = width))
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
+/// Logs the checklist at launch, each check at the level it deserves.
+pub fn log(checks: &[Check]) {
+    for check in checks {
+        if check.failed() {
+            error!(check = %check.name, detail = %check.detail, "startup check failed");
+        } else if check.status == ComponentState::Down {
+            warn!(check = %check.name, detail = %check.detail, "startup check");
+        } else {
+            info!(check = %check.name, detail = %check.detail, "startup check");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks() {
+        assert!(check_templates(Path::new("templates"), None, "hbs").is_ok());
+        assert_eq!(check_templates(Path::new("templates"), Some("missing"), "hbs"), Err(String::from("main, index, archive, search missing from templates/missing")));
+        assert!(check_static(Path::new("static")).is_ok());
+        assert!(check_manifest(&LocalSource::new(PathBuf::from("raw"))).is_ok());
+        assert!(check_manifest(&LocalSource::new(PathBuf::from("missing"))).unwrap_err().starts_with("Cannot read missing/manifest.json"));
+        assert_eq!(check_post_templates(&LocalSource::new(PathBuf::from("raw")), Path::new("templates"), None, "hbs"), Ok(String::from("every post renders with main")));
+
+        let directory = std::env::temp_dir().join(format!("blog-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("manifest.json"), r#"[{ "title": "Talks", "markdown": "talks.md", "updated": "2024-01-25T23:08:00Z", "template": "talks" }]"#).unwrap();
+        assert_eq!(check_post_templates(&LocalSource::new(directory.to_owned()), Path::new("templates"), None, "hbs"), Err(String::from("talks named in the manifest but missing from templates")));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let config = BlogConfig { admin_token: Some(String::from("short")), ..BlogConfig::default() };
+        assert_eq!(check_settings(&config), Err(String::from("admin_token is shorter than 16 characters")));
+
+        let checks = vec![
+            Check::new("templates", true, Ok(String::from("main"))),
+            Check::new("remote source", false, Err(String::from("timed out"))),
+        ];
+        assert!(passed(&checks));
+        assert_eq!(render(&checks), "[  ok] templates      main\n[warn] remote source  timed out");
+        assert!(!passed(&[Check::new("manifest", true, Err(String::from("not found")))]));
+    }
+}
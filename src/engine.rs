@@ -0,0 +1,211 @@
+//! Routes render templates by name through `Template`, which picks the engine by the file's extension; what differs
+//! between engines is what the blog adds to them, so templates written for either get the same helpers.
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rocket::fairing::Fairing;
+use rocket_dyn_templates::{Engines, Template};
+use tracing::warn;
+use crate::config::BlogConfig;
+use crate::theme::{static_fingerprints, Theme};
+use crate::{helpers, partials};
+
+const STATIC_DIR: &str = "static";
+
+pub trait TemplateEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Templates are `<name>.html.<extension>`.
+    fn extension(&self) -> &'static str;
+    /// Called whenever templates load, to register the helpers and whatever else the engine's templates use,
+    /// and the theme's templates over those of the same name.
+    fn customize(&self, engines: &mut Engines, template_dir: &Path, theme: Option<&Theme>);
+}
+
+/// A theme only replaces templates the blog has, as the blog only renders those; others are logged and left out.
+fn replaced(theme: &Theme, extension: &str, exists: impl Fn(&str) -> bool) -> Vec<(String, PathBuf)> {
+    let (replaced, unknown): (Vec<_>, Vec<_>) = theme.templates(extension).into_iter().partition(|(name, _)| exists(name));
+    for (name, path) in unknown {
+        warn!(theme = %theme.manifest.name, template = %name, path = %path.display(), "theme template replaces none of the blog's, left out");
+    }
+    return replaced;
+}
+
+pub struct HandlebarsEngine;
+
+impl TemplateEngine for HandlebarsEngine {
+    fn name(&self) -> &'static str {
+        return "handlebars";
+    }
+
+    fn extension(&self) -> &'static str {
+        return "hbs";
+    }
+
+    fn customize(&self, engines: &mut Engines, template_dir: &Path, theme: Option<&Theme>) {
+        let handlebars = &mut engines.handlebars;
+        helpers::register(handlebars, static_fingerprints(Path::new(STATIC_DIR), theme));
+        partials::register(handlebars, template_dir);
+
+        let theme = match theme {
+            Some(theme) => theme,
+            None => return
+        };
+        partials::register(handlebars, &theme.template_dir());
+        for (name, path) in replaced(theme, self.extension(), |name| handlebars.has_template(name)) {
+            if let Err(err) = handlebars.register_template_file(&name, &path) {
+                warn!(theme = %theme.manifest.name, template = %name, error = %err, "cannot register theme template");
+            }
+        }
+    }
+}
+
+/// The engine `template_engine` names.
+pub fn from_config(config: &BlogConfig) -> Result<Arc<dyn TemplateEngine>, String> {
+    return match config.template_engine.as_str() {
+        "handlebars" => Ok(Arc::new(HandlebarsEngine)),
+        #[cfg(feature = "tera")]
+        "tera" => Ok(Arc::new(tera::TeraEngine)),
+        other => Err(format!("template_engine {} isn't compiled in", other))
+    };
+}
+
+/// The `Template` fairing, customized by `engine` with the templates under `template_dir` and `theme`.
+pub fn fairing(engine: Arc<dyn TemplateEngine>, template_dir: PathBuf, theme: Option<Theme>) -> impl Fairing {
+    return Template::custom(move |engines| engine.customize(engines, &template_dir, theme.as_ref()));
+}
+
+/// Tera has inheritance with `{% extends %}` and `{% include %}` in place of partials, and the helpers as
+/// `{{ updated | format_date(format="%Y") }}`, `{{ summary | truncate(length=120) }}`,
+/// `{{ title | markdown_inline }}`, `{{ asset_url(file="styles.css") }}` and
+/// `{{ asset_tag(file="beacon.js", defer=true) }}`. `truncate` replaces Tera's own, cutting at a word boundary as the
+/// Handlebars helper does.
+#[cfg(feature = "tera")]
+pub mod tera {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use rocket_dyn_templates::Engines;
+    use rocket_dyn_templates::tera::{Error, Filter, Function, Result, Tera, Value};
+    use tracing::warn;
+    use crate::helpers::{asset_tag, asset_url, format_date, markdown_inline, truncate, Fingerprints, DEFAULT_DATE_FORMAT};
+    use crate::theme::{static_fingerprints, Theme};
+    use super::{replaced, TemplateEngine, STATIC_DIR};
+
+    fn string_arg<'a>(value: &'a Value, name: &str) -> Result<&'a str> {
+        return value.as_str().ok_or_else(|| Error::msg(format!("{} needs a string, not {}", name, value)));
+    }
+
+    fn format_date_filter(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+        let format = args.get("format").and_then(Value::as_str).unwrap_or(DEFAULT_DATE_FORMAT);
+        return format_date(string_arg(value, "format_date")?, format).map(Value::String).map_err(Error::msg);
+    }
+
+    fn truncate_filter(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+        let length = args.get("length")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| Error::msg("truncate needs a number of characters as length"))?;
+        return Ok(Value::String(truncate(string_arg(value, "truncate")?, length as usize)));
+    }
+
+    struct MarkdownInline;
+
+    impl Filter for MarkdownInline {
+        fn filter(&self, value: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+            return Ok(Value::String(markdown_inline(string_arg(value, "markdown_inline")?)));
+        }
+
+        fn is_safe(&self) -> bool {
+            return true;
+        }
+    }
+
+    /// Left unescaped, as Tera would escape every `/` of the URL.
+    struct AssetUrl(Fingerprints);
+
+    impl Function for AssetUrl {
+        fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+            let file = args.get("file").ok_or_else(|| Error::msg("asset_url needs a file"))?;
+            return Ok(Value::String(asset_url(&self.0, string_arg(file, "asset_url")?)));
+        }
+
+        fn is_safe(&self) -> bool {
+            return true;
+        }
+    }
+
+    struct AssetTag(Fingerprints);
+
+    impl Function for AssetTag {
+        fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+            let file = args.get("file").ok_or_else(|| Error::msg("asset_tag needs a file"))?;
+            let defer = args.get("defer").and_then(Value::as_bool).unwrap_or(false);
+            return asset_tag(&self.0, string_arg(file, "asset_tag")?, defer).map(Value::String).map_err(Error::msg);
+        }
+
+        fn is_safe(&self) -> bool {
+            return true;
+        }
+    }
+
+    pub fn register(tera: &mut Tera, fingerprints: Fingerprints) {
+        tera.register_filter("format_date", format_date_filter);
+        tera.register_filter("truncate", truncate_filter);
+        tera.register_filter("markdown_inline", MarkdownInline);
+        tera.register_function("asset_url", AssetUrl(fingerprints.clone()));
+        tera.register_function("asset_tag", AssetTag(fingerprints));
+    }
+
+    pub struct TeraEngine;
+
+    impl TemplateEngine for TeraEngine {
+        fn name(&self) -> &'static str {
+            return "tera";
+        }
+
+        fn extension(&self) -> &'static str {
+            return "tera";
+        }
+
+        fn customize(&self, engines: &mut Engines, _: &Path, theme: Option<&Theme>) {
+            let tera = &mut engines.tera;
+            register(tera, static_fingerprints(Path::new(STATIC_DIR), theme));
+
+            let theme = match theme {
+                Some(theme) => theme,
+                None => return
+            };
+            let names: Vec<String> = tera.get_template_names().map(str::to_owned).collect();
+            let templates: Vec<_> = replaced(theme, self.extension(), |name| names.iter().any(|known| known == name))
+                .into_iter()
+                .map(|(name, path)| (path, Some(name)))
+                .collect();
+            if let Err(err) = tera.add_template_files(templates) {
+                warn!(theme = %theme.manifest.name, error = %err, "cannot register theme templates");
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use rocket_dyn_templates::tera::Context;
+
+        #[test]
+        fn test_register() {
+            let mut tera = Tera::default();
+            tera.autoescape_on(vec![""]);
+            register(&mut tera, crate::helpers::asset_fingerprints(Path::new("static")));
+            let mut context = Context::new();
+            context.insert("updated", "2024-01-25T23:08:00Z");
+            context.insert("title", "`Maybe`");
+            context.insert("summary", "Zip is scan, or is it?");
+            let mut render = |template: &str| tera.render_str(template, &context).map_err(|err| err.to_string());
+
+            assert_eq!(render(r#"{{ updated | format_date(format="%Y") }}"#).unwrap(), "2024");
+            assert_eq!(render(r#"{{ updated | format_date }}"#).unwrap(), "25-Jan-2024");
+            assert_eq!(render(r#"{{ summary | truncate(length=8) }}"#).unwrap(), "Zip is…");
+            assert_eq!(render(r#"{{ title | markdown_inline }}"#).unwrap(), "<code>Maybe</code>");
+            assert!(render(r#"{{ asset_url(file="styles.css") }}"#).unwrap().ends_with("/styles.css"));
+            assert!(render(r#"{{ asset_tag(file="styles.css") }}"#).unwrap().contains(" integrity=\"sha384-"));
+            assert!(render(r#"{{ title | format_date }}"#).is_err());
+        }
+    }
+}
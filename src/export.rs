@@ -0,0 +1,198 @@
+use std::path::{Path, PathBuf};
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::response::content::Xml;
+use rocket::{Build, Rocket};
+use crate::assets::Assets;
+use crate::blog::{languages, page_of, tags, CachedSource, Post};
+use crate::config::config;
+use crate::public_url::PublicUrl;
+use crate::service_worker;
+use crate::tenant::Tenants;
+
+/// Where the response for `uri` is written under `out`. Pages go in `index.html` in a directory of their own so
+/// the URLs stay the same; anything with an extension, such as the feed or a post's markdown, keeps its name.
+pub fn output_path(out: &Path, uri: &str) -> PathBuf {
+    let path = uri.split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(out.to_owned(), |path, segment| path.join(segment));
+    return if path.extension().is_some() { path } else { path.join("index.html") };
+}
+
+/// Rewrites the root-relative links of the page at `uri`, e.g. `href="/static/styles.css"`, relative to where it is written,
+/// so the export works from any path on any host. Protocol-relative `//host` links are left alone.
+pub fn relativise(html: &str, uri: &str) -> String {
+    let depth = uri.split('/').filter(|segment| !segment.is_empty()).count();
+    let prefix = if depth == 0 { String::from("./") } else { "../".repeat(depth) };
+
+    return ["href", "src", "action"].iter().fold(html.to_owned(), |html, attribute| {
+        let root = format!("{}=\"/", attribute);
+        let mut relative = String::with_capacity(html.len());
+        let mut rest = html.as_str();
+        while let Some(index) = rest.find(&root) {
+            let after = &rest[index + root.len()..];
+            relative.push_str(&rest[..index]);
+            relative.push_str(&root[..root.len() - 1]);
+            if after.starts_with('/') { relative.push('/') } else { relative.push_str(&prefix) };
+            rest = after;
+        }
+        relative.push_str(rest);
+        relative
+    });
+}
+
+/// A sitemap of the listed pages; hidden posts are left out as they are from the index.
+pub fn sitemap(public_url: &PublicUrl, pages: &[String], posts: &[Post]) -> String {
+    let pages = pages.iter().map(|uri| format!("  <url><loc>{}{}</loc></url>", public_url.0, uri));
+    let posts = posts.iter()
+        .filter(|post| !post.hidden)
+        .map(|post| format!("  <url><loc>{}/{}</loc><lastmod>{}</lastmod></url>", public_url.0, post.slug, post.updated.format("%Y-%m-%d")));
+
+    return format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n{}\n</urlset>\n",
+        pages.chain(posts).collect::<Vec<_>>().join("\n")
+    );
+}
+
+/// The sitemap served at `/sitemap.xml`: the index and each of its pages, the archive, and every listed post.
+pub async fn build_sitemap(source: &CachedSource, public_url: &PublicUrl) -> Result<Option<Xml<String>>, String> {
+    let posts = source.all_posts().await?;
+    let total_pages = page_of(&posts, 1, config().page_size, None).map(|(_, total_pages)| total_pages).unwrap_or(1);
+    let pages: Vec<String> = [String::from("/"), String::from("/archive")].into_iter()
+        .chain((2..=total_pages).map(|page| format!("/page/{}", page)))
+        .collect();
+    return Ok(Some(Xml(sitemap(public_url, &pages, &posts))));
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    std::fs::create_dir_all(path.parent().unwrap()).map_err(|err| format!("Cannot create {}, {}", path.display(), err))?;
+    return std::fs::write(path, contents).map_err(|err| format!("Cannot write {}, {}", path.display(), err));
+}
+
+fn copy_dir(from: &Path, to: &Path, written: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = std::fs::read_dir(from).map_err(|err| format!("Cannot read {}, {}", from.display(), err))?;
+    for entry in entries {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        let target = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target, written)?;
+        } else {
+            write(&target, &std::fs::read(&path).map_err(|err| format!("Cannot read {}, {}", path.display(), err))?)?;
+            written.push(target);
+        }
+    }
+    return Ok(());
+}
+
+/// Renders the first blog through the same routes the server uses, and writes a static site under `out`:
+/// every page of the index, the archive, search, every post and its markdown, the feeds and their archives, the search index,
+/// a sitemap, the service worker, the static assets, as they are and where `asset_url` links to them, and the images
+/// uploaded to the local store.
+pub async fn export(rocket: Rocket<Build>, out: &Path) -> Result<Vec<PathBuf>, String> {
+    let client = Client::untracked(rocket).await.map_err(|err| err.to_string())?;
+    let tenant = &client.rocket().state::<Tenants>().ok_or("tenants are managed")?.0[0];
+    let posts = tenant.source.all_posts().await?;
+
+    let mut pages = vec![String::from("/"), String::from("/archive"), String::from("/search")];
+    let mut written = vec![];
+    for page in 2.. {
+        let uri = format!("/page/{}", page);
+        if client.get(uri.as_str()).dispatch().await.status() == Status::NotFound {
+            break;
+        }
+        pages.push(uri);
+    }
+
+    let mut archives = vec![];
+    for page in 1.. {
+        let uri = format!("/rss/page/{}.xml", page);
+        if client.get(uri.as_str()).dispatch().await.status() == Status::NotFound {
+            break;
+        }
+        archives.push(uri);
+    }
+
+    let post_pages = posts.iter().flat_map(|post| [format!("/{}", post.slug), format!("/{}/plain", post.slug)]);
+    let files = posts.iter().map(|post| format!("/{}.md", post.slug))
+        .chain([String::from("/rss/index.xml"), String::from("/feeds.opml"), String::from("/sitemap.xml"), String::from("/search-index.json"), String::from("/favicon.ico"), String::from("/manifest.webmanifest")])
+        .chain(languages(&posts).into_iter().map(|lang| format!("/rss/{}/index.xml", lang)))
+        .chain(tags(&posts).into_iter().map(|tag| format!("/rss/tags/{}/index.xml", tag)))
+        .chain(archives)
+        .chain(Some(String::from("/sw.js")).filter(|_| service_worker::enabled()))
+        .chain(client.rocket().state::<Assets>().map(Assets::urls).unwrap_or_default());
+
+    for uri in pages.iter().cloned().chain(post_pages) {
+        let response = client.get(uri.as_str()).dispatch().await;
+        if response.status() != Status::Ok {
+            return Err(format!("{} responded {}", uri, response.status()));
+        }
+        let html = relativise(&response.into_string().await.unwrap_or_default(), &uri);
+        let path = output_path(out, &uri);
+        write(&path, html.as_bytes())?;
+        written.push(path);
+    }
+    for uri in files {
+        let response = client.get(uri.as_str()).dispatch().await;
+        if response.status() != Status::Ok {
+            return Err(format!("{} responded {}", uri, response.status()));
+        }
+        let path = output_path(out, &uri);
+        write(&path, &response.into_bytes().await.unwrap_or_default())?;
+        written.push(path);
+    }
+
+    copy_dir(Path::new("static"), &out.join("static"), &mut written)?;
+    let uploads = Path::new(&config().upload_directory);
+    if config().upload_store == "local" && uploads.is_dir() {
+        copy_dir(uploads, &out.join("uploads"), &mut written)?;
+    }
+
+    return Ok(written);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use std::collections::BTreeMap;
+    use crate::blog::Kind;
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(output_path(Path::new("dist"), "/"), PathBuf::from("dist/index.html"));
+        assert_eq!(output_path(Path::new("dist"), "/page/2"), PathBuf::from("dist/page/2/index.html"));
+        assert_eq!(output_path(Path::new("dist"), "/zip-is-scan"), PathBuf::from("dist/zip-is-scan/index.html"));
+        assert_eq!(output_path(Path::new("dist"), "/zip-is-scan.md"), PathBuf::from("dist/zip-is-scan.md"));
+        assert_eq!(output_path(Path::new("dist"), "/rss/index.xml"), PathBuf::from("dist/rss/index.xml"));
+    }
+
+    #[test]
+    fn test_relativise() {
+        let html = r#"<a href="/">home</a><link href="/static/styles.css"><script src="//cdn.example/x.js"></script><a href="https://hacklewayne.com/fin">"#;
+        assert_eq!(relativise(html, "/"), r#"<a href="./">home</a><link href="./static/styles.css"><script src="//cdn.example/x.js"></script><a href="https://hacklewayne.com/fin">"#);
+        assert_eq!(relativise(html, "/page/2"), r#"<a href="../../">home</a><link href="../../static/styles.css"><script src="//cdn.example/x.js"></script><a href="https://hacklewayne.com/fin">"#);
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let post = |slug: &str, hidden: bool| Post {
+            slug: slug.to_owned(), title: slug.to_owned(), path: format!("{}.md", slug), hidden,
+            updated: Utc.ymd(2024, 1, 25).and_hms(23, 8, 0), tags: vec![], pinned: false, template: None, kind: Kind::Post, lang: None, translations: BTreeMap::new(), short: None, enclosure: None, cover: None,
+        };
+        let sitemap = sitemap(&PublicUrl(String::from("https://hacklewayne.com")), &[String::from("/")], &[post("fin", false), post("about", true)]);
+
+        assert!(sitemap.contains("<url><loc>https://hacklewayne.com/</loc></url>"));
+        assert!(sitemap.contains("<url><loc>https://hacklewayne.com/fin</loc><lastmod>2024-01-25</lastmod></url>"));
+        assert!(!sitemap.contains("about"));
+    }
+
+    #[rocket::async_test]
+    async fn test_build_sitemap() {
+        let source = CachedSource::new(None, crate::blog::LocalSource::new(std::path::PathBuf::from("raw")), std::time::Duration::from_secs(60));
+        let Xml(sitemap) = build_sitemap(&source, &PublicUrl(String::from("https://hacklewayne.com"))).await.unwrap().unwrap();
+
+        assert!(sitemap.contains("<url><loc>https://hacklewayne.com/archive</loc></url>"));
+        assert!(sitemap.contains("<url><loc>https://hacklewayne.com/page/2</loc></url>"));
+        assert!(sitemap.contains("<loc>https://hacklewayne.com/scan-is-zip-laziness-and-recursion-strike-again</loc>"));
+    }
+}
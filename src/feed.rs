@@ -0,0 +1,142 @@
+//! The built RSS feeds and sitemap, since feed readers and crawlers poll far more often than posts change. Each is
+//! served as built for `rss_ttl_secs`, then rebuilt only if the manifest hashes differently than when it was last
+//! built, or after a refresh drops them all; either way with an `ETag` and `Last-Modified` to answer a reader that
+//! already has it with a 304.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hasher;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use rocket::response::content::Xml;
+use crate::blog::{CachedSource, Kind, Post};
+use crate::cache::{CacheStats, TtlCache};
+use crate::conditional::{etag_for, Validated};
+use crate::config::BlogConfig;
+
+/// A feed or sitemap as built, with what a conditional request is checked against.
+#[derive(Clone, Debug)]
+pub struct Feed {
+    pub xml: String,
+    pub etag: String,
+    /// When the newest post in the manifest was updated.
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Feed {
+    pub fn new(xml: String, posts: &[Post]) -> Feed {
+        let last_modified = posts.iter().filter(|post| post.kind == Kind::Post).map(|post| post.updated).max();
+        return Feed { etag: etag_for(&xml), xml, last_modified };
+    }
+
+    pub fn respond(self) -> Validated<Xml<String>> {
+        return Validated::new(self.etag, self.last_modified, Xml(self.xml));
+    }
+}
+
+pub fn manifest_hash(posts: &[Post]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(serde_json::to_string(posts).unwrap_or_default().as_bytes());
+    return hasher.finish();
+}
+
+/// Feeds by what they were last built from, a manifest's hash.
+type Built = HashMap<String, (u64, Option<Feed>)>;
+
+/// A blog's feeds by the language and tag they are for, and its sitemap; `None` is a feed that doesn't exist, such as
+/// a tag's without posts, and is cached all the same.
+#[derive(Clone)]
+pub struct FeedCache {
+    fresh: TtlCache<Option<Feed>>,
+    built: Arc<Mutex<Built>>,
+}
+
+impl FeedCache {
+    pub fn new(ttl: Duration) -> FeedCache {
+        return FeedCache { fresh: TtlCache::new(ttl), built: Arc::new(Mutex::new(HashMap::new())) };
+    }
+
+    pub fn from_config(config: &BlogConfig) -> FeedCache {
+        return FeedCache::new(Duration::from_secs(config.rss_ttl_secs));
+    }
+
+    /// The feed under `key`, built with `build` only when it isn't fresh and the manifest changed since it was built.
+    pub async fn get<F, Fut>(&self, key: &str, source: &CachedSource, build: F) -> Result<Option<Feed>, String>
+        where F: FnOnce() -> Fut, Fut: Future<Output = Result<Option<Xml<String>>, String>> {
+        if let Some(feed) = self.fresh.get(key) {
+            return Ok(feed);
+        }
+        let posts = source.all_posts().await?;
+        let hash = manifest_hash(&posts);
+        let unchanged = self.built.lock().unwrap().get(key).filter(|(built_from, _)| *built_from == hash).map(|(_, feed)| feed.to_owned());
+        let feed = match unchanged {
+            Some(feed) => feed,
+            None => {
+                let feed = build().await?.map(|Xml(xml)| Feed::new(xml, &posts));
+                self.built.lock().unwrap().insert(key.to_owned(), (hash, feed.to_owned()));
+                feed
+            }
+        };
+        self.fresh.insert(key, feed.to_owned());
+        return Ok(feed);
+    }
+
+    pub fn purge(&self) -> usize {
+        self.fresh.clear();
+        let mut built = self.built.lock().unwrap();
+        let count = built.len();
+        built.clear();
+        return count;
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.fresh.stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::blog::LocalSource;
+
+    #[rocket::async_test]
+    async fn test_get() {
+        let source = CachedSource::new(None, LocalSource::new(PathBuf::from("raw")), Duration::from_secs(60));
+        let builds = AtomicUsize::new(0);
+        let build = || async {
+            builds.fetch_add(1, Ordering::Relaxed);
+            Ok(Some(Xml(String::from("<rss/>"))))
+        };
+
+        let feeds = FeedCache::new(Duration::from_secs(60));
+        let feed = feeds.get("rss", &source, build).await.unwrap().unwrap();
+        assert_eq!(feed.etag, etag_for("<rss/>"));
+        assert!(feed.last_modified.is_some());
+        feeds.get("rss", &source, build).await.unwrap();
+        assert_eq!(builds.load(Ordering::Relaxed), 1);
+
+        // stale, but the manifest is the same
+        let feeds = FeedCache { fresh: TtlCache::new(Duration::ZERO), ..feeds };
+        feeds.get("rss", &source, build).await.unwrap();
+        assert_eq!(builds.load(Ordering::Relaxed), 1);
+        feeds.get("rss/tags/haskell", &source, build).await.unwrap();
+        assert_eq!(builds.load(Ordering::Relaxed), 2);
+
+        assert_eq!(feeds.purge(), 2);
+        feeds.get("rss", &source, build).await.unwrap();
+        assert_eq!(builds.load(Ordering::Relaxed), 3);
+    }
+
+    #[rocket::async_test]
+    async fn test_manifest_hash() {
+        let source = CachedSource::new(None, LocalSource::new(PathBuf::from("raw")), Duration::from_secs(60));
+        let mut posts = source.all_posts().await.unwrap();
+        let hash = manifest_hash(&posts);
+        assert_eq!(hash, manifest_hash(&posts));
+        posts[0].title.push('!');
+        assert_ne!(hash, manifest_hash(&posts));
+    }
+}
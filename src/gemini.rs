@@ -0,0 +1,316 @@
+//! The posts over Gemini, for readers on the smolnet: with the `gemini` feature and `gemini_listen`, `gemini://<host>/`
+//! lists the posts and `gemini://<host>/<slug>` is one as gemtext, from the same sources as the web. Gemtext has
+//! headings, quotes, list items, preformatted blocks and links each on a line of their own, so a paragraph's links
+//! follow it.
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
+use comrak::{parse_document, Arena};
+use comrak::nodes::{AstNode, NodeValue};
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::time::timeout;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+use crate::blog::{comrak_options, find_post, CachedSource, Post};
+use crate::config::BlogConfig;
+use crate::tenant::Tenants;
+
+/// A request is a URL of at most 1024 bytes and CRLF.
+const MAX_REQUEST: usize = 1026;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The blog, or one of its tenants, as a capsule.
+#[derive(Clone)]
+pub struct Capsule {
+    pub hosts: Vec<String>,
+    pub title: String,
+    pub description: String,
+    pub public_url: String,
+    pub source: CachedSource,
+}
+
+impl Capsule {
+    pub fn all(config: &BlogConfig, tenants: &Tenants) -> Vec<Capsule> {
+        return tenants.0.iter()
+            .map(|tenant| Capsule {
+                hosts: tenant.hosts.to_owned(),
+                title: tenant.title.to_owned(),
+                description: tenant.description.to_owned(),
+                public_url: tenant.public_url.to_owned().unwrap_or_else(|| config.public_url.to_owned()),
+                source: tenant.source.clone(),
+            })
+            .collect();
+    }
+}
+
+/// The text of inline nodes, with the links among them.
+fn inlines<'a>(node: &'a AstNode<'a>, links: &mut Vec<(String, String)>) -> String {
+    return node.children()
+        .map(|child| match &child.data.borrow().value {
+            NodeValue::Text(text) => String::from_utf8_lossy(text).into_owned(),
+            NodeValue::Code(code) => String::from_utf8_lossy(&code.literal).into_owned(),
+            NodeValue::SoftBreak | NodeValue::LineBreak => String::from(" "),
+            NodeValue::Link(link) => {
+                let text = inlines(child, links);
+                links.push((String::from_utf8_lossy(&link.url).into_owned(), text.to_owned()));
+                text
+            },
+            NodeValue::Image(image) => {
+                let alt = inlines(child, &mut vec![]);
+                links.push((String::from_utf8_lossy(&image.url).into_owned(), if alt.is_empty() { String::from("image") } else { alt.to_owned() }));
+                alt
+            },
+            NodeValue::HtmlInline(_) => String::new(),
+            _ => inlines(child, links),
+        })
+        .collect();
+}
+
+/// A line of text, then a line for each of its links.
+fn with_links(line: String, links: Vec<(String, String)>) -> String {
+    return std::iter::once(line)
+        .chain(links.into_iter().map(|(url, text)| format!("=> {} {}", url, text)))
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
+fn blocks<'a>(node: &'a AstNode<'a>) -> Vec<String> {
+    return node.children().filter_map(block).collect();
+}
+
+fn block<'a>(node: &'a AstNode<'a>) -> Option<String> {
+    let mut links = vec![];
+    return match &node.data.borrow().value {
+        NodeValue::Paragraph => {
+            let text = inlines(node, &mut links);
+            Some(with_links(text, links))
+        },
+        NodeValue::Heading(heading) => {
+            let text = inlines(node, &mut links);
+            Some(with_links(format!("{} {}", "#".repeat(heading.level.clamp(1, 3) as usize), text), links))
+        },
+        NodeValue::CodeBlock(code_block) => Some(format!(
+            "```{}\n{}\n```",
+            String::from_utf8_lossy(&code_block.info).trim(),
+            String::from_utf8_lossy(&code_block.literal).trim_end(),
+        )),
+        NodeValue::BlockQuote => Some(
+            blocks(node).join("\n>\n").lines()
+                .map(|line| if line.starts_with("=> ") || line.starts_with('>') { line.to_owned() } else { format!("> {}", line) })
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        NodeValue::List(_) => Some(
+            node.children()
+                .map(|item| {
+                    let lines = blocks(item).join("\n");
+                    let (text, links): (Vec<&str>, Vec<&str>) = lines.lines().partition(|line| !line.starts_with("=> "));
+                    std::iter::once(format!("* {}", text.join(" "))).chain(links.into_iter().map(String::from)).collect::<Vec<_>>().join("\n")
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        NodeValue::Table(_) => Some(format!(
+            "```\n{}\n```",
+            node.children()
+                .map(|row| row.children().map(|cell| inlines(cell, &mut vec![])).collect::<Vec<_>>().join(" | "))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )),
+        NodeValue::ThematicBreak | NodeValue::HtmlBlock(_) | NodeValue::FrontMatter(_) => None,
+        _ => Some(blocks(node).join("\n\n")).filter(|text| !text.is_empty()),
+    };
+}
+
+/// Markdown as gemtext.
+pub fn gemtext(markdown: &str) -> String {
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, comrak_options());
+    let mut text = blocks(root).join("\n\n");
+    text.push('\n');
+    return text;
+}
+
+/// The listed posts, newest first, linked by slug.
+pub fn index(capsule: &Capsule, posts: &[Post]) -> String {
+    let mut listed: Vec<&Post> = posts.iter().filter(|post| post.is_listed()).collect();
+    listed.sort_by_key(|post| std::cmp::Reverse(post.updated));
+    let links: Vec<String> = listed.iter()
+        .map(|post| format!("=> /{} {} {}", post.slug, post.updated.format("%Y-%m-%d"), post.title))
+        .collect();
+    return format!("# {}\n\n{}\n\n=> {} On the web\n\n## Posts\n\n{}\n", capsule.title, capsule.description, capsule.public_url, links.join("\n"));
+}
+
+pub fn page(capsule: &Capsule, post: &Post, markdown: &str) -> String {
+    return format!(
+        "# {}\n\n{}\n=> / {}\n=> {}/{} On the web\n",
+        post.title, gemtext(markdown), capsule.title, capsule.public_url, post.slug,
+    );
+}
+
+/// The host and path of a request line, `gemini://<host>[:port]/<path>[?query]`, or none for anything else.
+pub fn parse_request(line: &str) -> Option<(String, String)> {
+    let rest = line.strip_suffix("\r\n")?.strip_prefix("gemini://")?;
+    let (authority, path) = rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+    let host = authority.rsplit_once(':').map(|(host, _)| host).unwrap_or(authority);
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    return if host.is_empty() { None } else { Some((host.to_ascii_lowercase(), path.to_owned())) };
+}
+
+/// The whole response to a request line: a header, and a body if it succeeded.
+pub async fn respond(capsules: &[Capsule], line: &str) -> String {
+    let (host, path) = match parse_request(line) {
+        Some(request) => request,
+        None => return String::from("59 Not a Gemini request\r\n"),
+    };
+    let capsule = capsules.iter().find(|capsule| capsule.hosts.contains(&host)).unwrap_or(&capsules[0]);
+    let posts = match capsule.source.all_posts().await {
+        Ok(posts) => posts,
+        Err(err) => {
+            warn!(error = %err, "cannot list posts for Gemini");
+            return String::from("41 Posts are unavailable\r\n");
+        }
+    };
+
+    let slug = path.trim_matches('/');
+    if slug.is_empty() {
+        return format!("20 text/gemini\r\n{}", index(capsule, &posts));
+    }
+    let post = match find_post(&posts, slug) {
+        Some(post) => post,
+        None => return String::from("51 Not found\r\n"),
+    };
+    return match capsule.source.content(&post).await {
+        Ok(markdown) => format!("20 text/gemini; lang={}\r\n{}", post.lang(), page(capsule, &post, &markdown)),
+        Err(err) => {
+            warn!(slug = %post.slug, error = %err, "cannot read the post for Gemini");
+            String::from("41 The post is unavailable\r\n")
+        }
+    };
+}
+
+/// TLS with the certificate chain and private key in the PEM files `cert` and `key`.
+pub fn acceptor(cert: &str, key: &str) -> Result<TlsAcceptor, String> {
+    let open = |path: &str| File::open(path).map(BufReader::new).map_err(|err| format!("Cannot read {}, {}", path, err));
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Cannot parse {}, {}", cert, err))?;
+    let private_key = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|err| format!("Cannot parse {}, {}", key, err))?
+        .ok_or_else(|| format!("No private key in {}", key))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, private_key))
+        .map_err(|err| format!("Cannot use {} and {}, {}", cert, key, err))?;
+    return Ok(TlsAcceptor::from(Arc::new(config)));
+}
+
+async fn handle(acceptor: TlsAcceptor, stream: TcpStream, capsules: &[Capsule]) -> Result<(), String> {
+    let mut stream = timeout(REQUEST_TIMEOUT, acceptor.accept(stream)).await
+        .map_err(|_| String::from("TLS handshake timed out"))?
+        .map_err(|err| format!("TLS handshake failed, {}", err))?;
+
+    let mut request = Vec::with_capacity(MAX_REQUEST);
+    let mut buffer = [0; MAX_REQUEST];
+    while !request.ends_with(b"\r\n") && request.len() < MAX_REQUEST {
+        let read = timeout(REQUEST_TIMEOUT, stream.read(&mut buffer[..MAX_REQUEST - request.len()])).await
+            .map_err(|_| String::from("request timed out"))?
+            .map_err(|err| format!("Cannot read the request, {}", err))?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = respond(capsules, &String::from_utf8_lossy(&request)).await;
+    stream.write_all(response.as_bytes()).await.map_err(|err| format!("Cannot write the response, {}", err))?;
+    return stream.shutdown().await.map_err(|err| format!("Cannot close the connection, {}", err));
+}
+
+/// Accepts Gemini requests at `listen` until the process exits.
+pub async fn serve(listen: String, acceptor: TlsAcceptor, capsules: Vec<Capsule>) {
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(%listen, error = %err, "cannot listen for Gemini");
+            return;
+        }
+    };
+    info!(%listen, "serving Gemini");
+    let capsules = Arc::new(capsules);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(error = %err, "cannot accept a Gemini connection");
+                continue;
+            }
+        };
+        let (acceptor, capsules) = (acceptor.clone(), capsules.clone());
+        rocket::tokio::spawn(async move {
+            if let Err(err) = handle(acceptor, stream, &capsules).await {
+                warn!(%peer, error = %err, "Gemini request failed");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use chrono::{TimeZone, Utc};
+    use crate::blog::{Kind, LocalSource};
+
+    #[test]
+    fn test_gemtext() {
+        let markdown = "# Scan is Zip?\n\nLaziness and [recursion](/fin) strike *again*, see ![the chart](/static/chart.png).\n\n#### Deeper\n\n```haskell\nfibs = 0 : 1 : zipWith (+) fibs (tail fibs)\n```\n\n> Quoted, [with a link](https://example.com)\n\n- one\n- [two](/two)\n\n---\n\n<div>raw</div>\n";
+        assert_eq!(gemtext(markdown), "# Scan is Zip?\n\nLaziness and recursion strike again, see the chart.\n=> /fin recursion\n=> /static/chart.png the chart\n\n### Deeper\n\n```haskell\nfibs = 0 : 1 : zipWith (+) fibs (tail fibs)\n```\n\n> Quoted, with a link\n=> https://example.com with a link\n\n* one\n* two\n=> /two two\n");
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(parse_request("gemini://hacklewayne.com/zip-is-scan\r\n"), Some((String::from("hacklewayne.com"), String::from("/zip-is-scan"))));
+        assert_eq!(parse_request("gemini://Hacklewayne.com:1965?q\r\n"), Some((String::from("hacklewayne.com"), String::new())));
+        assert_eq!(parse_request("gemini://hacklewayne.com?q\r\n"), Some((String::from("hacklewayne.com"), String::new())));
+        assert_eq!(parse_request("https://hacklewayne.com/\r\n"), None);
+        assert_eq!(parse_request("gemini://hacklewayne.com/"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_respond() {
+        let capsule = Capsule {
+            hosts: vec![String::from("hacklewayne.com")],
+            title: String::from("Hackle's blog"),
+            description: String::from("Types and tests"),
+            public_url: String::from("https://hacklewayne.com"),
+            source: CachedSource::new(None, LocalSource::new(PathBuf::from("raw")), Duration::from_secs(60)),
+        };
+        let index = respond(&[capsule.to_owned()], "gemini://hacklewayne.com/\r\n").await;
+        assert!(index.starts_with("20 text/gemini\r\n# Hackle's blog\n\nTypes and tests\n\n=> https://hacklewayne.com On the web\n\n## Posts\n\n=> /"));
+        assert!(respond(&[capsule.to_owned()], "gemini://hacklewayne.com/nope\r\n").await.starts_with("51 "));
+
+        let post = Post {
+            slug: String::from("fin"),
+            title: String::from("Fin"),
+            path: String::from("fin.md"),
+            hidden: false,
+            updated: Utc.ymd(2024, 2, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+        assert_eq!(page(&capsule, &post, "The end."), "# Fin\n\nThe end.\n\n=> / Hackle's blog\n=> https://hacklewayne.com/fin On the web\n");
+    }
+}
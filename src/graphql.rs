@@ -0,0 +1,159 @@
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema};
+use chrono::{DateTime, Utc};
+use rocket::http::ContentType;
+use rocket::{post, routes, Route, State};
+use crate::blog::{self, find_post, CachedSource, Post, RenderCache};
+use crate::request_id::RequestId;
+use crate::shortcodes::LinkCards;
+use crate::tenant::Tenant;
+
+pub type BlogSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn schema() -> BlogSchema {
+    return Schema::build(QueryRoot, EmptyMutation, EmptySubscription).finish();
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Posts newest first, optionally filtered by tag and by when they were last updated.
+    /// Hidden posts are left out unless asked for.
+    #[allow(clippy::too_many_arguments)]
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        tag: Option<String>,
+        updated_after: Option<DateTime<Utc>>,
+        updated_before: Option<DateTime<Utc>>,
+        #[graphql(default = false)] include_hidden: bool,
+        #[graphql(default = 0)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<PostNode>> {
+        let all_posts = ctx.data::<CachedSource>()?.all_posts().await?;
+
+        Ok(all_posts.into_iter()
+            .filter(|post| include_hidden || !post.hidden)
+            .filter(|post| tag.as_ref().map(|tag| post.tags.contains(tag)).unwrap_or(true))
+            .filter(|post| updated_after.map(|after| post.updated > after).unwrap_or(true))
+            .filter(|post| updated_before.map(|before| post.updated < before).unwrap_or(true))
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(PostNode)
+            .collect())
+    }
+
+    async fn post(&self, ctx: &Context<'_>, slug: String) -> Result<Option<PostNode>> {
+        let all_posts = ctx.data::<CachedSource>()?.all_posts().await?;
+        Ok(find_post(&all_posts, &slug).map(PostNode))
+    }
+
+    /// Every tag in use by a visible post, sorted.
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        let all_posts = ctx.data::<CachedSource>()?.all_posts().await?;
+        Ok(blog::tags(&all_posts))
+    }
+}
+
+pub struct PostNode(Post);
+
+#[Object(name = "Post")]
+impl PostNode {
+    async fn slug(&self) -> &String {
+        &self.0.slug
+    }
+
+    async fn title(&self) -> &String {
+        &self.0.title
+    }
+
+    async fn updated(&self) -> DateTime<Utc> {
+        self.0.updated
+    }
+
+    async fn tags(&self) -> &Vec<String> {
+        &self.0.tags
+    }
+
+    async fn pinned(&self) -> bool {
+        self.0.pinned
+    }
+
+    async fn hidden(&self) -> bool {
+        self.0.hidden
+    }
+
+    async fn markdown(&self, ctx: &Context<'_>) -> Result<String> {
+        Ok(ctx.data::<CachedSource>()?.content(&self.0).await?)
+    }
+
+    async fn summary(&self, ctx: &Context<'_>) -> Result<String> {
+        let markdown = self.markdown(ctx).await?;
+        Ok(blog::describe(&markdown_to_text::convert(&markdown)))
+    }
+
+    async fn word_count(&self, ctx: &Context<'_>) -> Result<usize> {
+        let markdown = self.markdown(ctx).await?;
+        Ok(blog::count_words(&markdown_to_text::convert(&markdown)))
+    }
+
+    async fn reading_time(&self, ctx: &Context<'_>) -> Result<usize> {
+        let word_count = self.word_count(ctx).await?;
+        Ok(blog::reading_time(word_count, blog::words_per_minute()))
+    }
+
+    async fn html(&self, ctx: &Context<'_>) -> Result<String> {
+        let source = ctx.data::<CachedSource>()?;
+        let all_posts = source.all_posts().await?;
+        let markdown = source.content(&self.0).await?;
+
+        let renderer = ctx.data::<RenderCache>()?;
+        renderer.warm(&markdown).await;
+        let blog = blog::make_blog(&self.0, &all_posts, &markdown, renderer);
+        Ok(ctx.data::<LinkCards>()?.expand(&blog.content).await)
+    }
+}
+
+#[post("/graphql", data = "<body>")]
+async fn graphql(body: String, schema: &State<BlogSchema>, tenant: &Tenant, renderer: &State<RenderCache>, link_cards: &State<LinkCards>, request_id: RequestId) -> (ContentType, String) {
+    let response = match serde_json::from_str::<async_graphql::Request>(&body) {
+        Ok(request) => schema.execute(request
+            .data(tenant.source.for_request(&request_id))
+            .data(renderer.inner().to_owned())
+            .data(link_cards.inner().to_owned())
+        ).await,
+        Err(err) => async_graphql::Response::from_errors(vec![
+            async_graphql::ServerError::new(format!("Invalid GraphQL request: {}", err), None)
+        ]),
+    };
+
+    return (ContentType::JSON, serde_json::to_string(&response).unwrap());
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![graphql];
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::blog::LocalSource;
+    use super::*;
+
+    #[rocket::async_test]
+    async fn test_query_local_posts() {
+        let source = CachedSource::new(None, LocalSource::default(), Duration::from_secs(60));
+        let request = async_graphql::Request::new(r#"{ post(slug: "fin") { title wordCount } posts(limit: 2) { slug } }"#)
+            .data(source)
+            .data(RenderCache::default())
+            .data(LinkCards::default());
+
+        let response = schema().execute(request).await;
+        let data = response.data.into_json().unwrap();
+
+        assert!(response.errors.is_empty());
+        assert_eq!(data["post"]["title"], "Fin");
+        assert!(data["post"]["wordCount"].as_u64().unwrap() > 0);
+        assert_eq!(data["posts"].as_array().unwrap().len(), 2);
+    }
+}
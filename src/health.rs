@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use futures::FutureExt;
+use rocket::http::Status;
+use rocket::response::content::Json;
+use rocket::{get, routes, Route, Shutdown};
+use rocket_dyn_templates::Metadata;
+use serde::Serialize;
+use crate::tenant::Tenant;
+
+const REMOTE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentState {
+    Ok,
+    Down,
+    Disabled,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ComponentStatus {
+    pub status: ComponentState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ComponentStatus {
+    pub fn from_check(check: Result<(), String>) -> ComponentStatus {
+        return match check {
+            Ok(()) => ComponentStatus { status: ComponentState::Ok, detail: None },
+            Err(detail) => ComponentStatus { status: ComponentState::Down, detail: Some(detail) },
+        };
+    }
+
+    pub fn disabled(detail: &str) -> ComponentStatus {
+        return ComponentStatus { status: ComponentState::Disabled, detail: Some(detail.to_owned()) };
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    /// `ok`, `degraded` when the remote source is down but the local copy can still serve, or `down`.
+    pub status: String,
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+    /// Seconds since the manifest was cached; absent when nothing fresh is cached.
+    pub manifest_cache_age: Option<u64>,
+}
+
+/// Pages can be served as long as templates load and the local source is there to fall back to.
+pub fn overall_status(components: &BTreeMap<&'static str, ComponentStatus>) -> String {
+    let is_down = |name: &str| components.get(name).map(|component| component.status == ComponentState::Down).unwrap_or(false);
+
+    return String::from(
+        if is_down("templates") || is_down("local_source") { "down" }
+        else if components.values().any(|component| component.status == ComponentState::Down) { "degraded" }
+        else { "ok" }
+    );
+}
+
+#[get("/health")]
+async fn health(tenant: &Tenant, metadata: Metadata<'_>) -> (Status, Json<String>) {
+    let mut components = BTreeMap::new();
+    components.insert("templates", ComponentStatus::from_check(
+        if metadata.contains_template("main") { Ok(()) } else { Err(String::from("main template not loaded")) }
+    ));
+    components.insert("local_source", ComponentStatus::from_check(tenant.source.local().check()));
+    components.insert("remote_source", match tenant.source.remote() {
+        Some(remote) => ComponentStatus::from_check(remote.check(REMOTE_CHECK_TIMEOUT).await),
+        None => ComponentStatus::disabled("REMOTE_MARKDOWN_PATH not set"),
+    });
+
+    let report = HealthReport {
+        status: overall_status(&components),
+        components,
+        manifest_cache_age: tenant.source.manifest_age().map(|age| age.as_secs()),
+    };
+    let status = if report.status == "down" { Status::ServiceUnavailable } else { Status::Ok };
+
+    return (status, Json(serde_json::to_string(&report).unwrap()));
+}
+
+/// Only passes once the manifest has been loaded, so a load balancer or Lambda alias
+/// doesn't send readers to an instance that can't list posts yet, and fails again once shutdown has started,
+/// so it stops sending them over connections kept alive while in-flight requests drain.
+#[get("/ready")]
+fn ready(tenant: &Tenant, shutdown: Shutdown) -> (Status, &'static str) {
+    return if shutdown.now_or_never().is_some() {
+        (Status::ServiceUnavailable, "SHUTTING DOWN")
+    } else if tenant.source.manifest_loaded() {
+        (Status::Ok, "READY")
+    } else {
+        (Status::ServiceUnavailable, "NOT READY")
+    };
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![health, ready];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overall_status() {
+        let ok = || ComponentStatus::from_check(Ok(()));
+        let down = || ComponentStatus::from_check(Err(String::from("unreachable")));
+
+        let components = BTreeMap::from([("templates", ok()), ("local_source", ok()), ("remote_source", ComponentStatus::disabled("not set"))]);
+        assert_eq!(overall_status(&components), "ok");
+
+        let components = BTreeMap::from([("templates", ok()), ("local_source", ok()), ("remote_source", down())]);
+        assert_eq!(overall_status(&components), "degraded");
+
+        let components = BTreeMap::from([("templates", ok()), ("local_source", down()), ("remote_source", ok())]);
+        assert_eq!(overall_status(&components), "down");
+    }
+}
@@ -0,0 +1,233 @@
+//! Handlebars helpers every template can use, alongside the values each route puts in its context:
+//!
+//! - `{{format_date updated "%e %B %Y"}}` formats an RFC 3339 date such as a post's `updated`, `%v` without a format.
+//! - `{{truncate summary 120}}` cuts text to at most that many characters at a word boundary, ending in `…`.
+//! - `{{markdown_inline title}}` renders a line of markdown, such as `` `Maybe` `` in a title, without the paragraph.
+//! - `{{asset_url "styles.css"}}` is the URL of a file under `static/` with its fingerprint in the path, so a
+//!   changed stylesheet is fetched again whatever a browser cached, and an unchanged one can be cached for good.
+//! - `{{asset_tag "styles.css"}}` is the `<link>` of a stylesheet or the `<script>` of a script at that URL, with an
+//!   `integrity` hash for the browser to refuse it if it was altered on the way; `{{asset_tag "beacon.js" defer=true}}`
+//!   defers the script.
+//!
+//! Their text is escaped like any other value; `{{{(truncate summary 120)}}}` leaves it as is. `markdown_inline` and
+//! `asset_tag` are HTML either way.
+use std::collections::BTreeMap;
+use std::path::Path;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::DateTime;
+use rocket_dyn_templates::handlebars::{Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError};
+use sha2::{Digest, Sha384};
+use crate::markdown_options::{comrak_options, fingerprint};
+
+pub const DEFAULT_DATE_FORMAT: &str = "%v";
+const ELLIPSIS: char = '…';
+
+pub fn format_date(date: &str, format: &str) -> Result<String, String> {
+    let date = DateTime::parse_from_rfc3339(date).map_err(|err| format!("{:?} is not an RFC 3339 date, {}", date, err))?;
+    return Ok(date.format(format).to_string());
+}
+
+pub fn truncate(text: &str, limit: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= limit {
+        return text.to_owned();
+    }
+
+    let cut: String = text.chars().take(limit).collect();
+    let at_boundary = text.chars().nth(limit).map(char::is_whitespace).unwrap_or(true);
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(boundary) if !at_boundary && boundary > 0 => &cut[..boundary],
+        _ => cut.as_str()
+    };
+    return format!("{}{}", cut.trim_end(), ELLIPSIS);
+}
+
+/// Raw HTML in the markdown is left out, as it is from posts.
+pub fn markdown_inline(markdown: &str) -> String {
+    let html = comrak::markdown_to_html(markdown, &comrak_options());
+    let html = html.trim_end();
+    return html.strip_prefix("<p>").and_then(|html| html.strip_suffix("</p>")).unwrap_or(html).to_owned();
+}
+
+/// What a static file's URL and tag are made of: its fingerprint, and for a stylesheet or script its subresource
+/// integrity hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssetHash {
+    pub fingerprint: u64,
+    pub integrity: Option<String>,
+}
+
+impl AssetHash {
+    pub fn new(file: &str, bytes: &[u8]) -> AssetHash {
+        let integrity = Some(format!("sha384-{}", STANDARD.encode(Sha384::digest(bytes)))).filter(|_| tag_kind(file).is_some());
+        return AssetHash { fingerprint: fingerprint(bytes), integrity };
+    }
+}
+
+/// Static files by their path relative to the directory they are in.
+pub type Fingerprints = BTreeMap<String, AssetHash>;
+
+/// The fingerprint of every file under `directory`, by its path relative to it.
+pub fn asset_fingerprints(directory: &Path) -> Fingerprints {
+    fn visit(root: &Path, directory: &Path, fingerprints: &mut Fingerprints) {
+        for entry in std::fs::read_dir(directory).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                visit(root, &path, fingerprints);
+            } else if let Ok(bytes) = std::fs::read(&path) {
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                let hash = AssetHash::new(&relative, &bytes);
+                fingerprints.insert(relative, hash);
+            }
+        }
+    }
+
+    let mut fingerprints = BTreeMap::new();
+    visit(directory, directory, &mut fingerprints);
+    return fingerprints;
+}
+
+/// Files that aren't there get a URL all the same, without a version, rather than failing the page.
+pub fn asset_url(fingerprints: &Fingerprints, file: &str) -> String {
+    let file = file.trim_start_matches('/');
+    return match fingerprints.get(file) {
+        Some(hash) => format!("/static/{:016x}/{}", hash.fingerprint, file),
+        None => format!("/static/{}", file)
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TagKind {
+    Stylesheet,
+    Script,
+}
+
+fn tag_kind(file: &str) -> Option<TagKind> {
+    return match file.rsplit('.').next().map(str::to_ascii_lowercase).as_deref() {
+        Some("css") => Some(TagKind::Stylesheet),
+        Some("js") => Some(TagKind::Script),
+        _ => None,
+    };
+}
+
+/// The tag linking a stylesheet or script under `static/`, with its `integrity` when it is there to hash.
+pub fn asset_tag(fingerprints: &Fingerprints, file: &str, defer: bool) -> Result<String, String> {
+    let url = asset_url(fingerprints, file);
+    let integrity = fingerprints.get(file.trim_start_matches('/'))
+        .and_then(|hash| hash.integrity.as_ref())
+        .map(|integrity| format!(" integrity=\"{}\"", integrity))
+        .unwrap_or_default();
+    return match tag_kind(file) {
+        Some(TagKind::Stylesheet) => Ok(format!("<link rel=\"stylesheet\" href=\"{}\"{} />", url, integrity)),
+        Some(TagKind::Script) => Ok(format!("<script src=\"{}\"{}{}></script>", url, integrity, if defer { " defer" } else { "" })),
+        None => Err(format!("asset_tag links stylesheets and scripts, not {}", file)),
+    };
+}
+
+fn string_param<'a>(helper: &'a Helper, index: usize) -> Result<&'a str, RenderError> {
+    return helper.param(index)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new(format!("{} needs a string as parameter {}", helper.name(), index + 1)));
+}
+
+/// Helpers write straight to the output, so text is escaped here as a value would be.
+fn write_text(handlebars: &Handlebars, context: &RenderContext, out: &mut dyn Output, text: &str) -> HelperResult {
+    let text = if context.is_disable_escape() { text.to_owned() } else { handlebars.get_escape_fn()(text) };
+    out.write(&text)?;
+    return Ok(());
+}
+
+fn format_date_helper(helper: &Helper, handlebars: &Handlebars, _: &Context, context: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    let date = string_param(helper, 0)?;
+    let format = helper.param(1).and_then(|param| param.value().as_str()).unwrap_or(DEFAULT_DATE_FORMAT);
+    return write_text(handlebars, context, out, &format_date(date, format).map_err(RenderError::new)?);
+}
+
+fn truncate_helper(helper: &Helper, handlebars: &Handlebars, _: &Context, context: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    let text = string_param(helper, 0)?;
+    let limit = helper.param(1)
+        .and_then(|param| param.value().as_u64())
+        .ok_or_else(|| RenderError::new("truncate needs a number of characters as parameter 2"))?;
+    return write_text(handlebars, context, out, &truncate(text, limit as usize));
+}
+
+fn markdown_inline_helper(helper: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output) -> HelperResult {
+    out.write(&markdown_inline(string_param(helper, 0)?))?;
+    return Ok(());
+}
+
+struct AssetUrl(Fingerprints);
+
+impl HelperDef for AssetUrl {
+    fn call<'reg: 'rc, 'rc>(&self, helper: &Helper<'reg, 'rc>, handlebars: &'reg Handlebars<'reg>, _: &'rc Context, context: &mut RenderContext<'reg, 'rc>, out: &mut dyn Output) -> HelperResult {
+        return write_text(handlebars, context, out, &asset_url(&self.0, string_param(helper, 0)?));
+    }
+}
+
+struct AssetTag(Fingerprints);
+
+impl HelperDef for AssetTag {
+    fn call<'reg: 'rc, 'rc>(&self, helper: &Helper<'reg, 'rc>, _: &'reg Handlebars<'reg>, _: &'rc Context, _: &mut RenderContext<'reg, 'rc>, out: &mut dyn Output) -> HelperResult {
+        let defer = helper.hash_get("defer").and_then(|param| param.value().as_bool()).unwrap_or(false);
+        out.write(&asset_tag(&self.0, string_param(helper, 0)?, defer).map_err(RenderError::new)?)?;
+        return Ok(());
+    }
+}
+
+/// Registers every helper, `asset_url` and `asset_tag` with the fingerprints of the static files they link to.
+pub fn register(handlebars: &mut Handlebars, fingerprints: Fingerprints) {
+    handlebars.register_helper("format_date", Box::new(format_date_helper));
+    handlebars.register_helper("truncate", Box::new(truncate_helper));
+    handlebars.register_helper("markdown_inline", Box::new(markdown_inline_helper));
+    handlebars.register_helper("asset_url", Box::new(AssetUrl(fingerprints.clone())));
+    handlebars.register_helper("asset_tag", Box::new(AssetTag(fingerprints)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(format_date("2024-01-25T23:08:00Z", "%Y-%m-%d"), Ok(String::from("2024-01-25")));
+        assert_eq!(format_date("2024-01-25T23:08:00+00:00", DEFAULT_DATE_FORMAT), Ok(String::from("25-Jan-2024")));
+        assert!(format_date("yesterday", DEFAULT_DATE_FORMAT).is_err());
+
+        assert_eq!(truncate("Zip is scan", 20), "Zip is scan");
+        assert_eq!(truncate("Zip is scan, or is it?", 12), "Zip is scan,…");
+        assert_eq!(truncate("Zip is scan, or is it?", 8), "Zip is…");
+        assert_eq!(truncate("Zipisscan", 3), "Zip…");
+
+        assert_eq!(markdown_inline("`Maybe` is *not* <b>null</b>"), "<code>Maybe</code> is <em>not</em> <!-- raw HTML omitted -->null<!-- raw HTML omitted -->");
+
+        let fingerprints = asset_fingerprints(Path::new("static"));
+        let url = asset_url(&fingerprints, "styles.css");
+        assert!(url.starts_with("/static/") && url.ends_with("/styles.css") && url.len() == "/static//styles.css".len() + 16);
+        assert_eq!(asset_url(&fingerprints, "/missing.css"), "/static/missing.css");
+
+        let integrity = fingerprints["styles.css"].integrity.to_owned().unwrap();
+        assert!(integrity.starts_with("sha384-") && integrity.len() == "sha384-".len() + 64);
+        assert_eq!(fingerprints["favicon.ico"].integrity, None);
+        assert_eq!(asset_tag(&fingerprints, "styles.css", false), Ok(format!("<link rel=\"stylesheet\" href=\"{}\" integrity=\"{}\" />", url, integrity)));
+        assert_eq!(asset_tag(&fingerprints, "missing.js", true), Ok(String::from("<script src=\"/static/missing.js\" defer></script>")));
+        assert!(asset_tag(&fingerprints, "favicon.ico", false).is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut handlebars = Handlebars::new();
+        register(&mut handlebars, asset_fingerprints(Path::new("static")));
+        let render = |template: &str| handlebars.render_template(template, &json!({ "updated": "2024-01-25T23:08:00Z", "title": "`Maybe`", "summary": "<b>" })).map_err(|err| err.to_string());
+
+        assert_eq!(render(r#"{{format_date updated "%Y"}}"#).unwrap(), "2024");
+        assert_eq!(render(r#"{{markdown_inline title}}"#).unwrap(), "<code>Maybe</code>");
+        assert_eq!(render(r#"{{truncate updated 4}}"#).unwrap(), "2024…");
+        assert_eq!(render(r#"{{truncate summary 10}}"#).unwrap(), "&lt;b&gt;");
+        assert_eq!(render(r#"{{{(truncate summary 10)}}}"#).unwrap(), "<b>");
+        assert!(render(r#"{{format_date title}}"#).is_err());
+        assert!(render(r#"{{asset_tag "beacon.js" defer=true}}"#).unwrap().ends_with(" defer></script>"));
+        assert!(render(r#"{{asset_tag "beacon.js"}}"#).unwrap().contains(" integrity=\"sha384-"));
+    }
+}
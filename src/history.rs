@@ -0,0 +1,193 @@
+//! A post's changelog: the latest `history_limit` commits touching its markdown, so readers see when and why a post
+//! changed after it went out. From the GitHub commits API for a remote source, or `git log` in the local directory,
+//! linked to GitHub when its `origin` is there. Kept for an hour; a history that can't be had is logged and left out.
+use std::path::Path;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::blog::{CachedSource, Post};
+use crate::cache::TtlCache;
+use crate::config::{config, BlogConfig};
+use crate::micropub::Repository;
+
+const USER_AGENT: &str = "blog-rust";
+const HISTORY_TTL: Duration = Duration::from_secs(60 * 60);
+/// What separates a commit's fields in the `git log` format, which no subject line has.
+const SEPARATOR: char = '\u{1f}';
+
+/// A commit touching a post.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Change {
+    /// RFC 3339, for the `format_date` helper.
+    pub date: String,
+    /// The first line of the commit message.
+    pub message: String,
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    html_url: String,
+    commit: CommitDetail,
+}
+
+#[derive(Deserialize)]
+struct CommitDetail {
+    message: String,
+    committer: Signature,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    date: String,
+}
+
+fn subject(message: &str) -> String {
+    return message.lines().next().unwrap_or_default().trim().to_owned();
+}
+
+/// The changes in a response from the commits API, newest first as it lists them.
+pub fn parse_commits(json: &str) -> Result<Vec<Change>, String> {
+    let commits: Vec<Commit> = serde_json::from_str(json).map_err(|err| format!("Cannot parse commits, {}", err))?;
+    return Ok(commits.into_iter()
+        .map(|commit| Change { date: commit.commit.committer.date, message: subject(&commit.commit.message), url: Some(commit.html_url) })
+        .collect());
+}
+
+/// Where a commit of the repository fetched from `origin` is seen, when that is GitHub.
+pub fn commit_base(origin: &str) -> Option<String> {
+    let origin = origin.trim().trim_end_matches('/').trim_end_matches(".git");
+    let path = origin.strip_prefix("https://github.com/").or_else(|| origin.strip_prefix("git@github.com:"))?;
+    let mut parts = path.split('/');
+    return match (parts.next(), parts.next(), parts.next()) {
+        (Some(owner), Some(repo), None) if !owner.is_empty() && !repo.is_empty() => Some(format!("https://github.com/{}/{}/commit", owner, repo)),
+        _ => None,
+    };
+}
+
+/// The changes in `git log` output of `--format=%H%x1f%cI%x1f%s`, linked under `base` if there is one.
+pub fn parse_log(log: &str, base: Option<&str>) -> Vec<Change> {
+    return log.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, SEPARATOR);
+            let (sha, date, message) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(Change { date: date.to_owned(), message: message.trim().to_owned(), url: base.map(|base| format!("{}/{}", base, sha)) })
+        })
+        .collect();
+}
+
+fn git(directory: &Path, args: &[&str]) -> Result<String, String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Cannot run git, {}", err))?;
+    return if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format!("git {} failed, {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    };
+}
+
+fn git_history(directory: &Path, name: &str, limit: usize) -> Result<Vec<Change>, String> {
+    let log = git(directory, &["log", &format!("-n{}", limit), "--format=%H%x1f%cI%x1f%s", "--", name])?;
+    let base = git(directory, &["remote", "get-url", "origin"]).ok().and_then(|origin| commit_base(&origin));
+    return Ok(parse_log(&log, base.as_deref()));
+}
+
+pub struct History {
+    limit: usize,
+    client: reqwest::Client,
+    cache: TtlCache<Vec<Change>>,
+}
+
+impl History {
+    pub fn from_config(config: &BlogConfig) -> History {
+        return History { limit: config.history_limit, client: reqwest::Client::new(), cache: TtlCache::new(HISTORY_TTL) };
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Vec<Change>, String> {
+        let mut request = self.client.get(url)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", USER_AGENT);
+        if let Some(token) = &config().github_api_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let response = request.send().await.and_then(|response| response.error_for_status()).map_err(|err| format!("Cannot fetch {}, {}", url, err))?;
+        return parse_commits(&response.text().await.map_err(|err| format!("Cannot read {}, {}", url, err))?);
+    }
+
+    async fn load(&self, source: &CachedSource, post: &Post) -> Result<Vec<Change>, String> {
+        if let Some(remote) = source.remote() {
+            let repository = Repository::of(remote).ok_or_else(|| format!("{} isn't on GitHub", remote.base_url))?;
+            return self.fetch(&repository.commits_url(&post.path, self.limit)).await;
+        }
+        let (directory, name, limit) = (source.local().directory.to_owned(), post.path.to_owned(), self.limit);
+        return rocket::tokio::task::spawn_blocking(move || git_history(&directory, &name, limit)).await
+            .map_err(|err| format!("Cannot run git, {}", err))?;
+    }
+
+    /// The post's latest changes, none when `history_limit` is 0.
+    pub async fn of(&self, source: &CachedSource, post: &Post) -> Vec<Change> {
+        if self.limit == 0 {
+            return vec![];
+        }
+        let key = match source.remote() {
+            Some(remote) => format!("{}/{}", remote.base_url, post.path),
+            None => source.local().directory.join(&post.path).display().to_string(),
+        };
+        if let Some(changes) = self.cache.get(&key) {
+            return changes;
+        }
+        let changes = self.load(source, post).await.unwrap_or_else(|err| {
+            warn!(slug = %post.slug, error = %err, "cannot load the post's history");
+            vec![]
+        });
+        self.cache.insert(&key, changes.to_owned());
+        return changes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commits() {
+        let json = r#"[
+            { "sha": "abc", "html_url": "https://github.com/hackle/blog-rust/commit/abc", "commit": { "message": "Fix a typo in fin\n\nIt said fni.", "committer": { "date": "2024-02-01T09:30:00Z" } } },
+            { "sha": "def", "html_url": "https://github.com/hackle/blog-rust/commit/def", "commit": { "message": "Add fin", "committer": { "date": "2024-01-25T23:08:00Z" } } }
+        ]"#;
+        let changes = parse_commits(json).unwrap();
+        assert_eq!(changes[0], Change {
+            date: String::from("2024-02-01T09:30:00Z"),
+            message: String::from("Fix a typo in fin"),
+            url: Some(String::from("https://github.com/hackle/blog-rust/commit/abc")),
+        });
+        assert_eq!(changes[1].message, "Add fin");
+        assert!(parse_commits(r#"{ "message": "Not Found" }"#).is_err());
+    }
+
+    #[test]
+    fn test_commit_base() {
+        assert_eq!(commit_base("https://github.com/hackle/blog-rust.git\n"), Some(String::from("https://github.com/hackle/blog-rust/commit")));
+        assert_eq!(commit_base("git@github.com:hackle/blog-rust.git"), Some(String::from("https://github.com/hackle/blog-rust/commit")));
+        assert_eq!(commit_base("https://gitlab.com/hackle/blog-rust.git"), None);
+        assert_eq!(commit_base("https://github.com/hackle"), None);
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let log = "abc\u{1f}2024-02-01T20:30:00+11:00\u{1f}Fix a typo in fin\ndef\u{1f}2024-01-25T23:08:00Z\u{1f}Add fin\n";
+        let changes = parse_log(log, Some("https://github.com/hackle/blog-rust/commit"));
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0], Change {
+            date: String::from("2024-02-01T20:30:00+11:00"),
+            message: String::from("Fix a typo in fin"),
+            url: Some(String::from("https://github.com/hackle/blog-rust/commit/abc")),
+        });
+        assert_eq!(parse_log(log, None)[1].url, None);
+        assert!(parse_log("", None).is_empty());
+    }
+}
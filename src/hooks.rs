@@ -0,0 +1,210 @@
+use hmac::{Hmac, Mac};
+use rocket::data::{ByteUnit, Data};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::Json;
+use rocket::{post, routes, Route, State};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::info;
+use crate::activitypub::ActivityPub;
+use crate::body;
+use crate::cdn::{purge_keys, Cdn};
+use crate::config::config;
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+use crate::webmention::send::Sender;
+
+/// A push of a few hundred commits; GitHub sends up to 25 MB, but the paths of a content repository's pushes are
+/// well under this.
+const MAX_DELIVERY: ByteUnit = ByteUnit::Mebibyte(1);
+
+/// Whether `signature`, as sent in `X-Hub-Signature-256` (`sha256=<hex>`), is the HMAC of `body` under `secret`.
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let expected = match signature.strip_prefix("sha256=").and_then(|hex| hex::decode(hex).ok()) {
+        Some(expected) => expected,
+        None => return false
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret) {
+        Ok(mac) => mac,
+        Err(_) => return false
+    };
+    mac.update(body);
+    return mac.verify_slice(&expected).is_ok();
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PushCommit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PushEvent {
+    #[serde(default)]
+    commits: Vec<PushCommit>,
+}
+
+impl PushEvent {
+    /// Every path added, modified or removed by the push, once each.
+    pub fn changed_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.commits.iter()
+            .flat_map(|commit| commit.added.iter().chain(&commit.modified).chain(&commit.removed))
+            .filter(|path| path.ends_with(".md") || path.ends_with("manifest.json"))
+            .cloned()
+            .collect();
+        paths.sort();
+        paths.dedup();
+        return paths;
+    }
+}
+
+/// The `X-GitHub-Event` and `X-Hub-Signature-256` headers of a delivery. Without `github_webhook_secret`
+/// the hook doesn't exist at all, rather than accepting unsigned pushes.
+pub struct GithubDelivery {
+    event: String,
+    signature: String,
+    secret: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GithubDelivery {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let secret = match config().github_webhook_secret.to_owned() {
+            Some(secret) => secret,
+            None => return Outcome::Forward(())
+        };
+
+        return match (request.headers().get_one("X-GitHub-Event"), request.headers().get_one("X-Hub-Signature-256")) {
+            (Some(event), Some(signature)) => Outcome::Success(GithubDelivery { event: event.to_owned(), signature: signature.to_owned(), secret }),
+            _ => Outcome::Failure((Status::BadRequest, ()))
+        };
+    }
+}
+
+#[derive(Serialize)]
+struct Invalidated {
+    paths: Vec<String>,
+    rewarmed: Vec<String>,
+    cdn_purged: Vec<String>,
+}
+
+/// The body of a delivery, once its signature checks out.
+async fn signed_body(delivery: &GithubDelivery, data: Data<'_>) -> Result<Vec<u8>, (Status, String)> {
+    let body = body::read(data, MAX_DELIVERY).await?;
+    if !verify_signature(delivery.secret.as_bytes(), &body, &delivery.signature) {
+        return Err((Status::Unauthorized, String::from("Signature does not match")));
+    }
+    return Ok(body);
+}
+
+/// Drops the manifest and posts at `paths` from the caches, with the feeds and sitemap built from them, and fetches them again, purges them from the CDN,
+/// and sends the posts that changed as webmentions and to followers. Returns the slugs refetched and the keys purged.
+pub async fn refresh(paths: &[String], request_id: &RequestId, tenant: &Tenant, cdn: &Cdn, sender: &Sender, activitypub: &ActivityPub) -> Result<(Vec<String>, Vec<String>), String> {
+    let rewarmed = if paths.is_empty() {
+        vec![]
+    } else {
+        tenant.search_index.purge();
+        tenant.feeds.purge();
+        tenant.source.for_request(request_id).invalidate(paths).await?
+    };
+
+    let manifest_changed = paths.iter().any(|path| path.ends_with("manifest.json"));
+    let cdn_purged = cdn.purge(&purge_keys(&rewarmed, manifest_changed)).await;
+    let public_url = tenant.public_url.as_deref().unwrap_or(&config().public_url);
+    sender.notify(&tenant.source, public_url, &rewarmed);
+    activitypub.notify(&tenant.source, public_url, &rewarmed);
+    return Ok((rewarmed, cdn_purged));
+}
+
+/// Invalidates the manifest and posts a push to the markdown repository touched, and fetches them again,
+/// so a published post is live within seconds instead of after `cache_ttl_secs`; then purges what changed from the CDN
+/// and sends the posts that changed as webmentions and to followers.
+#[post("/hooks/github", data = "<data>")]
+async fn github(delivery: GithubDelivery, data: Data<'_>, request_id: RequestId, tenant: &Tenant, cdn: &State<Cdn>, sender: &State<Sender>, activitypub: &State<ActivityPub>) -> Result<Json<String>, (Status, String)> {
+    let body = signed_body(&delivery, data).await?;
+    if delivery.event != "push" {
+        return Ok(Json(serde_json::to_string(&Invalidated { paths: vec![], rewarmed: vec![], cdn_purged: vec![] }).unwrap()));
+    }
+
+    let event: PushEvent = serde_json::from_slice(&body).map_err(|err| (Status::BadRequest, err.to_string()))?;
+    let paths = event.changed_paths();
+    let (rewarmed, cdn_purged) = refresh(&paths, &request_id, tenant, cdn, sender, activitypub).await.map_err(|err| (Status::BadGateway, err))?;
+
+    info!(%request_id, ?paths, ?rewarmed, ?cdn_purged, "github push");
+    return Ok(Json(serde_json::to_string(&Invalidated { paths, rewarmed, cdn_purged }).unwrap()));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![github];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    fn sign(secret: &[u8], body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(body);
+        return format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+    }
+
+    /// `github` itself takes `github_webhook_secret` from the configuration, so a delivery is checked here as it does.
+    #[post("/?<signature>", data = "<data>")]
+    async fn delivery(signature: String, data: Data<'_>) -> Result<String, (Status, String)> {
+        let delivery = GithubDelivery { event: String::from("push"), signature, secret: String::from("s3cret") };
+        return signed_body(&delivery, data).await.map(|body| body.len().to_string());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        // the example from GitHub's docs on validating webhook deliveries
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+        assert!(verify_signature(b"It's a Secret to Everybody", b"Hello, World!", signature));
+        assert!(!verify_signature(b"It's a Secret to Everybody", b"Hello, World?", signature));
+        assert!(!verify_signature(b"wrong secret", b"Hello, World!", signature));
+        assert!(!verify_signature(b"It's a Secret to Everybody", b"Hello, World!", "sha1=757107ea"));
+    }
+
+    #[rocket::async_test]
+    async fn test_signed_body() {
+        let client = Client::untracked(rocket::build().mount("/", routes![delivery])).await.unwrap();
+        let commit = r#"{ "added": [], "modified": ["raw/manifest.json", "raw/zip-is-scan.md"], "removed": [] }"#;
+        let push = format!(r#"{{ "ref": "refs/heads/master", "commits": [{}] }}"#, vec![commit; 200].join(","));
+        assert!(push.len() > 8 * 1024);
+
+        let post = |body: String, signature: String| client.post(format!("/?signature={}", signature)).body(body);
+        let response = post(push.to_owned(), sign(b"s3cret", push.as_bytes())).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await, Some(push.len().to_string()));
+
+        let response = post(push.to_owned(), sign(b"wrong", push.as_bytes())).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let huge = "x".repeat(MAX_DELIVERY.as_u64() as usize + 1);
+        let response = post(huge.to_owned(), sign(b"s3cret", huge.as_bytes())).dispatch().await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let event: PushEvent = serde_json::from_str(r#"{
+            "ref": "refs/heads/master",
+            "commits": [
+                { "added": ["raw/new-post.md"], "modified": ["raw/manifest.json"], "removed": [] },
+                { "added": [], "modified": ["raw/new-post.md", "src/main.rs"], "removed": ["raw/old-post.md"] }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(event.changed_paths(), vec!["raw/manifest.json", "raw/new-post.md", "raw/old-post.md"]);
+    }
+}
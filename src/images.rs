@@ -0,0 +1,206 @@
+//! `/img/<width>/<path..>`: an image from the content repository, read as posts are, scaled down to one of
+//! `image_widths` so a post can offer a `srcset` without resizing anything ahead of time. A JPEG stays a JPEG and
+//! anything else becomes a PNG, an animated GIF its first frame, unless the browser takes one of `image_formats` and
+//! that comes out smaller. Resized images are kept, the most recently asked for `image_cache_size` of them, a copy
+//! per format, and cached by browsers and CDNs for a year, by `Accept`.
+use std::io::Cursor;
+use std::path::PathBuf;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{get, routes, Responder, Route, State};
+use crate::cache::LruCache;
+use crate::config::{config, BlogConfig};
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+
+/// Not `immutable`, as the image at a path can change with the repository.
+pub const CACHE_CONTROL: &str = "public, max-age=31536000";
+const QUALITY: u8 = 80;
+/// Of 1 to 10, fast enough to encode while a reader waits, at the cost of a few percent in size.
+const AVIF_SPEED: u8 = 8;
+
+#[derive(Clone, Debug)]
+pub struct Resized {
+    pub bytes: Vec<u8>,
+    pub content_type: ContentType,
+}
+
+/// The first of `formats` the `Accept` header lists; `image/*` and `*/*` don't count, as browsers that can't decode
+/// AVIF or WebP send them too, and a `q=0` rules a format out.
+pub fn negotiate<'a>(accept: &str, formats: impl IntoIterator<Item = &'a str>) -> Option<ImageFormat> {
+    let accepted: Vec<String> = accept
+        .split(',')
+        .filter_map(|candidate| {
+            let mut parts = candidate.split(';').map(|part| part.trim());
+            let media_type = parts.next()?.to_lowercase();
+            let refused = parts.any(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+            if refused { None } else { Some(media_type) }
+        })
+        .collect();
+
+    return formats.into_iter()
+        .filter(|format| accepted.iter().any(|media_type| media_type.strip_prefix("image/") == Some(*format)))
+        .find_map(ImageFormat::from_extension);
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Resized, String> {
+    let mut encoded = Cursor::new(vec![]);
+    let content_type = match format {
+        ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut encoded, QUALITY);
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder).map_err(|err| err.to_string())?;
+            ContentType::JPEG
+        },
+        // the image crate only writes lossless WebP, no smaller than a PNG for a photo
+        ImageFormat::WebP => {
+            let rgba = image.to_rgba8();
+            let webp = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(QUALITY as f32);
+            return Ok(Resized { bytes: webp.to_vec(), content_type: ContentType::WEBP });
+        },
+        ImageFormat::Avif => {
+            let encoder = AvifEncoder::new_with_speed_quality(&mut encoded, AVIF_SPEED, QUALITY);
+            DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder).map_err(|err| err.to_string())?;
+            ContentType::AVIF
+        },
+        _ => {
+            image.write_to(&mut encoded, ImageFormat::Png).map_err(|err| err.to_string())?;
+            ContentType::PNG
+        }
+    };
+    return Ok(Resized { bytes: encoded.into_inner(), content_type });
+}
+
+/// Scales `bytes` down to `width`, keeping its aspect ratio; a narrower image keeps its own width. It is encoded as
+/// `preferred` if that is smaller, as a lossless original can come out bigger.
+pub fn resize(bytes: &[u8], width: u32, preferred: Option<ImageFormat>) -> Result<Resized, String> {
+    let format = image::guess_format(bytes).map_err(|err| err.to_string())?;
+    let image = image::load_from_memory_with_format(bytes, format).map_err(|err| err.to_string())?;
+    let image = if image.width() > width { image.resize(width, image.height(), FilterType::Lanczos3) } else { image };
+
+    let original = encode(&image, if format == ImageFormat::Jpeg { ImageFormat::Jpeg } else { ImageFormat::Png })?;
+    let preferred = match preferred {
+        Some(preferred) => Some(encode(&image, preferred)?),
+        None => None
+    };
+    return Ok(preferred.filter(|preferred| preferred.bytes.len() < original.bytes.len()).unwrap_or(original));
+}
+
+/// The format to serve instead of the original's, by the `Accept` header and `image_formats`.
+pub struct Preferred(Option<ImageFormat>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preferred {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let accept = request.headers().get_one("Accept").unwrap_or("");
+        return Outcome::Success(Preferred(negotiate(accept, config().image_formats())));
+    }
+}
+
+/// Resized images by blog, width and path.
+#[derive(Clone)]
+pub struct ImageCache(LruCache<Resized>);
+
+impl ImageCache {
+    pub fn from_config(config: &BlogConfig) -> ImageCache {
+        return ImageCache(LruCache::new(config.image_cache_size));
+    }
+}
+
+#[derive(Responder)]
+pub struct Image(Vec<u8>, ContentType, Header<'static>, Header<'static>);
+
+#[get("/img/<width>/<path..>")]
+async fn resized(width: u32, path: PathBuf, preferred: Preferred, tenant: &Tenant, cache: &State<ImageCache>, request_id: RequestId) -> Result<Option<Image>, Status> {
+    let known = matches!(ImageFormat::from_path(&path), Ok(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP));
+    if !known || !config().image_widths().unwrap_or_default().contains(&width) {
+        return Ok(None);
+    }
+    let path = path.to_string_lossy().replace('\\', "/");
+    let preferred = preferred.0;
+    let key = format!("{}/{}/{}/{}", tenant.hosts.join(","), width, preferred.map_or("original", |format| format.extensions_str()[0]), path);
+
+    let resized = match cache.0.get(&key) {
+        Some(resized) => resized,
+        None => {
+            let bytes = match tenant.source.for_request(&request_id).file(&path).await.map_err(|_| Status::BadGateway)? {
+                Some(bytes) => bytes,
+                None => return Ok(None)
+            };
+            // decoding and encoding hold a thread for a while, which shouldn't be one serving requests
+            let resized = rocket::tokio::task::spawn_blocking(move || resize(&bytes, width, preferred)).await
+                .map_err(|_| Status::InternalServerError)?
+                .map_err(|_| Status::UnprocessableEntity)?;
+            cache.0.insert(&key, resized.to_owned());
+            resized
+        }
+    };
+    let vary = Header::new("Vary", "Accept");
+    return Ok(Some(Image(resized.bytes, resized.content_type, Header::new("Cache-Control", CACHE_CONTROL), vary)));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![resized];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut bytes, format).unwrap();
+        return bytes.into_inner();
+    }
+
+    /// A gradient, which compresses about as a photo would.
+    fn photo(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        let image = RgbImage::from_fn(width, height, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8]));
+        DynamicImage::ImageRgb8(image).write_to(&mut bytes, format).unwrap();
+        return bytes.into_inner();
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert_eq!(negotiate(chrome, ["avif", "webp"]), Some(ImageFormat::Avif));
+        assert_eq!(negotiate(chrome, ["webp", "avif"]), Some(ImageFormat::WebP));
+        assert_eq!(negotiate("image/avif;q=0, image/webp", ["avif", "webp"]), Some(ImageFormat::WebP));
+        assert_eq!(negotiate("image/*,*/*;q=0.8", ["avif", "webp"]), None);
+        assert_eq!(negotiate(chrome, []), None);
+    }
+
+    #[test]
+    fn test_resize() {
+        let resized = resize(&encoded(400, 200, ImageFormat::Png), 100, None).unwrap();
+        assert_eq!(resized.content_type, ContentType::PNG);
+        assert_eq!(image::load_from_memory(&resized.bytes).unwrap().dimensions(), (100, 50));
+
+        let resized = resize(&encoded(400, 200, ImageFormat::Jpeg), 1000, None).unwrap();
+        assert_eq!(resized.content_type, ContentType::JPEG);
+        assert_eq!(image::load_from_memory(&resized.bytes).unwrap().dimensions(), (400, 200));
+
+        assert!(resize(b"not an image", 100, None).is_err());
+    }
+
+    #[test]
+    fn test_resize_preferred() {
+        let png = photo(400, 200, ImageFormat::Png);
+        let original = resize(&png, 200, None).unwrap();
+        let webp = resize(&png, 200, Some(ImageFormat::WebP)).unwrap();
+        assert_eq!(webp.content_type, ContentType::WEBP);
+        assert!(webp.bytes.len() < original.bytes.len());
+        assert_eq!(image::load_from_memory(&webp.bytes).unwrap().dimensions(), (200, 100));
+
+        let avif = resize(&png, 200, Some(ImageFormat::Avif)).unwrap();
+        assert_eq!(avif.content_type, ContentType::AVIF);
+        assert!(avif.bytes.len() < original.bytes.len());
+    }
+}
@@ -0,0 +1,408 @@
+//! The links posts make, checked before readers follow them. A link within the blog names a post by its slug, as
+//! `/zip-is-scan`, or by its markdown, as `fin.md`, which also works when browsing the repository; either may end in
+//! an `#anchor`, which must be an id in the post it names. Pages that aren't posts, and files, aren't checked.
+//! Links to other sites are checked by `blog check-links`, which asks each for its headers and reports those gone
+//! or moved, one request at a time to any one host.
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use comrak::{markdown_to_html, parse_document, Arena};
+use comrak::nodes::NodeValue;
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::redirect::Policy;
+use reqwest::{StatusCode, Url};
+use rocket::tokio::time::sleep;
+use crate::blog::{comrak_options, find_post, to_posts, LocalSource, Post};
+use crate::config::BlogConfig;
+use crate::tenant::Tenants;
+
+/// The blog's own pages, which a one-segment link may name without being a post.
+const PAGES: [&str; 14] = [
+    "archive", "search", "random", "posts", "subscribe", "unsubscribe", "login", "logout",
+    "health", "ready", "version", "micropub", "graphql", "webmention",
+];
+
+static IDS: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\b(?:id|name)="([^"]+)""#).unwrap());
+
+/// Every link and image in the markdown, in order.
+pub fn links(markdown: &str) -> Vec<String> {
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, comrak_options());
+    return root.descendants()
+        .filter_map(|node| match &node.data.borrow().value {
+            NodeValue::Link(link) | NodeValue::Image(link) => Some(String::from_utf8_lossy(&link.url).into_owned()),
+            _ => None,
+        })
+        .collect();
+}
+
+/// The ids and names in rendered HTML, which `#anchor`s can point at.
+pub fn anchors(html: &str) -> Vec<String> {
+    return IDS.captures_iter(html).map(|captures| captures[1].to_owned()).collect();
+}
+
+/// Where a link within the blog points.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Internal {
+    /// The slug or markdown name without `.md`, as `find_post` takes it; none for an anchor in the same post.
+    pub post: Option<String>,
+    pub anchor: Option<String>,
+}
+
+/// `url` as a link to a post, or an anchor in one; none for a link elsewhere, to a page that isn't a post or to a file.
+pub fn internal(url: &str, public_url: &str) -> Option<Internal> {
+    let url = url.strip_prefix(public_url)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/') || rest.starts_with('#'))
+        .unwrap_or(url);
+    let scheme = url.find(':').map(|colon| !url[..colon].contains(['/', '?', '#'])).unwrap_or(false);
+    if scheme || url.starts_with("//") {
+        return None;
+    }
+
+    let (path, anchor) = match url.split_once('#') {
+        Some((path, anchor)) => (path, Some(anchor.to_owned()).filter(|anchor| !anchor.is_empty())),
+        None => (url, None),
+    };
+    let path = path.split('?').next().unwrap_or_default().trim_start_matches("./").trim_start_matches('/');
+    if path.is_empty() {
+        return anchor.map(|anchor| Internal { post: None, anchor: Some(anchor) });
+    }
+    if path.contains('/') {
+        return None;
+    }
+    let post = match path.strip_suffix(".md") {
+        Some(name) => name,
+        None if path.contains('.') || PAGES.contains(&path) => return None,
+        None => path,
+    };
+    return Some(Internal { post: Some(post.to_owned()), anchor });
+}
+
+/// A link that doesn't resolve, in the post with slug `from`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeadLink {
+    pub from: String,
+    pub url: String,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for DeadLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{} links to {}, {}", self.from, self.url, self.reason);
+    }
+}
+
+/// The internal links each post makes that name no post, or an anchor the post doesn't have. `markdown` has each
+/// post's markdown by path; a post without any is skipped, and so are anchors into it.
+pub fn dead_links(posts: &Vec<Post>, markdown: &HashMap<String, String>, public_url: &str) -> (usize, Vec<DeadLink>) {
+    let anchors: HashMap<&str, Vec<String>> = markdown.iter()
+        .map(|(path, markdown)| (path.as_str(), anchors(&markdown_to_html(markdown, comrak_options()))))
+        .collect();
+    let mut checked = 0;
+    let mut dead = vec![];
+
+    for post in posts {
+        let links = markdown.get(&post.path).map(|markdown| links(markdown)).unwrap_or_default();
+        for (url, link) in links.iter().filter_map(|url| internal(url, public_url).map(|link| (url, link))) {
+            checked += 1;
+            let target = match &link.post {
+                Some(name) => find_post(posts, name),
+                None => Some(post.to_owned()),
+            };
+            let reason = match (target, &link.anchor) {
+                (None, _) => Some("no such post"),
+                (Some(target), Some(anchor)) => anchors.get(target.path.as_str())
+                    .filter(|ids| !ids.contains(anchor))
+                    .map(|_| "no such anchor"),
+                (Some(_), None) => None,
+            };
+            if let Some(reason) = reason {
+                dead.push(DeadLink { from: post.slug.to_owned(), url: url.to_owned(), reason });
+            }
+        }
+    }
+    return (checked, dead);
+}
+
+/// Every internal link in the local posts resolves.
+pub fn check_links(local: &LocalSource, public_url: &str) -> Result<String, String> {
+    let (posts, markdown) = read_posts(local)?;
+    let (checked, dead) = dead_links(&posts, &markdown, public_url);
+
+    return if dead.is_empty() {
+        Ok(format!("{} internal links in {} posts resolve", checked, posts.len()))
+    } else {
+        Err(dead.iter().map(DeadLink::to_string).collect::<Vec<_>>().join("; "))
+    };
+}
+
+const USER_AGENT: &str = "blog-rust link checker";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The host of `url` if it is a link to another site, over http(s).
+pub fn external_host(url: &str, public_url: &str) -> Option<String> {
+    let url = Url::parse(url).ok().filter(|url| ["http", "https"].contains(&url.scheme()))?;
+    let own = Url::parse(public_url).ok().and_then(|public_url| public_url.host_str().map(String::from));
+    return url.host_str().filter(|host| Some(*host) != own.as_deref()).map(String::from);
+}
+
+/// Whether `host` is in the comma-separated `allow`, or under one there.
+pub fn allowed(host: &str, allow: &str) -> bool {
+    return allow.split(',')
+        .map(|allowed| allowed.trim())
+        .filter(|allowed| !allowed.is_empty())
+        .any(|allowed| host == allowed || host.ends_with(&format!(".{}", allowed)));
+}
+
+/// Each link to another site, outside `allow`, with the slugs of the posts making it, by host.
+pub fn external_links(posts: &[Post], markdown: &HashMap<String, String>, public_url: &str, allow: &str) -> BTreeMap<String, BTreeMap<String, Vec<String>>> {
+    let mut hosts: BTreeMap<String, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+    for post in posts {
+        for url in markdown.get(&post.path).map(|markdown| links(markdown)).unwrap_or_default() {
+            let host = match external_host(&url, public_url).filter(|host| !allowed(host, allow)) {
+                Some(host) => host,
+                None => continue,
+            };
+            let slugs = hosts.entry(host).or_default().entry(url).or_default();
+            if !slugs.contains(&post.slug) {
+                slugs.push(post.slug.to_owned());
+            }
+        }
+    }
+    return hosts;
+}
+
+/// What asking for a link's headers found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// The status and where it points.
+    Redirect(u16, String),
+    Broken(u16),
+    Failed(String),
+}
+
+pub fn outcome(status: StatusCode, location: Option<&str>) -> Outcome {
+    return if status.is_success() {
+        Outcome::Ok
+    } else if status.is_redirection() {
+        Outcome::Redirect(status.as_u16(), location.unwrap_or_default().to_owned())
+    } else {
+        Outcome::Broken(status.as_u16())
+    };
+}
+
+/// A HEAD, or a GET from a server that won't answer HEAD.
+async fn check(client: &reqwest::Client, url: &str) -> Outcome {
+    let mut response = client.head(url).send().await;
+    if let Ok(head) = &response {
+        if [StatusCode::METHOD_NOT_ALLOWED, StatusCode::NOT_IMPLEMENTED].contains(&head.status()) {
+            response = client.get(url).send().await;
+        }
+    }
+    return match response {
+        Ok(response) => outcome(response.status(), response.headers().get("Location").and_then(|location| location.to_str().ok())),
+        Err(err) => Outcome::Failed(err.to_string()),
+    };
+}
+
+/// A link checked, with the posts making it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checked {
+    pub url: String,
+    pub posts: Vec<String>,
+    pub outcome: Outcome,
+}
+
+/// Checks every link, `concurrency` hosts at a time and one link at a time for each, `delay` apart.
+pub async fn check_external(hosts: BTreeMap<String, BTreeMap<String, Vec<String>>>, concurrency: usize, delay: Duration) -> Result<Vec<Checked>, String> {
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .timeout(CHECK_TIMEOUT)
+        .user_agent(USER_AGENT)
+        .build()
+        .map_err(|err| format!("Cannot build the link checker's client, {}", err))?;
+
+    let checked: Vec<Vec<Checked>> = stream::iter(hosts.into_values())
+        .map(|links| {
+            let client = &client;
+            async move {
+                let mut checked = vec![];
+                for (index, (url, posts)) in links.into_iter().enumerate() {
+                    if index > 0 {
+                        sleep(delay).await;
+                    }
+                    let outcome = check(client, &url).await;
+                    checked.push(Checked { url, posts, outcome });
+                }
+                checked
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    let mut checked: Vec<Checked> = checked.into_iter().flatten().collect();
+    checked.sort_by(|a, b| a.url.cmp(&b.url));
+    return Ok(checked);
+}
+
+/// A line for each link gone, failing or moved, and a count of each.
+pub fn report(checked: &[Checked]) -> String {
+    let mut lines: Vec<String> = checked.iter()
+        .filter_map(|checked| {
+            let status = match &checked.outcome {
+                Outcome::Ok => return None,
+                Outcome::Redirect(status, location) => format!("{} {} -> {}", status, checked.url, location),
+                Outcome::Broken(status) => format!("{} {}", status, checked.url),
+                Outcome::Failed(err) => format!("failed {}, {}", checked.url, err),
+            };
+            Some(format!("{} (in {})", status, checked.posts.join(", ")))
+        })
+        .collect();
+    let count = |matches: fn(&Outcome) -> bool| checked.iter().filter(|checked| matches(&checked.outcome)).count();
+    lines.push(format!(
+        "{} external links checked: {} broken, {} failed, {} redirected",
+        checked.len(),
+        count(|outcome| matches!(outcome, Outcome::Broken(_))),
+        count(|outcome| matches!(outcome, Outcome::Failed(_))),
+        count(|outcome| matches!(outcome, Outcome::Redirect(..))),
+    ));
+    return lines.join("\n");
+}
+
+/// How many links are gone or couldn't be checked.
+pub fn failing(checked: &[Checked]) -> usize {
+    return checked.iter().filter(|checked| matches!(checked.outcome, Outcome::Broken(_) | Outcome::Failed(_))).count();
+}
+
+/// Checks the links to other sites in every blog's local posts, for `blog check-links`.
+pub async fn check_all(config: &BlogConfig, tenants: &Tenants) -> Result<Vec<Checked>, String> {
+    let mut hosts: BTreeMap<String, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+    for tenant in &tenants.0 {
+        let (posts, markdown) = read_posts(tenant.source.local())?;
+        let public_url = tenant.public_url.as_deref().unwrap_or(&config.public_url);
+        for (host, links) in external_links(&posts, &markdown, public_url, &config.link_check_allow) {
+            let known = hosts.entry(host).or_default();
+            for (url, slugs) in links {
+                known.entry(url).or_default().extend(slugs);
+            }
+        }
+    }
+    return check_external(hosts, config.fetch_concurrency, Duration::from_millis(config.link_check_delay_ms)).await;
+}
+
+/// The local posts' markdown, by path.
+pub fn read_posts(local: &LocalSource) -> Result<(Vec<Post>, HashMap<String, String>), String> {
+    let posts = to_posts(&local.get_manifest()?);
+    let markdown: HashMap<String, String> = posts.iter()
+        .filter_map(|post| local.read_content(&post.path).ok().map(|markdown| (post.path.to_owned(), markdown)))
+        .collect();
+    return Ok((posts, markdown));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use chrono::{TimeZone, Utc};
+    use crate::blog::Kind;
+
+    const PUBLIC_URL: &str = "https://www.hacklewayne.com";
+
+    fn post(slug: &str) -> Post {
+        return Post {
+            slug: slug.to_owned(),
+            title: slug.to_owned(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2024, 2, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+    }
+
+    #[test]
+    fn test_links() {
+        let markdown = "See [fin](/fin) and ![scan](/static/scan.png).\n\n```\n[not](/a-link)\n```\n\n[ref]: ./zip-is-scan.md#laziness\n\n[again][ref]";
+        assert_eq!(links(markdown), vec!["/fin", "/static/scan.png", "./zip-is-scan.md#laziness"]);
+        assert_eq!(anchors(r#"<h2 id="laziness">Laziness</h2><a name="top"></a>"#), vec!["laziness", "top"]);
+    }
+
+    #[test]
+    fn test_internal() {
+        let to = |post: Option<&str>, anchor: Option<&str>| Some(Internal { post: post.map(String::from), anchor: anchor.map(String::from) });
+        assert_eq!(internal("/fin", PUBLIC_URL), to(Some("fin"), None));
+        assert_eq!(internal("fin", PUBLIC_URL), to(Some("fin"), None));
+        assert_eq!(internal("./zip-is-scan.md#laziness", PUBLIC_URL), to(Some("zip-is-scan"), Some("laziness")));
+        assert_eq!(internal("https://www.hacklewayne.com/fin?ref=x#", PUBLIC_URL), to(Some("fin"), None));
+        assert_eq!(internal("#laziness", PUBLIC_URL), to(None, Some("laziness")));
+
+        assert_eq!(internal("https://github.com/hackle", PUBLIC_URL), None);
+        assert_eq!(internal("https://www.hacklewayne.com.au/fin", PUBLIC_URL), None);
+        assert_eq!(internal("mailto:hackle@hacklewayne.com", PUBLIC_URL), None);
+        assert_eq!(internal("//cdn.example.com/fin", PUBLIC_URL), None);
+        assert_eq!(internal("/", PUBLIC_URL), None);
+        assert_eq!(internal("/archive", PUBLIC_URL), None);
+        assert_eq!(internal("/static/scan.png", PUBLIC_URL), None);
+        assert_eq!(internal("/feeds.opml", PUBLIC_URL), None);
+    }
+
+    #[test]
+    fn test_dead_links() {
+        let posts = vec![post("fin"), post("zip-is-scan")];
+        let markdown = HashMap::from([
+            (String::from("fin.md"), String::from("[zip](/zip-is-scan#laziness), [gone](/nope), [top](#top), [zip again](zip-is-scan.md#strict)")),
+            (String::from("zip-is-scan.md"), String::from("## Laziness\n\n[fin](https://www.hacklewayne.com/fin) [elsewhere](https://example.com/nope)")),
+        ]);
+        let (checked, dead) = dead_links(&posts, &markdown, PUBLIC_URL);
+        assert_eq!(checked, 5);
+        assert_eq!(dead.iter().map(DeadLink::to_string).collect::<Vec<_>>(), vec![
+            "fin links to /zip-is-scan#laziness, no such anchor",
+            "fin links to /nope, no such post",
+            "fin links to #top, no such anchor",
+            "fin links to zip-is-scan.md#strict, no such anchor",
+        ]);
+    }
+
+    #[test]
+    fn test_external_links() {
+        assert_eq!(external_host("https://github.com/hackle", PUBLIC_URL), Some(String::from("github.com")));
+        assert_eq!(external_host("https://www.hacklewayne.com/fin", PUBLIC_URL), None);
+        assert_eq!(external_host("/fin", PUBLIC_URL), None);
+        assert_eq!(external_host("mailto:hackle@hacklewayne.com", PUBLIC_URL), None);
+        assert!(allowed("www.linkedin.com", "twitter.com, linkedin.com"));
+        assert!(!allowed("notlinkedin.com", "linkedin.com"));
+        assert!(!allowed("github.com", ""));
+
+        let posts = vec![post("fin"), post("zip-is-scan")];
+        let markdown = HashMap::from([
+            (String::from("fin.md"), String::from("[a](https://github.com/hackle) [b](https://github.com/hackle) [c](https://www.linkedin.com/in/hackle) [d](/zip-is-scan)")),
+            (String::from("zip-is-scan.md"), String::from("[a](https://github.com/hackle) ![e](http://example.com/scan.png)")),
+        ]);
+        let hosts = external_links(&posts, &markdown, PUBLIC_URL, "linkedin.com");
+        assert_eq!(hosts.keys().collect::<Vec<_>>(), vec!["example.com", "github.com"]);
+        assert_eq!(hosts["github.com"]["https://github.com/hackle"], vec!["fin", "zip-is-scan"]);
+    }
+
+    #[test]
+    fn test_report() {
+        assert_eq!(outcome(StatusCode::OK, None), Outcome::Ok);
+        assert_eq!(outcome(StatusCode::MOVED_PERMANENTLY, Some("https://example.com/new")), Outcome::Redirect(301, String::from("https://example.com/new")));
+        assert_eq!(outcome(StatusCode::NOT_FOUND, None), Outcome::Broken(404));
+
+        let checked = vec![
+            Checked { url: String::from("https://example.com/gone"), posts: vec![String::from("fin")], outcome: Outcome::Broken(404) },
+            Checked { url: String::from("https://example.com/old"), posts: vec![String::from("fin"), String::from("zip-is-scan")], outcome: Outcome::Redirect(301, String::from("https://example.com/new")) },
+            Checked { url: String::from("https://github.com/hackle"), posts: vec![String::from("fin")], outcome: Outcome::Ok },
+        ];
+        assert_eq!(report(&checked), "404 https://example.com/gone (in fin)\n301 https://example.com/old -> https://example.com/new (in fin, zip-is-scan)\n3 external links checked: 1 broken, 0 failed, 1 redirected");
+    }
+}
@@ -0,0 +1,103 @@
+//! With `markdown_lint`, posts are checked for what makes them harder to read with a screen reader or to skim: images
+//! without alt text, empty headings, headings that skip a level, and raw HTML at the end, which the renderer leaves
+//! out. Found when a post is loaded, in `validate` and `doctor`, and on a hidden post's page while it is proofread.
+use comrak::{parse_document, Arena};
+use comrak::nodes::{AstNode, NodeValue};
+use serde::Serialize;
+use tracing::warn;
+use crate::blog::{comrak_options, LocalSource};
+
+/// A problem in a post, at the line it starts on.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Lint {
+    pub line: u32,
+    pub message: String,
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "line {}: {}", self.line, self.message);
+    }
+}
+
+fn text<'a>(node: &'a AstNode<'a>) -> String {
+    return node.descendants()
+        .filter_map(|node| match &node.data.borrow().value {
+            NodeValue::Text(text) => Some(String::from_utf8_lossy(text).into_owned()),
+            NodeValue::Code(code) => Some(String::from_utf8_lossy(&code.literal).into_owned()),
+            _ => None,
+        })
+        .collect();
+}
+
+/// Inline nodes don't know their line, so the block they are in does.
+fn line<'a>(node: &'a AstNode<'a>) -> u32 {
+    return node.ancestors().map(|node| node.data.borrow().start_line).find(|line| *line > 0).unwrap_or(1);
+}
+
+pub fn lint(markdown: &str) -> Vec<Lint> {
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, comrak_options());
+    let mut lints = vec![];
+    let mut level = 1;
+
+    for node in root.descendants() {
+        let lint = |message: String| Lint { line: line(node), message };
+        match &node.data.borrow().value {
+            NodeValue::Image(image) if text(node).trim().is_empty() => {
+                lints.push(lint(format!("image {} has no alt text", String::from_utf8_lossy(&image.url))));
+            },
+            NodeValue::Heading(heading) => {
+                if text(node).trim().is_empty() {
+                    lints.push(lint(String::from("empty heading")));
+                }
+                if heading.level > level + 1 {
+                    lints.push(lint(format!("heading level {} skips from {}", heading.level, level)));
+                }
+                level = heading.level;
+            },
+            _ => {},
+        }
+    }
+    if let Some(last) = root.last_child().filter(|last| matches!(last.data.borrow().value, NodeValue::HtmlBlock(_))) {
+        lints.push(Lint { line: line(last), message: String::from("ends in raw HTML, which isn't rendered") });
+    }
+    return lints;
+}
+
+/// Logs what linting `markdown` finds, as a post is loaded.
+pub fn log(slug: &str, markdown: &str) {
+    for lint in lint(markdown) {
+        warn!(%slug, line = lint.line, lint = %lint.message, "markdown lint");
+    }
+}
+
+/// Every local post without lint, for the checklist; what is found is a warning, not a failure.
+pub fn check_lint(local: &LocalSource) -> Result<String, String> {
+    let manifest = local.get_manifest()?;
+    let found: Vec<String> = manifest.iter()
+        .filter_map(|registry| local.read_content(&registry.markdown).ok().map(|markdown| (registry, lint(&markdown))))
+        .flat_map(|(registry, lints)| lints.into_iter().map(move |lint| format!("{} {}", registry.markdown, lint)))
+        .collect();
+
+    return if found.is_empty() { Ok(format!("no lint in {} posts", manifest.len())) } else { Err(found.join("; ")) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        assert_eq!(lint("# Fin\n\nThe end, ![the end](/static/fin.png).\n\n## Why\n\n### How\n"), vec![]);
+
+        let markdown = "Intro\n\n![](/static/scan.png)\n\n##\n\n#### Deep\n\nText\n\n<div class=\"embed\"></div>\n";
+        let lints: Vec<String> = lint(markdown).iter().map(Lint::to_string).collect();
+        assert_eq!(lints, vec![
+            "line 3: image /static/scan.png has no alt text",
+            "line 5: empty heading",
+            "line 7: heading level 4 skips from 2",
+            "line 11: ends in raw HTML, which isn't rendered",
+        ]);
+    }
+}
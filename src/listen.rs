@@ -0,0 +1,246 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::stream::{self, Stream};
+use hyper::server::accept;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Server};
+use rocket::http::{Cookie, Header, Method};
+use rocket::local::asynchronous::Client;
+use rocket::tokio::io::{AsyncRead, AsyncWrite};
+use rocket::tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use rocket::tokio::signal::unix::{signal, SignalKind};
+use rocket::tokio::time::timeout;
+use rocket::{Build, Rocket, Shutdown};
+use tracing::{info, warn};
+use crate::config::BlogConfig;
+
+/// The first descriptor systemd passes down, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A socket Rocket can't bind itself, where the blog listens instead of Rocket's `address` and `port`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Listen {
+    Unix { path: PathBuf, mode: u32 },
+    /// Opened by systemd socket activation (`Accept=no`), TCP or Unix.
+    Systemd(RawFd),
+}
+
+/// The descriptor systemd passed to this process, when `LISTEN_PID` is ours. Only the first of `LISTEN_FDS` is used.
+pub fn systemd_fd(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<RawFd> {
+    let listen_pid: u32 = listen_pid?.parse().ok()?;
+    let listen_fds: u32 = listen_fds?.parse().ok()?;
+    return if listen_pid == pid && listen_fds >= 1 { Some(SD_LISTEN_FDS_START) } else { None };
+}
+
+impl Listen {
+    /// A socket passed by systemd comes first, then `unix_socket`; `None` leaves it to Rocket.
+    pub fn from_config(config: &BlogConfig) -> Option<Listen> {
+        let env = |name: &str| std::env::var(name).ok();
+        if let Some(fd) = systemd_fd(env("LISTEN_PID").as_deref(), env("LISTEN_FDS").as_deref(), std::process::id()) {
+            return Some(Listen::Systemd(fd));
+        }
+
+        return config.unix_socket.as_ref().map(|path| Listen::Unix { path: PathBuf::from(path), mode: config.socket_mode() });
+    }
+}
+
+/// Binds `path`, replacing the socket a previous run left behind but nothing else, and opens it to `mode`
+/// so the proxy in front can connect.
+fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener, String> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(path).map_err(|err| format!("Cannot remove {}, {}", path.display(), err))?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|err| format!("Cannot bind {}, {}", path.display(), err))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).map_err(|err| format!("Cannot set the mode of {}, {}", path.display(), err))?;
+    return Ok(listener);
+}
+
+enum Passed {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// The listening socket systemd passed as `fd`; only a TCP socket has a local `SocketAddr`.
+fn from_systemd(fd: RawFd) -> Result<Passed, String> {
+    // SAFETY: systemd hands this process `fd`, open and listening, and nothing else here takes it
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true).map_err(|err| err.to_string())?;
+        return TcpListener::from_std(tcp).map(Passed::Tcp).map_err(|err| err.to_string());
+    }
+
+    // SAFETY: the same descriptor, handed over rather than closed
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    unix.set_nonblocking(true).map_err(|err| err.to_string())?;
+    return UnixListener::from_std(unix).map(Passed::Unix).map_err(|err| err.to_string());
+}
+
+fn tcp_incoming(listener: TcpListener) -> impl Stream<Item = std::io::Result<TcpStream>> {
+    return stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await.map(|(stream, _)| stream);
+        Some((accepted, listener))
+    });
+}
+
+fn unix_incoming(listener: UnixListener) -> impl Stream<Item = std::io::Result<UnixStream>> {
+    return stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await.map(|(stream, _)| stream);
+        Some((accepted, listener))
+    });
+}
+
+fn status_response(status: u16) -> hyper::Response<Body> {
+    let mut response = hyper::Response::new(Body::empty());
+    *response.status_mut() = hyper::StatusCode::from_u16(status).unwrap_or(hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    return response;
+}
+
+/// One request through the local client, as lambda_web does on Lambda; the response is buffered, not streamed.
+async fn dispatch(client: Arc<Client>, request: hyper::Request<Body>, remote: Option<SocketAddr>) -> Result<hyper::Response<Body>, Infallible> {
+    let (parts, body) = request.into_parts();
+    let method = match Method::from_str(parts.method.as_str()) {
+        Ok(method) => method,
+        Err(_) => return Ok(status_response(405))
+    };
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(_) => return Ok(status_response(400))
+    };
+
+    let uri = parts.uri.path_and_query().map(|uri| uri.as_str()).unwrap_or("/");
+    let mut request = client.req(method, uri).body(&body);
+    if let Some(remote) = remote {
+        request = request.remote(remote);
+    }
+    for (name, value) in &parts.headers {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue
+        };
+        if name == hyper::header::COOKIE {
+            for cookie in value.split(';').filter_map(|cookie| Cookie::parse_encoded(cookie.trim().to_owned()).ok()) {
+                request = request.cookie(cookie);
+            }
+        }
+        request = request.header(Header::new(name.as_str().to_owned(), value.to_owned()));
+    }
+
+    let response = request.dispatch().await;
+    let mut builder = hyper::Response::builder().status(response.status().code);
+    for header in response.headers().iter() {
+        builder = builder.header(header.name().as_str(), header.value());
+    }
+    let body = response.into_bytes().await.unwrap_or_default();
+
+    return Ok(builder.body(Body::from(body)).unwrap_or_else(|_| status_response(500)));
+}
+
+/// SIGTERM or ctrl-c starts the same graceful shutdown Rocket's own server would.
+async fn notify_on_signal(shutdown: Shutdown) {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => return warn!(error = %err, "cannot listen for SIGTERM")
+    };
+
+    rocket::tokio::select! {
+        _ = terminate.recv() => info!("received SIGTERM"),
+        _ = rocket::tokio::signal::ctrl_c() => info!("received ctrl-c"),
+    }
+    shutdown.notify();
+}
+
+async fn run<IO>(
+    client: Arc<Client>,
+    incoming: impl Stream<Item = std::io::Result<IO>> + Send,
+    remote: fn(&IO) -> Option<SocketAddr>,
+) -> Result<(), String>
+    where IO: AsyncRead + AsyncWrite + Unpin + Send + 'static
+{
+    let shutdown = client.rocket().shutdown();
+    let config = &client.rocket().config().shutdown;
+    let drain = Duration::from_secs(config.grace as u64 + config.mercy as u64);
+    rocket::tokio::spawn(notify_on_signal(shutdown.clone()));
+
+    let service_client = client.clone();
+    let make_service = make_service_fn(move |io: &IO| {
+        let client = service_client.clone();
+        let remote = remote(io);
+        async move { Ok::<_, Infallible>(service_fn(move |request| dispatch(client.clone(), request, remote))) }
+    });
+    let server = Server::builder(accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown.clone());
+    rocket::tokio::pin!(server);
+
+    // once shutdown starts nothing new is accepted, and in-flight requests get `grace` and `mercy` to finish
+    return rocket::tokio::select! {
+        result = &mut server => result.map_err(|err| err.to_string()),
+        _ = shutdown => match timeout(drain, server).await {
+            Ok(result) => result.map_err(|err| err.to_string()),
+            Err(_) => Err(format!("in-flight requests did not finish within {}s", drain.as_secs()))
+        },
+    };
+}
+
+/// Serves `rocket` on `listen` until SIGTERM or ctrl-c, and removes a socket file it created.
+pub async fn serve(rocket: Rocket<Build>, listen: Listen) -> Result<(), String> {
+    let client = Arc::new(Client::untracked(rocket).await.map_err(|err| err.to_string())?);
+
+    return match listen {
+        Listen::Unix { path, mode } => {
+            let listener = bind_unix(&path, mode)?;
+            info!(path = %path.display(), mode = format!("{:o}", mode), "listening on a unix socket");
+            let result = run(client, unix_incoming(listener), |_| None).await;
+            let _ = std::fs::remove_file(&path);
+            result
+        },
+        Listen::Systemd(fd) => match from_systemd(fd)? {
+            Passed::Tcp(listener) => {
+                info!(address = ?listener.local_addr().ok(), "listening on a tcp socket from systemd");
+                run(client, tcp_incoming(listener), |stream: &TcpStream| stream.peer_addr().ok()).await
+            },
+            Passed::Unix(listener) => {
+                info!("listening on a unix socket from systemd");
+                run(client, unix_incoming(listener), |_| None).await
+            },
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_fd() {
+        assert_eq!(systemd_fd(Some("42"), Some("1"), 42), Some(3));
+        assert_eq!(systemd_fd(Some("42"), Some("2"), 42), Some(3));
+        assert_eq!(systemd_fd(Some("41"), Some("1"), 42), None);
+        assert_eq!(systemd_fd(Some("42"), Some("0"), 42), None);
+        assert_eq!(systemd_fd(None, Some("1"), 42), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_bind_unix() {
+        let path = std::env::temp_dir().join(format!("blog-{}.sock", std::process::id()));
+        bind_unix(&path, 0o660).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        // a socket left behind is replaced
+        bind_unix(&path, 0o600).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        std::fs::write(&path, "").unwrap();
+        assert!(bind_unix(&path, 0o660).unwrap_err().ends_with("exists and is not a socket"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,88 +1,912 @@
+#![allow(clippy::needless_return, clippy::ptr_arg)]
+
+// rocket's route attribute re-exports a `uri!` macro per handler, which only counts as used from the crate root
+#[allow(unused_imports)]
+mod activitypub;
+#[allow(unused_imports)]
+mod admin;
+#[allow(unused_imports)]
+mod analytics;
+#[allow(unused_imports)]
+mod api;
+#[allow(unused_imports)]
+mod assets;
+mod auth;
+#[allow(unused_imports)]
+mod beacon;
 mod blog;
+mod body;
+mod cache;
+mod cache_backend;
+mod cache_control;
+mod cdn;
+mod cli;
+#[allow(unused_imports)]
+mod comments;
+mod compression;
+mod conditional;
+mod config;
+#[allow(unused_imports)]
+mod digest;
+mod cors;
+mod counters;
+mod doctor;
+mod engine;
+mod export;
+mod feed;
+#[cfg(feature = "gemini")]
+mod gemini;
+#[cfg(feature = "graphql")]
+#[allow(unused_imports)]
+mod graphql;
+#[cfg(feature = "images")]
+#[allow(unused_imports)]
+mod images;
+#[allow(unused_imports)]
+mod health;
+mod helpers;
+mod history;
+#[allow(unused_imports)]
+mod hooks;
+mod links;
+mod lint;
+mod listen;
+mod mail;
+mod markdown_options;
+mod microformats;
+#[allow(unused_imports)]
+mod micropub;
+mod minify;
+#[allow(unused_imports)]
+mod newsletter;
+#[allow(unused_imports)]
+mod oauth;
+#[allow(unused_imports)]
+mod opml;
+mod partials;
+#[allow(unused_imports)]
+mod prefs;
+mod preload;
+mod reporting;
+mod public_url;
+#[allow(unused_imports)]
+mod random;
+mod request_id;
+mod scaffold;
+#[allow(unused_imports)]
+mod reactions;
+mod search;
+mod security;
+#[allow(unused_imports)]
+mod service_worker;
+mod shortcodes;
+#[allow(unused_imports)]
+mod shortlinks;
+mod spam;
+mod store;
+mod telemetry;
+mod tenant;
+#[allow(unused_imports)]
+mod uploads;
+mod text;
+mod theme;
+#[allow(unused_imports)]
+mod version;
+mod views;
+mod watch;
+#[allow(unused_imports)]
+mod webmanifest;
+#[allow(unused_imports)]
+mod webmention;
 
-use blog::{build_rss};
+use auth::Challenge;
+use analytics::{AnalyticsProxy, AnalyticsTag};
+use assets::Assets;
+use beacon::Beacons;
+use blog::{build_rss, build_archive, build_index, ArchiveYear, Post, PostSummary, RenderCache};
+use cache_control::CacheControl;
+use cdn::{Cdn, SurrogateKeys};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use comments::{Comment, CommentNotifier, Comments};
+use cli::{Cli, Command};
+use compression::Compression;
+use config::{BlogConfig, IdentityLink, NavLink};
+use conditional::{ConditionalGet, Validated, WithETag, WithLastModified};
+use cors::Cors;
+use feed::Feed;
+use history::{Change, History};
+use lint::Lint;
+use listen::Listen;
+use mail::Mailer;
+use micropub::Repository;
+use minify::MinifyHtml;
+use newsletter::Newsletter;
+use prefs::{ColorScheme, ThemePreference};
+use preload::Preload;
+use public_url::PublicUrl;
+use reactions::{Reaction, Reactions};
+use reporting::{capture_error, ReportServerErrors};
+use request_id::{RequestId, RequestIds};
+use search::{SearchResponse, SearchResult};
+use security::{CspNonce, Embeddable, SecurityHeaders};
+use shortcodes::LinkCards;
+use spam::SpamFilter;
+use tenant::{Tenant, Tenants};
+use theme::Theme;
+use uploads::Uploads;
+use views::Views;
 use rocket::serde::{Serialize};
-use rocket::{routes, get};
+use rocket::{catch, catchers, routes, get, Build, Request, Rocket, State};
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Status};
+use rocket::request::FromParam;
 use std::string::String;
-use rocket_dyn_templates::Template;
-use std::collections::BTreeMap;
+use rocket_dyn_templates::{Metadata, Template};
+use std::path::{Path, PathBuf};
 use rocket::fs::{FileServer};
-use lambda_web::{is_running_on_lambda, launch_rocket_on_lambda, LambdaError};
-use rocket::response::content::Xml;
+use rocket::shield::Shield;
+#[cfg(feature = "search")]
+use rocket::fairing::AdHoc;
+#[cfg(feature = "lambda")]
+use lambda_web::{is_running_on_lambda, launch_rocket_on_lambda};
+use rocket::response::content::{Json, Xml};
+use std::time::{Duration, Instant};
+use tracing::{info, instrument, warn};
+use version::build_info;
+use activitypub::ActivityPub;
+use microformats::HEntry;
+use webmention::send::Sender;
+use webmention::{Mention, Webmentions};
 
 #[macro_use]
 extern crate rocket_include_static_resources;
 
+/// What `main` fails with, the same as the Lambda runtime's own error.
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
 #[derive(Serialize)]
-#[serde(untagged)]
-enum HandlebarsValue {
-    String(String),
-    Array(Vec<(String, String)>),
+struct ErrorContext {
+    title: Option<String>,
+    meta: String,
+    public_url: String,
+    request_id: String,
+    #[serde(flatten)]
+    chrome: Chrome,
+}
+
+fn error_context(tenant: &Tenant, request_id: &RequestId, nonce: &CspNonce, preference: &ThemePreference, public_url: &PublicUrl) -> ErrorContext {
+    return ErrorContext {
+        chrome: Chrome::new(tenant, None, nonce.to_owned(), preference.to_owned()),
+        title: None,
+        meta: String::from("Oh no! Something is not right"),
+        public_url: public_url.0.to_owned(),
+        request_id: request_id.0.to_owned(),
+    };
+}
+
+#[catch(default)]
+fn error_page(status: Status, request: &Request) -> (Status, Template) {
+    let tenant = Tenant::of(request);
+    let context = ErrorContext {
+        title: Some(status.reason().unwrap_or("Error").to_owned()),
+        ..error_context(tenant, &RequestId::of(request), &CspNonce::of(request), &ThemePreference::of(request), &PublicUrl::of(request))
+    };
+
+    return (status, Template::render(tenant.template("main"), &context));
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request) -> Challenge<(Status, Template)> {
+    return Challenge { inner: error_page(Status::Unauthorized, request) };
+}
+
+/// What every page's layout needs besides its own content: the blog, the links around it, and the scripts it loads.
+#[derive(Serialize)]
+struct Chrome {
+    site_title: String,
+    lang: String,
+    build: String,
+    csp_nonce: Option<String>,
+    color_scheme: Option<ColorScheme>,
+    nav: Vec<NavLink>,
+    footer: Vec<NavLink>,
+    me: Vec<IdentityLink>,
+    beacon: bool,
+    newsletter: bool,
+    analytics: Option<AnalyticsTag>,
+    theme_color: String,
+    service_worker: bool,
+}
+
+impl Chrome {
+    /// `lang` is the page's, `site_lang` when it lists every language.
+    fn new(tenant: &Tenant, lang: Option<&str>, nonce: CspNonce, preference: ThemePreference) -> Chrome {
+        let config = config::config();
+        return Chrome {
+            site_title: tenant.title.to_owned(),
+            lang: lang.unwrap_or(&config.site_lang).to_owned(),
+            build: build_info().summary(),
+            csp_nonce: nonce.0,
+            color_scheme: preference.0,
+            nav: config.nav.to_owned(),
+            footer: config.footer.to_owned(),
+            me: config.me.to_owned(),
+            beacon: beacon::enabled(),
+            newsletter: newsletter::enabled(),
+            analytics: analytics::tag(),
+            theme_color: config.theme_color.to_owned(),
+            service_worker: service_worker::enabled(),
+        };
+    }
+}
+
+#[derive(Serialize)]
+struct IndexContext {
+    title: String,
+    posts: Vec<PostSummary>,
+    page: usize,
+    total_pages: usize,
+    prev_page: Option<String>,
+    next_page: Option<String>,
+    #[serde(flatten)]
+    chrome: Chrome,
+    /// The feed of the languages listed.
+    feed: String,
+    micropub: Option<String>,
+}
+
+fn page_url(page: usize, lang: Option<&str>) -> String {
+    let path = if page <= 1 { String::from("/") } else { format!("/page/{}", page) };
+    return match lang {
+        Some(lang) => format!("{}?lang={}", path, lang),
+        None => path,
+    };
 }
 
-#[get("/health")]
-fn health() -> String { return String::from("OK") }
+/// `?lang=`, if it is a language tag; anything else lists every language.
+fn lang_filter(lang: Option<&str>) -> Option<&str> {
+    return lang.filter(|lang| blog::is_lang_tag(lang));
+}
 
-#[get("/")]
-async fn index() -> Template {
-    return blog_post("").await
+fn feed_url(lang: Option<&str>) -> String {
+    return match lang {
+        Some(lang) => format!("/rss/{}/index.xml", lang),
+        None => String::from("/rss/index.xml"),
+    };
+}
+
+#[get("/?<lang>")]
+async fn index(lang: Option<&str>, tenant: &Tenant, request_id: RequestId, nonce: CspNonce, preference: ThemePreference, public_url: PublicUrl) -> Option<Template> {
+    return index_page(1, lang, tenant, request_id, nonce, preference, public_url).await
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/page/<page>?<lang>")]
+#[instrument(skip(tenant, request_id, nonce, preference, public_url), fields(%request_id))]
+async fn index_page(page: usize, lang: Option<&str>, tenant: &Tenant, request_id: RequestId, nonce: CspNonce, preference: ThemePreference, public_url: PublicUrl) -> Option<Template> {
+    let lang = lang_filter(lang);
+    let index = build_index(&tenant.source.for_request(&request_id), page, lang).await;
+
+    return match index {
+        Err(err) => {
+            capture_error(&err, &[("request_id", &request_id.0)]);
+            Some(Template::render(tenant.template("main"), error_context(tenant, &request_id, &nonce, &preference, &public_url)))
+        },
+        Ok(None) => None,
+        Ok(Some(index)) => Some(Template::render(tenant.template("index"), &IndexContext {
+            chrome: Chrome::new(tenant, lang, nonce, preference),
+            feed: feed_url(lang),
+            micropub: micropub::endpoint(&public_url.0),
+            title: if page <= 1 { String::from("Home") } else { format!("Page {}", page) },
+            prev_page: Some(page - 1).filter(|prev| *prev >= 1).map(|prev| page_url(prev, lang)),
+            next_page: Some(page + 1).filter(|next| *next <= index.total_pages).map(|next| page_url(next, lang)),
+            posts: index.posts,
+            page: index.page,
+            total_pages: index.total_pages,
+        }))
+    };
 }
 
 #[get("/rss/index.xml")]
-async fn rss() -> Result<Xml<String>, String> {
-    return build_rss(
-        &std::env::var("REMOTE_MARKDOWN_PATH").map_err(|var_err| var_err.to_string())
-    ).await
-}
-
-#[get("/<slug>")]
-async fn blog_post(slug: &str) -> Template {
-    // if remote fails, use local anyway
-    let source = match std::env::var("REMOTE_MARKDOWN_PATH") {
-        Err(_) => Err(String::from("REMOTE_MARKDOWN_PATH not set")),
-        Ok(remote_url) => blog::load_remote(&remote_url, slug).await
-    }.or_else(|_| blog::load_local(slug));
-
-    let context: BTreeMap<&str, HandlebarsValue> =
-        if let Ok((current_post, all_posts, markdown)) = source {
-            let blog = blog::make_blog(&current_post, &all_posts, &markdown);
-
-             BTreeMap::from([
-                ("meta", HandlebarsValue::String(blog.content)),
-                ("title", HandlebarsValue::String(blog.current_post.title)),
-                ("description", HandlebarsValue::String(blog.description)),
-                ("slug", HandlebarsValue::String(blog.current_post.slug)),
-                ("see_also", HandlebarsValue::Array(blog.see_also)),
-                ("date_updated", HandlebarsValue::String(blog.date_updated))
-            ])
-        } else {
-            BTreeMap::from([
-                ("meta", HandlebarsValue::String(String::from("Oh no! Something is not right")))
-            ])
+#[instrument(skip(tenant, request_id, public_url), fields(%request_id))]
+async fn rss(tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Option<Validated<Xml<String>>>, String> {
+    let source = tenant.source.for_request(&request_id);
+    return tenant.feeds.get(&format!("{}/rss", public_url.0), &source, || build_rss(&source, &tenant.title, &tenant.description, &public_url.0, None, None, None)).await
+        .map(|feed| feed.map(Feed::respond))
+        .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0)]));
+}
+
+/// An archive of the feed at `/rss/page/<n>.xml`, numbered from 1.
+struct FeedArchive(usize);
+
+impl<'r> FromParam<'r> for FeedArchive {
+    type Error = &'r str;
+
+    fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        return param.strip_suffix(".xml")
+            .and_then(|page| page.parse().ok())
+            .filter(|page| *page > 0)
+            .map(FeedArchive)
+            .ok_or(param);
+    }
+}
+
+#[get("/rss/page/<page>", rank = 2)]
+#[instrument(skip(page, tenant, request_id, public_url), fields(%request_id, page = page.0))]
+async fn rss_archive(page: FeedArchive, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Option<Validated<Xml<String>>>, String> {
+    let source = tenant.source.for_request(&request_id);
+    return tenant.feeds.get(&format!("{}/rss/page/{}", public_url.0, page.0), &source, || build_rss(&source, &tenant.title, &tenant.description, &public_url.0, None, None, Some(page.0))).await
+        .map(|feed| feed.map(Feed::respond))
+        .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0)]));
+}
+
+#[get("/rss/<lang>/index.xml")]
+#[instrument(skip(tenant, request_id, public_url), fields(%request_id))]
+async fn rss_lang(lang: &str, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Option<Validated<Xml<String>>>, String> {
+    if !blog::is_lang_tag(lang) {
+        return Ok(None);
+    }
+    let source = tenant.source.for_request(&request_id);
+    return tenant.feeds.get(&format!("{}/rss/{}", public_url.0, lang), &source, || build_rss(&source, &tenant.title, &tenant.description, &public_url.0, Some(lang), None, None)).await
+        .map(|feed| feed.map(Feed::respond))
+        .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0), ("lang", lang)]));
+}
+
+#[get("/rss/tags/<tag>/index.xml")]
+#[instrument(skip(tenant, request_id, public_url), fields(%request_id))]
+async fn rss_tag(tag: &str, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Option<Validated<Xml<String>>>, String> {
+    let source = tenant.source.for_request(&request_id);
+    return tenant.feeds.get(&format!("{}/rss/tags/{}", public_url.0, tag), &source, || build_rss(&source, &tenant.title, &tenant.description, &public_url.0, None, Some(tag), None)).await
+        .map(|feed| feed.map(Feed::respond))
+        .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0), ("tag", tag)]));
+}
+
+#[get("/sitemap.xml")]
+#[instrument(skip(tenant, request_id, public_url), fields(%request_id))]
+async fn sitemap(tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Option<Validated<Xml<String>>>, String> {
+    let source = tenant.source.for_request(&request_id);
+    return tenant.feeds.get(&format!("{}/sitemap.xml", public_url.0), &source, || export::build_sitemap(&source, &public_url)).await
+        .map(|sitemap| sitemap.map(Feed::respond))
+        .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0)]));
+}
+
+#[derive(Serialize)]
+struct ArchiveContext {
+    title: String,
+    years: Vec<ArchiveYear>,
+    #[serde(flatten)]
+    chrome: Chrome,
+}
+
+#[get("/archive?<lang>")]
+#[instrument(skip(tenant, request_id, nonce, preference, public_url), fields(%request_id))]
+async fn archive(lang: Option<&str>, tenant: &Tenant, request_id: RequestId, nonce: CspNonce, preference: ThemePreference, public_url: PublicUrl) -> Template {
+    let lang = lang_filter(lang);
+    let archive = build_archive(&tenant.source.for_request(&request_id), lang).await;
+
+    return match archive {
+        Ok(years) => Template::render(tenant.template("archive"), &ArchiveContext {
+            chrome: Chrome::new(tenant, lang, nonce, preference),
+            title: String::from("Archive"),
+            years,
+        }),
+        Err(err) => {
+            capture_error(&err, &[("request_id", &request_id.0)]);
+            Template::render(tenant.template("main"), error_context(tenant, &request_id, &nonce, &preference, &public_url))
+        }
+    };
+}
+
+#[derive(Serialize)]
+struct SearchContext {
+    title: String,
+    query: String,
+    results: Vec<SearchResult>,
+    #[serde(flatten)]
+    chrome: Chrome,
+}
+
+#[get("/search?<q>")]
+#[instrument(skip(tenant, request_id, nonce, preference, public_url), fields(%request_id))]
+async fn search_page(q: Option<&str>, tenant: &Tenant, request_id: RequestId, nonce: CspNonce, preference: ThemePreference, public_url: PublicUrl) -> Template {
+    let query = q.unwrap_or_default().trim();
+
+    return match tenant.search_engine.search(&tenant.source.for_request(&request_id), query).await {
+        Ok(results) => Template::render(tenant.template("search"), &SearchContext {
+            chrome: Chrome::new(tenant, None, nonce, preference),
+            title: if query.is_empty() { String::from("Search") } else { format!("Search: {}", query) },
+            query: query.to_owned(),
+            results,
+        }),
+        Err(err) => {
+            capture_error(&err, &[("request_id", &request_id.0), ("query", query)]);
+            Template::render(tenant.template("main"), error_context(tenant, &request_id, &nonce, &preference, &public_url))
+        }
+    };
+}
+
+#[get("/api/search?<q>")]
+#[instrument(skip(tenant, request_id), fields(%request_id))]
+async fn api_search(q: Option<&str>, tenant: &Tenant, request_id: RequestId) -> Result<Json<String>, String> {
+    let query = q.unwrap_or_default().trim();
+    let results = tenant.search_engine.search(&tenant.source.for_request(&request_id), query).await?;
+
+    return serde_json::to_string(&SearchResponse { query: query.to_owned(), results })
+        .map(Json)
+        .map_err(|err| err.to_string());
+}
+
+#[get("/search-index.json")]
+async fn search_index(tenant: &Tenant, request_id: RequestId) -> Result<WithETag<Json<String>>, String> {
+    return tenant.search_index.json(&tenant.source.for_request(&request_id)).await
+        .map(|(etag, json)| WithETag::new(etag, Json(json)));
+}
+
+/// Only matches segments ending in `.md` or `.txt`, so that `/<slug>.md` and `/<slug>.txt`
+/// are tried before the post itself.
+enum PostFile<'r> {
+    Markdown(&'r str),
+    Text(&'r str),
+}
+
+impl<'r> FromParam<'r> for PostFile<'r> {
+    type Error = &'r str;
+
+    fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        let file = param.strip_suffix(".md").map(PostFile::Markdown)
+            .or_else(|| param.strip_suffix(".txt").map(PostFile::Text));
+
+        return match file {
+            Some(PostFile::Markdown(slug)) | Some(PostFile::Text(slug)) if slug.is_empty() => Err(param),
+            Some(file) => Ok(file),
+            None => Err(param)
         };
+    }
+}
+
+#[get("/<file>", rank = 1)]
+async fn post_file(file: PostFile<'_>, tenant: &Tenant, request_id: RequestId) -> Result<Option<(ContentType, String)>, String> {
+    let source = tenant.source.for_request(&request_id);
+    return match file {
+        PostFile::Markdown(slug) => Ok(source.markdown(slug).await
+            .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0), ("slug", slug)]))?
+            .map(|markdown| (ContentType::with_params("text", "markdown", ("charset", "utf-8")), markdown))),
+        PostFile::Text(slug) => Ok(source.markdown(slug).await
+            .inspect_err(|err| capture_error(err, &[("request_id", &request_id.0), ("slug", slug)]))?
+            .map(|markdown| (ContentType::Plain, text::markdown_to_plain_text(&markdown, text::TEXT_WIDTH)))),
+    };
+}
+
+/// Up to `popular_limit` of the listed posts most viewed lately, as (title, slug), but for `current_post`.
+async fn popular(views: &Views, tenant: &Tenant, all_posts: &[Post], current_post: &Post) -> Vec<(String, String)> {
+    let listed: Vec<Post> = all_posts.iter().filter(|post| post.is_listed() && post.slug != current_post.slug).cloned().collect();
+    return match views.popular(tenant, &listed).await {
+        Some(Ok(popular)) => popular.into_iter().take(config::config().popular_limit).map(|viewed| (viewed.title, viewed.slug)).collect(),
+        Some(Err(err)) => {
+            warn!(error = %err, "cannot read popular posts");
+            vec![]
+        },
+        None => vec![]
+    };
+}
+
+#[derive(Serialize)]
+struct PostContext {
+    meta: String,
+    title: String,
+    description: String,
+    public_url: String,
+    slug: String,
+    short_link: String,
+    featured: bool,
+    kind: &'static str,
+    alternates: Vec<(String, String)>,
+    see_also: Vec<(String, String)>,
+    popular: Vec<(String, String)>,
+    date_updated: String,
+    updated: String,
+    word_count: usize,
+    reading_time: usize,
+    webmentions: Vec<Mention>,
+    /// The endpoint to send webmentions to, when they are taken.
+    webmention: Option<String>,
+    comments: Vec<Comment>,
+    comments_open: bool,
+    reactions: Vec<Reaction>,
+    reactions_open: bool,
+    views: Option<u64>,
+    h_entry: Option<HEntry>,
+    hero: Option<String>,
+    edit_url: Option<String>,
+    history: Vec<Change>,
+    lint: Vec<Lint>,
+    #[serde(flatten)]
+    chrome: Chrome,
+}
+
+/// A post's page, or the error page in its place when it cannot be loaded.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum PostPage {
+    Post(Box<PostContext>),
+    Error(Box<ErrorContext>),
+}
+
+/// A post's context, for its page and its plain version alike, with when it was updated and the template it names.
+#[allow(clippy::too_many_arguments)]
+async fn post_context(slug: &str, tenant: &Tenant, renderer: &RenderCache, link_cards: &LinkCards, webmentions: &Webmentions, comments: &Comments, reactions: &Reactions, views: &Views, history: &History, request_id: &RequestId, nonce: &CspNonce, preference: &ThemePreference, public_url: &PublicUrl) -> (Option<DateTime<Utc>>, Option<String>, PostPage) {
+    let mut last_modified = None;
+    let mut template = None;
+    let context = match tenant.source.for_request(request_id).load(slug).await {
+        Ok((current_post, all_posts, markdown)) => {
+            last_modified = Some(current_post.updated);
+            template = current_post.template.to_owned();
+            renderer.warm(&markdown).await;
+            let blog = blog::make_blog(&current_post, &all_posts, &markdown, renderer);
+            let content = link_cards.expand(&blog.content).await;
+            let mentions = webmentions.approved(&format!("{}/{}", public_url.0, blog.current_post.slug)).await;
+            let comments_open = comments.open() && blog.current_post.kind == blog::Kind::Post;
+            let approved = comments.approved(tenant, &blog.current_post.slug).await;
+            let reactions_open = reactions.open() && blog.current_post.kind == blog::Kind::Post;
+            let counts = reactions.of(tenant, &blog.current_post.slug).await;
+            let viewed = views.count(tenant, &blog.current_post.slug).await;
+            let popular = popular(views, tenant, &all_posts, &blog.current_post).await;
+            let changes = history.of(&tenant.source, &blog.current_post).await;
+            // a hidden post is only seen while it is proofread, so its lint is shown on it
+            let lints = if blog.current_post.hidden && config::config().markdown_lint { lint::lint(&markdown) } else { vec![] };
+            let h_entry = HEntry::of(&blog.current_post, &blog.description, &public_url.0, &tenant.title);
+            let short_link = shortlinks::short_url(&public_url.0, &blog.current_post);
+            let edit_url = tenant.source.remote().and_then(Repository::of).map(|repository| repository.edit_url(&blog.current_post.path));
+            let hero = blog.current_post.cover.as_ref().map(|cover| blog::absolute_url(&public_url.0, cover));
 
-    Template::render("main", &context)
+            PostPage::Post(Box::new(PostContext {
+                chrome: Chrome::new(tenant, Some(blog.current_post.lang()), nonce.to_owned(), preference.to_owned()),
+                meta: content,
+                title: blog.current_post.title,
+                description: blog.description,
+                public_url: public_url.0.to_owned(),
+                slug: blog.current_post.slug,
+                short_link,
+                featured: blog.current_post.pinned,
+                kind: blog.current_post.kind.as_str(),
+                alternates: blog.alternates,
+                see_also: blog.see_also,
+                popular,
+                date_updated: blog.date_updated,
+                updated: blog.current_post.updated.to_rfc3339(),
+                word_count: blog.word_count,
+                reading_time: blog.reading_time,
+                webmentions: mentions,
+                webmention: webmentions.endpoint(public_url),
+                comments: approved,
+                comments_open,
+                reactions: counts,
+                reactions_open,
+                views: viewed,
+                h_entry,
+                hero,
+                edit_url,
+                history: changes,
+                lint: lints,
+            }))
+        },
+        Err(err) => {
+            capture_error(&err, &[("request_id", &request_id.0), ("slug", slug)]);
+            PostPage::Error(Box::new(error_context(tenant, request_id, nonce, preference, public_url)))
+        }
+    };
+
+    return (last_modified, template, context);
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get("/<slug>", rank = 2)]
+#[instrument(skip(tenant, renderer, link_cards, webmentions, comments, reactions, views, history, request_id, nonce, preference, public_url), fields(%request_id))]
+async fn blog_post(slug: &str, tenant: &Tenant, renderer: &State<RenderCache>, link_cards: &State<LinkCards>, webmentions: &State<Webmentions>, comments: &State<Comments>, reactions: &State<Reactions>, views: &State<Views>, history: &State<History>, request_id: RequestId, nonce: CspNonce, preference: ThemePreference, public_url: PublicUrl) -> WithLastModified<Template> {
+    let (last_modified, template, context) = post_context(slug, tenant, renderer, link_cards, webmentions, comments, reactions, views, history, &request_id, &nonce, &preference, &public_url).await;
+    WithLastModified::new(last_modified, Template::render(tenant.template(template.as_deref().unwrap_or("main")), &context))
+}
+
+/// The post alone, without the nav, footer or stylesheet around it, for printing and embedding. A blog's own
+/// templates directory may have a `plain` of its own; otherwise it is the blog's.
+#[allow(clippy::too_many_arguments)]
+#[get("/<slug>/plain", rank = 2)]
+#[instrument(skip(tenant, renderer, link_cards, webmentions, comments, reactions, views, history, request_id, nonce, preference, public_url, metadata), fields(%request_id))]
+async fn blog_post_plain(slug: &str, tenant: &Tenant, renderer: &State<RenderCache>, link_cards: &State<LinkCards>, webmentions: &State<Webmentions>, comments: &State<Comments>, reactions: &State<Reactions>, views: &State<Views>, history: &State<History>, request_id: RequestId, nonce: CspNonce, preference: ThemePreference, public_url: PublicUrl, metadata: Metadata<'_>) -> Embeddable<WithLastModified<Template>> {
+    let (last_modified, _, context) = post_context(slug, tenant, renderer, link_cards, webmentions, comments, reactions, views, history, &request_id, &nonce, &preference, &public_url).await;
+    let template = Some(tenant.template("plain")).filter(|template| metadata.contains_template(template)).unwrap_or_else(|| String::from("plain"));
+    Embeddable(WithLastModified::new(last_modified, Template::render(template, &context)))
 }
 
 static_response_handler! {
     "/favicon.ico" => favicon => "favicon",
 }
 
-#[rocket::main]
-async fn main() -> Result<(), LambdaError> {
-    let rocket = rocket::build()
+/// The validated configuration and the blogs it describes; exits with a readable message when it isn't valid.
+fn load(figment: &Figment) -> (&'static BlogConfig, Tenants) {
+    let config = match BlogConfig::from_figment(figment) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    config::init(config);
+    let config = config::config();
+    let tenants = or_exit(Tenants::from_config(config));
+
+    return (config, tenants);
+}
+
+/// What something set up from the configuration gave; exits with a readable message when it couldn't be set up.
+fn or_exit<T>(result: Result<T, String>) -> T {
+    return match result {
+        Ok(value) => value,
+        Err(message) => {
+            eprintln!("Invalid configuration: {}", message);
+            std::process::exit(1);
+        }
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build(figment: Figment, config: &BlogConfig, tenants: Tenants, renderer: RenderCache, cdn: Cdn, webmentions: Webmentions, comments: Comments, reactions: Reactions, views: Views, beacons: Beacons, newsletter: Newsletter, spam: SpamFilter, mailer: Mailer, sender: Sender, activitypub: ActivityPub, uploads: Uploads) -> Rocket<Build> {
+    let api_base = format!("/api/{}", api::API_VERSION);
+    let template_dir = doctor::template_dir(&figment);
+    let theme = or_exit(Theme::from_config(config));
+    let theme_static = theme.as_ref().map(Theme::static_dir).filter(|directory| directory.is_dir());
+    let assets = Assets::new(Path::new("static"), theme.as_ref());
+    let preload = Preload::from_config(config, &assets);
+    let rocket = rocket::custom(figment)
         .attach(static_resources_initializer!(
             "favicon" => "static/favicon.ico",
         ))
+        .manage(tenants)
+        .manage(renderer)
+        .manage(cdn)
+        .manage(LinkCards::default())
+        .manage(History::from_config(config))
+        .manage(webmentions)
+        .manage(comments)
+        .manage(reactions)
+        .manage(views.clone())
+        .manage(beacons)
+        .manage(newsletter)
+        .manage(AnalyticsProxy::from_config(config))
+        .manage(spam)
+        .manage(CommentNotifier::from_config(config, mailer.clone()))
+        .manage(mailer)
+        .manage(sender)
+        .manage(activitypub)
+        .manage(uploads)
+        .manage(assets)
         .mount("/static", FileServer::from("static"))
-        .mount("/", routes![favicon, health, index, rss, blog_post])
-        .attach(Template::fairing());
+        .mount("/", routes![favicon, index, index_page, rss, rss_archive, rss_lang, rss_tag, sitemap, archive, search_page, api_search, search_index, post_file, blog_post, blog_post_plain])
+        .mount("/", assets::routes())
+        .mount("/", health::routes())
+        .mount("/", version::routes())
+        .mount("/", random::routes())
+        .mount("/", opml::routes())
+        .mount("/", webmanifest::routes())
+        .mount("/", service_worker::routes())
+        .mount("/", uploads::routes())
+        .mount("/", shortlinks::routes())
+        .mount("/", beacon::routes())
+        .mount("/", newsletter::routes())
+        .mount(config.analytics_proxy_path.as_str(), analytics::routes())
+        .mount("/", hooks::routes())
+        .mount("/admin", admin::routes())
+        .mount("/admin", webmention::admin_routes())
+        .mount("/admin", comments::admin_routes())
+        .mount("/admin", beacon::admin_routes())
+        .mount("/admin", newsletter::admin_routes())
+        .mount("/admin", digest::admin_routes())
+        .mount("/admin", shortlinks::admin_routes())
+        .mount("/", webmention::routes())
+        .mount("/", comments::routes())
+        .mount("/", reactions::routes())
+        .mount("/", activitypub::routes())
+        .mount("/", micropub::routes())
+        .mount("/", oauth::routes())
+        .mount("/", prefs::routes())
+        .mount(api_base.as_str(), api::routes())
+        .register("/", catchers![error_page, unauthorized])
+        .register(api_base.as_str(), api::catchers())
+        .attach(RequestIds)
+        // answers preflights before anything else sees their 404
+        .attach(Cors::from_config(config))
+        .attach(telemetry::RequestLog)
+        .attach(views)
+        .attach(ReportServerErrors)
+        .attach(engine::fairing(or_exit(engine::from_config(config)), template_dir, theme));
 
+    // the theme's static files come first, falling through to the blog's own for those it hasn't
+    let rocket = match theme_static {
+        Some(directory) => rocket.mount("/static", FileServer::from(directory).rank(9)),
+        None => rocket
+    };
+    let rocket = if minify::enabled() { rocket.attach(MinifyHtml) } else { rocket };
+
+    let rocket = rocket
+        .attach(CacheControl::from_config(config))
+        .attach(SurrogateKeys)
+        // an empty Shield, so rocket's own defaults don't preempt the configured values
+        .attach(Shield::new())
+        .attach(SecurityHeaders::from_config(config))
+        .attach(preload)
+        .attach(ConditionalGet)
+        .attach(Compression);
+
+    #[cfg(feature = "graphql")]
+    let rocket = rocket
+        .manage(graphql::schema())
+        .mount("/", graphql::routes());
+
+    #[cfg(feature = "images")]
+    let rocket = rocket
+        .manage(images::ImageCache::from_config(config))
+        .mount("/", images::routes());
+
+    #[cfg(feature = "search")]
+    let rocket = rocket.attach(AdHoc::on_liftoff("Search index", |rocket| Box::pin(async move {
+        if let Some(tenants) = rocket.state::<Tenants>() {
+            for tenant in &tenants.0 {
+                let _ = tenant.search_engine.refresh(&tenant.source).await;
+            }
+        }
+    })));
+
+    return rocket;
+}
+
+async fn serve(figment: Figment) -> Result<(), Error> {
+    let (config, tenants) = load(&figment);
+    let backend = or_exit(cache_backend::from_config(config).await);
+    let tenants = tenants.with_backend(backend.clone());
+    let cdn = or_exit(Cdn::from_config(config).await);
+    let uploads = or_exit(Uploads::from_config(config).await);
+    let webmentions = or_exit(Webmentions::from_config(config).await);
+    let comments = or_exit(Comments::from_config(config).await);
+    let reactions = or_exit(Reactions::from_config(config).await);
+    let views = or_exit(Views::from_config(config).await);
+    let beacons = or_exit(Beacons::from_config(config).await);
+    let newsletter = or_exit(Newsletter::from_config(config).await);
+    let spam = or_exit(SpamFilter::from_config(config));
+    let mailer = or_exit(Mailer::from_config(config).await);
+    let sender = or_exit(Sender::from_config(config));
+    let activitypub = or_exit(ActivityPub::from_config(config));
+
+    // misconfigured deployments stop here, before any traffic arrives
+    let checks = doctor::checklist(config, &tenants, &doctor::template_dir(&figment)).await;
+    doctor::log(&checks);
+    if !doctor::passed(&checks) {
+        eprintln!("{}", doctor::render(&checks));
+        std::process::exit(1);
+    }
+
+    // renders the newest posts before the first request, so a cold start doesn't also pay for fetching and rendering
+    let renderer = RenderCache::default().with_backend(backend.clone());
+    let budget = Duration::from_millis(config.prerender_budget_ms);
+    if !budget.is_zero() {
+        let started = Instant::now();
+        for tenant in &tenants.0 {
+            let remaining = budget.saturating_sub(started.elapsed());
+            match blog::prerender(&tenant.source, &renderer, remaining).await {
+                Ok(rendered) => info!(tenant = %tenant.title, rendered, elapsed_ms = started.elapsed().as_millis() as u64, "prerendered"),
+                Err(err) => warn!(tenant = %tenant.title, %err, "prerender skipped"),
+            }
+        }
+    }
+
+    let interval = Duration::from_millis(config.watch_local_ms);
+    if !interval.is_zero() {
+        for tenant in tenants.0.iter().filter(|tenant| tenant.source.remote().is_none()) {
+            let public_url = tenant.public_url.to_owned().unwrap_or_else(|| config.public_url.to_owned());
+            rocket::tokio::spawn(watch::watch(tenant.source.clone(), tenant.search_index.clone(), tenant.feeds.clone(), cdn.clone(), sender.clone(), activitypub.clone(), public_url, interval));
+        }
+    }
+
+    if let Some(listen) = &config.gemini_listen {
+        #[cfg(feature = "gemini")]
+        {
+            let cert = config.gemini_cert.as_deref().unwrap_or_default();
+            let key = config.gemini_key.as_deref().unwrap_or_default();
+            let acceptor = or_exit(gemini::acceptor(cert, key));
+            rocket::tokio::spawn(gemini::serve(listen.to_owned(), acceptor, gemini::Capsule::all(config, &tenants)));
+        }
+        #[cfg(not(feature = "gemini"))]
+        return Err(Error::from(format!("gemini_listen is {}, but built without the gemini feature", listen)));
+    }
+
+    let rocket = build(figment, config, tenants, renderer, cdn, webmentions, comments, reactions, views, beacons, newsletter, spam, mailer, sender, activitypub, uploads);
+    #[cfg(feature = "lambda")]
     if is_running_on_lambda() {
-        launch_rocket_on_lambda(rocket).await?;
-    } else {
-        rocket.launch().await?;
+        return launch_rocket_on_lambda(rocket).await;
+    }
+    // without the feature the runtime would wait for an invocation loop that never starts
+    #[cfg(not(feature = "lambda"))]
+    if std::env::var("AWS_LAMBDA_RUNTIME_API").is_ok() {
+        return Err(Error::from("Running on Lambda, but built without the lambda feature"));
+    }
+
+    // SIGTERM or ctrl-c stops accepting connections, and `launch` returns once in-flight requests have drained,
+    // within `shutdown.grace`; logs and errors are flushed on the way out of `main`
+    let result = match Listen::from_config(config) {
+        Some(listen) => listen::serve(rocket, listen).await.map_err(Error::from),
+        None => rocket.launch().await.map(|_| ()).map_err(Error::from),
+    };
+    if let Some(backend) = &backend {
+        backend.flush().await;
+    }
+    info!("shut down");
+    return result;
+}
+
+/// Prints the checklist, and fails the command if a required check did.
+fn report(checks: &[doctor::Check]) -> Result<(), Error> {
+    println!("{}", doctor::render(checks));
+    return if doctor::passed(checks) { Ok(()) } else { Err(Error::from("a required check failed")) };
+}
+
+/// Sends each blog's digest to its own subscribers, or only that of the blog serving `host`.
+async fn send_digests(figment: &Figment, host: Option<&str>, count: Option<usize>, days: Option<u32>) -> Result<(), Error> {
+    let (config, tenants) = load(figment);
+    let tenants: Vec<&Tenant> = match host {
+        Some(host) => vec![tenants.0.iter().find(|tenant| tenant.hosts.iter().any(|served| served == host)).ok_or_else(|| format!("no blog serves {}", host))?],
+        None => tenants.0.iter().collect(),
+    };
+    let (newsletter, mailer) = (or_exit(Newsletter::from_config(config).await), or_exit(Mailer::from_config(config).await));
+    for tenant in tenants {
+        let public_url = tenant.public_url.to_owned().map(PublicUrl).unwrap_or_else(|| PublicUrl::from_config(config));
+        let sent = digest::send(tenant, &public_url.0, &RenderCache::default(), &newsletter, &mailer, count.unwrap_or(config.digest_posts), days).await?;
+        println!("Sent the digest of {} posts on {} to {} subscribers, {} failed", sent.posts.len(), tenant.title, sent.sent, sent.failed);
     }
+    return Ok(());
+}
 
-    Ok(())
-}
\ No newline at end of file
+#[rocket::main]
+async fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+    let telemetry = telemetry::init();
+    let _reporting = reporting::init();
+    let figment = BlogConfig::figment();
+
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(figment).await,
+        Command::Doctor => report(&doctor::diagnose(&figment).await),
+        Command::Validate => {
+            let (config, tenants) = load(&figment);
+            report(&doctor::validate(config, &tenants))
+        },
+        Command::CheckLinks => {
+            let (config, tenants) = load(&figment);
+            links::check_all(config, &tenants).await
+                .and_then(|checked| {
+                    println!("{}", links::report(&checked));
+                    let failing = links::failing(&checked);
+                    return if failing == 0 { Ok(()) } else { Err(format!("{} external links are broken or cannot be checked", failing)) };
+                })
+                .map_err(Error::from)
+        },
+        Command::Export { out } => {
+            let (config, tenants) = load(&figment);
+            export::export(build(figment, config, tenants, RenderCache::default(), Cdn::default(), Webmentions::default(), Comments::default(), Reactions::default(), Views::default(), Beacons::default(), Newsletter::default(), SpamFilter::default(), Mailer::default(), Sender::default(), ActivityPub::default(), Uploads::default()), &out).await
+                .map(|written| println!("Wrote {} files to {}", written.len(), out.display()))
+                .map_err(Error::from)
+        },
+        Command::Digest { host, count, days } => send_digests(&figment, host.as_deref(), count, days).await,
+        Command::New { title, edit } => {
+            let (config, _) = load(&figment);
+            scaffold::new_post(&PathBuf::from(&config.local_directory), &title, Utc::now())
+                .and_then(|path| {
+                    println!("Created {}, hidden until \"hidden\": true is taken out of its manifest entry", path.display());
+                    return if edit { scaffold::open_editor(&path) } else { Ok(()) };
+                })
+                .map_err(Error::from)
+        },
+    };
+
+    telemetry.shutdown();
+    result
+}
@@ -0,0 +1,28 @@
+//! Shared with build.rs, which prerenders with the same options, so this file can only depend on comrak.
+use comrak::{ComrakExtensionOptions, ComrakOptions};
+
+pub fn comrak_options() -> ComrakOptions {
+    return ComrakOptions {
+        extension: ComrakExtensionOptions {
+            table: true,
+            ..ComrakExtensionOptions::default()
+        },
+        ..ComrakOptions::default()
+    };
+}
+
+/// FNV-1a of the markdown, or of a static file, which unlike `DefaultHasher` is the same in build.rs and at run time.
+pub fn fingerprint(bytes: impl AsRef<[u8]>) -> u64 {
+    return bytes.as_ref().iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(""), 0xcbf29ce484222325);
+        assert_eq!(fingerprint("a"), 0xaf63dc4c8601ec8c);
+    }
+}
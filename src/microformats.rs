@@ -0,0 +1,70 @@
+//! [Microformats2](https://microformats.org/wiki/h-entry) for posts, so IndieWeb readers and the sites a post
+//! mentions can parse its title, date, author and content out of the page, as they do to show a webmention.
+use serde::Serialize;
+use crate::blog::{Kind, Post};
+
+/// The `p-author` of every entry: the blog itself, as it has no other author.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct HCard {
+    pub name: String,
+    pub url: String,
+}
+
+/// A post's `h-entry` properties, given to the template as `h_entry`, which marks its rendered content
+/// `e-content`. Pages have none, not being entries.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct HEntry {
+    /// `p-name`
+    pub name: String,
+    /// `u-url`, the canonical URL.
+    pub url: String,
+    /// `dt-published`, RFC 3339; the manifest only has the date a post was last updated.
+    pub published: String,
+    /// `p-summary`
+    pub summary: String,
+    /// `p-category`, its tags.
+    pub categories: Vec<String>,
+    pub author: HCard,
+}
+
+impl HEntry {
+    pub fn of(post: &Post, summary: &str, public_url: &str, site_title: &str) -> Option<HEntry> {
+        if post.kind != Kind::Post {
+            return None;
+        }
+        return Some(HEntry {
+            name: post.title.to_owned(),
+            url: format!("{}/{}", public_url, post.slug),
+            published: post.updated.to_rfc3339(),
+            summary: summary.to_owned(),
+            categories: post.tags.to_owned(),
+            author: HCard { name: site_title.to_owned(), url: public_url.to_owned() },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blog::{find_post, to_posts, Registry};
+
+    #[test]
+    fn test_h_entry() {
+        let manifest = r#"[
+            { "title": "Zip is scan", "markdown": "zip.md", "updated": "2024-01-25T23:08:00Z", "tags": ["haskell"] },
+            { "title": "About", "markdown": "about.md", "updated": "2024-01-25T23:08:00Z", "kind": "page" }
+        ]"#;
+        let posts = to_posts(&serde_json::from_str::<Vec<Registry>>(manifest).unwrap());
+        let (post, page) = (find_post(&posts, "zip-is-scan").unwrap(), find_post(&posts, "about").unwrap());
+
+        assert_eq!(HEntry::of(&post, "Or is it?", "https://hacklewayne.com", "Hackle's blog"), Some(HEntry {
+            name: String::from("Zip is scan"),
+            url: String::from("https://hacklewayne.com/zip-is-scan"),
+            published: String::from("2024-01-25T23:08:00+00:00"),
+            summary: String::from("Or is it?"),
+            categories: vec![String::from("haskell")],
+            author: HCard { name: String::from("Hackle's blog"), url: String::from("https://hacklewayne.com") },
+        }));
+        assert_eq!(HEntry::of(&page, "", "https://hacklewayne.com", "Hackle's blog"), None);
+    }
+}
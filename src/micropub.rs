@@ -0,0 +1,421 @@
+//! A [Micropub](https://www.w3.org/TR/micropub/) endpoint, so posts can be published from a phone or any other Micropub
+//! client. It only creates: the new post's markdown and manifest entry are written to `local_directory`, or, with
+//! `remote_markdown_path`, committed to its repository through the GitHub contents API and live once the push hook fires.
+//! Clients authenticate with the one `micropub_token` rather than IndieAuth.
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rocket::data::{ByteUnit, Data};
+use rocket::http::{ContentType, Header, RawStr, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::Json;
+use rocket::{get, post, routes, Responder, Route, State};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+use crate::activitypub::ActivityPub;
+use crate::auth::{bearer_token, constant_time_eq};
+use crate::blog::GithubSource;
+use crate::body;
+use crate::cdn::Cdn;
+use crate::config::config;
+use crate::hooks::refresh;
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::scaffold::{add_entry, write_post};
+use crate::tenant::Tenant;
+use crate::webmention::send::Sender;
+
+const USER_AGENT: &str = "blog-rust";
+/// How many words of a note's content make its title, as every post here has one.
+const TITLE_WORDS: usize = 8;
+/// A long-form post, many times over; images are uploaded on their own rather than in the entry.
+const MAX_ENTRY: ByteUnit = ByteUnit::Mebibyte(1);
+
+/// Where the homepage says Micropub clients can publish, when they can.
+pub fn endpoint(public_url: &str) -> Option<String> {
+    return config().micropub_token.as_ref().map(|_| format!("{}/micropub", public_url));
+}
+
+/// A post to create, from the form or JSON a client sends.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub name: Option<String>,
+    pub content: String,
+    pub categories: Vec<String>,
+    /// `post-status=draft`, written hidden.
+    pub draft: bool,
+}
+
+impl Entry {
+    /// A form-encoded create request, and the `access_token` it carries, if it does.
+    pub fn from_form(body: &str) -> Result<(Entry, Option<String>), String> {
+        let mut entry = Entry::default();
+        let mut access_token = None;
+        for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (name, value) = (RawStr::new(name).url_decode_lossy(), RawStr::new(value).url_decode_lossy().into_owned());
+            match name.as_ref() {
+                "h" if value != "entry" => return Err(format!("only h-entry can be created, not h-{}", value)),
+                "action" if value != "create" => return Err(format!("only create is supported, not {}", value)),
+                "access_token" => access_token = Some(value),
+                "name" => entry.name = Some(value).filter(|name| !name.trim().is_empty()),
+                "content" => entry.content = value,
+                "category" | "category[]" => entry.categories.push(value),
+                "post-status" => entry.draft = value == "draft",
+                _ => {}
+            }
+        }
+        return Ok((entry, access_token));
+    }
+
+    /// A JSON create request, `{ "type": ["h-entry"], "properties": { "content": ["..."] } }`.
+    pub fn from_json(body: &[u8]) -> Result<Entry, String> {
+        #[derive(Deserialize)]
+        struct Create {
+            #[serde(rename = "type", default)]
+            kind: Vec<String>,
+            action: Option<String>,
+            #[serde(default)]
+            properties: serde_json::Map<String, Value>,
+        }
+
+        let create: Create = serde_json::from_slice(body).map_err(|err| format!("not a Micropub request, {}", err))?;
+        if let Some(action) = create.action.filter(|action| action != "create") {
+            return Err(format!("only create is supported, not {}", action));
+        }
+        if create.kind.iter().any(|kind| kind != "h-entry") {
+            return Err(format!("only h-entry can be created, not {}", create.kind.join(", ")));
+        }
+
+        let values = |name: &str| -> Vec<&Value> {
+            return create.properties.get(name).and_then(Value::as_array).map(|values| values.iter().collect()).unwrap_or_default();
+        };
+        let first_string = |name: &str| values(name).first().and_then(|value| value.as_str()).map(String::from);
+        // content is a string, or `{ "html": "..." }`, which markdown takes as it is
+        let content = values("content").first()
+            .and_then(|content| content.as_str().or_else(|| content.get("html").and_then(Value::as_str)))
+            .unwrap_or_default()
+            .to_owned();
+
+        return Ok(Entry {
+            name: first_string("name").filter(|name| !name.trim().is_empty()),
+            content,
+            categories: values("category").iter().filter_map(|category| category.as_str()).map(String::from).collect(),
+            draft: first_string("post-status").as_deref() == Some("draft"),
+        });
+    }
+
+    /// Its `name`, or for a note the first words of its content.
+    pub fn title(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.trim().to_owned();
+        }
+        let first_line = self.content.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+        let words: Vec<&str> = first_line.split_whitespace().collect();
+        let title = words.iter().take(TITLE_WORDS).copied().collect::<Vec<&str>>().join(" ");
+        return if words.len() > TITLE_WORDS { format!("{}…", title) } else { title };
+    }
+}
+
+/// The repository, branch and directory `remote_markdown_path` serves, as
+/// `https://raw.githubusercontent.com/<owner>/<repo>/<branch>/<directory>`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Repository {
+    pub owner: String,
+    pub repo: String,
+    pub branch: String,
+    pub directory: String,
+}
+
+impl Repository {
+    pub fn of(remote: &GithubSource) -> Option<Repository> {
+        let path = remote.base_url.strip_prefix("https://raw.githubusercontent.com/")?.trim_end_matches('/');
+        let mut parts = path.splitn(4, '/');
+        let (owner, repo, branch) = (parts.next()?, parts.next()?, parts.next()?);
+        if owner.is_empty() || repo.is_empty() || branch.is_empty() {
+            return None;
+        }
+
+        return Some(Repository {
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+            branch: branch.to_owned(),
+            directory: parts.next().unwrap_or_default().to_owned(),
+        });
+    }
+
+    fn path(&self, name: &str) -> String {
+        return if self.directory.is_empty() { name.to_owned() } else { format!("{}/{}", self.directory, name) };
+    }
+
+    fn contents_url(&self, name: &str) -> String {
+        return format!("https://api.github.com/repos/{}/{}/contents/{}", self.owner, self.repo, self.path(name));
+    }
+
+    /// Where a reader edits the file on GitHub, which forks the repository and opens a pull request for them.
+    pub fn edit_url(&self, name: &str) -> String {
+        return format!("https://github.com/{}/{}/edit/{}/{}", self.owner, self.repo, self.branch, self.path(name));
+    }
+
+    /// The latest `limit` commits on the branch touching the file, from the commits API.
+    pub fn commits_url(&self, name: &str, limit: usize) -> String {
+        return format!(
+            "https://api.github.com/repos/{}/{}/commits?sha={}&path={}&per_page={}",
+            self.owner, self.repo, self.branch, self.path(name), limit,
+        );
+    }
+}
+
+/// A Micropub error, `{ "error": "invalid_request", "error_description": "..." }`.
+type Failure = (Status, Json<String>);
+
+fn failure(status: Status, error: &str, description: &str) -> Failure {
+    return (status, Json(json!({ "error": error, "error_description": description }).to_string()));
+}
+
+#[derive(Deserialize)]
+struct Contents {
+    sha: String,
+    content: String,
+}
+
+/// Commits `<slug>.md` and then the manifest listing it, so the manifest never names a file the repository doesn't have.
+/// Returns the new post's slug.
+async fn commit(repository: &Repository, token: &str, entry: &Entry, now: DateTime<Utc>) -> Result<String, Failure> {
+    let client = reqwest::Client::new();
+    let github = |request: reqwest::RequestBuilder| request
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", USER_AGENT);
+    let unavailable = |err: String| failure(Status::BadGateway, "server_error", &err);
+
+    let manifest_url = repository.contents_url("manifest.json");
+    let response = github(client.get(&manifest_url).query(&[("ref", &repository.branch)])).send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| unavailable(format!("Cannot read manifest.json, {}", err)))?;
+    let manifest: Contents = response.json().await.map_err(|err| unavailable(format!("Cannot read manifest.json, {}", err)))?;
+    let encoded: String = manifest.content.split_whitespace().collect();
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).ok().and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| unavailable(String::from("manifest.json is not UTF-8 base64")))?;
+
+    let (slug, markdown, updated) = add_entry(&decoded, &entry.title(), &entry.categories, entry.draft, now)
+        .map_err(|err| failure(Status::BadRequest, "invalid_request", &err))?;
+    let put = |name: &str, content: &str, sha: Option<&str>| {
+        let mut body = json!({
+            "message": format!("Publish {} from Micropub", slug),
+            "content": base64::engine::general_purpose::STANDARD.encode(content),
+            "branch": repository.branch,
+        });
+        if let Some(sha) = sha {
+            body["sha"] = json!(sha);
+        }
+        return github(client.put(repository.contents_url(name))).json(&body).send();
+    };
+
+    // without a sha GitHub refuses to overwrite, so an existing file is a 422 rather than lost
+    match put(&markdown, &entry.content, None).await.map_err(|err| unavailable(err.to_string()))?.status() {
+        status if status.is_success() => {},
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY => return Err(failure(Status::BadRequest, "invalid_request", &format!("{} already exists", markdown))),
+        status => return Err(unavailable(format!("Cannot commit {}, GitHub says {}", markdown, status))),
+    }
+    let status = put("manifest.json", &updated, Some(&manifest.sha)).await.map_err(|err| unavailable(err.to_string()))?.status();
+    if !status.is_success() {
+        return Err(unavailable(format!("Cannot commit manifest.json, GitHub says {}", status)));
+    }
+
+    return Ok(slug);
+}
+
+/// The token in `Authorization: Bearer`, to compare with `micropub_token`; a form may carry it as `access_token` instead.
+/// Without `micropub_token` the endpoint doesn't exist at all.
+pub struct MicropubAuth {
+    token: String,
+    bearer: Option<String>,
+}
+
+impl MicropubAuth {
+    fn check(&self, access_token: Option<&str>) -> Result<(), Failure> {
+        return match self.bearer.as_deref().or(access_token) {
+            None => Err(failure(Status::Unauthorized, "unauthorized", "no access token")),
+            Some(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => Ok(()),
+            Some(_) => Err(failure(Status::Forbidden, "forbidden", "the access token is not this blog's")),
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MicropubAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return match config().micropub_token.to_owned() {
+            Some(token) => Outcome::Success(MicropubAuth {
+                token,
+                bearer: request.headers().get_one("Authorization").and_then(bearer_token).map(String::from),
+            }),
+            None => Outcome::Forward(())
+        };
+    }
+}
+
+/// `201 Created`, or `202 Accepted` when the post is live only after the push hook, at `Location`.
+#[derive(Responder)]
+pub struct Published {
+    inner: (Status, String),
+    location: Header<'static>,
+}
+
+#[get("/micropub?<q>")]
+fn query(q: Option<&str>, auth: MicropubAuth) -> Result<Json<String>, Failure> {
+    auth.check(None)?;
+    return match q {
+        Some("config") | Some("syndicate-to") => Ok(Json(json!({ "syndicate-to": [] }).to_string())),
+        _ => Err(failure(Status::BadRequest, "invalid_request", "only q=config and q=syndicate-to are supported")),
+    };
+}
+
+/// The entry in a form or JSON body, and the access token if the form carries it.
+async fn read_entry(content_type: Option<&ContentType>, data: Data<'_>) -> Result<(Entry, Option<String>), Failure> {
+    let invalid = |err: String| failure(Status::BadRequest, "invalid_request", &err);
+    let body = body::read(data, MAX_ENTRY).await.map_err(|(status, err)| failure(status, "invalid_request", &err))?;
+    return match content_type {
+        Some(content_type) if content_type.is_json() => Ok((Entry::from_json(&body).map_err(invalid)?, None)),
+        Some(content_type) if content_type.is_form() => Entry::from_form(&String::from_utf8_lossy(&body)).map_err(invalid),
+        _ => Err(failure(Status::UnsupportedMediaType, "invalid_request", "send application/x-www-form-urlencoded or application/json")),
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/micropub", data = "<data>")]
+async fn create(auth: MicropubAuth, content_type: Option<&ContentType>, data: Data<'_>, request_id: RequestId, tenant: &Tenant, public_url: PublicUrl, cdn: &State<Cdn>, sender: &State<Sender>, activitypub: &State<ActivityPub>) -> Result<Published, Failure> {
+    let invalid = |err: String| failure(Status::BadRequest, "invalid_request", &err);
+    let (entry, access_token) = read_entry(content_type, data).await?;
+    auth.check(access_token.as_deref())?;
+    if entry.content.trim().is_empty() && entry.name.is_none() {
+        return Err(invalid(String::from("an entry needs content or a name")));
+    }
+
+    if let Some(remote) = tenant.source.remote() {
+        let repository = Repository::of(remote)
+            .ok_or_else(|| failure(Status::NotImplemented, "server_error", "remote_markdown_path is not on raw.githubusercontent.com"))?;
+        let token = config().github_api_token.as_deref()
+            .ok_or_else(|| failure(Status::NotImplemented, "server_error", "github_api_token is not set"))?;
+        let slug = commit(&repository, token, &entry, Utc::now()).await?;
+
+        info!(%request_id, %slug, "micropub commit");
+        return Ok(Published { inner: (Status::Accepted, String::new()), location: Header::new("Location", format!("{}/{}", public_url.0, slug)) });
+    }
+
+    let path = write_post(&tenant.source.local().directory, &entry.title(), &entry.content, &entry.categories, entry.draft, Utc::now()).map_err(invalid)?;
+    let markdown = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let slug = markdown.trim_end_matches(".md").to_owned();
+    refresh(&[String::from("manifest.json"), markdown], &request_id, tenant, cdn, sender, activitypub).await
+        .map_err(|err| failure(Status::BadGateway, "server_error", &err))?;
+
+    info!(%request_id, %slug, "micropub create");
+    return Ok(Published { inner: (Status::Created, String::new()), location: Header::new("Location", format!("{}/{}", public_url.0, slug)) });
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![query, create];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    /// `create` needs `micropub_token` in the configuration, so an entry is read here as it reads one.
+    #[post("/", data = "<data>")]
+    async fn entry(content_type: Option<&ContentType>, data: Data<'_>) -> Result<String, Failure> {
+        let (entry, _) = read_entry(content_type, data).await?;
+        return Ok(entry.content);
+    }
+
+    #[rocket::async_test]
+    async fn test_read_entry() {
+        let client = Client::untracked(rocket::build().mount("/", routes![entry])).await.unwrap();
+        let content = "Zip is scan, at length. ".repeat(2000);
+        assert!(content.len() > 8 * 1024);
+
+        let response = client.post("/").header(ContentType::JSON)
+            .body(json!({ "type": ["h-entry"], "properties": { "content": [content] } }).to_string())
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await, Some(content.to_owned()));
+
+        let response = client.post("/").header(ContentType::Form).body(format!("h=entry&content={}", content.replace(' ', "+"))).dispatch().await;
+        assert_eq!(response.into_string().await, Some(content.to_owned()));
+
+        let response = client.post("/").header(ContentType::Form).body("x".repeat(MAX_ENTRY.as_u64() as usize + 1)).dispatch().await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+    }
+
+    #[test]
+    fn test_from_form() {
+        let (entry, access_token) = Entry::from_form("h=entry&content=Zip+is+%2Ascan%2A&category%5B%5D=haskell&category[]=fp&access_token=s3cret").unwrap();
+        assert_eq!(entry, Entry {
+            name: None,
+            content: String::from("Zip is *scan*"),
+            categories: vec![String::from("haskell"), String::from("fp")],
+            draft: false,
+        });
+        assert_eq!(access_token.as_deref(), Some("s3cret"));
+
+        let (entry, access_token) = Entry::from_form("name=Fin&content=&post-status=draft").unwrap();
+        assert_eq!((entry.name.as_deref(), entry.draft, access_token), (Some("Fin"), true, None));
+
+        assert!(Entry::from_form("h=event&name=Fin").is_err());
+        assert!(Entry::from_form("action=delete&url=https://hacklewayne.com/fin").is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let entry = Entry::from_json(br#"{
+            "type": ["h-entry"],
+            "properties": { "name": ["Fin"], "content": [{ "html": "<p>The end</p>" }], "category": ["haskell"], "post-status": ["draft"] }
+        }"#).unwrap();
+        assert_eq!(entry, Entry {
+            name: Some(String::from("Fin")),
+            content: String::from("<p>The end</p>"),
+            categories: vec![String::from("haskell")],
+            draft: true,
+        });
+        assert_eq!(Entry::from_json(br#"{ "type": ["h-entry"], "properties": { "content": ["The end"] } }"#).unwrap().content, "The end");
+
+        assert!(Entry::from_json(br#"{ "action": "update", "url": "https://hacklewayne.com/fin" }"#).is_err());
+        assert!(Entry::from_json(br#"{ "type": ["h-event"], "properties": {} }"#).is_err());
+        assert!(Entry::from_json(b"content=The+end").is_err());
+    }
+
+    #[test]
+    fn test_title() {
+        let named = Entry { name: Some(String::from(" Zip is scan ")), content: String::from("Or is it?"), ..Entry::default() };
+        assert_eq!(named.title(), "Zip is scan");
+
+        let note = Entry { content: String::from("\nJust found out zip is scan, or is it? Let me explain\nwhy"), ..Entry::default() };
+        assert_eq!(note.title(), "Just found out zip is scan, or is…");
+        assert_eq!(Entry { content: String::from("Short note"), ..Entry::default() }.title(), "Short note");
+    }
+
+    #[test]
+    fn test_repository() {
+        let remote = GithubSource::new(&String::from("https://raw.githubusercontent.com/hackle/blog-rust/master/raw"));
+        assert_eq!(Repository::of(&remote), Some(Repository {
+            owner: String::from("hackle"),
+            repo: String::from("blog-rust"),
+            branch: String::from("master"),
+            directory: String::from("raw"),
+        }));
+        assert_eq!(Repository::of(&remote).unwrap().contents_url("fin.md"), "https://api.github.com/repos/hackle/blog-rust/contents/raw/fin.md");
+
+        let root = Repository::of(&GithubSource::new(&String::from("https://raw.githubusercontent.com/hackle/posts/main/"))).unwrap();
+        assert_eq!(root.contents_url("manifest.json"), "https://api.github.com/repos/hackle/posts/contents/manifest.json");
+        assert_eq!(Repository::of(&remote).unwrap().edit_url("fin.md"), "https://github.com/hackle/blog-rust/edit/master/raw/fin.md");
+        assert_eq!(root.edit_url("fin.md"), "https://github.com/hackle/posts/edit/main/fin.md");
+        assert_eq!(root.commits_url("fin.md", 5), "https://api.github.com/repos/hackle/posts/commits?sha=main&path=fin.md&per_page=5");
+
+        assert_eq!(Repository::of(&GithubSource::new(&String::from("https://example.com/hackle/blog-rust/master/raw"))), None);
+        assert_eq!(Repository::of(&GithubSource::new(&String::from("https://raw.githubusercontent.com/hackle"))), None);
+    }
+}
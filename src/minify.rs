@@ -0,0 +1,120 @@
+use std::io::Cursor;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::Response;
+use crate::config::config;
+
+/// Elements whose content is whitespace-sensitive and passed through untouched.
+const PRESERVED: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Minification is off unless `minify_html` is `true`, so pages stay readable in development.
+pub fn enabled() -> bool {
+    return config().minify_html;
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    return text.get(..prefix.len()).map(|start| start.eq_ignore_ascii_case(prefix)).unwrap_or(false);
+}
+
+fn find_ignore_case(text: &str, needle: &str) -> Option<usize> {
+    return text.to_ascii_lowercase().find(needle);
+}
+
+/// The preserved element `html` opens with, if any.
+fn preserved_element(html: &str) -> Option<&'static str> {
+    return PRESERVED.iter()
+        .find(|tag| {
+            starts_with_ignore_case(html, &format!("<{}", tag))
+                && html[tag.len() + 1..].starts_with(|c: char| c == '>' || c.is_whitespace())
+        })
+        .copied();
+}
+
+/// Collapses runs of whitespace to a single space and strips comments, leaving
+/// `<pre>`, `<textarea>`, `<script>` and `<style>` as they are. IE conditional comments are kept.
+pub fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|end| end + 3).unwrap_or(rest.len());
+            if rest.starts_with("<!--[if") {
+                minified.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        } else if let Some(tag) = preserved_element(rest) {
+            let closing = format!("</{}", tag);
+            let end = find_ignore_case(rest, &closing)
+                .and_then(|start| rest[start..].find('>').map(|close| start + close + 1))
+                .unwrap_or(rest.len());
+            minified.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if c.is_whitespace() {
+            if !minified.is_empty() && !minified.ends_with(' ') {
+                minified.push(' ');
+            }
+            rest = rest.trim_start();
+        } else {
+            minified.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    return minified.trim_end().to_owned();
+}
+
+/// Minifies every HTML response; attached ahead of the ETag and compression fairings
+/// so that they work on the minified body.
+pub struct MinifyHtml;
+
+#[rocket::async_trait]
+impl Fairing for MinifyHtml {
+    fn info(&self) -> Info {
+        return Info { name: "HTML minification", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, _: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.content_type() != Some(ContentType::HTML) {
+            return;
+        }
+
+        let body = match response.body_mut().to_string().await {
+            Ok(body) => body,
+            Err(_) => return
+        };
+        let minified = minify_html(&body);
+        response.set_sized_body(minified.len(), Cursor::new(minified));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_html() {
+        let html = r#"<html>
+    <!-- navigation -->
+    <body>
+        <p>A   lens,
+           focused</p>
+        <PRE>data Fin : Nat -> Type where
+  FZ : Fin (S k)</PRE>
+        <!--[if IE]><p>old</p><![endif]-->
+    </body>
+</html>
+"#;
+        let expected = "<html> <body> <p>A lens, focused</p> <PRE>data Fin : Nat -> Type where\n  FZ : Fin (S k)</PRE> <!--[if IE]><p>old</p><![endif]--> </body> </html>";
+
+        assert_eq!(minify_html(html), expected);
+    }
+
+    #[test]
+    fn test_preserved_element() {
+        assert_eq!(preserved_element("<pre class=\"x\">"), Some("pre"));
+        assert_eq!(preserved_element("<script>"), Some("script"));
+        assert_eq!(preserved_element("<preview>"), None);
+    }
+}
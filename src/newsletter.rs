@@ -0,0 +1,520 @@
+//! Newsletter subscriptions with double opt-in: `POST /subscribe` keeps an address as `pending` in the store
+//! `newsletter_store` names and mails it a link with a token, and only following the link, to
+//! `/subscribe/confirm`, makes it `confirmed`. Whether an address is already subscribed is never told apart;
+//! the same token unsubscribes it through `/unsubscribe`, and confirmed subscribers are exported from
+//! `/admin/subscribers.csv`. Each blog has its own subscribers: an address subscribes to the blog it asked on.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration as Days, Utc};
+use reqwest::Url;
+use rocket::form::{Form, FromForm};
+use rocket::http::{ContentType, Status};
+use rocket::response::content::Json;
+use rocket::{get, post, routes, Route, State};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::admin::AdminToken;
+use crate::auth::constant_time_eq;
+use crate::config::{config, BlogConfig};
+use crate::mail::{Mailer, Message};
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::store::{self, Backend};
+use crate::tenant::Tenant;
+
+const MAX_EMAIL_CHARS: usize = 254;
+/// How long before asking again mails the confirmation link again, so the form can't flood an inbox.
+const RESEND_AFTER_MINUTES: i64 = 60;
+/// What `/subscribe` answers, whether the address was new, pending or confirmed already.
+const CHECK_YOUR_INBOX: &str = "Thanks! Check your inbox for a link confirming the subscription.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriberState {
+    Pending,
+    Confirmed,
+}
+
+impl SubscriberState {
+    pub fn parse(value: &str) -> Option<SubscriberState> {
+        return match value {
+            "pending" => Some(SubscriberState::Pending),
+            "confirmed" => Some(SubscriberState::Confirmed),
+            _ => None,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            SubscriberState::Pending => "pending",
+            SubscriberState::Confirmed => "confirmed",
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscriber {
+    /// The blog subscribed to, its `Tenant::id`.
+    #[serde(default)]
+    pub blog: String,
+    /// Trimmed and lowercased, so an address is subscribed once however it is typed.
+    pub email: String,
+    /// What the confirmation link carries.
+    pub token: String,
+    pub state: SubscriberState,
+    /// When the confirmation link was last mailed.
+    pub requested: DateTime<Utc>,
+    pub confirmed: Option<DateTime<Utc>>,
+}
+
+/// `email` as it is kept, if it looks like an address: one `@` with something either side, a dot in the domain,
+/// and nothing that would need quoting in a header or a CSV.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+    let valid = email.chars().count() <= MAX_EMAIL_CHARS
+        && !local.is_empty()
+        && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+        && !domain.contains('@')
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, ',' | '"' | '<' | '>' | ';'));
+    return Some(email).filter(|_| valid);
+}
+
+/// The subscriber to keep and mail the confirmation link to after `email` asks to subscribe to `blog`, or none
+/// when nothing is to be sent: the address is confirmed already, or was mailed too recently.
+pub fn subscribe(existing: Option<Subscriber>, blog: &str, email: &str, now: DateTime<Utc>) -> Option<Subscriber> {
+    return match existing {
+        None => Some(Subscriber {
+            blog: blog.to_owned(),
+            email: email.to_owned(),
+            token: uuid::Uuid::new_v4().simple().to_string(),
+            state: SubscriberState::Pending,
+            requested: now,
+            confirmed: None,
+        }),
+        Some(subscriber) if subscriber.state == SubscriberState::Pending && now - subscriber.requested >= Days::minutes(RESEND_AFTER_MINUTES) =>
+            Some(Subscriber { requested: now, ..subscriber }),
+        Some(_) => None,
+    };
+}
+
+/// Whether `token` is the subscriber's, compared in constant time.
+pub fn confirms(subscriber: &Subscriber, token: &str) -> bool {
+    return constant_time_eq(subscriber.token.as_bytes(), token.as_bytes());
+}
+
+fn link(public_url: &str, path: &str, subscriber: &Subscriber) -> String {
+    return Url::parse_with_params(&format!("{}{}", public_url, path), &[("email", &subscriber.email), ("token", &subscriber.token)])
+        .map(String::from)
+        .unwrap_or_default();
+}
+
+/// The link that confirms the subscription.
+pub fn confirm_url(public_url: &str, subscriber: &Subscriber) -> String {
+    return link(public_url, "/subscribe/confirm", subscriber);
+}
+
+/// The link at the bottom of every newsletter that ends the subscription.
+pub fn unsubscribe_url(public_url: &str, subscriber: &Subscriber) -> String {
+    return link(public_url, "/unsubscribe", subscriber);
+}
+
+/// Confirmed subscribers as `email,requested,confirmed`, with a header.
+pub fn csv(subscribers: &[Subscriber]) -> String {
+    let mut csv = String::from("email,requested,confirmed\n");
+    for subscriber in subscribers.iter().filter(|subscriber| subscriber.state == SubscriberState::Confirmed) {
+        let confirmed = subscriber.confirmed.map(|confirmed| confirmed.to_rfc3339()).unwrap_or_default();
+        csv.push_str(&format!("{},{},{}\n", subscriber.email, subscriber.requested.to_rfc3339(), confirmed));
+    }
+    return csv;
+}
+
+/// Where subscribers are kept, by blog and address.
+#[rocket::async_trait]
+pub trait SubscriberStore: Send + Sync {
+    async fn get(&self, blog: &str, email: &str) -> Result<Option<Subscriber>, String>;
+    async fn put(&self, subscriber: &Subscriber) -> Result<(), String>;
+    async fn remove(&self, blog: &str, email: &str) -> Result<(), String>;
+    /// Every subscriber to `blog`.
+    async fn all(&self, blog: &str) -> Result<Vec<Subscriber>, String>;
+}
+
+pub type SharedStore = Arc<dyn SubscriberStore>;
+
+#[derive(Default)]
+pub struct MemoryStore(Mutex<BTreeMap<(String, String), Subscriber>>);
+
+#[rocket::async_trait]
+impl SubscriberStore for MemoryStore {
+    async fn get(&self, blog: &str, email: &str) -> Result<Option<Subscriber>, String> {
+        return Ok(self.0.lock().unwrap().get(&(blog.to_owned(), email.to_owned())).cloned());
+    }
+
+    async fn put(&self, subscriber: &Subscriber) -> Result<(), String> {
+        self.0.lock().unwrap().insert((subscriber.blog.to_owned(), subscriber.email.to_owned()), subscriber.to_owned());
+        return Ok(());
+    }
+
+    async fn remove(&self, blog: &str, email: &str) -> Result<(), String> {
+        self.0.lock().unwrap().remove(&(blog.to_owned(), email.to_owned()));
+        return Ok(());
+    }
+
+    async fn all(&self, blog: &str) -> Result<Vec<Subscriber>, String> {
+        return Ok(self.0.lock().unwrap().values().filter(|subscriber| subscriber.blog == blog).cloned().collect());
+    }
+}
+
+/// The store `newsletter_store` names, or none when it is `none` and there is no newsletter.
+#[derive(Clone, Default)]
+pub struct Newsletter(pub Option<SharedStore>);
+
+impl Newsletter {
+    pub async fn from_config(config: &BlogConfig) -> Result<Newsletter, String> {
+        let store: Option<SharedStore> = match store::backend("newsletter", &config.newsletter_store, config.newsletter_database.as_deref(), config.newsletter_table.as_deref()).await? {
+            Backend::None => None,
+            Backend::Memory => Some(Arc::new(MemoryStore::default())),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(path) => Some(Arc::new(sqlite::SqliteStore::open(&path)?)),
+            #[cfg(feature = "dynamodb")]
+            Backend::DynamoDb(table) => Some(Arc::new(dynamodb::DynamoStore(table))),
+        };
+        return Ok(Newsletter(store));
+    }
+}
+
+/// Whether pages show the subscribe form, given to every template as `newsletter`.
+pub fn enabled() -> bool {
+    return config().newsletter_store != "none";
+}
+
+fn store(newsletter: &Newsletter) -> Result<&SharedStore, (Status, String)> {
+    return newsletter.0.as_ref().ok_or((Status::NotFound, String::from("newsletter_store is none")));
+}
+
+#[derive(FromForm)]
+struct SubscribeForm<'r> {
+    email: &'r str,
+    /// The honeypot, hidden from people.
+    website: Option<&'r str>,
+}
+
+/// Keeps the address as pending and mails it the confirmation link in the background, answering the same
+/// whatever the address's state.
+#[post("/subscribe", data = "<form>")]
+async fn subscribe_route(form: Form<SubscribeForm<'_>>, newsletter: &State<Newsletter>, mailer: &State<Mailer>, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<(Status, String), (Status, String)> {
+    let store = store(newsletter)?;
+    let email = normalize_email(form.email).ok_or((Status::BadRequest, format!("{} is not an email address", form.email)))?;
+    if form.website.is_some_and(|website| !website.is_empty()) {
+        info!(%request_id, "subscription caught by the honeypot");
+        return Ok((Status::Accepted, String::from(CHECK_YOUR_INBOX)));
+    }
+
+    let existing = store.get(tenant.id(), &email).await.map_err(|err| (Status::BadGateway, err))?;
+    if let Some(subscriber) = subscribe(existing, tenant.id(), &email, Utc::now()) {
+        store.put(&subscriber).await.map_err(|err| (Status::BadGateway, err))?;
+        let message = Message {
+            to: subscriber.email.to_owned(),
+            subject: format!("Confirm your subscription to {}", tenant.title),
+            text: format!(
+                "Someone, hopefully you, asked for new posts on {} to be emailed to this address.\n\nConfirm by following {}\n\nIf it wasn't you, ignore this and nothing more will be sent.",
+                public_url.0, confirm_url(&public_url.0, &subscriber),
+            ),
+            html: None,
+        };
+        let mailer = mailer.inner().to_owned();
+        rocket::tokio::spawn(async move {
+            if let Err(err) = mailer.send(&message).await {
+                warn!(%request_id, error = %err, "cannot email the subscription confirmation");
+            }
+        });
+    }
+    return Ok((Status::Accepted, String::from(CHECK_YOUR_INBOX)));
+}
+
+/// Confirms a pending subscriber with the token they were mailed; following the link again changes nothing.
+#[get("/subscribe/confirm?<email>&<token>")]
+async fn confirm(email: &str, token: &str, newsletter: &State<Newsletter>, tenant: &Tenant, request_id: RequestId) -> Result<String, (Status, String)> {
+    let store = store(newsletter)?;
+    let not_found = || (Status::NotFound, String::from("This link is not, or no longer, a subscription to confirm."));
+    let email = normalize_email(email).ok_or_else(not_found)?;
+    let mut subscriber = store.get(tenant.id(), &email).await.map_err(|err| (Status::BadGateway, err))?
+        .filter(|subscriber| confirms(subscriber, token))
+        .ok_or_else(not_found)?;
+
+    let was = subscriber.state;
+    if subscriber.state == SubscriberState::Pending {
+        subscriber.state = SubscriberState::Confirmed;
+        subscriber.confirmed = Some(Utc::now());
+        store.put(&subscriber).await.map_err(|err| (Status::BadGateway, err))?;
+    }
+    info!(%request_id, was = was.as_str(), "subscription confirmed");
+    return Ok(format!("Subscribed! New posts on {} will be emailed to {}.", tenant.title, subscriber.email));
+}
+
+/// Forgets the subscriber the token is for, so nothing more is sent to them.
+#[get("/unsubscribe?<email>&<token>")]
+async fn unsubscribe(email: &str, token: &str, newsletter: &State<Newsletter>, tenant: &Tenant, request_id: RequestId) -> Result<String, (Status, String)> {
+    let store = store(newsletter)?;
+    let gone = format!("Unsubscribed. Nothing more from {} will be emailed to {}.", tenant.title, email);
+    let email = match normalize_email(email) {
+        Some(email) => email,
+        None => return Ok(gone),
+    };
+    let subscriber = store.get(tenant.id(), &email).await.map_err(|err| (Status::BadGateway, err))?;
+    if subscriber.is_some_and(|subscriber| confirms(&subscriber, token)) {
+        store.remove(tenant.id(), &email).await.map_err(|err| (Status::BadGateway, err))?;
+        info!(%request_id, "unsubscribed");
+    }
+    return Ok(gone);
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![subscribe_route, confirm, unsubscribe];
+}
+
+/// Every subscriber to the blog, or those in `?state=`, oldest first.
+#[get("/subscribers?<state>")]
+async fn list(_token: AdminToken, state: Option<&str>, newsletter: &State<Newsletter>, tenant: &Tenant) -> Result<Json<String>, (Status, String)> {
+    let state = state.map(|state| SubscriberState::parse(state).ok_or((Status::BadRequest, format!("no state {}", state)))).transpose()?;
+    let mut all = store(newsletter)?.all(tenant.id()).await.map_err(|err| (Status::BadGateway, err))?;
+    all.retain(|subscriber| state.map(|state| subscriber.state == state).unwrap_or(true));
+    all.sort_by_key(|subscriber| subscriber.requested);
+
+    return Ok(Json(serde_json::to_string(&all).unwrap()));
+}
+
+/// The blog's confirmed subscribers, for importing into a mailing list.
+#[get("/subscribers.csv")]
+async fn export(_token: AdminToken, newsletter: &State<Newsletter>, tenant: &Tenant) -> Result<(ContentType, String), (Status, String)> {
+    let mut all = store(newsletter)?.all(tenant.id()).await.map_err(|err| (Status::BadGateway, err))?;
+    all.sort_by_key(|subscriber| subscriber.requested);
+    return Ok((ContentType::CSV, csv(&all)));
+}
+
+/// Mounted under `/admin`.
+pub fn admin_routes() -> Vec<Route> {
+    return routes![list, export];
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::sync::Mutex;
+    use rusqlite::{params, Connection, OptionalExtension, Row};
+    use crate::store;
+    use super::{Subscriber, SubscriberState, SubscriberStore};
+
+    /// One row a blog and subscriber in `subscribers`.
+    pub struct SqliteStore(Mutex<Connection>);
+
+    fn subscriber(row: &Row) -> rusqlite::Result<Subscriber> {
+        let state: String = row.get(3)?;
+        return Ok(Subscriber {
+            blog: row.get(0)?,
+            email: row.get(1)?,
+            token: row.get(2)?,
+            state: SubscriberState::parse(&state).unwrap_or(SubscriberState::Pending),
+            requested: row.get(4)?,
+            confirmed: row.get(5)?,
+        });
+    }
+
+    impl SqliteStore {
+        pub fn open(path: &str) -> Result<SqliteStore, String> {
+            return Ok(SqliteStore(store::sqlite::open(path,
+                "CREATE TABLE IF NOT EXISTS subscribers (
+                    blog TEXT NOT NULL, email TEXT NOT NULL, token TEXT NOT NULL, state TEXT NOT NULL, requested TEXT NOT NULL, confirmed TEXT,
+                    PRIMARY KEY (blog, email)
+                )",
+            )?));
+        }
+    }
+
+    #[rocket::async_trait]
+    impl SubscriberStore for SqliteStore {
+        async fn get(&self, blog: &str, email: &str) -> Result<Option<Subscriber>, String> {
+            return self.0.lock().unwrap()
+                .query_row("SELECT blog, email, token, state, requested, confirmed FROM subscribers WHERE blog = ?1 AND email = ?2", [blog, email], subscriber)
+                .optional()
+                .map_err(|err| err.to_string());
+        }
+
+        async fn put(&self, subscriber: &Subscriber) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute(
+                    "INSERT OR REPLACE INTO subscribers (blog, email, token, state, requested, confirmed) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![subscriber.blog, subscriber.email, subscriber.token, subscriber.state.as_str(), subscriber.requested, subscriber.confirmed],
+                )
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, blog: &str, email: &str) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute("DELETE FROM subscribers WHERE blog = ?1 AND email = ?2", [blog, email])
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn all(&self, blog: &str) -> Result<Vec<Subscriber>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT blog, email, token, state, requested, confirmed FROM subscribers WHERE blog = ?1")
+                .map_err(|err| err.to_string())?;
+            let subscribers = statement.query_map([blog], subscriber).map_err(|err| err.to_string())?;
+            return subscribers.collect::<rusqlite::Result<Vec<_>>>().map_err(|err| err.to_string());
+        }
+    }
+}
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb {
+    use std::collections::HashMap;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use crate::store::dynamodb::Table;
+    use crate::tenant::scoped;
+    use super::{Subscriber, SubscriberStore};
+
+    /// Items are `{ id: S, blog: S, subscriber: S }`, with the address under its blog's id as `id`, the partition
+    /// key, the blog's id as `blog`, and the subscriber as JSON.
+    pub struct DynamoStore(pub Table);
+
+    fn subscriber(item: &HashMap<String, AttributeValue>) -> Option<Subscriber> {
+        return serde_json::from_str(item.get("subscriber")?.as_s().ok()?).ok();
+    }
+
+    #[rocket::async_trait]
+    impl SubscriberStore for DynamoStore {
+        async fn get(&self, blog: &str, email: &str) -> Result<Option<Subscriber>, String> {
+            let item = self.0.client.get_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(scoped(blog, email)))
+                .send().await
+                .map_err(|err| err.to_string())?
+                .item;
+            return Ok(item.as_ref().and_then(subscriber));
+        }
+
+        async fn put(&self, subscriber: &Subscriber) -> Result<(), String> {
+            return self.0.client.put_item()
+                .table_name(&self.0.name)
+                .item("id", AttributeValue::S(scoped(&subscriber.blog, &subscriber.email)))
+                .item("blog", AttributeValue::S(subscriber.blog.to_owned()))
+                .item("subscriber", AttributeValue::S(serde_json::to_string(subscriber).map_err(|err| err.to_string())?))
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, blog: &str, email: &str) -> Result<(), String> {
+            return self.0.client.delete_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(scoped(blog, email)))
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn all(&self, blog: &str) -> Result<Vec<Subscriber>, String> {
+            let items: Vec<HashMap<String, AttributeValue>> = self.0.client.scan()
+                .table_name(&self.0.name)
+                .filter_expression("blog = :blog")
+                .expression_attribute_values(":blog", AttributeValue::S(blog.to_owned()))
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<_, _>>().await
+                .map_err(|err| err.to_string())?;
+            return Ok(items.iter().filter_map(subscriber).collect());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email(" Hackle@HackleWayne.com "), Some(String::from("hackle@hacklewayne.com")));
+        assert_eq!(normalize_email("hackle+blog@mail.example.co.nz"), Some(String::from("hackle+blog@mail.example.co.nz")));
+        assert_eq!(normalize_email("hackle"), None);
+        assert_eq!(normalize_email("@hacklewayne.com"), None);
+        assert_eq!(normalize_email("hackle@localhost"), None);
+        assert_eq!(normalize_email("hackle@@hacklewayne.com"), None);
+        assert_eq!(normalize_email("hackle wayne@hacklewayne.com"), None);
+        assert_eq!(normalize_email("hackle@hacklewayne.com,victim@example.com"), None);
+        assert_eq!(normalize_email(&format!("{}@hacklewayne.com", "a".repeat(250))), None);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let now = Utc.ymd(2024, 2, 1).and_hms(9, 0, 0);
+        let pending = subscribe(None, "", "hackle@hacklewayne.com", now).unwrap();
+        assert_eq!((pending.state, pending.requested, pending.confirmed), (SubscriberState::Pending, now, None));
+        assert_eq!(pending.token.len(), 32);
+
+        assert_eq!(subscribe(Some(pending.to_owned()), "", "hackle@hacklewayne.com", now + Days::minutes(5)), None);
+        let again = subscribe(Some(pending.to_owned()), "", "hackle@hacklewayne.com", now + Days::hours(2)).unwrap();
+        assert_eq!((again.token.as_str(), again.requested), (pending.token.as_str(), now + Days::hours(2)));
+
+        let confirmed = Subscriber { state: SubscriberState::Confirmed, confirmed: Some(now), ..pending };
+        assert_eq!(subscribe(Some(confirmed), "", "hackle@hacklewayne.com", now + Days::days(2)), None);
+    }
+
+    #[test]
+    fn test_confirm_url() {
+        let subscriber = Subscriber {
+            blog: String::new(),
+            email: String::from("hackle+blog@hacklewayne.com"),
+            token: String::from("abc"),
+            state: SubscriberState::Pending,
+            requested: Utc.ymd(2024, 2, 1).and_hms(9, 0, 0),
+            confirmed: None,
+        };
+        assert_eq!(confirm_url("https://www.hacklewayne.com", &subscriber), "https://www.hacklewayne.com/subscribe/confirm?email=hackle%2Bblog%40hacklewayne.com&token=abc");
+        assert_eq!(unsubscribe_url("https://www.hacklewayne.com", &subscriber), "https://www.hacklewayne.com/unsubscribe?email=hackle%2Bblog%40hacklewayne.com&token=abc");
+        assert!(confirms(&subscriber, "abc"));
+        assert!(!confirms(&subscriber, "abd"));
+        assert!(!confirms(&subscriber, ""));
+    }
+
+    #[rocket::async_test]
+    async fn test_memory_store() {
+        let now = Utc.ymd(2024, 2, 1).and_hms(9, 0, 0);
+        let store = MemoryStore::default();
+        let pending = subscribe(None, "", "fan@example.com", now).unwrap();
+        store.put(&pending).await.unwrap();
+        store.put(&Subscriber { state: SubscriberState::Confirmed, confirmed: Some(now), ..subscribe(None, "", "hackle@hacklewayne.com", now).unwrap() }).await.unwrap();
+
+        store.put(&subscribe(None, "example.com", "fan@example.com", now).unwrap()).await.unwrap();
+
+        assert_eq!(store.get("", "fan@example.com").await.unwrap(), Some(pending));
+        assert_eq!(store.get("", "nobody@example.com").await.unwrap(), None);
+        assert_eq!(csv(&store.all("").await.unwrap()), "email,requested,confirmed\nhackle@hacklewayne.com,2024-02-01T09:00:00+00:00,2024-02-01T09:00:00+00:00\n");
+        store.remove("", "fan@example.com").await.unwrap();
+        assert_eq!(store.all("").await.unwrap().len(), 1);
+        assert_eq!(store.all("example.com").await.unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn test_sqlite_store() {
+        let path = std::env::temp_dir().join(format!("blog-newsletter-{}.db", std::process::id()));
+        let store = sqlite::SqliteStore::open(path.to_str().unwrap()).unwrap();
+        let now = Utc.ymd(2024, 2, 1).and_hms(9, 0, 0);
+
+        let mut subscriber = subscribe(None, "", "hackle@hacklewayne.com", now).unwrap();
+        store.put(&subscriber).await.unwrap();
+        subscriber.state = SubscriberState::Confirmed;
+        subscriber.confirmed = Some(now + Days::minutes(3));
+        store.put(&subscriber).await.unwrap();
+        store.put(&subscribe(None, "example.com", "hackle@hacklewayne.com", now).unwrap()).await.unwrap();
+        assert_eq!(store.get("", "hackle@hacklewayne.com").await.unwrap(), Some(subscriber.to_owned()));
+        assert_eq!(store.all("").await.unwrap(), vec![subscriber]);
+        store.remove("", "hackle@hacklewayne.com").await.unwrap();
+        assert!(store.all("").await.unwrap().is_empty());
+        assert_eq!(store.all("example.com").await.unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Redirect;
+use rocket::{get, routes, uri, Route};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::info;
+use crate::auth::constant_time_eq;
+use crate::config::{config, BlogConfig};
+use crate::reporting::capture_error;
+
+const SESSION_COOKIE: &str = "admin_session";
+const STATE_COOKIE: &str = "oauth_state";
+const USER_AGENT: &str = "blog-rust";
+const SESSION_HOURS: i64 = 12;
+
+/// Signing in with GitHub, configured by `github_client_id`, `github_client_secret`, the one `admin_github_user`
+/// allowed in, and the `session_secret` that signs the session cookie. Any of them missing and there is no login.
+#[derive(Clone, Debug)]
+pub struct GithubOAuth {
+    pub client_id: String,
+    pub client_secret: String,
+    pub user: String,
+    pub session_secret: String,
+}
+
+impl GithubOAuth {
+    pub fn from_config(config: &BlogConfig) -> Option<GithubOAuth> {
+        return Some(GithubOAuth {
+            client_id: config.github_client_id.to_owned()?,
+            client_secret: config.github_client_secret.to_owned()?,
+            user: config.admin_github_user.to_owned()?,
+            session_secret: config.session_secret.to_owned()?,
+        });
+    }
+}
+
+fn signature(secret: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(payload.as_bytes());
+    return hex::encode(mac.finalize().into_bytes());
+}
+
+/// `<user>.<expiry as unix seconds>.<HMAC of both>`
+pub fn sign_session(secret: &str, user: &str, expires: i64) -> String {
+    let payload = format!("{}.{}", user, expires);
+    return format!("{}.{}", payload, signature(secret, &payload));
+}
+
+/// The user a session cookie was signed for, as long as it hasn't been tampered with or expired.
+pub fn verify_session(secret: &str, cookie: &str, now: i64) -> Option<String> {
+    let (payload, given) = cookie.rsplit_once('.')?;
+    let (user, expires) = payload.rsplit_once('.')?;
+    let expires: i64 = expires.parse().ok()?;
+
+    let signed = constant_time_eq(signature(secret, payload).as_bytes(), given.as_bytes());
+    return Some(user.to_owned()).filter(|_| signed && expires > now);
+}
+
+/// Passes browsers holding a session cookie from signing in as `ADMIN_GITHUB_USER`; forwards when login isn't configured.
+pub struct AdminSession;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminSession {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let oauth = match GithubOAuth::from_config(config()) {
+            Some(oauth) => oauth,
+            None => return Outcome::Forward(())
+        };
+
+        let user = request.cookies().get(SESSION_COOKIE)
+            .and_then(|cookie| verify_session(&oauth.session_secret, cookie.value(), Utc::now().timestamp()));
+
+        return match user {
+            Some(user) if user.eq_ignore_ascii_case(&oauth.user) => Outcome::Success(AdminSession),
+            _ => Outcome::Failure((Status::Unauthorized, ()))
+        };
+    }
+}
+
+/// Lasts the browser session; the session cookie's own signed expiry is what ends it on the server.
+fn cookie(name: &'static str, value: String) -> Cookie<'static> {
+    return Cookie::build(name, value)
+        .path("/")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .finish();
+}
+
+#[get("/login")]
+fn login(cookies: &CookieJar<'_>) -> Option<Redirect> {
+    let oauth = GithubOAuth::from_config(config())?;
+    let state = uuid::Uuid::new_v4().to_string();
+    cookies.add(cookie(STATE_COOKIE, state.to_owned()));
+
+    return Some(Redirect::to(format!("https://github.com/login/oauth/authorize?client_id={}&state={}&allow_signup=false", oauth.client_id, state)));
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    login: String,
+}
+
+async fn github_login(oauth: &GithubOAuth, code: &str) -> Result<String, String> {
+    let client = reqwest::Client::new();
+    let token = client.post("https://github.com/login/oauth/access_token")
+        .header("Accept", "application/json")
+        .form(&[("client_id", oauth.client_id.as_str()), ("client_secret", oauth.client_secret.as_str()), ("code", code)])
+        .send().await
+        .map_err(|err| err.to_string())?
+        .json::<AccessToken>().await
+        .map_err(|err| format!("Cannot exchange the OAuth code, {}", err))?;
+
+    let user = client.get("https://api.github.com/user")
+        .header("Authorization", format!("Bearer {}", token.access_token))
+        .header("User-Agent", USER_AGENT)
+        .send().await
+        .map_err(|err| err.to_string())?
+        .json::<GithubUser>().await
+        .map_err(|err| format!("Cannot read the GitHub user, {}", err))?;
+
+    return Ok(user.login);
+}
+
+/// Where GitHub sends the browser back to; the app's callback URL must point here.
+#[get("/callback?<code>&<state>")]
+async fn callback(code: &str, state: &str, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+    let oauth = GithubOAuth::from_config(config()).ok_or(Status::NotFound)?;
+
+    let expected_state = cookies.get(STATE_COOKIE).map(|cookie| cookie.value().to_owned());
+    cookies.remove(Cookie::named(STATE_COOKIE));
+    if expected_state.as_deref() != Some(state) {
+        return Err(Status::BadRequest);
+    }
+
+    let user = github_login(&oauth, code).await
+        .map_err(|err| {
+            capture_error(&err, &[("source", "github oauth")]);
+            Status::BadGateway
+        })?;
+    if !user.eq_ignore_ascii_case(&oauth.user) {
+        info!(%user, "refused login");
+        return Err(Status::Forbidden);
+    }
+
+    let expires = (Utc::now() + Duration::hours(SESSION_HOURS)).timestamp();
+    cookies.add(cookie(SESSION_COOKIE, sign_session(&oauth.session_secret, &user, expires)));
+    info!(%user, "logged in");
+
+    return Ok(Redirect::to(uri!("/admin/posts")));
+}
+
+#[get("/logout")]
+fn logout(cookies: &CookieJar<'_>) -> Redirect {
+    cookies.remove(Cookie::named(SESSION_COOKIE));
+    return Redirect::to(uri!("/"));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![login, callback, logout];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_cookie() {
+        let cookie = sign_session("s3cret", "hackle", 1_000);
+
+        assert_eq!(verify_session("s3cret", &cookie, 999), Some(String::from("hackle")));
+        assert_eq!(verify_session("s3cret", &cookie, 1_000), None);
+        assert_eq!(verify_session("other secret", &cookie, 999), None);
+        assert_eq!(verify_session("s3cret", &cookie.replace("hackle.", "mallory."), 999), None);
+        assert_eq!(verify_session("s3cret", &cookie.replace(".1000.", ".9999."), 999), None);
+        assert_eq!(verify_session("s3cret", "hackle", 999), None);
+    }
+}
@@ -0,0 +1,95 @@
+//! `/feeds.opml`, every feed the blog has in one file a feed reader imports at once: the main feed, one per
+//! language and one per tag.
+use reqwest::Url;
+use rocket::http::{ContentType, Status};
+use rocket::{get, routes, Route};
+use crate::blog::{languages, tags};
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::shortcodes::escape_html;
+use crate::tenant::Tenant;
+
+/// A feed, as an OPML outline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Feed {
+    pub title: String,
+    pub url: String,
+}
+
+/// The URL of the feed at `segments` under `/rss`, each segment percent-encoded.
+fn feed_url(public_url: &str, segments: &[&str]) -> String {
+    let mut url = match Url::parse(public_url) {
+        Ok(url) => url,
+        Err(_) => return format!("{}/rss/{}", public_url, segments.join("/")),
+    };
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.pop_if_empty().push("rss").extend(segments);
+    }
+    return url.to_string();
+}
+
+/// The main feed, then one per language when there is more than one, then one per tag.
+pub fn feeds(title: &str, public_url: &str, languages: &[String], tags: &[String]) -> Vec<Feed> {
+    let mut feeds = vec![Feed { title: title.to_owned(), url: feed_url(public_url, &["index.xml"]) }];
+    if languages.len() > 1 {
+        feeds.extend(languages.iter().map(|lang| Feed { title: format!("{} ({})", title, lang), url: feed_url(public_url, &[lang, "index.xml"]) }));
+    }
+    feeds.extend(tags.iter().map(|tag| Feed { title: format!("{}: {}", title, tag), url: feed_url(public_url, &["tags", tag, "index.xml"]) }));
+    return feeds;
+}
+
+pub fn opml(title: &str, public_url: &str, feeds: &[Feed]) -> String {
+    let outlines: Vec<String> = feeds.iter()
+        .map(|feed| format!(
+            "    <outline type=\"rss\" text=\"{0}\" title=\"{0}\" xmlUrl=\"{1}\" htmlUrl=\"{2}\"/>",
+            escape_html(&feed.title), escape_html(&feed.url), escape_html(public_url),
+        ))
+        .collect();
+    return format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n  <head>\n    <title>{}</title>\n  </head>\n  <body>\n{}\n  </body>\n</opml>\n",
+        escape_html(title), outlines.join("\n"),
+    );
+}
+
+#[get("/feeds.opml")]
+async fn feeds_opml(tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<(ContentType, String), Status> {
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|_| Status::ServiceUnavailable)?;
+    let feeds = feeds(&tenant.title, &public_url.0, &languages(&posts), &tags(&posts));
+    return Ok((ContentType::with_params("text", "x-opml", ("charset", "utf-8")), opml(&tenant.title, &public_url.0, &feeds)));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![feeds_opml];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_URL: &str = "https://www.hacklewayne.com";
+
+    #[test]
+    fn test_feeds() {
+        let feeds = feeds("Hackle's blog", PUBLIC_URL, &[String::from("en"), String::from("zh")], &[String::from("c#"), String::from("haskell")]);
+        let urls: Vec<&str> = feeds.iter().map(|feed| feed.url.as_str()).collect();
+        assert_eq!(urls, vec![
+            "https://www.hacklewayne.com/rss/index.xml",
+            "https://www.hacklewayne.com/rss/en/index.xml",
+            "https://www.hacklewayne.com/rss/zh/index.xml",
+            "https://www.hacklewayne.com/rss/tags/c%23/index.xml",
+            "https://www.hacklewayne.com/rss/tags/haskell/index.xml",
+        ]);
+        assert_eq!(feeds[4].title, "Hackle's blog: haskell");
+
+        assert_eq!(self::feeds("Hackle's blog", PUBLIC_URL, &[String::from("en")], &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_opml() {
+        let feeds = vec![Feed { title: String::from("Hackle's blog"), url: String::from("https://www.hacklewayne.com/rss/index.xml") }];
+        let opml = opml("Hackle's blog", PUBLIC_URL, &feeds);
+        assert!(opml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">"));
+        assert!(opml.contains("<title>Hackle&#39;s blog</title>"));
+        assert!(opml.contains(r#"<outline type="rss" text="Hackle&#39;s blog" title="Hackle&#39;s blog" xmlUrl="https://www.hacklewayne.com/rss/index.xml" htmlUrl="https://www.hacklewayne.com"/>"#));
+    }
+}
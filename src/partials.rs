@@ -0,0 +1,96 @@
+//! Every `partials/<name>.hbs` under the template directory is a partial, `{{> name}}`, so pages share their
+//! pieces rather than each repeating them. Those of a blog's own templates are named by their directory,
+//! `example/partials/header.hbs` is `{{> example/header}}`.
+//!
+//! Pages are laid out by `partials/layout.hbs` as a partial block, filling in what differs between them:
+//!
+//! ```handlebars
+//! {{#> layout}}
+//!     {{#*inline "head"}}<meta name="description" content="{{description}}">{{/inline}}
+//!     {{#*inline "content"}}<h1>{{title}}</h1>{{/inline}}
+//! {{/layout}}
+//! ```
+//!
+//! where the layout renders `{{> head}}` and `{{> content}}`, and may give either a default with
+//! `{{#> head}}...{{/head}}`. Partials are registered again whenever templates reload.
+use std::path::{Component, Path};
+use rocket_dyn_templates::handlebars::Handlebars;
+use tracing::warn;
+
+const PARTIALS_DIR: &str = "partials";
+const EXTENSION: &str = "hbs";
+
+/// The name of the partial at `path`, relative to the template directory, if it is one.
+pub fn partial_name(path: &Path) -> Option<String> {
+    if path.extension()?.to_str()? != EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let mut directories: Vec<&str> = path.parent()?.components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if directories.pop()? != PARTIALS_DIR {
+        return None;
+    }
+
+    return Some(directories.into_iter().chain([stem]).collect::<Vec<_>>().join("/"));
+}
+
+fn visit(root: &Path, directory: &Path, partials: &mut Vec<(String, std::path::PathBuf)>) {
+    for entry in std::fs::read_dir(directory).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            visit(root, &path, partials);
+        } else if let Some(name) = path.strip_prefix(root).ok().and_then(partial_name) {
+            partials.push((name, path));
+        }
+    }
+}
+
+/// Registers the partials under `template_dir`. One that doesn't parse is logged and left out, and so fails
+/// only the pages that use it.
+pub fn register(handlebars: &mut Handlebars, template_dir: &Path) {
+    let mut partials = vec![];
+    visit(template_dir, template_dir, &mut partials);
+
+    for (name, path) in partials {
+        let registered = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|partial| handlebars.register_partial(&name, partial).map_err(|err| err.to_string()));
+        if let Err(err) = registered {
+            warn!(partial = %name, path = %path.display(), error = %err, "cannot register partial");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_partial_name() {
+        assert_eq!(partial_name(Path::new("partials/header.hbs")), Some(String::from("header")));
+        assert_eq!(partial_name(Path::new("example/partials/header.hbs")), Some(String::from("example/header")));
+        assert_eq!(partial_name(Path::new("partials/header.html")), None);
+        assert_eq!(partial_name(Path::new("main.html.hbs")), None);
+        assert_eq!(partial_name(Path::new("example/main.html.hbs")), None);
+    }
+
+    #[test]
+    fn test_register() {
+        let mut handlebars = Handlebars::new();
+        crate::helpers::register(&mut handlebars, crate::helpers::asset_fingerprints(Path::new("static")));
+        register(&mut handlebars, Path::new("templates"));
+        let page = r#"{{#> layout}}{{#*inline "head"}}<meta name="robots" content="noindex">{{/inline}}{{#*inline "content"}}<h1>{{title}}</h1>{{/inline}}{{/layout}}"#;
+        let html = handlebars.render_template(page, &json!({ "title": "Search", "site_title": "Blog", "build": "v1" })).unwrap();
+
+        assert!(html.contains("<title> Search | Blog </title>"));
+        assert!(html.contains(r#"<meta name="robots" content="noindex">"#));
+        assert!(html.contains("<h1>Search</h1>"));
+        assert!(html.contains(r#"<p class="build">v1</p>"#));
+    }
+}
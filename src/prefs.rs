@@ -0,0 +1,135 @@
+use rocket::form::{Form, FromForm};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Redirect;
+use rocket::{post, routes, Route};
+use serde::Serialize;
+
+pub const THEME_COOKIE: &str = "theme";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    pub fn parse(value: &str) -> Option<ColorScheme> {
+        return match value {
+            "light" => Some(ColorScheme::Light),
+            "dark" => Some(ColorScheme::Dark),
+            _ => None,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        };
+    }
+}
+
+/// The color scheme a reader picked, given to templates as `color_scheme` so the page is rendered with it rather
+/// than switching once a script has run. `None` follows the browser's `prefers-color-scheme`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThemePreference(pub Option<ColorScheme>);
+
+impl ThemePreference {
+    pub fn of(request: &Request<'_>) -> ThemePreference {
+        return ThemePreference(request.cookies().get(THEME_COOKIE).and_then(|cookie| ColorScheme::parse(cookie.value())));
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ThemePreference {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(ThemePreference::of(request));
+    }
+}
+
+/// The path and query of the page the form was posted from, so the reader lands back on it; anything that isn't
+/// a path on this site goes to the home page instead.
+pub fn back_to(referer: Option<&str>) -> String {
+    let path = referer
+        .map(|referer| match referer.split_once("://") {
+            Some((_, rest)) => rest.find('/').map(|start| &rest[start..]).unwrap_or("/"),
+            None => referer,
+        })
+        .filter(|path| path.starts_with('/') && !path.starts_with("//"))
+        .unwrap_or("/");
+    return path.split('#').next().unwrap_or("/").to_owned();
+}
+
+struct Referer(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Referer {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(Referer(request.headers().get_one("Referer").map(str::to_owned)));
+    }
+}
+
+#[derive(FromForm)]
+struct ThemeForm<'r> {
+    /// `light`, `dark`, or `system` to follow the browser again.
+    theme: &'r str,
+}
+
+#[post("/prefs/theme", data = "<form>")]
+fn set_theme(form: Form<ThemeForm<'_>>, cookies: &CookieJar<'_>, referer: Referer) -> Redirect {
+    match ColorScheme::parse(form.theme) {
+        Some(scheme) => cookies.add(Cookie::build(THEME_COOKIE, scheme.as_str())
+            .path("/")
+            .permanent()
+            .secure(true)
+            .same_site(SameSite::Lax)
+            .finish()),
+        None => cookies.remove(Cookie::build(THEME_COOKIE, "").path("/").finish()),
+    }
+
+    return Redirect::to(back_to(referer.0.as_deref()));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![set_theme];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_back_to() {
+        assert_eq!(back_to(Some("https://hacklewayne.com/zip-is-scan?x=1#top")), "/zip-is-scan?x=1");
+        assert_eq!(back_to(Some("https://hacklewayne.com")), "/");
+        assert_eq!(back_to(Some("/archive")), "/archive");
+        assert_eq!(back_to(Some("//evil.example/")), "/");
+        assert_eq!(back_to(Some("javascript:alert(1)")), "/");
+        assert_eq!(back_to(None), "/");
+    }
+
+    #[rocket::async_test]
+    async fn test_theme() {
+        let client = Client::untracked(rocket::build().mount("/", routes())).await.unwrap();
+        let post = |theme: &'static str| client.post("/prefs/theme")
+            .header(ContentType::Form)
+            .header(Header::new("Referer", "https://hacklewayne.com/archive"))
+            .body(format!("theme={}", theme));
+
+        let response = post("dark").dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("Location"), Some("/archive"));
+        assert_eq!(response.cookies().get(THEME_COOKIE).map(|cookie| cookie.value()), Some("dark"));
+
+        let response = post("system").cookie(Cookie::new(THEME_COOKIE, "dark")).dispatch().await;
+        assert_eq!(response.cookies().get(THEME_COOKIE).map(|cookie| cookie.value()), Some(""));
+    }
+}
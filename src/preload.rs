@@ -0,0 +1,88 @@
+//! `Link: rel=preload` on every page for the stylesheets and fonts in `preload`, so a browser fetches them while it
+//! is still reading the HTML. Rocket can't send a 103 itself, but a CDN in front that supports Early Hints, such as
+//! Cloudflare or Fastly, sends these ahead as one while the page is rendered.
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::Response;
+use crate::assets::Assets;
+use crate::config::BlogConfig;
+
+/// How a browser fetches a file, by its extension: its `as`, and for a font its type and `crossorigin`, as fonts are
+/// always fetched with CORS and a preload without it would be fetched twice.
+pub fn destination(file: &str) -> Option<&'static str> {
+    let extension = file.rsplit('.').next().unwrap_or_default().to_ascii_lowercase();
+    return match extension.as_str() {
+        "css" => Some("as=style"),
+        "js" => Some("as=script"),
+        "woff2" => Some("as=font; type=\"font/woff2\"; crossorigin"),
+        "woff" => Some("as=font; type=\"font/woff\"; crossorigin"),
+        "ttf" => Some("as=font; type=\"font/ttf\"; crossorigin"),
+        "otf" => Some("as=font; type=\"font/otf\"; crossorigin"),
+        "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" => Some("as=image"),
+        _ => None,
+    };
+}
+
+/// A file under `static/` at its fingerprinted URL, or a URL elsewhere as it is.
+pub fn link(file: &str, assets: &Assets) -> Option<String> {
+    let destination = destination(file)?;
+    let url = if file.starts_with("https://") || file.starts_with("http://") { file.to_owned() } else { assets.url(file) };
+    return Some(format!("<{}>; rel=preload; {}", url, destination));
+}
+
+pub struct Preload {
+    links: Option<String>,
+}
+
+impl Preload {
+    pub fn new<'a>(files: impl IntoIterator<Item = &'a str>, assets: &Assets) -> Preload {
+        let links: Vec<String> = files.into_iter().filter_map(|file| link(file, assets)).collect();
+        return Preload { links: Some(links.join(", ")).filter(|links| !links.is_empty()) };
+    }
+
+    pub fn from_config(config: &BlogConfig, assets: &Assets) -> Preload {
+        return Preload::new(config.preload_files(), assets);
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Preload {
+    fn info(&self) -> Info {
+        return Info { name: "Link preload headers", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
+        let page = response.status() == Status::Ok && response.content_type() == Some(ContentType::HTML);
+        if let Some(links) = self.links.as_ref().filter(|_| page) {
+            response.adjoin_raw_header("Link", links.to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_link() {
+        let assets = Assets::new(Path::new("static"), None);
+        assert_eq!(link("styles.css", &assets), Some(format!("<{}>; rel=preload; as=style", assets.url("styles.css"))));
+        assert_eq!(link("fonts/inter.woff2", &assets), Some(String::from("</static/fonts/inter.woff2>; rel=preload; as=font; type=\"font/woff2\"; crossorigin")));
+        assert_eq!(
+            link("https://cdnjs.cloudflare.com/ajax/libs/github-markdown-css/2.10.0/github-markdown.min.css", &assets),
+            Some(String::from("<https://cdnjs.cloudflare.com/ajax/libs/github-markdown-css/2.10.0/github-markdown.min.css>; rel=preload; as=style")),
+        );
+        assert_eq!(link("favicon.ico", &assets), None);
+    }
+
+    #[test]
+    fn test_new() {
+        let assets = Assets::new(Path::new("static"), None);
+        let preload = Preload::new(["styles.css", "favicon.ico", "prism-idris.js"], &assets);
+        let expected = format!("<{}>; rel=preload; as=style, <{}>; rel=preload; as=script", assets.url("styles.css"), assets.url("prism-idris.js"));
+        assert_eq!(preload.links, Some(expected));
+        assert_eq!(Preload::new([], &assets).links, None);
+    }
+}
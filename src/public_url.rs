@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+use rocket::request::{FromRequest, Outcome, Request};
+use crate::config::{config, BlogConfig};
+use crate::tenant::Tenant;
+
+/// Where the blog is reachable, e.g. `https://hacklewayne.com`, without a trailing slash; the start of every absolute URL
+/// in feeds, canonical links and share tags. It is the blog's `public_url`, or else the configured one, unless `trust_proxy_headers` is `true`, when it comes
+/// from `X-Forwarded-Proto` and `X-Forwarded-Host` (or `Host`) as set by API Gateway, CloudFront or a reverse proxy.
+/// Only trust those behind a proxy that overwrites them, otherwise any client can pick the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicUrl(pub String);
+
+pub fn trust_proxy_headers() -> bool {
+    return config().trust_proxy_headers;
+}
+
+/// The address a request came from: the peer's, unless `trust_proxy_headers` is `true`, when it is the first of
+/// `X-Forwarded-For`, or `X-Real-IP`, as the proxy in front saw it. Rocket's own `client_ip` reads `X-Real-IP`
+/// from anyone, so anything counted or limited by address goes through this instead.
+pub fn client_address(request: &Request<'_>) -> Option<IpAddr> {
+    if trust_proxy_headers() {
+        let headers = request.headers();
+        let forwarded = headers.get_one("X-Forwarded-For").map(PublicUrl::first).or_else(|| headers.get_one("X-Real-IP"));
+        if let Some(address) = forwarded.and_then(|address| address.trim().parse().ok()) {
+            return Some(address);
+        }
+    }
+    return request.remote().map(|remote| remote.ip());
+}
+
+impl PublicUrl {
+    pub fn from_config(config: &BlogConfig) -> PublicUrl {
+        return PublicUrl(config.public_url.trim_end_matches('/').to_owned());
+    }
+
+    /// The first of a comma separated list of forwarded values, the one the client sent to the outermost proxy.
+    fn first(value: &str) -> &str {
+        return value.split(',').next().unwrap_or_default().trim();
+    }
+
+    pub fn from_headers(proto: Option<&str>, host: Option<&str>) -> Option<PublicUrl> {
+        let host = host.map(PublicUrl::first)
+            .filter(|host| !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':'))?;
+        let proto = match proto.map(PublicUrl::first) {
+            Some("http") => "http",
+            _ => "https"
+        };
+
+        return Some(PublicUrl(format!("{}://{}", proto, host)));
+    }
+
+    pub fn of(request: &Request<'_>) -> PublicUrl {
+        return request.local_cache(|| {
+            let headers = request.headers();
+            let forwarded = if trust_proxy_headers() {
+                PublicUrl::from_headers(headers.get_one("X-Forwarded-Proto"), headers.get_one("X-Forwarded-Host").or_else(|| headers.get_one("Host")))
+            } else {
+                None
+            };
+            forwarded
+                .or_else(|| Tenant::of(request).public_url.to_owned().map(PublicUrl))
+                .unwrap_or_else(|| PublicUrl::from_config(config()))
+        }).to_owned();
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PublicUrl {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(PublicUrl::of(request));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_from_headers() {
+        assert_eq!(PublicUrl::from_headers(Some("https"), Some("hacklewayne.com")), Some(PublicUrl(String::from("https://hacklewayne.com"))));
+        assert_eq!(PublicUrl::from_headers(Some("http, https"), Some("localhost:8000, proxy.internal")), Some(PublicUrl(String::from("http://localhost:8000"))));
+        assert_eq!(PublicUrl::from_headers(None, Some("abc.execute-api.ap-southeast-2.amazonaws.com")), Some(PublicUrl(String::from("https://abc.execute-api.ap-southeast-2.amazonaws.com"))));
+        assert_eq!(PublicUrl::from_headers(Some("https"), Some("evil.example/\"><script>")), None);
+        assert_eq!(PublicUrl::from_headers(Some("https"), None), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_client_address() {
+        let client = Client::untracked(rocket::build()).await.unwrap();
+        let spoofed = client.get("/")
+            .header(Header::new("X-Real-IP", "203.0.113.9"))
+            .header(Header::new("X-Forwarded-For", "203.0.113.10"));
+
+        assert!(!trust_proxy_headers());
+        assert_eq!(client_address(spoofed.inner()), spoofed.inner().remote().map(|remote| remote.ip()));
+    }
+}
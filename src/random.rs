@@ -0,0 +1,93 @@
+//! `/random`, for wandering the archive: a listed post picked at random, or with `random_daily` the same post all
+//! day, a post of the day.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use chrono::{NaiveDate, Utc};
+use rocket::http::{Header, Status};
+use rocket::response::Redirect;
+use rocket::{get, routes, Responder, Route};
+use tracing::info;
+use crate::blog::Post;
+use crate::config::config;
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+
+/// The listed post `seed` picks, spread evenly over them; a seed picks the same post while they stay the same.
+pub fn pick(posts: &[Post], seed: u64) -> Option<&Post> {
+    let listed: Vec<&Post> = posts.iter().filter(|post| post.is_listed()).collect();
+    if listed.is_empty() {
+        return None;
+    }
+    return Some(listed[(seed % listed.len() as u64) as usize]);
+}
+
+/// The seed of `day`, the same on every instance.
+pub fn day_seed(day: NaiveDate) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(day.format("%F").to_string().as_bytes());
+    return hasher.finish();
+}
+
+/// A `302`, never cached, so each visit picks again.
+#[derive(Responder)]
+pub struct RandomPost {
+    inner: Redirect,
+    cache_control: Header<'static>,
+}
+
+#[get("/random")]
+async fn random(tenant: &Tenant, request_id: RequestId) -> Result<RandomPost, Status> {
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|_| Status::ServiceUnavailable)?;
+    let seed = if config().random_daily { day_seed(Utc::today().naive_utc()) } else { uuid::Uuid::new_v4().as_u128() as u64 };
+    let post = pick(&posts, seed).ok_or(Status::NotFound)?;
+
+    info!(%request_id, slug = %post.slug, "random post");
+    return Ok(RandomPost { inner: Redirect::found(format!("/{}", post.slug)), cache_control: Header::new("Cache-Control", "no-store") });
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![random];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashSet};
+    use chrono::TimeZone;
+    use crate::blog::Kind;
+
+    fn post(slug: &str) -> Post {
+        return Post {
+            slug: slug.to_owned(),
+            title: slug.to_owned(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+    }
+
+    #[test]
+    fn test_pick() {
+        let posts = vec![post("fin"), Post { hidden: true, ..post("draft") }, Post { kind: Kind::Page, ..post("about") }, post("zip-is-scan")];
+
+        let picked: HashSet<&str> = (0..10).filter_map(|seed| pick(&posts, seed)).map(|post| post.slug.as_str()).collect();
+        assert_eq!(picked, HashSet::from(["fin", "zip-is-scan"]));
+        assert!(pick(&posts[1..3], 7).is_none());
+    }
+
+    #[test]
+    fn test_day_seed() {
+        let day = NaiveDate::from_ymd(2021, 5, 3);
+        assert_eq!(day_seed(day), day_seed(NaiveDate::from_ymd(2021, 5, 3)));
+        assert_ne!(day_seed(day), day_seed(day.succ()));
+    }
+}
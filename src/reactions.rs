@@ -0,0 +1,174 @@
+//! Reactions: readers press 👍, ❤️, 💡 or 😄 under a post, without signing in or writing a comment, and the post
+//! shows how many of each it has. Counters are kept in the store `reaction_store` names; each address gets
+//! `reaction_rate_limit` reactions a minute, so a script can only inflate them slowly.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use rocket::form::{Form, FromForm};
+use rocket::http::Status;
+use rocket::response::content::Json;
+use rocket::response::Redirect;
+use rocket::{get, post, routes, Route, State};
+use serde::Serialize;
+use tracing::info;
+use crate::blog::{find_post, Kind};
+use crate::config::BlogConfig;
+use crate::counters::{self, SharedCounters};
+use crate::request_id::RequestId;
+use crate::spam::Visitor;
+use crate::tenant::Tenant;
+
+/// Every kind of reaction, and how it shows.
+pub const KINDS: [(&str, &str); 4] = [("like", "👍"), ("love", "❤️"), ("insightful", "💡"), ("laugh", "😄")];
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// One kind's count on a post, given to the template as `reactions`.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Reaction {
+    pub kind: &'static str,
+    pub emoji: &'static str,
+    pub count: u64,
+}
+
+/// Every kind, in the order of `KINDS`, with its count in `counts` or 0.
+pub fn reactions(counts: &BTreeMap<String, u64>) -> Vec<Reaction> {
+    return KINDS.iter()
+        .map(|(kind, emoji)| Reaction { kind, emoji, count: counts.get(*kind).copied().unwrap_or(0) })
+        .collect();
+}
+
+/// The counters' key for the reactions to `slug` on `tenant`, with a name a kind.
+fn key(tenant: &Tenant, slug: &str) -> String {
+    return tenant.key(&format!("reactions/{}", slug));
+}
+
+/// At most `limit` hits a key in each `window`, counted from its first; a key whose window is over starts again.
+#[derive(Default)]
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    hits: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> RateLimiter {
+        return RateLimiter { limit, window, hits: Mutex::new(HashMap::new()) };
+    }
+
+    pub fn allow(&self, key: &str, now: Instant) -> bool {
+        let mut hits = self.hits.lock().unwrap();
+        // forget windows that are over, so the map only holds recent addresses
+        hits.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        let (_, count) = hits.entry(key.to_owned()).or_insert((now, 0));
+        if *count >= self.limit {
+            return false;
+        }
+        *count += 1;
+        return true;
+    }
+}
+
+/// The store `reaction_store` names, or none when it is `none` and reactions are off.
+#[derive(Clone, Default)]
+pub struct Reactions {
+    store: Option<SharedCounters>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl Reactions {
+    pub async fn from_config(config: &BlogConfig) -> Result<Reactions, String> {
+        let store = counters::from_config("reaction", &config.reaction_store, config.reaction_database.as_deref(), config.reaction_table.as_deref()).await?;
+        return Ok(Reactions { store, limiter: Arc::new(RateLimiter::new(config.reaction_rate_limit, RATE_WINDOW)) });
+    }
+
+    pub fn open(&self) -> bool {
+        return self.store.is_some();
+    }
+
+    /// Every kind's count on `slug` on `tenant`; a store that fails is logged and shows none.
+    pub async fn of(&self, tenant: &Tenant, slug: &str) -> Vec<Reaction> {
+        let counts = match &self.store {
+            Some(store) => store.totals(&key(tenant, slug)).await.unwrap_or_else(|err| {
+                tracing::warn!(%slug, error = %err, "cannot read reactions");
+                BTreeMap::new()
+            }),
+            None => BTreeMap::new()
+        };
+        return reactions(&counts);
+    }
+}
+
+fn store(reactions: &Reactions) -> Result<&SharedCounters, (Status, String)> {
+    return reactions.store.as_ref().ok_or((Status::NotFound, String::from("reactions are off")));
+}
+
+#[derive(FromForm)]
+struct ReactionForm<'r> {
+    kind: &'r str,
+}
+
+/// Counts a reaction and sends the reader back to the post.
+#[post("/react/<slug>", data = "<form>")]
+async fn react(slug: &str, form: Form<ReactionForm<'_>>, reactions: &State<Reactions>, visitor: Visitor, tenant: &Tenant, request_id: RequestId) -> Result<Redirect, (Status, String)> {
+    let store = store(reactions)?;
+    let kind = KINDS.iter().map(|(kind, _)| *kind).find(|kind| *kind == form.kind).ok_or((Status::BadRequest, format!("no reaction {}", form.kind)))?;
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?;
+    let post = find_post(&posts, slug).filter(|post| post.kind == Kind::Post).ok_or((Status::NotFound, format!("no post {}", slug)))?;
+
+    // without an address readers can't be told apart, so they aren't limited rather than limiting each other
+    if visitor.ip.as_deref().is_some_and(|ip| !reactions.limiter.allow(ip, Instant::now())) {
+        return Err((Status::TooManyRequests, String::from("Too many reactions, try again in a minute")));
+    }
+    let counts = store.increment(&key(tenant, &post.slug), Utc::today().naive_utc(), &[kind]).await.map_err(|err| (Status::BadGateway, err))?;
+
+    info!(%request_id, slug = %post.slug, kind, count = counts[0], "reaction");
+    return Ok(Redirect::to(format!("/{}#reactions", post.slug)));
+}
+
+/// `[{ "kind": "like", "emoji": "👍", "count": 3 }, ...]`
+#[get("/react/<slug>")]
+async fn counts(slug: &str, reactions: &State<Reactions>, tenant: &Tenant) -> Result<Json<String>, (Status, String)> {
+    let counts = store(reactions)?.totals(&key(tenant, slug)).await.map_err(|err| (Status::BadGateway, err))?;
+    return Ok(Json(serde_json::to_string(&self::reactions(&counts)).unwrap()));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![react, counts];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counters::{CounterStore, MemoryStore};
+    use crate::tenant::TenantConfig;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(limiter.allow("203.0.113.1", start));
+        assert!(limiter.allow("203.0.113.1", start + Duration::from_secs(1)));
+        assert!(!limiter.allow("203.0.113.1", start + Duration::from_secs(2)));
+        assert!(limiter.allow("203.0.113.2", start + Duration::from_secs(2)));
+        assert!(limiter.allow("203.0.113.1", start + Duration::from_secs(61)));
+    }
+
+    #[rocket::async_test]
+    async fn test_of() {
+        let store = Arc::new(MemoryStore::default());
+        let today = Utc::today().naive_utc();
+        let only = Tenant::new(TenantConfig::default());
+        let example = Tenant::new(TenantConfig { hosts: vec![String::from("example.com")], ..TenantConfig::default() });
+        store.increment(&key(&only, "zip-is-scan"), today, &["like"]).await.unwrap();
+        store.increment(&key(&only, "zip-is-scan"), today, &["like", "laugh"]).await.unwrap();
+        let reactions = Reactions { store: Some(store), ..Reactions::default() };
+
+        let counts: Vec<u64> = reactions.of(&only, "zip-is-scan").await.into_iter().map(|reaction| reaction.count).collect();
+        assert_eq!(counts, vec![2, 0, 0, 1]);
+        assert!(reactions.of(&only, "fin").await.iter().all(|reaction| reaction.count == 0));
+        assert!(reactions.of(&example, "zip-is-scan").await.iter().all(|reaction| reaction.count == 0));
+        assert!(Reactions::default().of(&only, "zip-is-scan").await.iter().all(|reaction| reaction.count == 0));
+    }
+}
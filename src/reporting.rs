@@ -0,0 +1,61 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::Request;
+use rocket::response::Response;
+use tracing::error;
+use crate::request_id::RequestId;
+
+/// Keeps the Sentry client alive until the end of `main`, flushing queued events when dropped.
+pub struct Reporting {
+    #[cfg(feature = "sentry")]
+    _guard: Option<sentry::ClientInitGuard>,
+}
+
+/// With the `sentry` feature and `SENTRY_DSN` set, errors, panics and 5xx responses are sent to Sentry.
+/// Otherwise they are only logged.
+pub fn init() -> Reporting {
+    #[cfg(feature = "sentry")]
+    return Reporting {
+        _guard: std::env::var("SENTRY_DSN").ok().map(|dsn| sentry::init((dsn, sentry::ClientOptions {
+            release: sentry::release_name!(),
+            ..sentry::ClientOptions::default()
+        }))),
+    };
+    #[cfg(not(feature = "sentry"))]
+    return Reporting {};
+}
+
+/// Logs an error and reports it with its context, e.g. `[("slug", "fin"), ("source", "remote")]`, as tags.
+pub fn capture_error(message: &str, context: &[(&str, &str)]) {
+    error!(context = ?context, "{}", message);
+
+    #[cfg(feature = "sentry")]
+    sentry::with_scope(
+        |scope| for (key, value) in context {
+            scope.set_tag(key, value);
+        },
+        || sentry::capture_message(message, sentry::Level::Error)
+    );
+}
+
+/// Reports every 5xx response, whatever produced it.
+pub struct ReportServerErrors;
+
+#[rocket::async_trait]
+impl Fairing for ReportServerErrors {
+    fn info(&self) -> Info {
+        return Info { name: "Report 5xx responses", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status().code < 500 {
+            return;
+        }
+
+        let request_id = RequestId::of(request);
+        let path = request.uri().path().to_string();
+        capture_error(
+            &format!("{} {} responded {}", request.method(), path, response.status()),
+            &[("request_id", &request_id.0), ("path", &path)]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Response;
+use rocket::Data;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Identifies one request across the response header, log lines, error pages and upstream fetches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn generate() -> RequestId {
+        return RequestId(uuid::Uuid::new_v4().to_string());
+    }
+
+    /// Keeps an incoming id from a CDN or load balancer, as long as it is short and plain enough to log and echo back.
+    pub fn from_header(value: &str) -> Option<RequestId> {
+        let value = value.trim();
+        let plain = value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+        return Some(value)
+            .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH && plain)
+            .map(|value| RequestId(value.to_owned()));
+    }
+
+    pub fn of(request: &Request<'_>) -> RequestId {
+        return request.local_cache(|| {
+            request.headers().get_one(REQUEST_ID_HEADER)
+                .and_then(RequestId::from_header)
+                .unwrap_or_else(RequestId::generate)
+        }).to_owned();
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(RequestId::of(request));
+    }
+}
+
+/// Assigns every request an id, or adopts the one it came with, and echoes it in `X-Request-Id`.
+pub struct RequestIds;
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        return Info { name: "X-Request-Id", kind: Kind::Request | Kind::Response };
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        RequestId::of(request);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_raw_header(REQUEST_ID_HEADER, RequestId::of(request).0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_header() {
+        assert_eq!(RequestId::from_header(" 1-67891233-abcdef012345678912345678 "), Some(RequestId(String::from("1-67891233-abcdef012345678912345678"))));
+        assert_eq!(RequestId::from_header(""), None);
+        assert_eq!(RequestId::from_header("<script>"), None);
+        assert_eq!(RequestId::from_header(&"a".repeat(129)), None);
+        assert_ne!(RequestId::generate(), RequestId::generate());
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, SecondsFormat, Utc};
+use crate::blog::{to_slug, Registry};
+
+/// The manifest with one more entry at the end, written the way the rest are, one per line, with `tags` and `hidden`
+/// only when there are any and it is.
+pub fn append_entry(manifest: &str, title: &str, markdown: &str, tags: &[String], hidden: bool, updated: DateTime<Utc>) -> Result<String, String> {
+    let entries = manifest.trim_end().strip_suffix(']').ok_or("manifest.json is not a JSON array")?.trim_end();
+    let separator = if entries.ends_with('[') { "" } else { "," };
+    let mut entry = format!(
+        r#"    {{ "updated": {}, "title": {}, "markdown": {}"#,
+        serde_json::to_string(&updated.to_rfc3339_opts(SecondsFormat::Secs, true)).unwrap(),
+        serde_json::to_string(title).unwrap(),
+        serde_json::to_string(markdown).unwrap(),
+    );
+    if !tags.is_empty() {
+        entry.push_str(&format!(r#", "tags": {}"#, serde_json::to_string(tags).unwrap()));
+    }
+    if hidden {
+        entry.push_str(r#", "hidden": true"#);
+    }
+
+    return Ok(format!("{}{}\n{} }}\n]\n", entries, separator, entry));
+}
+
+/// Lists `title` in `manifest` as `<slug>.md`, returning the slug, the markdown file's name and the new manifest,
+/// unless the title makes no slug or one already taken.
+pub fn add_entry(manifest: &str, title: &str, tags: &[String], hidden: bool, now: DateTime<Utc>) -> Result<(String, String, String), String> {
+    let slug = to_slug(title);
+    if slug.is_empty() {
+        // slugs are made of ASCII letters alone, so every post's URL stays as it was
+        return Err(format!("{:?} has no letters, a to z, to make a slug of", title));
+    }
+
+    let registries: Vec<Registry> = serde_json::from_str(manifest).map_err(|err| format!("manifest.json is already invalid, {}", err))?;
+    if let Some(existing) = registries.iter().find(|registry| to_slug(&registry.title) == slug) {
+        return Err(format!("{:?} already has the slug {}", existing.title, slug));
+    }
+    let markdown = format!("{}.md", slug);
+    let manifest = append_entry(manifest, title, &markdown, tags, hidden, now)?;
+    serde_json::from_str::<Vec<Registry>>(&manifest).map_err(|err| format!("The new entry would break manifest.json, {}", err))?;
+
+    return Ok((slug, markdown, manifest));
+}
+
+/// Creates `<slug>.md` in `directory` holding `content` and lists it in its `manifest.json`, returning the new file.
+pub fn write_post(directory: &Path, title: &str, content: &str, tags: &[String], hidden: bool, now: DateTime<Utc>) -> Result<PathBuf, String> {
+    let manifest_path = directory.join("manifest.json");
+    let manifest = std::fs::read_to_string(&manifest_path).map_err(|err| format!("Cannot read {}, {}", manifest_path.display(), err))?;
+    let (_, markdown, manifest) = add_entry(&manifest, title, tags, hidden, now)?;
+
+    let path = directory.join(&markdown);
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    std::fs::write(&path, content).map_err(|err| format!("Cannot write {}, {}", path.display(), err))?;
+    std::fs::write(&manifest_path, manifest).map_err(|err| format!("Cannot write {}, {}", manifest_path.display(), err))?;
+    return Ok(path);
+}
+
+/// An empty post to write, hidden: reachable by its URL for proofreading but not listed until `hidden` is taken out.
+pub fn new_post(directory: &Path, title: &str, now: DateTime<Utc>) -> Result<PathBuf, String> {
+    return write_post(directory, title, "", &[], true, now);
+}
+
+/// Opens `path` in `$VISUAL` or `$EDITOR`, which may carry arguments of its own, e.g. `code --wait`.
+pub fn open_editor(path: &Path) -> Result<(), String> {
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .ok_or("Neither VISUAL nor EDITOR is set")?;
+    let mut words = editor.split_whitespace();
+    let status = std::process::Command::new(words.next().unwrap())
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|err| format!("Cannot run {}, {}", editor, err))?;
+
+    return if status.success() { Ok(()) } else { Err(format!("{} exited with {}", editor, status)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_append_entry() {
+        let updated = Utc.ymd(2024, 2, 1).and_hms(9, 30, 0);
+        let manifest = "[\n    { \"updated\": \"2024-01-25T23:08:00Z\", \"title\": \"Fin\", \"markdown\": \"fin.md\" }\n]";
+
+        let appended = append_entry(manifest, "Say \"hello\"", "say-hello.md", &[], true, updated).unwrap();
+        assert_eq!(appended, "[\n    { \"updated\": \"2024-01-25T23:08:00Z\", \"title\": \"Fin\", \"markdown\": \"fin.md\" },\n    { \"updated\": \"2024-02-01T09:30:00Z\", \"title\": \"Say \\\"hello\\\"\", \"markdown\": \"say-hello.md\", \"hidden\": true }\n]\n");
+
+        let registries: Vec<Registry> = serde_json::from_str(&append_entry("[]", "Fin", "fin.md", &[], true, updated).unwrap()).unwrap();
+        assert_eq!(registries[0].markdown, "fin.md");
+        assert!(registries[0].hidden);
+
+        assert!(append_entry("{}", "Fin", "fin.md", &[], true, updated).is_err());
+
+        let appended = append_entry("[]", "Fin", "fin.md", &[String::from("haskell")], false, updated).unwrap();
+        assert_eq!(appended, "[\n    { \"updated\": \"2024-02-01T09:30:00Z\", \"title\": \"Fin\", \"markdown\": \"fin.md\", \"tags\": [\"haskell\"] }\n]\n");
+    }
+
+    #[test]
+    fn test_add_entry() {
+        let now = Utc.ymd(2024, 2, 1).and_hms(9, 30, 0);
+        let manifest = "[\n    { \"updated\": \"2024-01-25T23:08:00Z\", \"title\": \"Fin\", \"markdown\": \"fin.md\" }\n]";
+
+        let (slug, markdown, added) = add_entry(manifest, "Zip is scan", &[], false, now).unwrap();
+        assert_eq!((slug.as_str(), markdown.as_str()), ("zip-is-scan", "zip-is-scan.md"));
+        assert_eq!(serde_json::from_str::<Vec<Registry>>(&added).unwrap().len(), 2);
+
+        assert!(add_entry(manifest, "Fin!", &[], false, now).unwrap_err().contains("already has the slug fin"));
+        assert!(add_entry(manifest, "?!", &[], false, now).is_err());
+        assert_eq!(add_entry(manifest, "2024", &[], false, now).unwrap_err(), "\"2024\" has no letters, a to z, to make a slug of");
+        assert!(add_entry("[{", "Zip is scan", &[], false, now).unwrap_err().contains("already invalid"));
+    }
+}
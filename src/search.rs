@@ -0,0 +1,300 @@
+#[cfg(feature = "search")]
+mod full_text;
+
+#[cfg(feature = "search")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "search")]
+use std::time::Instant;
+use regex::Regex;
+use serde::Serialize;
+use crate::blog::{describe, CachedSource, Post};
+use crate::cache::{CacheStats, TtlCache};
+use crate::conditional::etag_for;
+use crate::shortcodes::escape_html;
+
+const TITLE_WEIGHT: usize = 10;
+const SNIPPET_RADIUS: usize = 120;
+#[cfg(feature = "search")]
+const RESULT_LIMIT: usize = 50;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchResult {
+    pub title: String,
+    pub slug: String,
+    pub date: String,
+    /// HTML, already escaped, with matching terms wrapped in `<mark>`.
+    pub snippet: String,
+    pub score: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub results: Vec<SearchResult>,
+}
+
+/// The lowercased words of `query`, each once, in the order they first appear.
+pub fn tokenize_query(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = vec![];
+    for term in query.split(|c: char| !c.is_alphanumeric()).filter(|term| !term.is_empty()).map(|term| term.to_lowercase()) {
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+
+    return terms;
+}
+
+fn terms_regex(terms: &[String]) -> Regex {
+    let alternation = terms.iter().map(|term| regex::escape(term)).collect::<Vec<_>>().join("|");
+    return Regex::new(&format!(r"(?i)\b(?:{})", alternation)).unwrap();
+}
+
+/// Every term found in the title is worth `TITLE_WEIGHT`, every occurrence in the content is worth one.
+/// A post has to match all terms somewhere to score at all.
+pub fn score(title: &str, text: &str, terms: &[String]) -> usize {
+    let title = title.to_lowercase();
+    let text = text.to_lowercase();
+
+    let per_term: Vec<usize> = terms.iter()
+        .map(|term| {
+            let in_title = if title.contains(term.as_str()) { TITLE_WEIGHT } else { 0 };
+            in_title + text.matches(term.as_str()).count()
+        })
+        .collect();
+
+    return if per_term.iter().all(|score| *score > 0) { per_term.iter().sum() } else { 0 };
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    return index;
+}
+
+/// Cuts a window of text around the first match and highlights every term within it.
+pub fn snippet(text: &str, terms: &[String]) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let matcher = terms_regex(terms);
+    let first_match = matcher.find(&text).map(|found| found.start()).unwrap_or(0);
+
+    let start = floor_char_boundary(&text, first_match.saturating_sub(SNIPPET_RADIUS));
+    let start = if start == 0 { 0 } else { text[start..].find(' ').map(|space| start + space + 1).unwrap_or(start) };
+    let end = floor_char_boundary(&text, std::cmp::min(text.len(), first_match + SNIPPET_RADIUS));
+    let end = if end == text.len() { end } else { text[..end].rfind(' ').filter(|space| *space > start).unwrap_or(end) };
+
+    let window = &text[start..end];
+    let highlighted = matcher
+        .split(window)
+        .zip(matcher.find_iter(window).map(|found| Some(found.as_str())).chain(std::iter::once(None)))
+        .map(|(between, found)| match found {
+            Some(found) => format!("{}<mark>{}</mark>", escape_html(between), escape_html(found)),
+            None => escape_html(between),
+        })
+        .collect::<String>();
+
+    let prefix = if start > 0 { "… " } else { "" };
+    let suffix = if end < text.len() { " …" } else { "" };
+    return format!("{}{}{}", prefix, highlighted, suffix);
+}
+
+pub async fn search(source: &CachedSource, query: &str) -> Result<Vec<SearchResult>, String> {
+    let terms = tokenize_query(query);
+    if terms.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut results: Vec<SearchResult> = vec![];
+    for (post, text) in visible_texts(source).await? {
+        let score = score(&post.title, &text, &terms);
+        if score > 0 {
+            results.push(search_result(&post, &text, &terms, score as f32));
+        }
+    }
+
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    return Ok(results);
+}
+
+/// Every visible post with its content as plain text, fetched concurrently.
+async fn visible_texts(source: &CachedSource) -> Result<Vec<(Post, String)>, String> {
+    let visible: Vec<Post> = source.all_posts().await?
+        .into_iter()
+        .filter(|Post{ hidden, .. }| !*hidden)
+        .collect();
+    let contents = source.prefetch(&visible).await;
+
+    return Ok(visible.into_iter()
+        .zip(contents)
+        .map(|(post, content)| {
+            let text = content.map(|markdown| markdown_to_text::convert(&markdown)).unwrap_or_default();
+            (post, text)
+        })
+        .collect());
+}
+
+fn search_result(post: &Post, text: &str, terms: &[String], score: f32) -> SearchResult {
+    return SearchResult {
+        title: post.title.to_owned(),
+        slug: post.slug.to_owned(),
+        date: format!("{}", post.updated.format("%v")),
+        snippet: snippet(text, terms),
+        score,
+    };
+}
+
+/// What `/search` and `/api/search` go through. With the `search` feature this is a tantivy index
+/// built from every visible post, rebuilt once it is older than the cache TTL; without it, or when
+/// the index cannot be built, it is the term counting above.
+pub struct SearchEngine {
+    #[cfg(feature = "search")]
+    ttl: Duration,
+    #[cfg(feature = "search")]
+    index: Mutex<Option<(Instant, Arc<full_text::FullTextIndex>)>>,
+}
+
+impl SearchEngine {
+    #[cfg(feature = "search")]
+    pub fn new(ttl: Duration) -> SearchEngine {
+        return SearchEngine { ttl, index: Mutex::new(None) };
+    }
+
+    #[cfg(not(feature = "search"))]
+    pub fn new(_ttl: Duration) -> SearchEngine {
+        return SearchEngine {};
+    }
+
+    #[cfg(not(feature = "search"))]
+    pub async fn search(&self, source: &CachedSource, query: &str) -> Result<Vec<SearchResult>, String> {
+        return search(source, query).await;
+    }
+
+    #[cfg(feature = "search")]
+    pub async fn search(&self, source: &CachedSource, query: &str) -> Result<Vec<SearchResult>, String> {
+        let terms = tokenize_query(query);
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let fresh = self.index.lock().unwrap().as_ref()
+            .filter(|(built, _)| built.elapsed() < self.ttl)
+            .map(|(_, index)| index.to_owned());
+        let index = match fresh {
+            Some(index) => Ok(index),
+            None => self.refresh(source).await
+        };
+        let hits = match index.map(|index| index.search(query, RESULT_LIMIT)) {
+            Ok(Ok(hits)) => hits,
+            _ => return search(source, query).await
+        };
+
+        let all_posts = source.all_posts().await?;
+        let matched: Vec<(Post, f32)> = hits.into_iter()
+            .filter_map(|(slug, score)| all_posts.iter().find(|post| post.slug == slug).map(|post| (post.to_owned(), score)))
+            .collect();
+        let posts: Vec<Post> = matched.iter().map(|(post, _)| post.to_owned()).collect();
+        let contents = source.prefetch(&posts).await;
+
+        return Ok(matched.iter()
+            .zip(contents)
+            .map(|((post, score), content)| {
+                let text = content.map(|markdown| markdown_to_text::convert(&markdown)).unwrap_or_default();
+                search_result(post, &text, &terms, *score)
+            })
+            .collect());
+    }
+
+    /// Rebuilds the index from the source and swaps it in.
+    #[cfg(feature = "search")]
+    pub async fn refresh(&self, source: &CachedSource) -> Result<Arc<full_text::FullTextIndex>, String> {
+        let documents = visible_texts(source).await?;
+        let index = Arc::new(full_text::FullTextIndex::build(&documents).map_err(|err| err.to_string())?);
+        *self.index.lock().unwrap() = Some((Instant::now(), index.to_owned()));
+        return Ok(index);
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchIndexEntry {
+    pub slug: String,
+    pub title: String,
+    pub summary: String,
+    pub tags: Vec<String>,
+}
+
+pub async fn build_search_index(source: &CachedSource) -> Result<Vec<SearchIndexEntry>, String> {
+    return Ok(visible_texts(source).await?
+        .into_iter()
+        .map(|(post, text)| SearchIndexEntry {
+            summary: describe(&text),
+            slug: post.slug,
+            title: post.title,
+            tags: post.tags,
+        })
+        .collect());
+}
+
+/// The serialised `/search-index.json` together with its ETag, rebuilt at most once per cache TTL.
+#[derive(Clone)]
+pub struct SearchIndex {
+    cache: TtlCache<(String, String)>,
+}
+
+impl SearchIndex {
+    pub fn new(ttl: Duration) -> SearchIndex {
+        return SearchIndex { cache: TtlCache::new(ttl) };
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.cache.stats();
+    }
+
+    pub fn purge(&self) -> usize {
+        return self.cache.clear();
+    }
+
+    pub async fn json(&self, source: &CachedSource) -> Result<(String, String), String> {
+        if let Some(cached) = self.cache.get("search-index") {
+            return Ok(cached);
+        }
+
+        let entries = build_search_index(source).await?;
+        let json = serde_json::to_string(&entries).map_err(|err| err.to_string())?;
+        let cached = (etag_for(&json), json);
+
+        self.cache.insert("search-index", cached.to_owned());
+        return Ok(cached);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_query() {
+        assert_eq!(tokenize_query("  Lens, lens & TypeScript! "), vec![String::from("lens"), String::from("typescript")]);
+        assert_eq!(tokenize_query("rust lens Rust"), vec![String::from("rust"), String::from("lens")]);
+        assert!(tokenize_query(" ?! ").is_empty());
+    }
+
+    #[test]
+    fn test_score() {
+        let terms = tokenize_query("lens reducer");
+
+        assert_eq!(score("Reducer to reduce, with lens", "a lens focuses; a lens updates", &terms), 22);
+        assert_eq!(score("Lens in TypeScript", "a lens focuses", &terms), 0);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let text = format!("{} the Lens <focuses> {}", "word ".repeat(50), "tail ".repeat(50));
+        let snippet = snippet(&text, &tokenize_query("lens"));
+
+        assert!(snippet.starts_with("… word"));
+        assert!(snippet.ends_with("tail …"));
+        assert!(snippet.contains("the <mark>Lens</mark> &lt;focuses&gt;"));
+    }
+}
@@ -0,0 +1,102 @@
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+use tantivy::{doc, Index, IndexReader, ReloadPolicy, TantivyDocument};
+use crate::blog::Post;
+
+const WRITER_MEMORY_BYTES: usize = 15_000_000;
+const TITLE_BOOST: f32 = 3.0;
+
+/// An in-memory tantivy index over post titles and bodies, ranked with BM25.
+/// It is never updated in place: a refresh builds a new one and swaps it in.
+pub struct FullTextIndex {
+    index: Index,
+    reader: IndexReader,
+    slug: Field,
+    title: Field,
+    body: Field,
+}
+
+impl FullTextIndex {
+    pub fn build(documents: &[(Post, String)]) -> tantivy::Result<FullTextIndex> {
+        let mut schema_builder = Schema::builder();
+        let slug = schema_builder.add_text_field("slug", STRING | STORED);
+        let title = schema_builder.add_text_field("title", TEXT);
+        let body = schema_builder.add_text_field("body", TEXT);
+        let index = Index::create_in_ram(schema_builder.build());
+
+        let mut writer = index.writer_with_num_threads(1, WRITER_MEMORY_BYTES)?;
+        for (post, text) in documents {
+            writer.add_document(doc!(
+                slug => post.slug.to_owned(),
+                title => post.title.to_owned(),
+                body => text.to_owned(),
+            ))?;
+        }
+        writer.commit()?;
+
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+        return Ok(FullTextIndex { index, reader, slug, title, body });
+    }
+
+    /// Slugs with their BM25 scores, best first. Every term has to match, and
+    /// query syntax errors are forgiven rather than reported back to the reader.
+    pub fn search(&self, query: &str, limit: usize) -> tantivy::Result<Vec<(String, f32)>> {
+        let mut parser = QueryParser::for_index(&self.index, vec![self.title, self.body]);
+        parser.set_conjunction_by_default();
+        parser.set_field_boost(self.title, TITLE_BOOST);
+        let (query, _) = parser.parse_query_lenient(query);
+
+        let searcher = self.reader.searcher();
+        let mut hits: Vec<(String, f32)> = vec![];
+        for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+            let document: TantivyDocument = searcher.doc(address)?;
+            if let Some(slug) = document.get_first(self.slug).and_then(|value| value.as_str()) {
+                hits.push((slug.to_owned(), score));
+            }
+        }
+
+        return Ok(hits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use std::collections::BTreeMap;
+    use crate::blog::Kind;
+    use super::*;
+
+    fn post(title: &str, slug: &str) -> Post {
+        Post {
+            slug: slug.to_owned(),
+            title: title.to_owned(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        }
+    }
+
+    #[test]
+    fn test_search_ranks_title_matches_first() {
+        let index = FullTextIndex::build(&[
+            (post("Fin", "fin"), String::from("Fin is a type for bounded natural numbers, a lens would not help.")),
+            (post("Lens in TypeScript", "lens"), String::from("A record viewer and updater.")),
+            (post("Covariance", "covariance"), String::from("Nothing to see.")),
+        ]).unwrap();
+
+        let slugs: Vec<String> = index.search("lens", 10).unwrap().into_iter().map(|(slug, _)| slug).collect();
+
+        assert_eq!(slugs, vec![String::from("lens"), String::from("fin")]);
+        assert!(index.search("lens \"unbalanced", 10).is_ok());
+    }
+}
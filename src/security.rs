@@ -0,0 +1,166 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use crate::config::{config, BlogConfig};
+
+/// The Content-Security-Policy, one directive per entry. Each is overridable by `csp_<directive>`, e.g.
+/// `CSP_SCRIPT_SRC="'self' 'unsafe-inline'"`, so allowing inline assets is a deliberate change of config;
+/// an empty value drops the directive.
+#[derive(Clone, Debug)]
+pub struct ContentSecurityPolicy {
+    pub directives: Vec<(&'static str, String)>,
+}
+
+const DEFAULT_DIRECTIVES: [(&str, &str); 9] = [
+    ("default-src", "'self'"),
+    ("script-src", "'self' https://cdnjs.cloudflare.com"),
+    ("style-src", "'self' https://cdnjs.cloudflare.com"),
+    // link cards show whatever image the linked page names
+    ("img-src", "'self' https: data:"),
+    ("frame-src", "https://www.youtube-nocookie.com https://player.vimeo.com"),
+    ("connect-src", "'self'"),
+    ("object-src", "'none'"),
+    ("base-uri", "'self'"),
+    ("frame-ancestors", "'none'"),
+];
+
+impl ContentSecurityPolicy {
+    pub fn from_config(config: &BlogConfig) -> ContentSecurityPolicy {
+        let overrides = [
+            &config.csp_default_src, &config.csp_script_src, &config.csp_style_src, &config.csp_img_src, &config.csp_frame_src,
+            &config.csp_connect_src, &config.csp_object_src, &config.csp_base_uri, &config.csp_frame_ancestors,
+        ];
+        let directives = DEFAULT_DIRECTIVES.iter().zip(overrides)
+            .map(|((directive, default), sources)| (*directive, sources.to_owned().unwrap_or_else(|| String::from(*default))))
+            .collect();
+        return ContentSecurityPolicy { directives };
+    }
+
+    /// With a nonce, inline scripts and styles carrying it are allowed too.
+    pub fn header_value(&self, nonce: Option<&str>) -> String {
+        return self.directives.iter()
+            .filter(|(_, sources)| !sources.trim().is_empty())
+            .map(|(directive, sources)| match nonce {
+                Some(nonce) if *directive == "script-src" || *directive == "style-src" => format!("{} {} 'nonce-{}'", directive, sources.trim(), nonce),
+                _ => format!("{} {}", directive, sources.trim())
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+    }
+
+    /// The policy for a page with its own inline styles, which any site may frame.
+    pub fn embeddable(&self) -> ContentSecurityPolicy {
+        let directives = self.directives.iter()
+            .map(|(directive, sources)| match *directive {
+                "style-src" => (*directive, format!("{} 'unsafe-inline'", sources.trim())),
+                "frame-ancestors" => (*directive, String::from("*")),
+                _ => (*directive, sources.to_owned()),
+            })
+            .collect();
+        return ContentSecurityPolicy { directives };
+    }
+}
+
+/// A page with inline styles that other sites embed, such as a post's plain version, with the policy that lets them;
+/// `frame-ancestors` takes the place of `X-Frame-Options` where browsers know it.
+pub struct Embeddable<R>(pub R);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Embeddable<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(request)?;
+        let csp = ContentSecurityPolicy::from_config(config()).embeddable();
+        response.set_raw_header("Content-Security-Policy", csp.header_value(CspNonce::of(request).0.as_deref()));
+        return Ok(response);
+    }
+}
+
+/// Per-request nonces are opt-in with `csp_nonce`: they make every HTML response unique,
+/// so ETag revalidation and CDN caching of pages stop paying off.
+pub fn nonces_enabled() -> bool {
+    return config().csp_nonce;
+}
+
+/// This request's CSP nonce, if nonces are enabled. Templates get it as `csp_nonce`, for
+/// `<script nonce="{{csp_nonce}}">`, and the same value goes into the `Content-Security-Policy` header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CspNonce(pub Option<String>);
+
+impl CspNonce {
+    pub fn of(request: &Request<'_>) -> CspNonce {
+        return request.local_cache(|| {
+            CspNonce(nonces_enabled().then(|| uuid::Uuid::new_v4().simple().to_string()))
+        }).to_owned();
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CspNonce {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(CspNonce::of(request));
+    }
+}
+
+/// Sets the security headers on every response that doesn't set its own, each from config.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    pub csp: ContentSecurityPolicy,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(config: &BlogConfig) -> SecurityHeaders {
+        return SecurityHeaders {
+            csp: ContentSecurityPolicy::from_config(config),
+            headers: vec![
+                ("X-Content-Type-Options", String::from("nosniff")),
+                ("Referrer-Policy", config.referrer_policy.to_owned()),
+                ("X-Frame-Options", config.x_frame_options.to_owned()),
+                ("Strict-Transport-Security", config.strict_transport_security.to_owned()),
+            ],
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        return Info { name: "Security headers", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !response.headers().contains("Content-Security-Policy") {
+            response.set_raw_header("Content-Security-Policy", self.csp.header_value(CspNonce::of(request).0.as_deref()));
+        }
+        for (name, value) in &self.headers {
+            if !value.is_empty() && !response.headers().contains(*name) {
+                response.set_raw_header(*name, value.to_owned());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_value() {
+        let policy = ContentSecurityPolicy {
+            directives: vec![
+                ("default-src", String::from("'self'")),
+                ("script-src", String::from(" 'self' 'unsafe-inline' ")),
+                ("frame-src", String::new()),
+            ],
+        };
+
+        assert_eq!(policy.header_value(None), "default-src 'self'; script-src 'self' 'unsafe-inline'");
+        assert_eq!(policy.header_value(Some("abc")), "default-src 'self'; script-src 'self' 'unsafe-inline' 'nonce-abc'");
+
+        let policy = ContentSecurityPolicy::from_config(&BlogConfig::default()).embeddable();
+        assert!(policy.header_value(None).contains("style-src 'self' https://cdnjs.cloudflare.com 'unsafe-inline'"));
+        assert!(policy.header_value(None).ends_with("frame-ancestors *"));
+    }
+}
@@ -0,0 +1,46 @@
+// Precaches the blog's shell and its newest posts, and keeps each page read since, for reading offline. Generated by
+// /sw.js, whose version changes with the manifest and the static files, so a new one replaces this and its cache.
+var CACHE = "blog-__VERSION__";
+var PRECACHE = __PRECACHE__;
+
+self.addEventListener("install", function (event) {
+    event.waitUntil(caches.open(CACHE)
+        .then(function (cache) { return cache.addAll(PRECACHE); })
+        .then(function () { return self.skipWaiting(); }));
+});
+
+self.addEventListener("activate", function (event) {
+    event.waitUntil(caches.keys()
+        .then(function (keys) {
+            var stale = keys.filter(function (key) { return key.indexOf("blog-") === 0 && key !== CACHE; });
+            return Promise.all(stale.map(function (key) { return caches.delete(key); }));
+        })
+        .then(function () { return self.clients.claim(); }));
+});
+
+self.addEventListener("fetch", function (event) {
+    var request = event.request;
+    var url = new URL(request.url);
+    if (request.method !== "GET" || url.origin !== location.origin || url.pathname.indexOf("/admin") === 0) {
+        return;
+    }
+    if (url.pathname.indexOf("/static/") === 0) {
+        // fingerprinted, so a cached file is the current one
+        event.respondWith(caches.match(request).then(function (cached) { return cached || fetch(request); }));
+        return;
+    }
+    if (request.mode === "navigate") {
+        // the network's page when there is one, the cached copy when offline
+        event.respondWith(fetch(request)
+            .then(function (response) {
+                if (response.ok) {
+                    var copy = response.clone();
+                    caches.open(CACHE).then(function (cache) { cache.put(request, copy); });
+                }
+                return response;
+            })
+            .catch(function () {
+                return caches.match(request).then(function (cached) { return cached || caches.match("/"); });
+            }));
+    }
+});
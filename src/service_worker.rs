@@ -0,0 +1,101 @@
+//! `/sw.js`, a service worker precaching the static files, the index and the newest `offline_posts` posts, so a
+//! reader can open them, and any page read since, offline. It is generated afresh from the manifest, and versioned
+//! by its hash and the assets' fingerprints: a new post or stylesheet makes a new worker, which drops the old cache.
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use rocket::http::{ContentType, Header, Status};
+use rocket::{get, routes, Responder, Route, State};
+use crate::assets::Assets;
+use crate::blog::Post;
+use crate::config;
+use crate::feed::manifest_hash;
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+
+const TEMPLATE: &str = include_str!("service_worker.js");
+
+/// Whether pages register the worker.
+pub fn enabled() -> bool {
+    return config::config().offline_posts > 0;
+}
+
+/// The index, the static files, then the `limit` most recently updated posts.
+pub fn precache(assets: Vec<String>, posts: &[Post], limit: usize) -> Vec<String> {
+    let mut listed: Vec<&Post> = posts.iter().filter(|post| post.is_listed()).collect();
+    listed.sort_by_key(|post| Reverse(post.updated));
+    return [String::from("/")].into_iter()
+        .chain(assets)
+        .chain(listed.into_iter().take(limit).map(|post| format!("/{}", post.slug)))
+        .collect();
+}
+
+pub fn version(posts: &[Post], urls: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_u64(manifest_hash(posts));
+    for url in urls {
+        hasher.write(url.as_bytes());
+    }
+    return hasher.finish();
+}
+
+pub fn script(version: u64, urls: &[String]) -> String {
+    return TEMPLATE
+        .replace("__VERSION__", &format!("{:016x}", version))
+        .replace("__PRECACHE__", &serde_json::to_string(urls).unwrap());
+}
+
+/// Browsers check for a new worker at most daily on their own; `no-cache` has them check on every visit.
+#[derive(Responder)]
+pub struct ServiceWorker(String, ContentType, Header<'static>);
+
+#[get("/sw.js")]
+async fn service_worker(tenant: &Tenant, assets: &State<Assets>, request_id: RequestId) -> Result<Option<ServiceWorker>, Status> {
+    if !enabled() {
+        return Ok(None);
+    }
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|_| Status::ServiceUnavailable)?;
+    let urls = precache(assets.urls(), &posts, config::config().offline_posts);
+    let script = script(version(&posts, &urls), &urls);
+    return Ok(Some(ServiceWorker(script, ContentType::JavaScript, Header::new("Cache-Control", "no-cache"))));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![service_worker];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use chrono::{TimeZone, Utc};
+    use crate::blog::Kind;
+
+    fn post(slug: &str, day: u32, hidden: bool) -> Post {
+        return Post {
+            slug: slug.to_owned(), title: slug.to_owned(), path: format!("{}.md", slug), hidden,
+            updated: Utc.ymd(2024, 1, day).and_hms(0, 0, 0), tags: vec![], pinned: false, template: None, kind: Kind::Post, lang: None, translations: BTreeMap::new(), short: None, enclosure: None, cover: None,
+        };
+    }
+
+    #[test]
+    fn test_precache() {
+        let posts = [post("old", 1, false), post("new", 3, false), post("hidden", 4, true), post("recent", 2, false)];
+        let urls = precache(vec![String::from("/static/0123456789abcdef/styles.css")], &posts, 2);
+        assert_eq!(urls, vec!["/", "/static/0123456789abcdef/styles.css", "/new", "/recent"]);
+        assert_eq!(precache(vec![], &posts, 0), vec!["/"]);
+    }
+
+    #[test]
+    fn test_version() {
+        let posts = [post("old", 1, false)];
+        let urls = precache(vec![], &posts, 10);
+        let version = version(&posts, &urls);
+        assert_ne!(version, super::version(&[post("old", 2, false)], &urls));
+        assert_ne!(version, super::version(&posts, &[String::from("/static/0123456789abcdef/styles.css")]));
+
+        let script = script(version, &urls);
+        assert!(script.contains(&format!("var CACHE = \"blog-{:016x}\";", version)));
+        assert!(script.contains("var PRECACHE = [\"/\",\"/old\"];"));
+    }
+}
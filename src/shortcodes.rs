@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use tracing::instrument;
+use crate::cache::{CacheStats, TtlCache};
+
+const LINK_CARD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const LINK_CARD_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkCard {
+    pub url: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+/// Expands paragraphs that are solely a URL, or a `{{card <url>}}` shortcode, into preview cards.
+/// OpenGraph metadata is fetched from the target and cached, failures included, so a dead link
+/// costs one round trip per TTL rather than one per page view.
+#[derive(Clone)]
+pub struct LinkCards {
+    cache: TtlCache<Option<LinkCard>>,
+    client: reqwest::Client,
+}
+
+impl LinkCards {
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.cache.stats();
+    }
+
+    pub fn default() -> LinkCards {
+        let client = reqwest::Client::builder()
+            .timeout(LINK_CARD_TIMEOUT)
+            .build()
+            .unwrap();
+
+        return LinkCards { cache: TtlCache::new(LINK_CARD_TTL), client };
+    }
+
+    /// Runs over rendered HTML rather than markdown: comrak leaves such a line as a `<p>` of its own,
+    /// and code blocks are already out of the way inside `<pre>`.
+    #[instrument(name = "link cards", skip_all)]
+    pub async fn expand(&self, html: &str) -> String {
+        let mut cards = HashMap::new();
+        for url in link_card_urls(html) {
+            let card = self.card_for(&url).await;
+            cards.insert(url, card);
+        }
+
+        return replace_link_cards(html, &cards);
+    }
+
+    async fn card_for(&self, url: &str) -> Option<LinkCard> {
+        if let Some(card) = self.cache.get(url) {
+            return card;
+        }
+
+        let card = match self.client.get(url).send().await {
+            Err(_) => None,
+            Ok(response) => match response.text().await {
+                Err(_) => None,
+                Ok(page) => parse_open_graph(url, &page)
+            }
+        };
+
+        self.cache.insert(url, card.to_owned());
+        return card;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Video {
+    YouTube(String),
+    Vimeo(String),
+}
+
+static VIDEO_EMBED: Lazy<Regex> = Lazy::new(|| Regex::new(r"<p>(?:\{\{(?P<kind>youtube|vimeo)\s+(?P<arg>[^\s<}]+)\s*\}\}|(?P<url>https?://[^\s<]+))</p>").unwrap());
+static YOUTUBE_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^https?://(?:www\.|m\.)?(?:youtube\.com/(?:watch\?(?:.*&)?v=|embed/|shorts/)|youtu\.be/)([A-Za-z0-9_-]{11})(?:[?&#].*)?$").unwrap());
+static VIMEO_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^https?://(?:www\.|player\.)?vimeo\.com/(?:video/)?(\d+)(?:[/?#].*)?$").unwrap());
+static LINK_CARD_PARAGRAPH: Lazy<Regex> = Lazy::new(|| Regex::new(r"<p>(?:\{\{card\s+(?P<card>https?://[^\s<]+?)\s*\}\}|(?P<bare>https?://[^\s<]+))</p>").unwrap());
+static META_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?is)([a-z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+static TITLE_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+/// Expands `{{youtube <id|url>}}`, `{{vimeo <id|url>}}` and paragraphs that are solely a video URL
+/// into embedded players. YouTube goes through its no-cookie domain and Vimeo is asked not to track.
+pub fn expand_video_embeds(html: &str) -> String {
+    return VIDEO_EMBED
+        .replace_all(html, |captures: &Captures| {
+            let video = match (captures.name("kind"), captures.name("arg"), captures.name("url")) {
+                (Some(kind), Some(arg), _) => parse_video_shortcode(kind.as_str(), &unescape_html(arg.as_str())),
+                (_, _, Some(url)) => parse_video_url(&unescape_html(url.as_str())),
+                _ => None
+            };
+
+            return match video {
+                Some(video) => render_video_embed(&video),
+                None => captures[0].to_owned(),
+            };
+        })
+        .into_owned();
+}
+
+fn parse_video_shortcode(kind: &str, arg: &str) -> Option<Video> {
+    return parse_video_url(arg).or_else(|| match kind {
+        "youtube" => Some(arg).filter(|id| is_youtube_id(id)).map(|id| Video::YouTube(id.to_owned())),
+        "vimeo" => Some(arg).filter(|id| is_vimeo_id(id)).map(|id| Video::Vimeo(id.to_owned())),
+        _ => None
+    });
+}
+
+pub fn parse_video_url(url: &str) -> Option<Video> {
+    return YOUTUBE_URL.captures(url).map(|captures| Video::YouTube(captures[1].to_owned()))
+        .or_else(|| VIMEO_URL.captures(url).map(|captures| Video::Vimeo(captures[1].to_owned())));
+}
+
+fn is_youtube_id(id: &str) -> bool {
+    return id.len() == 11 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+}
+
+fn is_vimeo_id(id: &str) -> bool {
+    return !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+}
+
+pub fn render_video_embed(video: &Video) -> String {
+    let (src, title) = match video {
+        Video::YouTube(id) => (format!("https://www.youtube-nocookie.com/embed/{}", id), "YouTube video"),
+        Video::Vimeo(id) => (format!("https://player.vimeo.com/video/{}?dnt=1", id), "Vimeo video"),
+    };
+
+    return format!(
+        "<div class=\"video-embed\"><iframe src=\"{}\" title=\"{}\" loading=\"lazy\" referrerpolicy=\"strict-origin-when-cross-origin\" allow=\"fullscreen; picture-in-picture\" allowfullscreen></iframe></div>",
+        src, title
+    );
+}
+
+fn captured_url(captures: &Captures) -> String {
+    let raw = captures.name("card").or_else(|| captures.name("bare")).unwrap().as_str();
+    return unescape_html(raw);
+}
+
+pub fn link_card_urls(html: &str) -> Vec<String> {
+    return LINK_CARD_PARAGRAPH
+        .captures_iter(html)
+        .map(|captures| captured_url(&captures))
+        .collect();
+}
+
+pub fn replace_link_cards(html: &str, cards: &HashMap<String, Option<LinkCard>>) -> String {
+    return LINK_CARD_PARAGRAPH
+        .replace_all(html, |captures: &Captures| {
+            let url = captured_url(captures);
+            return match cards.get(&url) {
+                Some(Some(card)) => render_link_card(card),
+                _ => format!("<p><a href=\"{0}\">{0}</a></p>", escape_html(&url)),
+            };
+        })
+        .into_owned();
+}
+
+pub fn render_link_card(card: &LinkCard) -> String {
+    let image = card.image.as_ref()
+        .map(|image| format!("<img class=\"link-card-image\" src=\"{}\" alt=\"\" loading=\"lazy\">", escape_html(image)))
+        .unwrap_or_default();
+    let description = card.description.as_ref()
+        .map(|description| format!("<span class=\"link-card-description\">{}</span>", escape_html(description)))
+        .unwrap_or_default();
+    let site_name = card.site_name.as_ref()
+        .map(|site_name| format!("<span class=\"link-card-site\">{}</span>", escape_html(site_name)))
+        .unwrap_or_default();
+
+    return format!(
+        "<a class=\"link-card\" href=\"{}\">{}<span class=\"link-card-body\"><strong class=\"link-card-title\">{}</strong>{}{}</span></a>",
+        escape_html(&card.url), image, escape_html(&card.title), description, site_name
+    );
+}
+
+pub fn parse_open_graph(url: &str, page: &str) -> Option<LinkCard> {
+
+    let mut properties: HashMap<String, String> = HashMap::new();
+    for meta in META_TAG.find_iter(page) {
+        let attributes: HashMap<String, String> = ATTRIBUTE
+            .captures_iter(meta.as_str())
+            .map(|captures| (
+                captures[1].to_ascii_lowercase(),
+                captures.get(2).or_else(|| captures.get(3)).unwrap().as_str().to_owned()
+            ))
+            .collect();
+
+        let key = attributes.get("property").or_else(|| attributes.get("name"));
+        if let (Some(key), Some(content)) = (key, attributes.get("content")) {
+            properties.entry(key.to_ascii_lowercase()).or_insert_with(|| unescape_html(content.trim()));
+        }
+    }
+
+    let property = |keys: &[&str]| keys.iter()
+        .find_map(|key| properties.get(*key))
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_owned());
+
+    let title = property(&["og:title", "twitter:title"])
+        .or_else(|| TITLE_TAG.captures(page).map(|captures| unescape_html(captures[1].trim())))
+        .filter(|title| !title.is_empty())?;
+
+    return Some(LinkCard {
+        url: url.to_owned(),
+        title,
+        description: property(&["og:description", "twitter:description", "description"]),
+        image: property(&["og:image", "twitter:image"]),
+        site_name: property(&["og:site_name"]).or_else(|| host_of(url)),
+    });
+}
+
+fn host_of(url: &str) -> Option<String> {
+    return url.split("://").nth(1)
+        .and_then(|rest| rest.split(['/', '?', '#']).next())
+        .filter(|host| !host.is_empty())
+        .map(|host| host.trim_start_matches("www.").to_owned());
+}
+
+pub fn escape_html(raw: &str) -> String {
+    return raw
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;");
+}
+
+pub fn unescape_html(raw: &str) -> String {
+    return raw
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_video_url() {
+        assert_eq!(parse_video_url("https://www.youtube.com/watch?v=NKeHrApPWlo"), Some(Video::YouTube(String::from("NKeHrApPWlo"))));
+        assert_eq!(parse_video_url("https://www.youtube.com/watch?list=x&v=NKeHrApPWlo&t=42"), Some(Video::YouTube(String::from("NKeHrApPWlo"))));
+        assert_eq!(parse_video_url("https://youtu.be/oyLBGkS5ICk"), Some(Video::YouTube(String::from("oyLBGkS5ICk"))));
+        assert_eq!(parse_video_url("https://vimeo.com/76979871"), Some(Video::Vimeo(String::from("76979871"))));
+        assert_eq!(parse_video_url("https://example.com/watch?v=NKeHrApPWlo"), None);
+    }
+
+    #[test]
+    fn test_expand_video_embeds() {
+        let html = "<p>https://youtu.be/oyLBGkS5ICk</p>\n<p>{{vimeo 76979871}}</p>\n<p>https://example.com</p>\n<p>{{youtube nope}}</p>";
+        let expanded = expand_video_embeds(html);
+
+        assert!(expanded.contains("src=\"https://www.youtube-nocookie.com/embed/oyLBGkS5ICk\""));
+        assert!(expanded.contains("src=\"https://player.vimeo.com/video/76979871?dnt=1\""));
+        assert!(expanded.contains("<p>https://example.com</p>"));
+        assert!(expanded.contains("<p>{{youtube nope}}</p>"));
+    }
+
+    #[test]
+    fn test_link_card_urls() {
+        let html = r#"<p>https://example.com/a?b=1&amp;c=2</p>
+<p>{{card https://example.com/card}}</p>
+<p>see https://example.com/inline</p>
+<pre><code>https://example.com/code
+</code></pre>"#;
+
+        assert_eq!(link_card_urls(html), vec![
+            String::from("https://example.com/a?b=1&c=2"),
+            String::from("https://example.com/card"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_open_graph() {
+        let page = r#"<html><head>
+<title>Fallback title</title>
+<meta property="og:title" content="Equality is Hard" />
+<meta content='Why &amp; how' name='description'>
+<meta property="og:image" content="https://example.com/cover.png">
+</head></html>"#;
+
+        assert_eq!(parse_open_graph("https://www.example.com/posts/1", page), Some(LinkCard {
+            url: String::from("https://www.example.com/posts/1"),
+            title: String::from("Equality is Hard"),
+            description: Some(String::from("Why & how")),
+            image: Some(String::from("https://example.com/cover.png")),
+            site_name: Some(String::from("example.com")),
+        }));
+        assert_eq!(parse_open_graph("https://example.com", "<html></html>"), None);
+    }
+
+    #[test]
+    fn test_replace_link_cards_falls_back_to_plain_link() {
+        let html = "<p>{{card https://example.com/gone}}</p>";
+        let cards = HashMap::from([(String::from("https://example.com/gone"), None)]);
+
+        assert_eq!(
+            replace_link_cards(html, &cards),
+            "<p><a href=\"https://example.com/gone\">https://example.com/gone</a></p>"
+        );
+    }
+}
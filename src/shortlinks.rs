@@ -0,0 +1,177 @@
+//! Short links for sharing in talks and print: `/s/<code>` permanently redirects to a post's canonical URL. A
+//! post's code is its manifest's `short`, or else hashed from its slug, so it stays the same as long as the slug
+//! does. Hits are counted by day in the beacon store, as the `short` dimension under the blog's own key, when there is
+//! one.
+use chrono::{Duration as Days, NaiveDate, Utc};
+use rocket::http::Status;
+use rocket::response::content::Json;
+use rocket::response::Redirect;
+use rocket::{get, routes, Route, State};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use crate::admin::AdminToken;
+use crate::beacon::{self, Beacons};
+use crate::blog::Post;
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::spam::Visitor;
+use crate::tenant::Tenant;
+use crate::views::is_bot;
+
+/// The beacon dimension hits are counted under, by code.
+pub const SHORT: &str = "short";
+const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const HASHED_CHARS: usize = 6;
+
+/// The post's short link code.
+pub fn code(post: &Post) -> String {
+    if let Some(short) = &post.short {
+        return short.to_owned();
+    }
+    let digest = Sha256::digest(post.slug.as_bytes());
+    let mut hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    let mut code = String::with_capacity(HASHED_CHARS);
+    for _ in 0..HASHED_CHARS {
+        code.push(ALPHABET[(hash % ALPHABET.len() as u64) as usize] as char);
+        hash /= ALPHABET.len() as u64;
+    }
+    return code;
+}
+
+/// The post that isn't hidden whose code is `code`.
+pub fn resolve<'a>(posts: &'a [Post], code: &str) -> Option<&'a Post> {
+    return posts.iter().find(|post| !post.hidden && self::code(post) == code);
+}
+
+/// The short link to give out for `post`.
+pub fn short_url(public_url: &str, post: &Post) -> String {
+    return format!("{}/s/{}", public_url, code(post));
+}
+
+#[get("/s/<code>")]
+async fn short(code: &str, tenant: &Tenant, beacons: &State<Beacons>, visitor: Visitor, request_id: RequestId, public_url: PublicUrl) -> Result<Redirect, Status> {
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|_| Status::ServiceUnavailable)?;
+    let post = resolve(&posts, code).ok_or(Status::NotFound)?;
+
+    if let Some(store) = &beacons.store {
+        if !is_bot(visitor.user_agent.as_deref().unwrap_or("")) {
+            if let Err(err) = beacon::record(store.as_ref(), &beacon::key(tenant), Utc::today().naive_utc(), &[(SHORT, code.to_owned())]).await {
+                warn!(%request_id, %code, error = %err, "cannot count the short link hit");
+            }
+        }
+    }
+    return Ok(Redirect::moved(format!("{}/{}", public_url.0, post.slug)));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![short];
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ShortLink {
+    pub code: String,
+    pub slug: String,
+    pub url: String,
+    /// Over the days asked for; none without a beacon store.
+    pub hits: Option<u64>,
+}
+
+/// Each post's short link with its hits among `hits`, counts by code, most hit first.
+pub fn short_links(posts: &[Post], public_url: &str, hits: Option<&[(String, u64)]>) -> Vec<ShortLink> {
+    let mut links: Vec<ShortLink> = posts.iter()
+        .filter(|post| !post.hidden)
+        .map(|post| {
+            let code = code(post);
+            ShortLink {
+                hits: hits.map(|hits| hits.iter().filter(|(hit, _)| *hit == code).map(|(_, count)| count).sum()),
+                url: short_url(public_url, post),
+                slug: post.slug.to_owned(),
+                code,
+            }
+        })
+        .collect();
+    links.sort_by_key(|link| std::cmp::Reverse(link.hits));
+    return links;
+}
+
+/// Every post's short link, with its hits on this blog over the last `?days=` days, 30 unless given.
+#[get("/shortlinks?<days>")]
+async fn list(_token: AdminToken, days: Option<u32>, tenant: &Tenant, beacons: &State<Beacons>, request_id: RequestId, public_url: PublicUrl) -> Result<Json<String>, (Status, String)> {
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?;
+    let since: NaiveDate = Utc::today().naive_utc() - Days::days(days.unwrap_or(30).saturating_sub(1) as i64);
+    let hits = match &beacons.store {
+        Some(store) => Some(store.since(&beacon::key(tenant), since).await.map_err(|err| (Status::BadGateway, err))?
+            .into_iter()
+            .filter_map(|count| match beacon::hit(&count.name) {
+                Some((SHORT, code)) => Some((code.to_owned(), count.count)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()),
+        None => None,
+    };
+    return Ok(Json(serde_json::to_string(&short_links(&posts, &public_url.0, hits.as_deref())).unwrap()));
+}
+
+/// Mounted under `/admin`.
+pub fn admin_routes() -> Vec<Route> {
+    return routes![list];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use chrono::TimeZone;
+    use crate::blog::Kind;
+
+    fn post(slug: &str) -> Post {
+        return Post {
+            slug: slug.to_owned(),
+            title: slug.to_owned(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+    }
+
+    #[test]
+    fn test_code() {
+        let zip = post("zip-is-scan");
+        assert_eq!(code(&zip).len(), 6);
+        assert!(code(&zip).chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(code(&zip), code(&post("zip-is-scan")));
+        assert_ne!(code(&zip), code(&post("fin")));
+        assert_eq!(code(&Post { short: Some(String::from("zip")), ..zip }), "zip");
+
+        let registry: crate::blog::Registry = serde_json::from_str(r#"{ "title": "Zip is scan", "markdown": "zip-is-scan.md", "updated": "2021-05-01T00:00:00Z", "short": "zip" }"#).unwrap();
+        assert_eq!(registry.short, Some(String::from("zip")));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let posts = vec![post("fin"), Post { short: Some(String::from("zip")), ..post("zip-is-scan") }, Post { hidden: true, ..post("draft") }];
+        assert_eq!(resolve(&posts, "zip").unwrap().slug, "zip-is-scan");
+        assert_eq!(resolve(&posts, &code(&posts[0])).unwrap().slug, "fin");
+        assert!(resolve(&posts, &code(&posts[2])).is_none());
+        assert!(resolve(&posts, "nope").is_none());
+    }
+
+    #[test]
+    fn test_short_links() {
+        let posts = vec![post("fin"), Post { short: Some(String::from("zip")), ..post("zip-is-scan") }];
+        let links = short_links(&posts, "https://www.hacklewayne.com", Some(&[(String::from("zip"), 2), (String::from("zip"), 3)]));
+        assert_eq!(links[0], ShortLink { code: String::from("zip"), slug: String::from("zip-is-scan"), url: String::from("https://www.hacklewayne.com/s/zip"), hits: Some(5) });
+        assert_eq!(links[1].hits, Some(0));
+        assert_eq!(short_links(&posts, "https://www.hacklewayne.com", None)[0].hits, None);
+    }
+}
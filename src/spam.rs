@@ -0,0 +1,208 @@
+//! Scores comments and webmentions for spam before they are queued for moderation, through the check `spam_filter`
+//! names: a few heuristics, or [Akismet](https://akismet.com/developers/comment-check/). Those scoring
+//! `spam_reject_score` or more out of 100 are kept already rejected, so they don't wait in the queue or announce
+//! themselves, but can still be approved by hand.
+use std::sync::Arc;
+use rocket::request::{FromRequest, Outcome, Request};
+use tracing::{info, warn};
+use crate::config::BlogConfig;
+use crate::public_url::client_address;
+
+/// Words hardly any post here is commented on with but a lot of spam is.
+const SPAM_WORDS: [&str; 12] = [
+    "viagra", "cialis", "casino", "betting", "porn", "loan", "payday", "crypto", "bitcoin", "forex", "backlinks", "seo services",
+];
+
+/// Who sent a submission, as Akismet wants to know; `ip` is the trusted one of `client_address`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Visitor {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub referrer: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Visitor {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = |name: &str| request.headers().get_one(name).map(String::from);
+        return Outcome::Success(Visitor {
+            ip: client_address(request).map(|ip| ip.to_string()),
+            user_agent: header("User-Agent"),
+            referrer: header("Referer"),
+        });
+    }
+}
+
+/// A comment or webmention to score.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Submission {
+    /// Akismet's `comment_type`: `comment`, or `pingback` for a webmention.
+    pub kind: &'static str,
+    pub author: String,
+    pub author_url: Option<String>,
+    pub content: String,
+    /// The post's URL.
+    pub permalink: String,
+    pub visitor: Visitor,
+}
+
+/// Scores a submission from 0, not spam at all, to 100, certainly spam.
+#[rocket::async_trait]
+pub trait SpamCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn score(&self, submission: &Submission) -> Result<u32, String>;
+}
+
+/// Adds up links, spam words and shouting; a comment with three links is already likely spam.
+pub fn heuristic_score(submission: &Submission) -> u32 {
+    let content = submission.content.to_lowercase();
+    let links = content.matches("http://").count() + content.matches("https://").count() + content.matches("[url=").count();
+    let mut score = match links {
+        0 => 0,
+        1 => 10,
+        2 => 40,
+        _ => 80,
+    };
+
+    let author = submission.author.to_lowercase();
+    score += 50 * SPAM_WORDS.iter().filter(|word| content.contains(*word) || author.contains(*word)).count() as u32;
+    if author.contains("http") || author.contains("www.") {
+        score += 30;
+    }
+    let letters: Vec<char> = submission.content.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.len() >= 20 && letters.iter().all(|c| !c.is_lowercase()) {
+        score += 20;
+    }
+
+    return score.min(100);
+}
+
+pub struct Heuristic;
+
+#[rocket::async_trait]
+impl SpamCheck for Heuristic {
+    fn name(&self) -> &'static str {
+        return "heuristic";
+    }
+
+    async fn score(&self, submission: &Submission) -> Result<u32, String> {
+        return Ok(heuristic_score(submission));
+    }
+}
+
+/// Akismet says spam or not; spam it is sure enough of to say discard scores 100, other spam 90.
+pub struct Akismet {
+    client: reqwest::Client,
+    key: String,
+    blog: String,
+    lang: String,
+}
+
+#[rocket::async_trait]
+impl SpamCheck for Akismet {
+    fn name(&self) -> &'static str {
+        return "akismet";
+    }
+
+    async fn score(&self, submission: &Submission) -> Result<u32, String> {
+        let visitor = &submission.visitor;
+        let mut form = vec![
+            ("blog", self.blog.as_str()),
+            ("blog_lang", self.lang.as_str()),
+            ("comment_type", submission.kind),
+            ("comment_author", submission.author.as_str()),
+            ("comment_content", submission.content.as_str()),
+            ("permalink", submission.permalink.as_str()),
+        ];
+        for (key, value) in [("comment_author_url", &submission.author_url), ("user_ip", &visitor.ip), ("user_agent", &visitor.user_agent), ("referrer", &visitor.referrer)] {
+            if let Some(value) = value {
+                form.push((key, value.as_str()));
+            }
+        }
+
+        let response = self.client.post(format!("https://{}.rest.akismet.com/1.1/comment-check", self.key))
+            .form(&form)
+            .send().await
+            .map_err(|err| err.to_string())?;
+        let discard = response.headers().get("X-akismet-pro-tip").map(|tip| tip == "discard").unwrap_or(false);
+        let body = response.text().await.map_err(|err| err.to_string())?;
+
+        return match body.trim() {
+            "true" if discard => Ok(100),
+            "true" => Ok(90),
+            "false" => Ok(0),
+            other => Err(format!("Akismet said {:?}", other)),
+        };
+    }
+}
+
+/// The check `spam_filter` names and the score from which it rejects; without one nothing is rejected.
+#[derive(Clone, Default)]
+pub struct SpamFilter {
+    check: Option<Arc<dyn SpamCheck>>,
+    reject_score: u32,
+}
+
+impl SpamFilter {
+    pub fn from_config(config: &BlogConfig) -> Result<SpamFilter, String> {
+        let check: Option<Arc<dyn SpamCheck>> = match config.spam_filter.as_str() {
+            "none" => None,
+            "heuristic" => Some(Arc::new(Heuristic)),
+            "akismet" => Some(Arc::new(Akismet {
+                client: reqwest::Client::new(),
+                key: config.akismet_key.to_owned().ok_or("spam_filter akismet needs akismet_key")?,
+                blog: config.public_url.trim_end_matches('/').to_owned(),
+                lang: config.site_lang.to_owned(),
+            })),
+            other => return Err(format!("spam_filter must be none, heuristic or akismet, not {:?}", other)),
+        };
+        return Ok(SpamFilter { check, reject_score: config.spam_reject_score });
+    }
+
+    /// Whether to reject the submission as spam. A check that fails is logged and lets it through to moderation.
+    pub async fn rejects(&self, submission: &Submission) -> bool {
+        let check = match &self.check {
+            Some(check) => check,
+            None => return false
+        };
+        return match check.score(submission).await {
+            Ok(score) => {
+                info!(filter = check.name(), kind = submission.kind, permalink = %submission.permalink, score, "spam scored");
+                score >= self.reject_score
+            },
+            Err(err) => {
+                warn!(filter = check.name(), kind = submission.kind, permalink = %submission.permalink, error = %err, "cannot score for spam");
+                false
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(author: &str, content: &str) -> Submission {
+        return Submission { kind: "comment", author: author.to_owned(), content: content.to_owned(), ..Submission::default() };
+    }
+
+    #[test]
+    fn test_heuristic_score() {
+        assert_eq!(heuristic_score(&comment("Hackle", "Isn't zip just scan? See https://hacklewayne.com/zip-is-scan")), 10);
+        assert_eq!(heuristic_score(&comment("Hackle", "Or is it?")), 0);
+        assert_eq!(heuristic_score(&comment("Hackle", "http://a.example http://b.example http://c.example")), 80);
+        assert_eq!(heuristic_score(&comment("Cheap loans", "Great post")), 50);
+        assert_eq!(heuristic_score(&comment("www.example.com", "GREAT POST, VISIT MY SITE NOW")), 50);
+        assert_eq!(heuristic_score(&comment("Casino", "Best crypto casino https://example.com")), 100);
+    }
+
+    #[rocket::async_test]
+    async fn test_rejects() {
+        let filter = SpamFilter { check: Some(Arc::new(Heuristic)), reject_score: 80 };
+        assert!(filter.rejects(&comment("Casino", "Best casino for crypto loans")).await);
+        assert!(!filter.rejects(&comment("Hackle", "Or is it?")).await);
+        assert!(!SpamFilter::default().rejects(&comment("Casino", "Best casino")).await);
+    }
+}
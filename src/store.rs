@@ -0,0 +1,128 @@
+//! What the stores behind webmentions, comments, reactions, views, beacons, subscribers and followers share: which
+//! one `<name>_store` picks, the SQLite database or DynamoDB table it is kept in, and the states of moderation.
+use serde::{Deserialize, Serialize};
+
+/// Where a comment or webmention is in moderation; only approved ones are shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Moderation {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl Moderation {
+    pub fn parse(value: &str) -> Option<Moderation> {
+        return match value {
+            "pending" => Some(Moderation::Pending),
+            "approved" => Some(Moderation::Approved),
+            "rejected" => Some(Moderation::Rejected),
+            _ => None,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Moderation::Pending => "pending",
+            Moderation::Approved => "approved",
+            Moderation::Rejected => "rejected",
+        };
+    }
+}
+
+/// Where `<name>_store` keeps things: nowhere, as the feature is off; in memory until restart; in the SQLite
+/// database at `<name>_database`; or in the DynamoDB table `<name>_table`.
+pub enum Backend {
+    None,
+    Memory,
+    #[cfg(feature = "sqlite")]
+    Sqlite(String),
+    #[cfg(feature = "dynamodb")]
+    DynamoDb(dynamodb::Table),
+}
+
+/// The backend `store` names for `<name>_store`, with the database or table beside it that it needs.
+#[cfg_attr(not(all(feature = "sqlite", feature = "dynamodb")), allow(unused_variables))]
+pub async fn backend(name: &str, store: &str, database: Option<&str>, table: Option<&str>) -> Result<Backend, String> {
+    return match store {
+        "none" => Ok(Backend::None),
+        "memory" => Ok(Backend::Memory),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => database.map(|path| Backend::Sqlite(path.to_owned())).ok_or(format!("{}_store sqlite needs {}_database", name, name)),
+        #[cfg(feature = "dynamodb")]
+        "dynamodb" => {
+            let table = table.ok_or(format!("{}_store dynamodb needs {}_table", name, name))?;
+            Ok(Backend::DynamoDb(dynamodb::Table::from_env(table).await))
+        },
+        other => Err(format!("{}_store {} isn't compiled in", name, other))
+    };
+}
+
+/// What is wrong with `<name>_store` and the database or table beside it, if anything, whether or not the store it
+/// names is compiled in.
+pub fn problem(name: &str, store: &str, database: Option<&str>, table: Option<&str>) -> Option<String> {
+    return match store {
+        "none" | "memory" => None,
+        "sqlite" if database.is_none() => Some(format!("{}_store sqlite needs {}_database", name, name)),
+        "dynamodb" if table.is_none() => Some(format!("{}_store dynamodb needs {}_table", name, name)),
+        "sqlite" | "dynamodb" => None,
+        other => Some(format!("{}_store must be none, memory, sqlite or dynamodb, not {:?}", name, other)),
+    };
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::sync::Mutex;
+    use rusqlite::Connection;
+
+    /// The database at `path`, with `schema`, a store's `CREATE TABLE IF NOT EXISTS` statements, run on it so its
+    /// tables are there from the first open.
+    pub fn open(path: &str, schema: &str) -> Result<Mutex<Connection>, String> {
+        let connection = Connection::open(path).map_err(|err| format!("Cannot open {}, {}", path, err))?;
+        connection.execute_batch(schema).map_err(|err| format!("Cannot create tables in {}, {}", path, err))?;
+        return Ok(Mutex::new(connection));
+    }
+}
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb {
+    use aws_sdk_dynamodb::Client;
+
+    /// A DynamoDB table and the client to reach it with.
+    #[derive(Clone)]
+    pub struct Table {
+        pub client: Client,
+        pub name: String,
+    }
+
+    impl Table {
+        /// Credentials and region come from the usual AWS environment, the Lambda's role included.
+        pub async fn from_env(name: &str) -> Table {
+            let config = aws_config::load_from_env().await;
+            return Table { client: Client::new(&config), name: name.to_owned() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem() {
+        assert_eq!(problem("comment", "memory", None, None), None);
+        assert_eq!(problem("comment", "sqlite", Some("comments.db"), None), None);
+        assert_eq!(problem("comment", "sqlite", None, Some("comments")), Some(String::from("comment_store sqlite needs comment_database")));
+        assert_eq!(problem("view", "dynamodb", None, None), Some(String::from("view_store dynamodb needs view_table")));
+        assert_eq!(problem("view", "redis", None, None), Some(String::from("view_store must be none, memory, sqlite or dynamodb, not \"redis\"")));
+        assert_eq!(Moderation::parse("approved"), Some(Moderation::Approved));
+        assert_eq!(Moderation::parse("spam"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_backend() {
+        assert!(matches!(backend("comment", "none", None, None).await, Ok(Backend::None)));
+        assert!(matches!(backend("comment", "memory", None, None).await, Ok(Backend::Memory)));
+        assert_eq!(backend("comment", "redis", None, None).await.err(), Some(String::from("comment_store redis isn't compiled in")));
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Instant;
+#[cfg(feature = "lambda")]
+use lambda_web::is_running_on_lambda;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::Request;
+use rocket::response::Response;
+use rocket::Data;
+use tracing::info;
+use crate::request_id::RequestId;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::TracerProvider;
+
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "blog-rust";
+
+/// Without the `lambda` feature the blog never serves on Lambda, so its logs are always for a terminal.
+#[cfg(not(feature = "lambda"))]
+fn is_running_on_lambda() -> bool {
+    return false;
+}
+
+/// Keeps the span exporter alive; `shutdown` flushes whatever spans are still batched.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<TracerProvider>,
+}
+
+/// Logs and spans for requests, manifest and content fetches and rendering, filtered by `RUST_LOG`
+/// (default `info`, with rocket's own chatter at `warn` and its routing notes at `error`). Rocket's logging goes through here too.
+/// On Lambda every line is JSON, span fields included, so CloudWatch Insights can query them.
+/// With the `otel` feature spans are also exported over OTLP/HTTP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init() -> Telemetry {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,rocket=warn,_=error"));
+
+    let (json, text) = if is_running_on_lambda() {
+        let json = tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_events(FmtSpan::CLOSE);
+        (Some(json), None)
+    } else {
+        (None, Some(tracing_subscriber::fmt::layer().compact()))
+    };
+
+    #[cfg(feature = "otel")]
+    let provider = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().and_then(|_| otlp_provider().ok());
+    #[cfg(feature = "otel")]
+    let otel = provider.as_ref().map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+    #[cfg(not(feature = "otel"))]
+    let otel: Option<tracing_subscriber::layer::Identity> = None;
+
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(json)
+        .with(text)
+        .with(otel)
+        .try_init();
+
+    #[cfg(feature = "otel")]
+    return Telemetry { provider };
+    #[cfg(not(feature = "otel"))]
+    return Telemetry {};
+}
+
+#[cfg(feature = "otel")]
+fn otlp_provider() -> Result<TracerProvider, String> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|err| err.to_string())?;
+
+    return Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new(vec![
+            opentelemetry::KeyValue::new("service.name", SERVICE_NAME),
+        ]))
+        .build());
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider {
+            let _ = provider.shutdown();
+        }
+    }
+}
+
+struct RequestStart(Instant);
+
+/// One log line per request, with its status and how long it took.
+pub struct RequestLog;
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        return Info { name: "Request log", kind: Kind::Request | Kind::Response };
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = request.local_cache(|| RequestStart(Instant::now()));
+        info!(
+            request_id = %RequestId::of(request),
+            method = %request.method(),
+            path = %request.uri().path(),
+            status = response.status().code,
+            duration_ms = started.0.elapsed().as_millis() as u64,
+            "request"
+        );
+    }
+}
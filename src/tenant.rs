@@ -0,0 +1,197 @@
+use std::path::PathBuf;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use crate::cache_backend::SharedBackend;
+use crate::config::BlogConfig;
+use crate::blog::{cache_ttl, CachedSource, GithubSource, LocalSource};
+use crate::feed::FeedCache;
+use crate::public_url::trust_proxy_headers;
+use crate::search::{SearchEngine, SearchIndex};
+
+/// One blog in `tenants_file`, e.g.
+/// `{ "hosts": ["example.com"], "title": "Example", "remote_markdown_path": "https://...", "templates": "example" }`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct TenantConfig {
+    pub hosts: Vec<String>,
+    pub title: String,
+    pub description: Option<String>,
+    pub remote_markdown_path: Option<String>,
+    /// Relative to the working directory, `raw` unless given.
+    pub local_directory: Option<String>,
+    /// A subdirectory of `templates` with this blog's own `main`, `index`, `archive` and `search`.
+    pub templates: Option<String>,
+    pub public_url: Option<String>,
+}
+
+/// A blog served from this deployment, with its own content source and caches.
+pub struct Tenant {
+    pub hosts: Vec<String>,
+    pub title: String,
+    pub description: String,
+    pub templates: Option<String>,
+    pub public_url: Option<String>,
+    pub source: CachedSource,
+    pub search_index: SearchIndex,
+    pub search_engine: SearchEngine,
+    /// The feeds and sitemap as built.
+    pub feeds: FeedCache,
+}
+
+impl Tenant {
+    pub fn new(config: TenantConfig) -> Tenant {
+        let remote = config.remote_markdown_path.map(|remote_url| GithubSource::new(&remote_url));
+        let local = match config.local_directory {
+            Some(directory) => LocalSource::new(PathBuf::from(directory)),
+            None => LocalSource::default()
+        };
+
+        return Tenant {
+            hosts: config.hosts.iter().map(|host| host.to_lowercase()).collect(),
+            title: config.title,
+            description: config.description.unwrap_or_else(|| crate::config::config().site_description.to_owned()),
+            templates: config.templates,
+            public_url: config.public_url.map(|url| url.trim_end_matches('/').to_owned()),
+            source: CachedSource::new(remote, local, cache_ttl()),
+            search_index: SearchIndex::new(cache_ttl()),
+            search_engine: SearchEngine::new(cache_ttl()),
+            feeds: FeedCache::from_config(crate::config::config()),
+        };
+    }
+
+    /// The only blog when there is no `tenants_file`, configured as before by `remote_markdown_path` and `public_url`.
+    pub fn from_config(config: &BlogConfig) -> Tenant {
+        return Tenant {
+            hosts: vec![],
+            title: config.site_title.to_owned(),
+            description: config.site_description.to_owned(),
+            templates: None,
+            public_url: None,
+            source: CachedSource::from_config(config),
+            search_index: SearchIndex::new(cache_ttl()),
+            search_engine: SearchEngine::new(cache_ttl()),
+            feeds: FeedCache::from_config(config),
+        };
+    }
+
+    /// The name to render `template` by, from this blog's own templates if it has them.
+    pub fn template(&self, template: &str) -> String {
+        return match &self.templates {
+            Some(directory) => format!("{}/{}", directory, template),
+            None => template.to_owned()
+        };
+    }
+
+    /// What keeps this blog's keys apart from other blogs' in the stores they share: its first host, or nothing
+    /// for the only blog, whose keys are as they were.
+    pub fn id(&self) -> &str {
+        return self.hosts.first().map(String::as_str).unwrap_or("");
+    }
+
+    /// `key` under this blog's id, e.g. `example.com/views`.
+    pub fn key(&self, key: &str) -> String {
+        return scoped(self.id(), key);
+    }
+
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r Tenant {
+        return request.rocket().state::<Tenants>()
+            .expect("tenants are managed")
+            .for_host(request_host(request).as_deref());
+    }
+}
+
+/// `key` under the blog with id `id`, see `Tenant::id`.
+pub fn scoped(id: &str, key: &str) -> String {
+    return if id.is_empty() { key.to_owned() } else { format!("{}/{}", id, key) };
+}
+
+/// The host a request was made to, without a port: `X-Forwarded-Host` when proxy headers are trusted, otherwise `Host`.
+pub fn request_host(request: &Request<'_>) -> Option<String> {
+    let forwarded = if trust_proxy_headers() { request.headers().get_one("X-Forwarded-Host") } else { None };
+    return forwarded.or_else(|| request.headers().get_one("Host"))
+        .and_then(|host| host.split(',').next())
+        .map(|host| host.trim().rsplit_once(':').map(|(host, _)| host).unwrap_or(host.trim()).to_lowercase());
+}
+
+/// Every blog this deployment serves, picked by the host each request is made to.
+/// `tenants_file` names a JSON array of `TenantConfig`; without it there is the one blog configured as a whole.
+pub struct Tenants(pub Vec<Tenant>);
+
+impl Tenants {
+    pub fn from_config(config: &BlogConfig) -> Result<Tenants, String> {
+        let file = match &config.tenants_file {
+            Some(file) => file,
+            None => return Ok(Tenants(vec![Tenant::from_config(config)]))
+        };
+
+        let json = std::fs::read_to_string(file).map_err(|err| format!("Cannot read {}, {}", file, err))?;
+        let configs: Vec<TenantConfig> = serde_json::from_str(&json).map_err(|err| format!("Cannot parse {}, {}", file, err))?;
+        if configs.is_empty() {
+            return Err(format!("{} lists no blogs", file));
+        }
+
+        return Ok(Tenants(configs.into_iter().map(Tenant::new).collect()));
+    }
+
+    /// Every blog's source sharing `backend` behind its in-memory caches.
+    pub fn with_backend(self, backend: Option<SharedBackend>) -> Tenants {
+        return Tenants(self.0.into_iter().map(|tenant| Tenant { source: tenant.source.with_backend(backend.clone()), ..tenant }).collect());
+    }
+
+    /// The blog serving `host`, or the first one for hosts none of them lists, such as the Lambda's own URL.
+    pub fn for_host(&self, host: Option<&str>) -> &Tenant {
+        return host
+            .and_then(|host| self.0.iter().find(|tenant| tenant.hosts.iter().any(|served| served == host)))
+            .unwrap_or(&self.0[0]);
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Tenant {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        return Outcome::Success(Tenant::of(request));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_host() {
+        let config = |host: &str, title: &str| TenantConfig { hosts: vec![host.to_owned()], title: title.to_owned(), ..TenantConfig::default() };
+        let tenants = Tenants(vec![Tenant::new(config("hacklewayne.com", "Hackle's blog")), Tenant::new(config("Example.com", "Example"))]);
+
+        assert_eq!(tenants.for_host(Some("example.com")).title, "Example");
+        assert_eq!(tenants.for_host(Some("hacklewayne.com")).title, "Hackle's blog");
+        assert_eq!(tenants.for_host(Some("abc.lambda-url.ap-southeast-2.on.aws")).title, "Hackle's blog");
+        assert_eq!(tenants.for_host(None).title, "Hackle's blog");
+        assert_eq!(tenants.0[1].template("main"), "main");
+        assert_eq!(Tenant { templates: Some(String::from("example")), ..Tenant::new(config("example.com", "Example")) }.template("main"), "example/main");
+    }
+
+    #[test]
+    fn test_key() {
+        let config = TenantConfig { hosts: vec![String::from("Example.com"), String::from("www.example.com")], ..TenantConfig::default() };
+
+        assert_eq!(Tenant::new(config).key("views"), "example.com/views");
+        assert_eq!(Tenant { hosts: vec![], ..Tenant::new(TenantConfig::default()) }.key("views"), "views");
+    }
+
+    #[test]
+    fn test_deserialise_tenants() {
+        let configs: Vec<TenantConfig> = serde_json::from_str(r#"[
+            { "hosts": ["hacklewayne.com", "www.hacklewayne.com"], "title": "Hackle's blog", "remote_markdown_path": "https://raw.githubusercontent.com/hackle/blog-rust/master/raw" },
+            { "hosts": ["example.com"], "title": "Example", "local_directory": "example", "templates": "example" }
+        ]"#).unwrap();
+
+        assert_eq!(configs[1], TenantConfig {
+            hosts: vec![String::from("example.com")],
+            title: String::from("Example"),
+            local_directory: Some(String::from("example")),
+            templates: Some(String::from("example")),
+            ..TenantConfig::default()
+        });
+    }
+}
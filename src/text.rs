@@ -0,0 +1,168 @@
+use comrak::{parse_document, Arena};
+use comrak::nodes::{AstNode, ListType, NodeValue};
+use crate::blog::comrak_options;
+
+pub const TEXT_WIDTH: usize = 72;
+
+/// Renders markdown to plain text for terminals: paragraphs wrapped to `width`, headings underlined,
+/// code blocks indented and left alone, links followed by their URL in angle brackets.
+pub fn markdown_to_plain_text(markdown: &str, width: usize) -> String {
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, comrak_options());
+
+    let mut text = blocks(root, width).join("\n\n");
+    text.push('\n');
+    return text;
+}
+
+fn blocks<'a>(node: &'a AstNode<'a>, width: usize) -> Vec<String> {
+    return node.children().filter_map(|child| block(child, width)).collect();
+}
+
+fn block<'a>(node: &'a AstNode<'a>, width: usize) -> Option<String> {
+    return match &node.data.borrow().value {
+        NodeValue::Paragraph => Some(wrap(&inlines(node), width)),
+        NodeValue::Heading(heading) => {
+            let title = inlines(node).replace('\n', " ");
+            Some(match heading.level {
+                1 => format!("{}\n{}", title, "=".repeat(title.chars().count())),
+                2 => format!("{}\n{}", title, "-".repeat(title.chars().count())),
+                level => format!("{} {}", "#".repeat(level as usize), title),
+            })
+        },
+        NodeValue::CodeBlock(code_block) => Some(
+            String::from_utf8_lossy(&code_block.literal)
+                .trim_end()
+                .lines()
+                .map(|line| if line.is_empty() { String::new() } else { format!("    {}", line) })
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        NodeValue::BlockQuote => Some(prefix_lines(&blocks(node, width.saturating_sub(2)).join("\n\n"), "> ", "> ")),
+        NodeValue::List(list) => {
+            let mut number = list.start;
+            let items: Vec<String> = node.children()
+                .map(|item| {
+                    let marker = match list.list_type {
+                        ListType::Bullet => String::from("- "),
+                        ListType::Ordered => format!("{}. ", number),
+                    };
+                    number += 1;
+                    let indent = " ".repeat(marker.len());
+                    prefix_lines(&blocks(item, width.saturating_sub(marker.len())).join("\n\n"), &marker, &indent)
+                })
+                .collect();
+            Some(items.join(if list.tight { "\n" } else { "\n\n" }))
+        },
+        NodeValue::ThematicBreak => Some(String::from("* * *")),
+        NodeValue::Table(_) => Some(
+            node.children()
+                .map(|row| row.children().map(|cell| inlines(cell)).collect::<Vec<_>>().join(" | "))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        NodeValue::FootnoteDefinition(name) => Some(format!("[^{}]: {}", String::from_utf8_lossy(name), blocks(node, width).join("\n\n"))),
+        NodeValue::HtmlBlock(_) | NodeValue::FrontMatter(_) => None,
+        _ => Some(blocks(node, width).join("\n\n")).filter(|text| !text.is_empty()),
+    };
+}
+
+fn inlines<'a>(node: &'a AstNode<'a>) -> String {
+    return node.children().map(|child| inline(child)).collect();
+}
+
+fn inline<'a>(node: &'a AstNode<'a>) -> String {
+    return match &node.data.borrow().value {
+        NodeValue::Text(text) => String::from_utf8_lossy(text).into_owned(),
+        NodeValue::Code(code) => String::from_utf8_lossy(&code.literal).into_owned(),
+        NodeValue::SoftBreak => String::from(" "),
+        NodeValue::LineBreak => String::from("\n"),
+        NodeValue::Link(link) => {
+            let text = inlines(node);
+            let url = String::from_utf8_lossy(&link.url).into_owned();
+            if text == url { format!("<{}>", url) } else { format!("{} <{}>", text, url) }
+        },
+        NodeValue::Image(_) => format!("[image: {}]", inlines(node)),
+        NodeValue::FootnoteReference(name) => format!("[^{}]", String::from_utf8_lossy(name)),
+        NodeValue::HtmlInline(_) => String::new(),
+        _ => inlines(node),
+    };
+}
+
+fn prefix_lines(text: &str, first: &str, rest: &str) -> String {
+    return text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let prefix = if index == 0 { first } else { rest };
+            if line.is_empty() { prefix.trim_end().to_owned() } else { format!("{}{}", prefix, line) }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
+/// Greedy word wrap; hard line breaks are kept, and words longer than `width` get a line to themselves.
+pub fn wrap(text: &str, width: usize) -> String {
+    return text.split('\n')
+        .map(|line| {
+            let mut lines: Vec<String> = vec![];
+            let mut current = String::new();
+            for word in line.split_whitespace() {
+                if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+                    lines.push(std::mem::take(&mut current));
+                }
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(word);
+            }
+            lines.push(current);
+            lines.join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("the quick brown fox jumps over", 10), "the quick\nbrown fox\njumps over");
+        assert_eq!(wrap("a\nb c", 10), "a\nb c");
+        assert_eq!(wrap("incomprehensibilities ok", 10), "incomprehensibilities\nok");
+    }
+
+    #[test]
+    fn test_markdown_to_plain_text() {
+        let markdown = r#"# Fin
+
+A *type* for [bounded](https://example.com) numbers, see `Fin n`.
+
+```idris
+data Fin : Nat -> Type where
+  FZ : Fin (S k)
+```
+
+- one
+- two
+
+> quoted
+"#;
+        let expected = r#"Fin
+===
+
+A type for bounded <https://example.com> numbers, see Fin n.
+
+    data Fin : Nat -> Type where
+      FZ : Fin (S k)
+
+- one
+- two
+
+> quoted
+"#;
+
+        assert_eq!(markdown_to_plain_text(markdown, 72), expected);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::config::BlogConfig;
+use crate::helpers::{asset_fingerprints, Fingerprints};
+
+const THEME_MANIFEST: &str = "theme.json";
+
+/// A post's context, for `main` and for `plain`, its version for printing and embedding.
+const POST: &[&str] = &[
+    "title", "site_title", "meta", "description", "public_url", "slug", "short_link", "featured", "kind", "lang", "alternates", "see_also", "popular",
+    "date_updated", "updated", "word_count", "reading_time", "request_id", "build", "csp_nonce", "color_scheme",
+    "nav", "footer", "me", "beacon", "newsletter", "analytics", "theme_color", "service_worker", "webmention", "webmentions", "comments", "comments_open",
+    "reactions", "reactions_open", "views", "h_entry", "edit_url", "history", "lint", "hero",
+];
+
+/// What each page's template is given to render, beyond the helpers; a theme's templates can use no more.
+pub const CONTEXT: [(&str, &[&str]); 5] = [
+    ("main", POST),
+    ("plain", POST),
+    ("index", &["title", "site_title", "posts", "page", "total_pages", "prev_page", "next_page", "build", "csp_nonce", "color_scheme", "nav", "footer", "me", "beacon", "newsletter", "analytics", "theme_color", "service_worker", "lang", "feed", "micropub"]),
+    ("archive", &["title", "site_title", "years", "build", "csp_nonce", "color_scheme", "nav", "footer", "me", "beacon", "newsletter", "analytics", "theme_color", "service_worker", "lang"]),
+    ("search", &["title", "site_title", "query", "results", "build", "csp_nonce", "color_scheme", "nav", "footer", "me", "beacon", "newsletter", "analytics", "theme_color", "service_worker", "lang"]),
+];
+
+/// `theme.json`, e.g. `{ "name": "Solarized", "required_context": { "main": ["title", "meta", "updated"] } }`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ThemeManifest {
+    pub name: String,
+    /// The keys each of its templates needs, so a theme written for another version of the blog fails `doctor`
+    /// rather than rendering pages with holes in them.
+    #[serde(default)]
+    pub required_context: BTreeMap<String, Vec<String>>,
+}
+
+/// A directory with `theme.json`, and `templates` and `static` laid out as the blog's own. Its templates and
+/// partials replace those of the same name and its static files those of the same path; anything it leaves out
+/// is the blog's own.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub directory: PathBuf,
+    pub manifest: ThemeManifest,
+}
+
+impl Theme {
+    pub fn load(directory: &Path) -> Result<Theme, String> {
+        let path = directory.join(THEME_MANIFEST);
+        let manifest = std::fs::read_to_string(&path).map_err(|err| format!("Cannot read {}, {}", path.display(), err))?;
+        let manifest = serde_json::from_str(&manifest).map_err(|err| format!("Cannot parse {}, {}", path.display(), err))?;
+        return Ok(Theme { directory: directory.to_owned(), manifest });
+    }
+
+    /// The theme `theme` points at, if any.
+    pub fn from_config(config: &BlogConfig) -> Result<Option<Theme>, String> {
+        return config.theme.as_ref().map(|directory| Theme::load(Path::new(directory))).transpose();
+    }
+
+    pub fn template_dir(&self) -> PathBuf {
+        return self.directory.join("templates");
+    }
+
+    pub fn static_dir(&self) -> PathBuf {
+        return self.directory.join("static");
+    }
+
+    /// Required context the blog doesn't give, as `template.key`.
+    pub fn missing_context(&self) -> Vec<String> {
+        let context: BTreeMap<&str, &[&str]> = CONTEXT.into_iter().collect();
+        return self.manifest.required_context.iter()
+            .flat_map(|(template, keys)| {
+                let given = context.get(template.as_str()).copied().unwrap_or_default();
+                keys.iter().filter(move |key| !given.contains(&key.as_str())).map(move |key| format!("{}.{}", template, key))
+            })
+            .collect();
+    }
+
+    /// Its `<name>.html.<extension>` templates by name, partials left to `partials::register`.
+    pub fn templates(&self, extension: &str) -> Vec<(String, PathBuf)> {
+        fn visit(root: &Path, directory: &Path, suffix: &str, templates: &mut Vec<(String, PathBuf)>) {
+            for entry in std::fs::read_dir(directory).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    if !path.ends_with("partials") {
+                        visit(root, &path, suffix, templates);
+                    }
+                    continue;
+                }
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                if let Some(name) = relative.strip_suffix(suffix) {
+                    templates.push((name.to_owned(), path.to_owned()));
+                }
+            }
+        }
+
+        let template_dir = self.template_dir();
+        let mut templates = vec![];
+        visit(&template_dir, &template_dir, &format!(".html.{}", extension), &mut templates);
+        templates.sort();
+        return templates;
+    }
+}
+
+/// Fingerprints of the static files `asset_url` links to, the theme's where it has them.
+pub fn static_fingerprints(static_dir: &Path, theme: Option<&Theme>) -> Fingerprints {
+    let mut fingerprints = asset_fingerprints(static_dir);
+    if let Some(theme) = theme {
+        fingerprints.extend(asset_fingerprints(&theme.static_dir()));
+    }
+    return fingerprints;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn theme(required_context: &[(&str, &[&str])]) -> Theme {
+        let required_context = required_context.iter()
+            .map(|(template, keys)| (template.to_string(), keys.iter().map(|key| key.to_string()).collect()))
+            .collect();
+        return Theme { directory: PathBuf::from("."), manifest: ThemeManifest { name: String::from("Test"), required_context } };
+    }
+
+    #[test]
+    fn test_missing_context() {
+        assert!(theme(&[("main", &["title", "meta", "updated"]), ("index", &["posts"])]).missing_context().is_empty());
+        assert_eq!(
+            theme(&[("main", &["title", "cover_image"]), ("talks", &["title"])]).missing_context(),
+            vec!["main.cover_image", "talks.title"]
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let directory = std::env::temp_dir().join(format!("blog-theme-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("templates/partials")).unwrap();
+        std::fs::create_dir_all(directory.join("static")).unwrap();
+        std::fs::write(directory.join("theme.json"), r#"{ "name": "Solarized", "required_context": { "main": ["title"] } }"#).unwrap();
+        std::fs::write(directory.join("templates/main.html.hbs"), "<h1>{{title}}</h1>").unwrap();
+        std::fs::write(directory.join("templates/partials/header.hbs"), "<header></header>").unwrap();
+        std::fs::write(directory.join("static/styles.css"), "body {}").unwrap();
+
+        let theme = Theme::load(&directory).unwrap();
+        assert_eq!(theme.manifest.name, "Solarized");
+        assert_eq!(theme.templates("hbs"), vec![(String::from("main"), directory.join("templates/main.html.hbs"))]);
+        assert_eq!(
+            static_fingerprints(Path::new("static"), Some(&theme)).get("styles.css"),
+            asset_fingerprints(&theme.static_dir()).get("styles.css")
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(Theme::load(&directory).unwrap_err().starts_with("Cannot read"));
+    }
+}
@@ -0,0 +1,196 @@
+//! Images uploaded through `POST /admin/assets`, stored where `upload_store` says, if anywhere: `local`, under
+//! `upload_directory` and served from `/uploads`, or `s3`, in `upload_bucket`. Each is named for what it is and a hash of its bytes, so
+//! uploading the same image twice stores it once, and it can be cached for good wherever it is served from.
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rocket::fs::NamedFile;
+use rocket::http::Header;
+use rocket::{get, routes, Responder, Route};
+use serde::Serialize;
+use crate::assets::IMMUTABLE;
+use crate::blog::to_slug;
+use crate::config::{config, BlogConfig};
+use crate::markdown_options::fingerprint;
+
+/// The kinds of image taken, by their first bytes rather than what the upload claims to be. SVG isn't one of them,
+/// as it can carry script that would run on the blog's own origin.
+pub fn image_type(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    return match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some(("image/png", "png")),
+        [0xff, 0xd8, 0xff, ..] => Some(("image/jpeg", "jpg")),
+        [b'G', b'I', b'F', b'8', ..] => Some(("image/gif", "gif")),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(("image/webp", "webp")),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f', ..] => Some(("image/avif", "avif")),
+        _ => None,
+    };
+}
+
+/// `<name>-<hash>.<extension>`, the name as a slug of what it was uploaded as.
+pub fn file_name(name: Option<&str>, bytes: &[u8], extension: &str) -> String {
+    let name = Some(to_slug(name.unwrap_or_default())).filter(|name| !name.is_empty()).unwrap_or_else(|| String::from("image"));
+    return format!("{}-{:016x}.{}", name, fingerprint(bytes), extension);
+}
+
+/// An image in a post, its alt text kept from closing the brackets early.
+pub fn markdown(alt: &str, url: &str) -> String {
+    let alt: String = alt.chars().filter(|c| *c != '[' && *c != ']').collect();
+    return format!("![{}]({})", alt.trim(), url);
+}
+
+#[rocket::async_trait]
+pub trait UploadStore: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Stores `bytes` as `file`, returning its URL; the local store's is relative to the blog's public URL.
+    async fn put(&self, file: &str, bytes: Vec<u8>, content_type: &str) -> Result<String, String>;
+}
+
+pub struct LocalStore {
+    directory: PathBuf,
+}
+
+#[rocket::async_trait]
+impl UploadStore for LocalStore {
+    fn name(&self) -> &'static str {
+        return "local";
+    }
+
+    async fn put(&self, file: &str, bytes: Vec<u8>, _content_type: &str) -> Result<String, String> {
+        let path = self.directory.join(file);
+        rocket::tokio::fs::create_dir_all(&self.directory).await.map_err(|err| format!("Cannot create {}, {}", self.directory.display(), err))?;
+        rocket::tokio::fs::write(&path, bytes).await.map_err(|err| format!("Cannot write {}, {}", path.display(), err))?;
+        return Ok(format!("/uploads/{}", file));
+    }
+}
+
+/// The store `upload_store` names, or none when it is `none` and uploads are off.
+#[derive(Clone, Default)]
+pub struct Uploads(Option<Arc<dyn UploadStore>>);
+
+impl Uploads {
+    pub async fn from_config(config: &BlogConfig) -> Result<Uploads, String> {
+        let store: Option<Arc<dyn UploadStore>> = match config.upload_store.as_str() {
+            "none" => None,
+            "local" => Some(Arc::new(LocalStore { directory: PathBuf::from(&config.upload_directory) })),
+            #[cfg(feature = "s3")]
+            "s3" => {
+                let bucket = config.upload_bucket.to_owned().ok_or("upload_store s3 needs upload_bucket")?;
+                Some(Arc::new(s3::S3Store::from_env(bucket, config.upload_public_url.to_owned()).await))
+            },
+            other => return Err(format!("upload_store {} isn't compiled in", other))
+        };
+
+        return Ok(Uploads(store));
+    }
+
+    pub fn store(&self) -> Option<&dyn UploadStore> {
+        return self.0.as_deref();
+    }
+}
+
+/// What an upload answers with: where the image is, and the markdown to put it in a post.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Uploaded {
+    pub url: String,
+    pub markdown: String,
+}
+
+#[derive(Responder)]
+pub struct Upload(NamedFile, Header<'static>);
+
+#[get("/uploads/<file>")]
+async fn upload(file: &str) -> Option<Upload> {
+    let config = config();
+    // only names as `file_name` makes them, which can't leave the directory
+    let named = file.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') && !file.starts_with('.');
+    if config.upload_store != "local" || !named {
+        return None;
+    }
+    let file = NamedFile::open(Path::new(&config.upload_directory).join(file)).await.ok()?;
+    return Some(Upload(file, Header::new("Cache-Control", IMMUTABLE)));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![upload];
+}
+
+#[cfg(feature = "s3")]
+pub mod s3 {
+    use aws_sdk_s3::primitives::ByteStream;
+    use aws_sdk_s3::Client;
+    use crate::assets::IMMUTABLE;
+    use super::UploadStore;
+
+    /// Puts images in a bucket, under `uploads/`. Credentials come from the usual AWS environment, the Lambda's role
+    /// included; the bucket, or `upload_public_url` in front of it, has to be readable by anyone.
+    pub struct S3Store {
+        client: Client,
+        bucket: String,
+        public_url: String,
+    }
+
+    impl S3Store {
+        pub async fn from_env(bucket: String, public_url: Option<String>) -> S3Store {
+            let config = aws_config::load_from_env().await;
+            let public_url = public_url.unwrap_or_else(|| format!("https://{}.s3.amazonaws.com", bucket));
+            return S3Store { client: Client::new(&config), bucket, public_url: public_url.trim_end_matches('/').to_owned() };
+        }
+    }
+
+    #[rocket::async_trait]
+    impl UploadStore for S3Store {
+        fn name(&self) -> &'static str {
+            return "s3";
+        }
+
+        async fn put(&self, file: &str, bytes: Vec<u8>, content_type: &str) -> Result<String, String> {
+            let key = format!("uploads/{}", file);
+            self.client.put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .body(ByteStream::from(bytes))
+                .content_type(content_type)
+                .cache_control(IMMUTABLE)
+                .send().await
+                .map_err(|err| err.to_string())?;
+            return Ok(format!("{}/{}", self.public_url, key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_type() {
+        assert_eq!(image_type(b"\x89PNG\r\n\x1a\n"), Some(("image/png", "png")));
+        assert_eq!(image_type(b"\xff\xd8\xff\xe0"), Some(("image/jpeg", "jpg")));
+        assert_eq!(image_type(b"RIFF\x24\x00\x00\x00WEBPVP8 "), Some(("image/webp", "webp")));
+        assert_eq!(image_type(b"\x00\x00\x00\x1cftypavif"), Some(("image/avif", "avif")));
+        assert_eq!(image_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), None);
+        assert_eq!(image_type(b""), None);
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(Some("Zip is Scan"), b"png", "png"), format!("zip-is-scan-{:016x}.png", fingerprint(b"png")));
+        assert_eq!(file_name(None, b"png", "png"), format!("image-{:016x}.png", fingerprint(b"png")));
+        assert_eq!(markdown("a [diagram]", "/uploads/a.png"), "![a diagram](/uploads/a.png)");
+    }
+
+    #[rocket::async_test]
+    async fn test_from_config() {
+        assert!(Uploads::from_config(&BlogConfig::default()).await.unwrap().store().is_none());
+        let config = BlogConfig { upload_store: String::from("local"), ..BlogConfig::default() };
+        assert_eq!(Uploads::from_config(&config).await.unwrap().store().map(|store| store.name()), Some("local"));
+    }
+
+    #[rocket::async_test]
+    async fn test_local_store() {
+        let directory = std::env::temp_dir().join(format!("blog-uploads-{}", std::process::id()));
+        let store = LocalStore { directory: directory.clone() };
+        assert_eq!(store.put("a.png", b"png".to_vec(), "image/png").await, Ok(String::from("/uploads/a.png")));
+        assert_eq!(std::fs::read(directory.join("a.png")).unwrap(), b"png");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
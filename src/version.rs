@@ -0,0 +1,55 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rocket::response::content::Json;
+use rocket::{get, routes, Route};
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub built_at: DateTime<Utc>,
+}
+
+/// Embedded at compile time by `build.rs`.
+pub fn build_info() -> BuildInfo {
+    let built_at = env!("BUILD_TIMESTAMP").parse::<i64>().unwrap_or(0);
+
+    return BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("BUILD_GIT_SHA"),
+        built_at: Utc.timestamp(built_at, 0),
+    };
+}
+
+impl BuildInfo {
+    /// For the page footer, e.g. "v0.1.0 (3f2c1ab, built 2021-09-05)".
+    pub fn summary(&self) -> String {
+        return format!("v{} ({}, built {})", self.version, self.git_sha, self.built_at.format("%Y-%m-%d"));
+    }
+}
+
+#[get("/version")]
+fn version() -> Json<String> {
+    return Json(serde_json::to_string(&build_info()).unwrap());
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![version];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let info = BuildInfo {
+            version: "0.1.0",
+            git_sha: "3f2c1ab",
+            built_at: Utc.ymd(2021, 9, 5).and_hms(3, 53, 47),
+        };
+
+        assert_eq!(info.summary(), "v0.1.0 (3f2c1ab, built 2021-09-05)");
+        assert_eq!(build_info().version, env!("CARGO_PKG_VERSION"));
+    }
+}
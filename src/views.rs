@@ -0,0 +1,267 @@
+//! Counts views of posts, so it's known what's read without handing readers to a third-party analytics
+//! service. No address is kept: a reader is only a hash of their address and user agent with a salt that is
+//! drawn at start and never stored, remembered for `view_dedupe_secs` so reloading a post doesn't count again.
+//! Counts are kept in the store `view_store` names. Pages the CDN serves from its cache aren't seen, so
+//! behind one the counts are of readers it sent on, not all of them. Views are counted by day as well, for the
+//! posts most read over the last `popular_days`.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{Duration as Days, Utc};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::request::Request;
+use rocket::response::Response;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use crate::blog::{find_post, Post};
+use crate::cache::TtlCache;
+use crate::config::BlogConfig;
+use crate::counters::{self, SharedCounters};
+use crate::public_url::client_address;
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+
+/// User agents that are crawlers and previewers rather than readers.
+const BOTS: [&str; 6] = ["bot", "crawler", "spider", "slurp", "preview", "curl"];
+/// How long the counts over `popular_days` are reused before they are summed again.
+const RECENT_TTL: Duration = Duration::from_secs(300);
+
+pub fn is_bot(user_agent: &str) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    return BOTS.iter().any(|bot| user_agent.contains(bot));
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct PostViews {
+    pub slug: String,
+    pub title: String,
+    pub views: u64,
+}
+
+/// The posts in `posts` that were viewed, most viewed first.
+pub fn most_viewed(posts: &[Post], views: &BTreeMap<String, u64>) -> Vec<PostViews> {
+    let mut viewed: Vec<PostViews> = posts.iter()
+        .filter_map(|post| Some(PostViews { slug: post.slug.to_owned(), title: post.title.to_owned(), views: *views.get(&post.slug)? }))
+        .collect();
+    viewed.sort_by(|a, b| b.views.cmp(&a.views).then_with(|| a.slug.cmp(&b.slug)));
+    return viewed;
+}
+
+/// The counters' key for views, under each blog's `Tenant::key`, with a name a post's slug.
+const VIEWS: &str = "views";
+
+/// Remembers which readers viewed which post in the last `window`, by a salted hash of them and the post.
+#[derive(Default)]
+pub struct Seen {
+    salt: String,
+    window: Duration,
+    seen: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl Seen {
+    pub fn new(window: Duration) -> Seen {
+        return Seen { salt: uuid::Uuid::new_v4().to_string(), window, seen: Mutex::new(HashMap::new()) };
+    }
+
+    /// Whether this is the reader's first view of `slug` in the window, remembering it if so.
+    pub fn first(&self, reader: &str, user_agent: &str, slug: &str, now: Instant) -> bool {
+        let hash: [u8; 32] = Sha256::new()
+            .chain_update(&self.salt)
+            .chain_update([0]).chain_update(reader)
+            .chain_update([0]).chain_update(user_agent)
+            .chain_update([0]).chain_update(slug)
+            .finalize()
+            .into();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now.duration_since(*at) < self.window);
+        if seen.contains_key(&hash) {
+            return false;
+        }
+        seen.insert(hash, now);
+        return true;
+    }
+}
+
+/// The store `view_store` names, or none when it is `none` and views aren't counted. Attached, it counts every
+/// post served to a reader.
+#[derive(Clone)]
+pub struct Views {
+    store: Option<SharedCounters>,
+    seen: Arc<Seen>,
+    popular_days: u32,
+    recent: TtlCache<BTreeMap<String, u64>>,
+}
+
+impl Default for Views {
+    fn default() -> Views {
+        return Views { store: None, seen: Arc::default(), popular_days: 0, recent: TtlCache::new(RECENT_TTL) };
+    }
+}
+
+impl Views {
+    pub async fn from_config(config: &BlogConfig) -> Result<Views, String> {
+        let store = counters::from_config("view", &config.view_store, config.view_database.as_deref(), config.view_table.as_deref()).await?;
+        return Ok(Views {
+            store,
+            seen: Arc::new(Seen::new(Duration::from_secs(config.view_dedupe_secs))),
+            popular_days: config.popular_days,
+            recent: TtlCache::new(RECENT_TTL),
+        });
+    }
+
+    /// The views of `slug` on `tenant`, if they are counted; a store that fails is logged and shows none.
+    pub async fn count(&self, tenant: &Tenant, slug: &str) -> Option<u64> {
+        let store = self.store.as_ref()?;
+        return store.count(&tenant.key(VIEWS), slug).await
+            .inspect_err(|err| warn!(%slug, error = %err, "cannot read views"))
+            .ok();
+    }
+
+    /// Every post's views on `tenant`, if they are counted.
+    pub async fn all(&self, tenant: &Tenant) -> Option<Result<BTreeMap<String, u64>, String>> {
+        return Some(self.store.as_ref()?.totals(&tenant.key(VIEWS)).await);
+    }
+
+    /// The posts in `posts`, on `tenant`, most viewed over the last `popular_days`, if views are counted. The
+    /// counts are summed at most every few minutes.
+    pub async fn popular(&self, tenant: &Tenant, posts: &[Post]) -> Option<Result<Vec<PostViews>, String>> {
+        let store = self.store.as_ref()?;
+        let key = tenant.key(VIEWS);
+        let counts = match self.recent.get(&key) {
+            Some(counts) => counts,
+            None => {
+                let since = Utc::today().naive_utc() - Days::days(self.popular_days.saturating_sub(1) as i64);
+                match store.since(&key, since).await {
+                    Ok(daily) => {
+                        let mut counts = BTreeMap::new();
+                        for count in daily {
+                            *counts.entry(count.name).or_default() += count.count;
+                        }
+                        self.recent.insert(&key, counts.to_owned());
+                        counts
+                    },
+                    Err(err) => return Some(Err(err))
+                }
+            }
+        };
+        return Some(Ok(most_viewed(posts, &counts)));
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Views {
+    fn info(&self) -> Info {
+        return Info { name: "View counter", kind: Kind::Response };
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return
+        };
+        let is_post = request.route().and_then(|route| route.name.as_deref()) == Some("blog_post");
+        let served = response.status() == Status::Ok || response.status() == Status::NotModified;
+        let user_agent = request.headers().get_one("User-Agent").unwrap_or("");
+        if request.method() != Method::Get || !is_post || !served || is_bot(user_agent) {
+            return;
+        }
+
+        // count the post under its own slug, however it was asked for
+        let request_id = RequestId::of(request);
+        let tenant = Tenant::of(request);
+        let posts = match tenant.source.for_request(&request_id).all_posts().await {
+            Ok(posts) => posts,
+            Err(_) => return
+        };
+        let requested = request.routed_segment(0).unwrap_or("");
+        let post = match find_post(&posts, requested) {
+            Some(post) => post,
+            None => return
+        };
+        // without an address readers can't be told apart, so each view counts rather than one of them all
+        let reader = client_address(request).map(|ip| ip.to_string());
+        if reader.is_some_and(|reader| !self.seen.first(&reader, user_agent, &tenant.key(&post.slug), Instant::now())) {
+            return;
+        }
+
+        match store.increment(&tenant.key(VIEWS), Utc::today().naive_utc(), &[&post.slug]).await {
+            Ok(counts) => info!(%request_id, slug = %post.slug, count = counts[0], "viewed"),
+            Err(err) => warn!(%request_id, slug = %post.slug, error = %err, "cannot count view"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+    use crate::counters::{CounterStore, MemoryStore};
+    use crate::tenant::TenantConfig;
+
+    fn post(slug: &str) -> Post {
+        return Post {
+            slug: slug.to_owned(),
+            title: slug.to_uppercase(),
+            path: format!("{}.md", slug),
+            hidden: false,
+            updated: Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+            tags: vec![],
+            pinned: false,
+            template: None,
+            kind: crate::blog::Kind::Post,
+            lang: None,
+            translations: BTreeMap::new(),
+            short: None,
+            enclosure: None,
+            cover: None,
+        };
+    }
+
+    #[test]
+    fn test_most_viewed() {
+        let posts = vec![post("fin"), post("zip-is-scan"), post("unseen")];
+        let views = BTreeMap::from([(String::from("fin"), 3), (String::from("zip-is-scan"), 7), (String::from("gone"), 9)]);
+
+        let slugs: Vec<(String, u64)> = most_viewed(&posts, &views).into_iter().map(|viewed| (viewed.slug, viewed.views)).collect();
+        assert_eq!(slugs, vec![(String::from("zip-is-scan"), 7), (String::from("fin"), 3)]);
+    }
+
+    #[test]
+    fn test_seen() {
+        let seen = Seen::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(seen.first("203.0.113.1", "Firefox", "zip-is-scan", start));
+        assert!(!seen.first("203.0.113.1", "Firefox", "zip-is-scan", start + Duration::from_secs(30)));
+        assert!(seen.first("203.0.113.1", "Firefox", "fin", start + Duration::from_secs(30)));
+        assert!(seen.first("203.0.113.1", "Safari", "zip-is-scan", start + Duration::from_secs(30)));
+        assert!(seen.first("203.0.113.1", "Firefox", "zip-is-scan", start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_is_bot() {
+        assert!(is_bot("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"));
+        assert!(is_bot("curl/8.5.0"));
+        assert!(!is_bot("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"));
+    }
+
+    #[rocket::async_test]
+    async fn test_views() {
+        let (monday, tuesday) = (NaiveDate::from_ymd(2021, 5, 3), NaiveDate::from_ymd(2021, 5, 4));
+        let store = Arc::new(MemoryStore::default());
+        store.increment(VIEWS, monday, &["zip-is-scan", "fin"]).await.unwrap();
+        store.increment(VIEWS, tuesday, &["zip-is-scan"]).await.unwrap();
+        store.increment("example.com/views", tuesday, &["zip-is-scan"]).await.unwrap();
+        let views = Views { store: Some(store), ..Views::default() };
+        let only = Tenant::new(TenantConfig::default());
+        let example = Tenant::new(TenantConfig { hosts: vec![String::from("example.com")], ..TenantConfig::default() });
+
+        assert_eq!(views.count(&only, "zip-is-scan").await, Some(2));
+        assert_eq!(views.count(&only, "unseen").await, Some(0));
+        assert_eq!(views.count(&example, "zip-is-scan").await, Some(1));
+        assert_eq!(views.all(&only).await, Some(Ok(BTreeMap::from([(String::from("fin"), 1), (String::from("zip-is-scan"), 2)]))));
+        assert_eq!(Views::default().count(&only, "zip-is-scan").await, None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use rocket::tokio::time::sleep;
+use tracing::{info, warn};
+use crate::blog::CachedSource;
+use crate::cdn::{purge_keys, Cdn};
+use crate::feed::FeedCache;
+use crate::activitypub::ActivityPub;
+use crate::search::SearchIndex;
+use crate::webmention::send::Sender;
+
+/// Markdown and manifest files under a directory, by their path relative to it, with when each was last modified.
+pub type Snapshot = BTreeMap<String, SystemTime>;
+
+fn is_watched(path: &str) -> bool {
+    return path.ends_with(".md") || path.ends_with("manifest.json");
+}
+
+fn visit(root: &Path, directory: &Path, snapshot: &mut Snapshot) {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue
+        };
+        if metadata.is_dir() {
+            visit(root, &path, snapshot);
+            continue;
+        }
+
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        if is_watched(&relative) {
+            snapshot.insert(relative, metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+        }
+    }
+}
+
+pub fn snapshot(directory: &Path) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    visit(directory, directory, &mut snapshot);
+    return snapshot;
+}
+
+/// Every path added, modified or removed between the two snapshots.
+pub fn changes(before: &Snapshot, after: &Snapshot) -> Vec<String> {
+    let modified = after.iter()
+        .filter(|(path, modified)| before.get(*path) != Some(*modified))
+        .map(|(path, _)| path.to_owned());
+    let removed = before.keys().filter(|path| !after.contains_key(*path)).cloned();
+
+    let mut paths: Vec<String> = modified.chain(removed).collect();
+    paths.sort();
+    return paths;
+}
+
+/// Looks at the local directory every `interval` and, when files changed, drops them from the caches as a push
+/// to the markdown repository would, so editing a local-only blog shows without a restart. The posts that changed
+/// are sent as webmentions and delivered to followers under `public_url`.
+#[allow(clippy::too_many_arguments)]
+pub async fn watch(source: CachedSource, search_index: SearchIndex, feeds: FeedCache, cdn: Cdn, sender: Sender, activitypub: ActivityPub, public_url: String, interval: Duration) {
+    let directory = source.local().directory.to_owned();
+    let mut before = snapshot(&directory);
+
+    loop {
+        sleep(interval).await;
+        let after = snapshot(&directory);
+        let paths = changes(&before, &after);
+        before = after;
+        if paths.is_empty() {
+            continue;
+        }
+
+        search_index.purge();
+        feeds.purge();
+        match source.invalidate(&paths).await {
+            Ok(rewarmed) => {
+                let manifest_changed = paths.iter().any(|path| path.ends_with("manifest.json"));
+                let cdn_purged = cdn.purge(&purge_keys(&rewarmed, manifest_changed)).await;
+                sender.notify(&source, &public_url, &rewarmed);
+                activitypub.notify(&source, &public_url, &rewarmed);
+                info!(directory = %directory.display(), ?paths, ?rewarmed, ?cdn_purged, "local files changed");
+            },
+            Err(err) => warn!(directory = %directory.display(), ?paths, error = %err, "local files changed, but cannot re-warm them"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let before = Snapshot::from([
+            (String::from("manifest.json"), at(1)),
+            (String::from("fin.md"), at(1)),
+            (String::from("about.md"), at(1)),
+        ]);
+        let after = Snapshot::from([
+            (String::from("manifest.json"), at(1)),
+            (String::from("fin.md"), at(2)),
+            (String::from("new-post.md"), at(2)),
+        ]);
+
+        assert_eq!(changes(&before, &after), vec!["about.md", "fin.md", "new-post.md"]);
+        assert!(changes(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let snapshot = snapshot(Path::new("raw"));
+        assert!(snapshot.contains_key("manifest.json"));
+        assert!(snapshot.contains_key("about.md"));
+        assert!(snapshot.keys().all(|path| is_watched(path)));
+    }
+}
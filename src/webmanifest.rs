@@ -0,0 +1,91 @@
+//! `/manifest.webmanifest`, what a phone needs to install the blog to its home screen: the blog's name, the
+//! `theme_color`, and icons in every size the favicon has.
+use rocket::http::ContentType;
+use rocket::{get, routes, Route, State};
+use serde::Serialize;
+use crate::assets::Assets;
+use crate::config;
+use crate::tenant::Tenant;
+
+const ICON: &str = "favicon.ico";
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Icon {
+    pub src: String,
+    pub sizes: String,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebManifest {
+    pub name: String,
+    pub short_name: String,
+    pub description: String,
+    pub lang: String,
+    pub start_url: String,
+    pub scope: String,
+    pub display: String,
+    pub theme_color: String,
+    pub background_color: String,
+    pub icons: Vec<Icon>,
+}
+
+/// The sizes of the images in an ICO file, e.g. `16x16 32x32`, from its directory of entries; empty if it isn't one.
+pub fn icon_sizes(ico: &[u8]) -> String {
+    if ico.len() < 6 || ico[0..4] != [0, 0, 1, 0] {
+        return String::new();
+    }
+    let count = u16::from_le_bytes([ico[4], ico[5]]) as usize;
+    // a width or height of 0 is 256
+    let size = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+    let sizes: Vec<String> = ico[6..].chunks_exact(16).take(count)
+        .map(|entry| format!("{}x{}", size(entry[0]), size(entry[1])))
+        .collect();
+    return sizes.join(" ");
+}
+
+pub fn web_manifest(tenant: &Tenant, icon: Option<Icon>) -> WebManifest {
+    let config = config::config();
+    return WebManifest {
+        name: tenant.title.to_owned(),
+        short_name: tenant.title.to_owned(),
+        description: tenant.description.to_owned(),
+        lang: config.site_lang.to_owned(),
+        start_url: String::from("/"),
+        scope: String::from("/"),
+        display: String::from("standalone"),
+        theme_color: config.theme_color.to_owned(),
+        background_color: config.theme_color.to_owned(),
+        icons: icon.into_iter().collect(),
+    };
+}
+
+#[get("/manifest.webmanifest")]
+fn manifest(tenant: &Tenant, assets: &State<Assets>) -> (ContentType, String) {
+    let icon = assets.read(ICON).map(|ico| Icon { src: assets.url(ICON), sizes: icon_sizes(&ico), mime_type: String::from("image/x-icon") })
+        .filter(|icon| !icon.sizes.is_empty());
+    let manifest = web_manifest(tenant, icon);
+    return (ContentType::new("application", "manifest+json"), serde_json::to_string(&manifest).unwrap());
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![manifest];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_icon_sizes() {
+        let ico = std::fs::read("static/favicon.ico").unwrap();
+        assert_eq!(icon_sizes(&ico), "16x16 32x32 48x48 64x64");
+
+        let mut ico = vec![0, 0, 1, 0, 2, 0];
+        ico.extend([[0u8; 16], [64; 16]].concat());
+        assert_eq!(icon_sizes(&ico), "256x256 64x64");
+        assert_eq!(icon_sizes(&ico[..22]), "256x256");
+        assert_eq!(icon_sizes(b"\x89PNG\r\n\x1a\n"), "");
+    }
+}
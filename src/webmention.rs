@@ -0,0 +1,678 @@
+//! Receiving [webmentions](https://www.w3.org/TR/webmention/): another site posts the URL of one of its pages, the
+//! source, and the URL of one of our posts it links to, the target. The request is checked and accepted straight away,
+//! and the source fetched afterwards to see that it does link to the target. Mentions wait for approval through
+//! `/admin/webmentions` before they show under the post; one sent again is updated, or removed once the source
+//! no longer links to the target.
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{StatusCode, Url};
+use rocket::form::{Form, FromForm};
+use rocket::http::Status;
+use rocket::response::content::Json;
+use rocket::response::status::Accepted;
+use rocket::{delete, get, post, routes, Route, State};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use crate::admin::AdminToken;
+use crate::blog::{find_post, Post};
+use crate::cdn::{purge_keys, Cdn};
+use crate::config::BlogConfig;
+use crate::public_url::PublicUrl;
+use crate::request_id::RequestId;
+use crate::shortcodes::unescape_html;
+use crate::spam::{SpamFilter, Submission, Visitor};
+use crate::store::{self, Backend, Moderation};
+use crate::tenant::Tenant;
+
+pub mod send;
+
+pub const ENDPOINT: &str = "/webmention";
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Enough for any page that links to a post; the rest of a larger one isn't read.
+const MAX_SOURCE_BYTES: usize = 1024 * 1024;
+const MAX_TITLE_CHARS: usize = 200;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mention {
+    /// The same for every mention of a target by a source, see `mention_id`.
+    pub id: String,
+    pub source: String,
+    /// The post's canonical URL, `public_url` and slug.
+    pub target: String,
+    /// The source's `<title>`, if it has one.
+    pub title: Option<String>,
+    pub received: DateTime<Utc>,
+    pub state: Moderation,
+}
+
+pub fn mention_id(source: &str, target: &str) -> String {
+    let digest = Sha256::digest(format!("{}\n{}", source, target));
+    return hex::encode(&digest[..8]);
+}
+
+/// Where mentions are kept, by id.
+#[rocket::async_trait]
+pub trait MentionStore: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<Mention>, String>;
+    async fn put(&self, mention: &Mention) -> Result<(), String>;
+    async fn remove(&self, id: &str) -> Result<(), String>;
+    /// Every mention, for the moderation queue.
+    async fn all(&self) -> Result<Vec<Mention>, String>;
+    /// The approved mentions of `target`, in any order, without reading the rest.
+    async fn approved_for(&self, target: &str) -> Result<Vec<Mention>, String>;
+}
+
+pub type SharedStore = Arc<dyn MentionStore>;
+
+/// The store `webmention_store` names, or none when it is `none` and the endpoint is off.
+#[derive(Clone, Default)]
+pub struct Webmentions(pub Option<SharedStore>);
+
+impl Webmentions {
+    pub async fn from_config(config: &BlogConfig) -> Result<Webmentions, String> {
+        let store: Option<SharedStore> = match store::backend("webmention", &config.webmention_store, config.webmention_database.as_deref(), config.webmention_table.as_deref()).await? {
+            Backend::None => None,
+            Backend::Memory => Some(Arc::new(MemoryStore::default())),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite(path) => Some(Arc::new(sqlite::SqliteStore::open(&path)?)),
+            #[cfg(feature = "dynamodb")]
+            Backend::DynamoDb(table) => Some(Arc::new(dynamodb::DynamoStore(table))),
+        };
+        return Ok(Webmentions(store));
+    }
+
+    /// Where sources send mentions, advertised on every post; `None` when the endpoint is off.
+    pub fn endpoint(&self, public_url: &PublicUrl) -> Option<String> {
+        return self.0.as_ref().map(|_| format!("{}{}", public_url.0, ENDPOINT));
+    }
+
+    /// Approved mentions of `target`, oldest first. A store that fails is logged and shows none.
+    pub async fn approved(&self, target: &str) -> Vec<Mention> {
+        let store = match &self.0 {
+            Some(store) => store,
+            None => return vec![]
+        };
+        let mut mentions: Vec<Mention> = match store.approved_for(target).await {
+            Ok(mentions) => mentions,
+            Err(err) => {
+                warn!(%target, error = %err, "cannot read webmentions");
+                return vec![];
+            }
+        };
+        mentions.sort_by_key(|mention| mention.received);
+        return mentions;
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore(Mutex<BTreeMap<String, Mention>>);
+
+#[rocket::async_trait]
+impl MentionStore for MemoryStore {
+    async fn get(&self, id: &str) -> Result<Option<Mention>, String> {
+        return Ok(self.0.lock().unwrap().get(id).cloned());
+    }
+
+    async fn put(&self, mention: &Mention) -> Result<(), String> {
+        self.0.lock().unwrap().insert(mention.id.to_owned(), mention.to_owned());
+        return Ok(());
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), String> {
+        self.0.lock().unwrap().remove(id);
+        return Ok(());
+    }
+
+    async fn all(&self) -> Result<Vec<Mention>, String> {
+        return Ok(self.0.lock().unwrap().values().cloned().collect());
+    }
+
+    async fn approved_for(&self, target: &str) -> Result<Vec<Mention>, String> {
+        return Ok(self.0.lock().unwrap().values()
+            .filter(|mention| mention.target == target && mention.state == Moderation::Approved)
+            .cloned()
+            .collect());
+    }
+}
+
+/// Whether `ip` is an address on the internet, rather than this machine, its network or the cloud's metadata service.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    return match ip {
+        IpAddr::V4(ip) => {
+            // 100.64.0.0/10, shared by carrier-grade NAT, and 0.0.0.0/8, this network
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || shared || ip.octets()[0] == 0)
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => !(ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80),
+        }
+    };
+}
+
+/// Whether `url` is an http(s) URL of a host that may be on the internet: not a private address, nor a name only
+/// this network knows. A name that resolves to a private address passes, and is refused by `PublicResolver` instead.
+pub fn is_public(url: &Url) -> bool {
+    if !["http", "https"].contains(&url.scheme()) {
+        return false;
+    }
+    return match url.host_str().map(|host| host.trim_start_matches('[').trim_end_matches(']')) {
+        None => false,
+        Some(host) if host.eq_ignore_ascii_case("localhost") || host.ends_with(".localhost") || host.ends_with(".internal") => false,
+        Some(host) => host.parse::<IpAddr>().map(is_public_ip).unwrap_or(true),
+    };
+}
+
+/// Resolves names as the system does, but fails for any that resolves to an address that isn't public, so a source
+/// or endpoint whose name points at this machine or its network is never connected to, whatever it redirects to.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        return Box::pin(async move {
+            let addrs: Vec<SocketAddr> = rocket::tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+                return Err(format!("{} resolves to {}, which is not public", name.as_str(), addr.ip()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            return Ok(addrs);
+        });
+    }
+}
+
+/// A client for URLs that others give, sources, endpoints and actors, which only ever connects to public addresses:
+/// a URL's host is checked by `is_public` before each redirect, and the addresses it resolves to by `PublicResolver`.
+pub fn public_client() -> Result<reqwest::Client, String> {
+    return reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= 5 || !is_public(attempt.url()) { attempt.stop() } else { attempt.follow() }
+        }))
+        .build()
+        .map_err(|err| err.to_string());
+}
+
+/// The post's canonical URL if `source` and `target` make a mention this blog accepts: both http(s) URLs, the
+/// source somewhere else, and the target a post of this blog.
+pub fn validate(source: &str, target: &str, public_url: &PublicUrl, posts: &Vec<Post>) -> Result<String, String> {
+    let source_url = Url::parse(source).map_err(|_| format!("source {} is not a URL", source))?;
+    if !is_public(&source_url) {
+        return Err(format!("source {} is not a public http(s) URL", source));
+    }
+    let target_url = Url::parse(target).map_err(|_| format!("target {} is not a URL", target))?;
+    let root = Url::parse(&public_url.0).map_err(|err| err.to_string())?;
+    if !matches!(target_url.scheme(), "http" | "https") || target_url.host_str() != root.host_str() {
+        return Err(format!("target {} is not on {}", target, public_url.0));
+    }
+    if source_url.host_str() == target_url.host_str() {
+        return Err(String::from("source and target are on the same site"));
+    }
+
+    let slug = target_url.path().trim_matches('/');
+    return match find_post(posts, slug) {
+        Some(post) if !slug.is_empty() => Ok(format!("{}/{}", public_url.0, post.slug)),
+        _ => Err(format!("target {} is not a post", target)),
+    };
+}
+
+static LINK_ATTRIBUTE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?is)\b(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>"']+))"#).unwrap());
+static TITLE_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+/// A URL without its fragment or a trailing slash, to compare links by.
+fn normalize(url: &Url) -> String {
+    let mut url = url.to_owned();
+    url.set_fragment(None);
+    return url.as_str().trim_end_matches('/').to_owned();
+}
+
+/// Whether the page at `source` links to `target`, relative links included.
+pub fn links_to(source: &str, html: &str, target: &str) -> bool {
+    let (base, target) = match (Url::parse(source), Url::parse(target)) {
+        (Ok(base), Ok(target)) => (base, normalize(&target)),
+        _ => return false
+    };
+    return LINK_ATTRIBUTE.captures_iter(html)
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(2)).or_else(|| captures.get(3)))
+        .filter_map(|link| base.join(unescape_html(link.as_str().trim()).as_str()).ok())
+        .any(|link| normalize(&link) == target);
+}
+
+pub fn title_of(html: &str) -> Option<String> {
+    let title = unescape_html(TITLE_TAG.captures(html)?.get(1)?.as_str().trim());
+    let title: String = title.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(MAX_TITLE_CHARS).collect();
+    return Some(title).filter(|title| !title.is_empty());
+}
+
+/// The source's HTML, or `None` if it is gone.
+async fn fetch_source(source: &str) -> Result<Option<String>, String> {
+    let client = public_client()?;
+    let mut response = client.get(source).send().await.map_err(|err| err.to_string())?;
+    if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("source responded {}", response.status()));
+    }
+
+    let mut body = vec![];
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_SOURCE_BYTES {
+            break;
+        }
+    }
+    return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+}
+
+/// Fetches the source and keeps the mention if it links to the target, or drops one kept before if it no longer
+/// does. An update keeps its moderation; a new mention `spam` rejects is kept rejected.
+pub async fn verify(store: &dyn MentionStore, spam: &SpamFilter, visitor: Visitor, source: &str, target: &str) -> Result<(), String> {
+    let id = mention_id(source, target);
+    let html = fetch_source(source).await?.filter(|html| links_to(source, html, target));
+    let existing = store.get(&id).await?;
+
+    return match (html, existing) {
+        (Some(html), existing) => {
+            let title = title_of(&html);
+            let (received, state) = match existing {
+                Some(mention) => (mention.received, mention.state),
+                None => {
+                    let submission = Submission {
+                        kind: "pingback",
+                        author: title.to_owned().unwrap_or_default(),
+                        author_url: Some(source.to_owned()),
+                        content: title.to_owned().unwrap_or_default(),
+                        permalink: target.to_owned(),
+                        visitor,
+                    };
+                    let state = if spam.rejects(&submission).await { Moderation::Rejected } else { Moderation::Pending };
+                    (Utc::now(), state)
+                }
+            };
+            info!(%source, %target, state = state.as_str(), "webmention verified");
+            store.put(&Mention { id, source: source.to_owned(), target: target.to_owned(), title, received, state }).await
+        },
+        (None, Some(_)) => {
+            info!(%source, %target, "webmention source no longer links to its target, removed");
+            store.remove(&id).await
+        },
+        (None, None) => Err(String::from("source does not link to target")),
+    };
+}
+
+#[derive(FromForm)]
+struct MentionForm<'r> {
+    source: &'r str,
+    target: &'r str,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/webmention", data = "<form>")]
+async fn receive(form: Form<MentionForm<'_>>, webmentions: &State<Webmentions>, spam: &State<SpamFilter>, visitor: Visitor, tenant: &Tenant, request_id: RequestId, public_url: PublicUrl) -> Result<Accepted<String>, (Status, String)> {
+    let store = webmentions.0.to_owned().ok_or((Status::NotFound, String::from("webmentions are not accepted here")))?;
+    let posts = tenant.source.for_request(&request_id).all_posts().await.map_err(|err| (Status::ServiceUnavailable, err))?;
+    let target = validate(form.source, form.target, &public_url, &posts).map_err(|message| (Status::BadRequest, message))?;
+
+    let (source, spam) = (form.source.to_owned(), spam.inner().to_owned());
+    rocket::tokio::spawn(async move {
+        if let Err(err) = verify(store.as_ref(), &spam, visitor, &source, &target).await {
+            warn!(%source, %target, error = %err, "webmention not kept");
+        }
+    });
+
+    return Ok(Accepted(Some(String::from("Thanks, the mention will show once it is verified and approved"))));
+}
+
+pub fn routes() -> Vec<Route> {
+    return routes![receive];
+}
+
+fn store(webmentions: &Webmentions) -> Result<&SharedStore, (Status, String)> {
+    return webmentions.0.as_ref().ok_or((Status::NotFound, String::from("webmention_store is none")));
+}
+
+/// Every mention, or those in `?state=`, newest first.
+#[get("/webmentions?<state>")]
+async fn list(_token: AdminToken, state: Option<&str>, webmentions: &State<Webmentions>) -> Result<Json<String>, (Status, String)> {
+    let state = state.map(|state| Moderation::parse(state).ok_or((Status::BadRequest, format!("no state {}", state)))).transpose()?;
+    let mut mentions = store(webmentions)?.all().await.map_err(|err| (Status::BadGateway, err))?;
+    mentions.retain(|mention| state.map(|state| mention.state == state).unwrap_or(true));
+    mentions.sort_by_key(|mention| std::cmp::Reverse(mention.received));
+
+    return Ok(Json(serde_json::to_string(&mentions).unwrap()));
+}
+
+/// Moves a mention to `pending`, `approved` or `rejected`, purging its post from the CDN.
+#[post("/webmentions/<id>/<state>")]
+async fn moderate(_token: AdminToken, id: &str, state: &str, webmentions: &State<Webmentions>, cdn: &State<Cdn>) -> Result<Json<String>, (Status, String)> {
+    let state = Moderation::parse(state).ok_or((Status::NotFound, format!("no state {}", state)))?;
+    let store = store(webmentions)?;
+    let mut mention = store.get(id).await.map_err(|err| (Status::BadGateway, err))?.ok_or((Status::NotFound, format!("no webmention {}", id)))?;
+    mention.state = state;
+    store.put(&mention).await.map_err(|err| (Status::BadGateway, err))?;
+    info!(%id, state = state.as_str(), source = %mention.source, "webmention moderated");
+    purge_target(&mention, cdn).await;
+
+    return Ok(Json(serde_json::to_string(&mention).unwrap()));
+}
+
+#[delete("/webmentions/<id>")]
+async fn remove(_token: AdminToken, id: &str, webmentions: &State<Webmentions>, cdn: &State<Cdn>) -> Result<Status, (Status, String)> {
+    let store = store(webmentions)?;
+    let mention = store.get(id).await.map_err(|err| (Status::BadGateway, err))?.ok_or((Status::NotFound, format!("no webmention {}", id)))?;
+    store.remove(id).await.map_err(|err| (Status::BadGateway, err))?;
+    purge_target(&mention, cdn).await;
+
+    return Ok(Status::NoContent);
+}
+
+async fn purge_target(mention: &Mention, cdn: &Cdn) {
+    if let Some(slug) = mention.target.rsplit('/').next() {
+        cdn.purge(&purge_keys(&[slug.to_owned()], false)).await;
+    }
+}
+
+/// Mounted under `/admin`.
+pub fn admin_routes() -> Vec<Route> {
+    return routes![list, moderate, remove];
+}
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite {
+    use std::sync::Mutex;
+    use rusqlite::{params, Connection, OptionalExtension, Row};
+    use crate::store::{self, Moderation};
+    use super::{Mention, MentionStore};
+
+    /// One row a mention in `webmentions`, indexed by target and state for a post's approved mentions.
+    pub struct SqliteStore(Mutex<Connection>);
+
+    fn mention(row: &Row) -> rusqlite::Result<Mention> {
+        let state: String = row.get(5)?;
+        return Ok(Mention {
+            id: row.get(0)?,
+            source: row.get(1)?,
+            target: row.get(2)?,
+            title: row.get(3)?,
+            received: row.get(4)?,
+            state: Moderation::parse(&state).unwrap_or(Moderation::Pending),
+        });
+    }
+
+    impl SqliteStore {
+        pub fn open(path: &str) -> Result<SqliteStore, String> {
+            return Ok(SqliteStore(store::sqlite::open(path,
+                "CREATE TABLE IF NOT EXISTS webmentions (
+                    id TEXT PRIMARY KEY, source TEXT NOT NULL, target TEXT NOT NULL, title TEXT, received TEXT NOT NULL, state TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS webmentions_target ON webmentions (target, state);",
+            )?));
+        }
+    }
+
+    #[rocket::async_trait]
+    impl MentionStore for SqliteStore {
+        async fn get(&self, id: &str) -> Result<Option<Mention>, String> {
+            return self.0.lock().unwrap()
+                .query_row("SELECT id, source, target, title, received, state FROM webmentions WHERE id = ?1", [id], mention)
+                .optional()
+                .map_err(|err| err.to_string());
+        }
+
+        async fn put(&self, mention: &Mention) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute(
+                    "INSERT OR REPLACE INTO webmentions (id, source, target, title, received, state) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![mention.id, mention.source, mention.target, mention.title, mention.received, mention.state.as_str()],
+                )
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, id: &str) -> Result<(), String> {
+            return self.0.lock().unwrap()
+                .execute("DELETE FROM webmentions WHERE id = ?1", [id])
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn all(&self) -> Result<Vec<Mention>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection.prepare("SELECT id, source, target, title, received, state FROM webmentions")
+                .map_err(|err| err.to_string())?;
+            let mentions = statement.query_map([], mention).map_err(|err| err.to_string())?;
+            return mentions.collect::<rusqlite::Result<Vec<_>>>().map_err(|err| err.to_string());
+        }
+
+        async fn approved_for(&self, target: &str) -> Result<Vec<Mention>, String> {
+            let connection = self.0.lock().unwrap();
+            let mut statement = connection
+                .prepare("SELECT id, source, target, title, received, state FROM webmentions WHERE target = ?1 AND state = ?2")
+                .map_err(|err| err.to_string())?;
+            let mentions = statement.query_map(params![target, Moderation::Approved.as_str()], mention).map_err(|err| err.to_string())?;
+            return mentions.collect::<rusqlite::Result<Vec<_>>>().map_err(|err| err.to_string());
+        }
+    }
+}
+
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb {
+    use std::collections::HashMap;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use crate::store::dynamodb::Table;
+    use crate::store::Moderation;
+    use super::{Mention, MentionStore};
+
+    /// Items are `{ id: S, mention: S, approved: S }`, with `id` the partition key and the mention as JSON. An
+    /// approved mention's target is in `approved`, the partition key of the global secondary index `approved`; the
+    /// attribute is left off every other mention, so the index only holds what a post shows.
+    pub struct DynamoStore(pub Table);
+
+    const APPROVED_INDEX: &str = "approved";
+
+    fn mention(item: &HashMap<String, AttributeValue>) -> Option<Mention> {
+        return serde_json::from_str(item.get("mention")?.as_s().ok()?).ok();
+    }
+
+    #[rocket::async_trait]
+    impl MentionStore for DynamoStore {
+        async fn get(&self, id: &str) -> Result<Option<Mention>, String> {
+            let item = self.0.client.get_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(id.to_owned()))
+                .send().await
+                .map_err(|err| err.to_string())?
+                .item;
+            return Ok(item.as_ref().and_then(mention));
+        }
+
+        async fn put(&self, mention: &Mention) -> Result<(), String> {
+            let mut item = HashMap::from([
+                (String::from("id"), AttributeValue::S(mention.id.to_owned())),
+                (String::from("mention"), AttributeValue::S(serde_json::to_string(mention).map_err(|err| err.to_string())?)),
+            ]);
+            if mention.state == Moderation::Approved {
+                item.insert(String::from("approved"), AttributeValue::S(mention.target.to_owned()));
+            }
+            return self.0.client.put_item()
+                .table_name(&self.0.name)
+                .set_item(Some(item))
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn remove(&self, id: &str) -> Result<(), String> {
+            return self.0.client.delete_item()
+                .table_name(&self.0.name)
+                .key("id", AttributeValue::S(id.to_owned()))
+                .send().await
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+
+        async fn all(&self) -> Result<Vec<Mention>, String> {
+            let mut mentions = vec![];
+            let mut start = None;
+            loop {
+                let page = self.0.client.scan()
+                    .table_name(&self.0.name)
+                    .set_exclusive_start_key(start)
+                    .send().await
+                    .map_err(|err| err.to_string())?;
+                mentions.extend(page.items().iter().filter_map(mention));
+                start = page.last_evaluated_key().cloned();
+                if start.is_none() {
+                    return Ok(mentions);
+                }
+            }
+        }
+
+        async fn approved_for(&self, target: &str) -> Result<Vec<Mention>, String> {
+            let mut mentions = vec![];
+            let mut start = None;
+            loop {
+                let page = self.0.client.query()
+                    .table_name(&self.0.name)
+                    .index_name(APPROVED_INDEX)
+                    .key_condition_expression("approved = :target")
+                    .expression_attribute_values(":target", AttributeValue::S(target.to_owned()))
+                    .set_exclusive_start_key(start)
+                    .send().await
+                    .map_err(|err| err.to_string())?;
+                mentions.extend(page.items().iter().filter_map(mention));
+                start = page.last_evaluated_key().cloned();
+                if start.is_none() {
+                    return Ok(mentions);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::blog::{to_posts, Registry};
+
+    fn posts() -> Vec<Post> {
+        let manifest = r#"[{ "title": "Zip is scan", "markdown": "zip.md", "updated": "2024-01-25T23:08:00Z" }]"#;
+        return to_posts(&serde_json::from_str::<Vec<Registry>>(manifest).unwrap());
+    }
+
+    #[test]
+    fn test_validate() {
+        let public_url = PublicUrl(String::from("https://hacklewayne.com"));
+        let validate = |source: &str, target: &str| validate(source, target, &public_url, &posts());
+
+        assert_eq!(validate("https://example.com/reply", "https://hacklewayne.com/zip-is-scan/"), Ok(String::from("https://hacklewayne.com/zip-is-scan")));
+        assert!(validate("https://example.com/reply", "https://hacklewayne.com/no-such-post").is_err());
+        assert!(validate("https://example.com/reply", "https://hacklewayne.com/").is_err());
+        assert!(validate("https://example.com/reply", "https://example.org/zip-is-scan").is_err());
+        assert!(validate("https://hacklewayne.com/fin", "https://hacklewayne.com/zip-is-scan").is_err());
+        assert!(validate("http://127.0.0.1/admin", "https://hacklewayne.com/zip-is-scan").is_err());
+        assert!(validate("http://[::1]/", "https://hacklewayne.com/zip-is-scan").is_err());
+        assert!(validate("file:///etc/passwd", "https://hacklewayne.com/zip-is-scan").is_err());
+        assert!(validate("not a url", "https://hacklewayne.com/zip-is-scan").is_err());
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        let public = |ip: &str| is_public_ip(ip.parse().unwrap());
+        assert!(public("93.184.216.34"));
+        assert!(public("2606:2800:220:1::"));
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!public(ip), "{}", ip);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_public_client() {
+        let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let err = PublicResolver.resolve("localhost".parse().unwrap()).await.err().unwrap();
+        assert!(err.to_string().contains("which is not public"));
+        // a name, unlike an address, isn't refused until it is resolved
+        assert!(public_client().unwrap().get(format!("http://localhost:{}/", port)).send().await.is_err());
+    }
+
+    #[test]
+    fn test_links_to() {
+        let target = "https://hacklewayne.com/zip-is-scan";
+
+        assert!(links_to("https://example.com/reply", r#"<a class="u-in-reply-to" href="https://hacklewayne.com/zip-is-scan/">zip</a>"#, target));
+        assert!(links_to("https://example.com/reply", r#"<a href='https://hacklewayne.com/zip-is-scan#comments'>zip</a>"#, target));
+        assert!(links_to("https://hacklewayne.com.example.com/reply", r#"<img src=https://hacklewayne.com/zip-is-scan>"#, target));
+        assert!(!links_to("https://example.com/reply", r#"<p>https://hacklewayne.com/zip-is-scan</p>"#, target));
+        assert!(!links_to("https://example.com/reply", r#"<a href="https://hacklewayne.com/zip-is-scan-again">zip</a>"#, target));
+    }
+
+    #[test]
+    fn test_title_of() {
+        assert_eq!(title_of("<html><title>\n  Re: zip &amp; scan\n</title>"), Some(String::from("Re: zip & scan")));
+        assert_eq!(title_of("<title> </title>"), None);
+        assert_eq!(title_of("<p>no title</p>"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_approved() {
+        let store = Arc::new(MemoryStore::default());
+        let target = "https://hacklewayne.com/zip-is-scan";
+        let mention = |source: &str, state: Moderation, day: u32| Mention {
+            id: mention_id(source, target),
+            source: source.to_owned(),
+            target: target.to_owned(),
+            title: None,
+            received: Utc.ymd(2024, 2, day).and_hms(0, 0, 0),
+            state,
+        };
+        store.put(&mention("https://example.com/later", Moderation::Approved, 2)).await.unwrap();
+        store.put(&mention("https://example.com/earlier", Moderation::Approved, 1)).await.unwrap();
+        store.put(&mention("https://example.com/spam", Moderation::Rejected, 1)).await.unwrap();
+        let webmentions = Webmentions(Some(store));
+
+        let sources: Vec<String> = webmentions.approved(target).await.into_iter().map(|mention| mention.source).collect();
+        assert_eq!(sources, vec![String::from("https://example.com/earlier"), String::from("https://example.com/later")]);
+        assert!(webmentions.approved("https://hacklewayne.com/fin").await.is_empty());
+        assert!(Webmentions::default().approved(target).await.is_empty());
+        assert_eq!(Webmentions::default().endpoint(&PublicUrl(String::from("https://hacklewayne.com"))), None);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[rocket::async_test]
+    async fn test_sqlite_store() {
+        let path = std::env::temp_dir().join(format!("blog-webmentions-{}.db", std::process::id()));
+        let store = sqlite::SqliteStore::open(path.to_str().unwrap()).unwrap();
+        let mention = Mention {
+            id: mention_id("https://example.com/reply", "https://hacklewayne.com/zip-is-scan"),
+            source: String::from("https://example.com/reply"),
+            target: String::from("https://hacklewayne.com/zip-is-scan"),
+            title: Some(String::from("Re: zip")),
+            received: Utc.ymd(2024, 2, 1).and_hms(0, 0, 0),
+            state: Moderation::Pending,
+        };
+
+        store.put(&mention).await.unwrap();
+        store.put(&Mention { state: Moderation::Approved, ..mention.to_owned() }).await.unwrap();
+        assert_eq!(store.get(&mention.id).await.unwrap(), Some(Mention { state: Moderation::Approved, ..mention.to_owned() }));
+        assert_eq!(store.all().await.unwrap().len(), 1);
+        assert_eq!(store.approved_for(&mention.target).await.unwrap(), vec![Mention { state: Moderation::Approved, ..mention.to_owned() }]);
+        assert!(store.approved_for("https://hacklewayne.com/fin").await.unwrap().is_empty());
+        store.put(&Mention { state: Moderation::Rejected, ..mention.to_owned() }).await.unwrap();
+        assert!(store.approved_for(&mention.target).await.unwrap().is_empty());
+        store.remove(&mention.id).await.unwrap();
+        assert_eq!(store.get(&mention.id).await.unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
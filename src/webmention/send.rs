@@ -0,0 +1,203 @@
+//! Sending webmentions for our own posts: once a post is published or changed, every page it links to is told,
+//! through the webmention endpoint that page advertises. A failed send is retried with a growing delay, unless
+//! the target said no or has no endpoint.
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use comrak::markdown_to_html;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::header::{CONTENT_TYPE, LINK};
+use reqwest::{StatusCode, Url};
+use rocket::tokio::time::sleep;
+use tracing::{info, warn};
+use crate::blog::{comrak_options, find_post, CachedSource, Kind};
+use crate::config::BlogConfig;
+use crate::shortcodes::unescape_html;
+use super::{is_public, public_client, MAX_SOURCE_BYTES};
+
+const ATTEMPTS: u32 = 4;
+const FIRST_RETRY: Duration = Duration::from_secs(30);
+
+static ATTRIBUTE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?s)([\w-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>"']+))"#).unwrap());
+static ANCHOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<a\s[^>]*>").unwrap());
+static LINK_OR_ANCHOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<(?:link|a)\s[^>]*>").unwrap());
+static LINK_HEADER_VALUE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<([^>]*)>\s*((?:;\s*[^;,]+)*)"#).unwrap());
+static REL_PARAM: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)rel\s*=\s*(?:"([^"]*)"|([^\s;,]+))"#).unwrap());
+
+fn attributes(tag: &str) -> HashMap<String, String> {
+    return ATTRIBUTE.captures_iter(tag)
+        .filter_map(|captures| {
+            let value = captures.get(2).or_else(|| captures.get(3)).or_else(|| captures.get(4))?;
+            Some((captures[1].to_ascii_lowercase(), unescape_html(value.as_str().trim())))
+        })
+        .collect();
+}
+
+fn has_webmention_rel(rel: &str) -> bool {
+    return rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("webmention"));
+}
+
+/// The pages on other sites `html` links to, without fragments, once each.
+pub fn outbound_links(html: &str, public_url: &str) -> Vec<String> {
+    let own = Url::parse(public_url).ok().and_then(|url| url.host_str().map(str::to_owned));
+    let links: BTreeSet<String> = ANCHOR.find_iter(html)
+        .filter_map(|tag| attributes(tag.as_str()).remove("href"))
+        .filter_map(|href| Url::parse(&href).ok())
+        .filter(|url| is_public(url) && url.host_str().map(str::to_owned) != own)
+        .map(|mut url| {
+            url.set_fragment(None);
+            url.to_string()
+        })
+        .collect();
+    return links.into_iter().collect();
+}
+
+/// The webmention endpoint `target` advertises: the first `rel="webmention"` of its `Link` headers, or else of
+/// its `<link>` and `<a>` tags, relative to the target.
+pub fn discover(target: &Url, link_headers: &[String], html: &str) -> Option<Url> {
+    let from_headers = link_headers.iter()
+        .flat_map(|header| LINK_HEADER_VALUE.captures_iter(header).map(|captures| (captures[1].to_owned(), captures[2].to_owned())).collect::<Vec<_>>())
+        .find(|(_, params)| REL_PARAM.captures_iter(params).any(|rel| has_webmention_rel(rel.get(1).or_else(|| rel.get(2)).map(|rel| rel.as_str()).unwrap_or_default())))
+        .map(|(href, _)| href);
+    let from_html = || LINK_OR_ANCHOR.find_iter(html)
+        .map(|tag| attributes(tag.as_str()))
+        .find(|attributes| attributes.get("rel").map(|rel| has_webmention_rel(rel)).unwrap_or(false) && attributes.contains_key("href"))
+        .and_then(|mut attributes| attributes.remove("href"));
+
+    return target.join(&from_headers.or_else(from_html)?).ok();
+}
+
+/// How a send went; only a `Retry` is tried again.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Sent,
+    NoEndpoint,
+    Refused(String),
+    Retry(String),
+}
+
+/// Posts' outbound links, sent to as posts change when `webmention_send` is on. What was sent for a post is
+/// remembered until restart, so a link taken out is also told, and can drop the mention.
+#[derive(Clone, Default)]
+pub struct Sender {
+    enabled: bool,
+    client: reqwest::Client,
+    sent: Arc<Mutex<HashMap<String, BTreeSet<String>>>>,
+}
+
+impl Sender {
+    pub fn from_config(config: &BlogConfig) -> Result<Sender, String> {
+        let client = public_client()?;
+
+        return Ok(Sender { enabled: config.webmention_send, client, sent: Arc::new(Mutex::new(HashMap::new())) });
+    }
+
+    /// Sends for the posts behind `slugs` in the background, with their URLs under `public_url`.
+    pub fn notify(&self, source: &CachedSource, public_url: &str, slugs: &[String]) {
+        if !self.enabled || slugs.is_empty() {
+            return;
+        }
+        let (sender, source, public_url, slugs) = (self.to_owned(), source.to_owned(), public_url.to_owned(), slugs.to_vec());
+        rocket::tokio::spawn(async move {
+            for slug in slugs {
+                if let Err(err) = sender.send_for(&source, &public_url, &slug).await {
+                    warn!(%slug, error = %err, "cannot send webmentions");
+                }
+            }
+        });
+    }
+
+    async fn send_for(&self, source: &CachedSource, public_url: &str, slug: &str) -> Result<(), String> {
+        let post = match find_post(&source.all_posts().await?, slug) {
+            Some(post) if !post.hidden && post.kind == Kind::Post => post,
+            _ => return Ok(())
+        };
+        let html = markdown_to_html(&source.content(&post).await?, comrak_options());
+        let links: BTreeSet<String> = outbound_links(&html, public_url).into_iter().collect();
+        let removed = self.sent.lock().unwrap().insert(slug.to_owned(), links.to_owned()).unwrap_or_default();
+
+        let post_url = format!("{}/{}", public_url, slug);
+        let targets: BTreeSet<String> = links.union(&removed).cloned().collect();
+        for target in targets {
+            let (sender, post_url) = (self.to_owned(), post_url.to_owned());
+            rocket::tokio::spawn(async move { sender.send_with_retry(&post_url, &target).await });
+        }
+        return Ok(());
+    }
+
+    async fn send_with_retry(&self, source: &str, target: &str) {
+        let mut delay = FIRST_RETRY;
+        for attempt in 1..=ATTEMPTS {
+            match self.send(source, target).await {
+                Outcome::Sent => return info!(%source, %target, "webmention sent"),
+                Outcome::NoEndpoint => return,
+                Outcome::Refused(reason) => return warn!(%source, %target, %reason, "webmention refused"),
+                Outcome::Retry(reason) if attempt == ATTEMPTS => return warn!(%source, %target, %reason, attempt, "webmention not sent, giving up"),
+                Outcome::Retry(reason) => warn!(%source, %target, %reason, attempt, "webmention not sent, retrying"),
+            }
+            sleep(delay).await;
+            delay *= 4;
+        }
+    }
+
+    async fn send(&self, source: &str, target: &str) -> Outcome {
+        let target_url = match Url::parse(target) {
+            Ok(url) if is_public(&url) => url,
+            _ => return Outcome::Refused(format!("{} is not a public URL", target))
+        };
+        let mut response = match self.client.get(target_url.to_owned()).send().await {
+            Ok(response) => response,
+            Err(err) => return Outcome::Retry(err.to_string())
+        };
+        let link_headers: Vec<String> = response.headers().get_all(LINK).iter().filter_map(|value| value.to_str().ok().map(str::to_owned)).collect();
+        let is_html = response.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(|value| value.contains("html")).unwrap_or(false);
+        let mut html = vec![];
+        while is_html && html.len() < MAX_SOURCE_BYTES {
+            match response.chunk().await {
+                Ok(Some(chunk)) => html.extend_from_slice(&chunk),
+                _ => break,
+            }
+        }
+
+        let endpoint = match discover(response.url(), &link_headers, &String::from_utf8_lossy(&html)) {
+            Some(endpoint) if is_public(&endpoint) => endpoint,
+            _ => return Outcome::NoEndpoint
+        };
+        return match self.client.post(endpoint).form(&[("source", source), ("target", target)]).send().await {
+            Ok(response) if response.status().is_success() => Outcome::Sent,
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS || response.status().is_server_error() =>
+                Outcome::Retry(format!("endpoint responded {}", response.status())),
+            Ok(response) => Outcome::Refused(format!("endpoint responded {}", response.status())),
+            Err(err) => Outcome::Retry(err.to_string()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outbound_links() {
+        let html = r#"<p><a href="https://example.com/post#reply">a</a> <a href="/archive">b</a>
+            <a class="x" href="https://hacklewayne.com/fin">c</a> <a href='https://example.com/post'>d</a>
+            <a href="mailto:hackle@example.com">e</a> <a href="http://192.168.1.1/">f</a></p>"#;
+
+        assert_eq!(outbound_links(html, "https://hacklewayne.com"), vec!["https://example.com/post"]);
+    }
+
+    #[test]
+    fn test_discover() {
+        let target = Url::parse("https://example.com/post/1").unwrap();
+        let endpoint = |headers: &[&str], html: &str| discover(&target, &headers.iter().map(|header| header.to_string()).collect::<Vec<_>>(), html)
+            .map(|url| url.to_string());
+
+        assert_eq!(endpoint(&[r#"<https://webmention.io/example>; rel="webmention""#], ""), Some(String::from("https://webmention.io/example")));
+        assert_eq!(endpoint(&[r#"</style.css>; rel="preload", </mention>; rel="other webmention""#], ""), Some(String::from("https://example.com/mention")));
+        assert_eq!(endpoint(&[], r#"<head><link rel="stylesheet" href="/s.css"><link href="endpoint?a=1&amp;b=2" rel="webmention"></head>"#),
+            Some(String::from("https://example.com/post/endpoint?a=1&b=2")));
+        assert_eq!(endpoint(&[], r#"<a rel="webmention" href="">here</a>"#), Some(String::from("https://example.com/post/1")));
+        assert_eq!(endpoint(&[r#"<https://example.com/x>; rel="webmentions""#], "<p>none</p>"), None);
+    }
+}
@@ -0,0 +1,9 @@
+// Tells /beacon which page is read, where from and on how big a screen; nothing else about the reader.
+(function () {
+    var width = window.innerWidth;
+    var hint = width < 600 ? "mobile" : width < 1024 ? "tablet" : "desktop";
+    var data = new URLSearchParams({ page: location.pathname, referrer: document.referrer, hint: hint });
+    if (navigator.sendBeacon) {
+        navigator.sendBeacon("/beacon", data);
+    }
+})();
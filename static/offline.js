@@ -0,0 +1,6 @@
+// Installs /sw.js, which keeps the newest posts and those read for reading offline.
+(function () {
+    if ("serviceWorker" in navigator) {
+        navigator.serviceWorker.register("/sw.js").catch(function () {});
+    }
+})();